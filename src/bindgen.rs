@@ -0,0 +1,507 @@
+#![allow(dead_code)]
+
+//! Generates Rust bindings for a WIT world: a type for each type it defines, a trait
+//! for the host to implement its imports with, and a struct calling its exports.
+//!
+//! The bindings are a module of this crate, reaching the embedding API through
+//! `crate::` paths. Values cross as [`ComponentValue`](crate::canonical::ComponentValue)s,
+//! converted by the [`Marshal`](crate::canonical::Marshal) impls generated alongside
+//! each type.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::wit::{FuncKind, Function, Package, Type, TypeDef, TypeDefKind, Use, WorldItem};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+    "mut", "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true", "try",
+    "type", "unsafe", "use", "where", "while", "yield",
+];
+
+/// Generates the bindings of the world `world` of `package`.
+pub fn generate(package: &Package, world: &str) -> Result<String, String> {
+    let world = package.world(world).ok_or_else(|| format!("No world {world}"))?;
+    let mut out = String::new();
+    writeln!(out, "// Generated by `wavm bindgen` for the world {}.", world.name).unwrap();
+    out.push_str("#![allow(dead_code, unused_imports)]\n\n");
+    out.push_str("use std::sync::Arc;\n\n");
+    out.push_str("use crate::canonical::{self, Borrow, ComponentValue, Marshal, Resource};\n");
+    out.push_str("use crate::component::Component;\n");
+    out.push_str("use crate::component_vm::{ComponentImports, ComponentInstance};\n");
+    out.push_str("use crate::vm::Trap;\n");
+
+    // Every type the package defines, since interfaces may use each other's
+    let mut emitted = HashSet::new();
+    let mut uses: Vec<&Use> = Vec::new();
+    for interface in &package.interfaces {
+        interface.types.iter().for_each(|ty| type_def(ty, &mut emitted, &mut out));
+        uses.extend(&interface.uses);
+    }
+    for item in world.imports.iter().chain(&world.exports) {
+        if let WorldItem::Inline { interface, .. } = item {
+            interface.types.iter().for_each(|ty| type_def(ty, &mut emitted, &mut out));
+            uses.extend(&interface.uses);
+        }
+    }
+    world.types.iter().for_each(|ty| type_def(ty, &mut emitted, &mut out));
+    uses.extend(&world.uses);
+    for (name, alias) in uses.iter().flat_map(|used| &used.names) {
+        if emitted.insert(alias.clone()) {
+            writeln!(out, "\npub type {} = {};", camel(alias), camel(name)).unwrap();
+        }
+    }
+
+    let imports = funcs(package, &world.imports)?;
+    let exports = funcs(package, &world.exports)?;
+    let name = camel(&world.name);
+
+    writeln!(out, "\n/// The functions the host provides for the imports of {}.", world.name).unwrap();
+    writeln!(out, "pub trait {name}Imports: Send + Sync {{").unwrap();
+    for (_, rust_name, func) in &imports {
+        writeln!(out, "    fn {rust_name}(&self{}){};", params(func, true), returns(func, false))
+            .unwrap();
+    }
+    out.push_str("}\n");
+
+    writeln!(out, "\npub struct {name}<'a> {{\n    instance: ComponentInstance<'a>,\n}}").unwrap();
+    writeln!(out, "\nimpl<'a> {name}<'a> {{").unwrap();
+    out.push_str("    /// Instantiates `component`, with `host` providing its imports.\n");
+    writeln!(
+        out,
+        "    pub fn instantiate(component: &'a Component<'a>, host: impl {name}Imports + 'a) \
+         -> Result<Self, Trap> {{"
+    )
+    .unwrap();
+    if !imports.is_empty() {
+        out.push_str("        let host = Arc::new(host);\n");
+    }
+    out.push_str("        let mut imports = ComponentImports::default();\n");
+    for (key, rust_name, func) in &imports {
+        out.push_str("        let host_ = host.clone();\n");
+        writeln!(out, "        imports.functions.insert(String::from({key:?}), Arc::new(move |args: &[ComponentValue]| \
+             -> Result<Vec<ComponentValue>, Trap> {{")
+            .unwrap();
+        out.push_str("            let mut args = args.iter().cloned();\n");
+        let args: Vec<_> = func.params.iter()
+            .map(|_| "Marshal::lift(canonical::next_value(&mut args)?)?")
+            .collect();
+        let call = format!("host_.{rust_name}({})", args.join(", "));
+        match func.results.len() {
+            0 => writeln!(out, "            {call};\n            Ok(Vec::new())").unwrap(),
+            1 => writeln!(out, "            Ok(vec![{call}.lower()])").unwrap(),
+            n => {
+                let names: Vec<_> = (0..n).map(|i| format!("r{i}")).collect();
+                writeln!(out, "            let ({}) = {call};", names.join(", ")).unwrap();
+                let lowered: Vec<_> = names.iter().map(|name| format!("{name}.lower()")).collect();
+                writeln!(out, "            Ok(vec![{}])", lowered.join(", ")).unwrap();
+            }
+        }
+        out.push_str("        }));\n");
+    }
+    out.push_str("        Ok(Self { instance: ComponentInstance::new(component, imports)? })\n");
+    out.push_str("    }\n");
+
+    for (key, rust_name, func) in &exports {
+        writeln!(out, "\n    pub fn {rust_name}(&self{}){} {{", params(func, true), returns(func, true))
+            .unwrap();
+        let args: Vec<_> = func.params.iter()
+            .map(|(name, _)| format!("{}.lower()", snake(name)))
+            .collect();
+        let call = format!("self.instance.call({key:?}, &[{}])?", args.join(", "));
+        let lift = "Marshal::lift(canonical::next_value(&mut results)?)?";
+        match func.results.len() {
+            0 => writeln!(out, "        {call};\n        Ok(())").unwrap(),
+            n => {
+                writeln!(out, "        let mut results = {call}.into_iter();").unwrap();
+                match n {
+                    1 => writeln!(out, "        Marshal::lift(canonical::next_value(&mut results)?)")
+                        .unwrap(),
+                    _ => writeln!(out, "        Ok(({}))", vec![lift; n].join(", ")).unwrap(),
+                }
+            }
+        }
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// The functions of world items: each with the name a component imports or exports
+/// it by, and its name in Rust.
+fn funcs<'p>(package: &'p Package, items: &'p [WorldItem])
+    -> Result<Vec<(String, String, &'p Function)>, String>
+{
+    let mut funcs = Vec::new();
+    for item in items {
+        let (instance, interface) = match item {
+            WorldItem::Func(func) => {
+                funcs.push((func.component_name(), rust_name(None, func), func));
+                continue;
+            }
+            WorldItem::Inline { name, interface } => (name.clone(), interface),
+            WorldItem::Interface(name) => {
+                let interface = package.interface(name)
+                    .ok_or_else(|| format!("No interface {name}"))?;
+                (package.qualified(name), interface)
+            }
+        };
+        let resource_funcs = interface.types.iter().flat_map(|ty| match &ty.kind {
+            TypeDefKind::Resource(funcs) => funcs.as_slice(),
+            _ => &[],
+        });
+        for func in interface.funcs.iter().chain(resource_funcs) {
+            let key = format!("{instance}#{}", func.component_name());
+            funcs.push((key, rust_name(Some(&interface.name), func), func));
+        }
+    }
+    Ok(funcs)
+}
+
+fn rust_name(interface: Option<&str>, func: &Function) -> String {
+    let name = match &func.kind {
+        FuncKind::Freestanding => snake(&func.name),
+        FuncKind::Constructor(resource) => format!("{}_new", snake(resource)),
+        FuncKind::Method(resource) | FuncKind::Static(resource) => {
+            format!("{}_{}", snake(resource), snake(&func.name))
+        }
+    };
+    match interface {
+        Some(interface) => format!("{}_{name}", snake(interface)),
+        None => escape(name),
+    }
+}
+
+/// The parameters after `self`, each preceded by a comma.
+fn params(func: &Function, typed: bool) -> String {
+    func.params.iter()
+        .map(|(name, ty)| match typed {
+            true => format!(", {}: {}", snake(name), rust_type(ty)),
+            false => format!(", {}", snake(name)),
+        })
+        .collect()
+}
+
+/// The return type of a function, which exports wrap in a `Result` for traps.
+fn returns(func: &Function, fallible: bool) -> String {
+    let ty = match func.results.as_slice() {
+        [] => String::from("()"),
+        [(_, ty)] => rust_type(ty),
+        results => {
+            let types: Vec<_> = results.iter().map(|(_, ty)| rust_type(ty)).collect();
+            format!("({})", types.join(", "))
+        }
+    };
+    match (fallible, func.results.is_empty()) {
+        (true, _) => format!(" -> Result<{ty}, Trap>"),
+        (false, true) => String::new(),
+        (false, false) => format!(" -> {ty}"),
+    }
+}
+
+fn rust_type(ty: &Type) -> String {
+    match ty {
+        Type::Bool => String::from("bool"),
+        Type::S8 => String::from("i8"),
+        Type::U8 => String::from("u8"),
+        Type::S16 => String::from("i16"),
+        Type::U16 => String::from("u16"),
+        Type::S32 => String::from("i32"),
+        Type::U32 => String::from("u32"),
+        Type::S64 => String::from("i64"),
+        Type::U64 => String::from("u64"),
+        Type::F32 => String::from("f32"),
+        Type::F64 => String::from("f64"),
+        Type::Char => String::from("char"),
+        Type::String => String::from("String"),
+        Type::List(elem) => format!("Vec<{}>", rust_type(elem)),
+        Type::Option(ty) => format!("Option<{}>", rust_type(ty)),
+        Type::Result(ok, err) => {
+            let payload = |ty: &Option<Box<Type>>| ty.as_deref().map_or(String::from("()"), rust_type);
+            format!("Result<{}, {}>", payload(ok), payload(err))
+        }
+        Type::Tuple(types) => {
+            let types: Vec<_> = types.iter().map(rust_type).collect();
+            match types.len() {
+                1 => format!("({},)", types[0]),
+                _ => format!("({})", types.join(", ")),
+            }
+        }
+        Type::Borrow(resource) => format!("Borrow<{}>", camel(resource)),
+        Type::Named(name) => camel(name),
+    }
+}
+
+/// Appends a type definition and its `Marshal` impl, unless one of its name already was.
+fn type_def(def: &TypeDef, emitted: &mut HashSet<String>, out: &mut String) {
+    if !emitted.insert(def.name.clone()) {
+        return;
+    }
+    let name = camel(&def.name);
+    let expected = format!("{:?}", def.name);
+    out.push('\n');
+    let (lower, lift) = match &def.kind {
+        TypeDefKind::Alias(ty) => {
+            writeln!(out, "pub type {name} = {};", rust_type(ty)).unwrap();
+            return;
+        }
+        TypeDefKind::Record(fields) => {
+            writeln!(out, "#[derive(Debug, Clone, PartialEq)]\npub struct {name} {{").unwrap();
+            for (field, ty) in fields {
+                writeln!(out, "    pub {}: {},", snake(field), rust_type(ty)).unwrap();
+            }
+            out.push_str("}\n");
+            let lowered: Vec<_> = fields.iter()
+                .map(|(field, _)| format!("(String::from({field:?}), self.{}.lower())", snake(field)))
+                .collect();
+            let lifted: Vec<_> = fields.iter()
+                .map(|(field, _)| format!("{}: Marshal::lift(fields.next().unwrap())?", snake(field)))
+                .collect();
+            (
+                format!("ComponentValue::Record(vec![{}])", lowered.join(", ")),
+                format!(
+                    "let mut fields = canonical::fields(value, {})?.into_iter();\n        \
+                     Ok(Self {{ {} }})",
+                    fields.len(),
+                    lifted.join(", "),
+                ),
+            )
+        }
+        TypeDefKind::Variant(cases) => {
+            writeln!(out, "#[derive(Debug, Clone, PartialEq)]\npub enum {name} {{").unwrap();
+            for (case, payload) in cases {
+                match payload {
+                    Some(ty) => writeln!(out, "    {}({}),", camel(case), rust_type(ty)).unwrap(),
+                    None => writeln!(out, "    {},", camel(case)).unwrap(),
+                }
+            }
+            out.push_str("}\n");
+            let mut lower = String::from("match self {\n");
+            let mut lift = String::from(
+                "match value {\n            ComponentValue::Variant(case, payload) => \
+                 match case.as_str() {\n",
+            );
+            for (case, payload) in cases {
+                let variant = camel(case);
+                match payload {
+                    Some(_) => {
+                        writeln!(
+                            lower,
+                            "            Self::{variant}(payload) => \
+                             ComponentValue::Variant(String::from({case:?}), payload.lower_payload()),"
+                        )
+                        .unwrap();
+                        writeln!(
+                            lift,
+                            "                {case:?} => Ok(Self::{variant}(Marshal::lift_payload(payload)?)),"
+                        )
+                        .unwrap();
+                    }
+                    None => {
+                        writeln!(
+                            lower,
+                            "            Self::{variant} => ComponentValue::Variant(String::from({case:?}), None),"
+                        )
+                        .unwrap();
+                        writeln!(
+                            lift,
+                            "                {case:?} => <()>::lift_payload(payload).map(|_| Self::{variant}),"
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+            lower.push_str("        }");
+            writeln!(
+                lift,
+                "                _ => Err(canonical::unexpected(&ComponentValue::Variant(case, payload), \
+                 {expected})),\n            }},\n            \
+                 other => Err(canonical::unexpected(&other, {expected})),\n        }}"
+            )
+            .unwrap();
+            (lower, lift)
+        }
+        TypeDefKind::Enum(cases) => {
+            writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]\npub enum {name} {{").unwrap();
+            for case in cases {
+                writeln!(out, "    {},", camel(case)).unwrap();
+            }
+            out.push_str("}\n");
+            let mut lower = String::from("ComponentValue::Enum(String::from(match self {\n");
+            let mut lift = String::from(
+                "match value {\n            ComponentValue::Enum(case) => match case.as_str() {\n",
+            );
+            for case in cases {
+                writeln!(lower, "            Self::{} => {case:?},", camel(case)).unwrap();
+                writeln!(lift, "                {case:?} => Ok(Self::{}),", camel(case)).unwrap();
+            }
+            lower.push_str("        }))");
+            writeln!(
+                lift,
+                "                _ => Err(canonical::unexpected(&ComponentValue::Enum(case), {expected})),\n            \
+                 }},\n            other => Err(canonical::unexpected(&other, {expected})),\n        }}"
+            )
+            .unwrap();
+            (lower, lift)
+        }
+        TypeDefKind::Flags(flags) => {
+            writeln!(out, "#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]\npub struct {name} {{")
+                .unwrap();
+            for flag in flags {
+                writeln!(out, "    pub {}: bool,", snake(flag)).unwrap();
+            }
+            out.push_str("}\n");
+            let mut lower = String::from("let mut names = Vec::new();\n");
+            for flag in flags {
+                writeln!(lower, "        if self.{} {{\n            names.push(String::from({flag:?}));\n        }}", snake(flag))
+                    .unwrap();
+            }
+            lower.push_str("        ComponentValue::Flags(names)");
+            let lifted: Vec<_> = flags.iter()
+                .map(|flag| format!("{}: names.iter().any(|name| name == {flag:?})", snake(flag)))
+                .collect();
+            let lift = format!(
+                "match value {{\n            ComponentValue::Flags(names) => Ok(Self {{ {} }}),\n            \
+                 other => Err(canonical::unexpected(&other, {expected})),\n        }}",
+                lifted.join(", "),
+            );
+            (lower, lift)
+        }
+        TypeDefKind::Resource(_) => {
+            writeln!(out, "/// A handle to a {}, as its representation.", def.name).unwrap();
+            writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]\npub struct {name}(pub u32);")
+                .unwrap();
+            writeln!(
+                out,
+                "\nimpl From<u32> for {name} {{\n    fn from(rep: u32) -> Self {{\n        Self(rep)\n    }}\n}}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "\nimpl Resource for {name} {{\n    fn rep(&self) -> u32 {{\n        self.0\n    }}\n}}"
+            )
+            .unwrap();
+            (
+                String::from("ComponentValue::Own(self.0)"),
+                format!(
+                    "match value {{\n            ComponentValue::Own(rep) => Ok(Self(rep)),\n            \
+                     other => Err(canonical::unexpected(&other, {expected})),\n        }}"
+                ),
+            )
+        }
+    };
+    writeln!(out, "\nimpl Marshal for {name} {{").unwrap();
+    writeln!(out, "    fn lower(self) -> ComponentValue {{\n        {lower}\n    }}\n").unwrap();
+    writeln!(out, "    fn lift(value: ComponentValue) -> Result<Self, Trap> {{\n        {}\n    }}", lift.trim_end())
+        .unwrap();
+    out.push_str("}\n");
+}
+
+/// `kebab-case` as `UpperCamelCase`.
+fn camel(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            chars.next().map(|c| c.to_ascii_uppercase()).into_iter().chain(chars).collect::<String>()
+        })
+        .collect()
+}
+
+/// `kebab-case` as `snake_case`, avoiding keywords.
+fn snake(name: &str) -> String {
+    escape(name.replace('-', "_").to_ascii_lowercase())
+}
+
+fn escape(name: String) -> String {
+    match KEYWORDS.contains(&name.as_str()) {
+        true => name + "_",
+        false => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{component, test_util, wit};
+
+    const APP: &str = "
+        package demo:app;
+        interface files {
+            resource file {
+                constructor(path: string);
+                read: func(len: u32) -> result<list<u8>, error-code>;
+            }
+            enum error-code { not-found, denied }
+        }
+        world app {
+            import files;
+            import log: func(level: level, msg: string);
+            variant level { info, custom(string) }
+            flags mode { fast, safe }
+            export run: func(mode: mode) -> (code: s32, type: string);
+        }
+    ";
+
+    const GREETER: &str = "
+        package demo:greeter;
+        world greeter {
+            import greet: func(name: string) -> string;
+            export run: func(name: string) -> string;
+        }
+    ";
+
+    #[test]
+    fn generates() {
+        let package = wit::parse(APP).unwrap();
+        let bindings = generate(&package, "app").unwrap();
+        for expected in [
+            "pub struct File(pub u32);",
+            "pub enum ErrorCode {\n    NotFound,\n    Denied,\n}",
+            "    Custom(String),",
+            "pub trait AppImports: Send + Sync {",
+            "    fn files_file_new(&self, path: String) -> File;",
+            "    fn files_file_read(&self, self_: Borrow<File>, len: u32) -> Result<Vec<u8>, ErrorCode>;",
+            "    fn log(&self, level: Level, msg: String);",
+            "imports.functions.insert(String::from(\"demo:app/files#[method]file.read\")",
+            "    pub fn run(&self, mode: Mode) -> Result<(i32, String), Trap> {",
+            "self.instance.call(\"run\", &[mode.lower()])?.into_iter();",
+        ] {
+            assert!(bindings.contains(expected), "{expected} not in:\n{bindings}");
+        }
+        assert_eq!(Err(String::from("No world lib")), generate(&package, "lib"));
+    }
+
+    /// The bindings of both worlds are checked in as modules of the crate, so building
+    /// the tests compiles them.
+    #[test]
+    fn checked_in_bindings_are_current() {
+        for (source, world, checked_in) in [
+            (APP, "app", include_str!("bindgen_app.rs")),
+            (GREETER, "greeter", include_str!("bindgen_greeter.rs")),
+        ] {
+            let package = wit::parse(source).unwrap();
+            let bindings = generate(&package, world).unwrap();
+            assert!(checked_in == bindings, "Regenerate the bindings of {world}:\n{bindings}");
+        }
+    }
+
+    #[test]
+    fn bindings_call_a_component() {
+        use crate::bindgen_greeter::{Greeter, GreeterImports};
+
+        struct Host;
+
+        impl GreeterImports for Host {
+            fn greet(&self, name: String) -> String {
+                format!("Hello, {name}!")
+            }
+        }
+
+        let bytes = test_util::greeter();
+        let component = component::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        let greeter = Greeter::instantiate(&component, Host).unwrap();
+        assert_eq!(Ok(String::from("Hello, wörld!")), greeter.run(String::from("wörld")));
+        assert_eq!(Ok(String::from("Hello, !")), greeter.run(String::new()));
+    }
+}
@@ -0,0 +1,157 @@
+// Generated by `wavm bindgen` for the world app.
+#![allow(dead_code, unused_imports)]
+
+use std::sync::Arc;
+
+use crate::canonical::{self, Borrow, ComponentValue, Marshal, Resource};
+use crate::component::Component;
+use crate::component_vm::{ComponentImports, ComponentInstance};
+use crate::vm::Trap;
+
+/// A handle to a file, as its representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct File(pub u32);
+
+impl From<u32> for File {
+    fn from(rep: u32) -> Self {
+        Self(rep)
+    }
+}
+
+impl Resource for File {
+    fn rep(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Marshal for File {
+    fn lower(self) -> ComponentValue {
+        ComponentValue::Own(self.0)
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Own(rep) => Ok(Self(rep)),
+            other => Err(canonical::unexpected(&other, "file")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NotFound,
+    Denied,
+}
+
+impl Marshal for ErrorCode {
+    fn lower(self) -> ComponentValue {
+        ComponentValue::Enum(String::from(match self {
+            Self::NotFound => "not-found",
+            Self::Denied => "denied",
+        }))
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Enum(case) => match case.as_str() {
+                "not-found" => Ok(Self::NotFound),
+                "denied" => Ok(Self::Denied),
+                _ => Err(canonical::unexpected(&ComponentValue::Enum(case), "error-code")),
+            },
+            other => Err(canonical::unexpected(&other, "error-code")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Level {
+    Info,
+    Custom(String),
+}
+
+impl Marshal for Level {
+    fn lower(self) -> ComponentValue {
+        match self {
+            Self::Info => ComponentValue::Variant(String::from("info"), None),
+            Self::Custom(payload) => ComponentValue::Variant(String::from("custom"), payload.lower_payload()),
+        }
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Variant(case, payload) => match case.as_str() {
+                "info" => <()>::lift_payload(payload).map(|_| Self::Info),
+                "custom" => Ok(Self::Custom(Marshal::lift_payload(payload)?)),
+                _ => Err(canonical::unexpected(&ComponentValue::Variant(case, payload), "level")),
+            },
+            other => Err(canonical::unexpected(&other, "level")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mode {
+    pub fast: bool,
+    pub safe: bool,
+}
+
+impl Marshal for Mode {
+    fn lower(self) -> ComponentValue {
+        let mut names = Vec::new();
+        if self.fast {
+            names.push(String::from("fast"));
+        }
+        if self.safe {
+            names.push(String::from("safe"));
+        }
+        ComponentValue::Flags(names)
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Flags(names) => Ok(Self { fast: names.iter().any(|name| name == "fast"), safe: names.iter().any(|name| name == "safe") }),
+            other => Err(canonical::unexpected(&other, "mode")),
+        }
+    }
+}
+
+/// The functions the host provides for the imports of app.
+pub trait AppImports: Send + Sync {
+    fn files_file_new(&self, path: String) -> File;
+    fn files_file_read(&self, self_: Borrow<File>, len: u32) -> Result<Vec<u8>, ErrorCode>;
+    fn log(&self, level: Level, msg: String);
+}
+
+pub struct App<'a> {
+    instance: ComponentInstance<'a>,
+}
+
+impl<'a> App<'a> {
+    /// Instantiates `component`, with `host` providing its imports.
+    pub fn instantiate(component: &'a Component<'a>, host: impl AppImports + 'a) -> Result<Self, Trap> {
+        let host = Arc::new(host);
+        let mut imports = ComponentImports::default();
+        let host_ = host.clone();
+        imports.functions.insert(String::from("demo:app/files#[constructor]file"), Arc::new(move |args: &[ComponentValue]| -> Result<Vec<ComponentValue>, Trap> {
+            let mut args = args.iter().cloned();
+            Ok(vec![host_.files_file_new(Marshal::lift(canonical::next_value(&mut args)?)?).lower()])
+        }));
+        let host_ = host.clone();
+        imports.functions.insert(String::from("demo:app/files#[method]file.read"), Arc::new(move |args: &[ComponentValue]| -> Result<Vec<ComponentValue>, Trap> {
+            let mut args = args.iter().cloned();
+            Ok(vec![host_.files_file_read(Marshal::lift(canonical::next_value(&mut args)?)?, Marshal::lift(canonical::next_value(&mut args)?)?).lower()])
+        }));
+        let host_ = host.clone();
+        imports.functions.insert(String::from("log"), Arc::new(move |args: &[ComponentValue]| -> Result<Vec<ComponentValue>, Trap> {
+            let mut args = args.iter().cloned();
+            host_.log(Marshal::lift(canonical::next_value(&mut args)?)?, Marshal::lift(canonical::next_value(&mut args)?)?);
+            Ok(Vec::new())
+        }));
+        Ok(Self { instance: ComponentInstance::new(component, imports)? })
+    }
+
+    pub fn run(&self, mode: Mode) -> Result<(i32, String), Trap> {
+        let mut results = self.instance.call("run", &[mode.lower()])?.into_iter();
+        Ok((Marshal::lift(canonical::next_value(&mut results)?)?, Marshal::lift(canonical::next_value(&mut results)?)?))
+    }
+}
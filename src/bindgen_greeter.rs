@@ -0,0 +1,37 @@
+// Generated by `wavm bindgen` for the world greeter.
+#![allow(dead_code, unused_imports)]
+
+use std::sync::Arc;
+
+use crate::canonical::{self, Borrow, ComponentValue, Marshal, Resource};
+use crate::component::Component;
+use crate::component_vm::{ComponentImports, ComponentInstance};
+use crate::vm::Trap;
+
+/// The functions the host provides for the imports of greeter.
+pub trait GreeterImports: Send + Sync {
+    fn greet(&self, name: String) -> String;
+}
+
+pub struct Greeter<'a> {
+    instance: ComponentInstance<'a>,
+}
+
+impl<'a> Greeter<'a> {
+    /// Instantiates `component`, with `host` providing its imports.
+    pub fn instantiate(component: &'a Component<'a>, host: impl GreeterImports + 'a) -> Result<Self, Trap> {
+        let host = Arc::new(host);
+        let mut imports = ComponentImports::default();
+        let host_ = host.clone();
+        imports.functions.insert(String::from("greet"), Arc::new(move |args: &[ComponentValue]| -> Result<Vec<ComponentValue>, Trap> {
+            let mut args = args.iter().cloned();
+            Ok(vec![host_.greet(Marshal::lift(canonical::next_value(&mut args)?)?).lower()])
+        }));
+        Ok(Self { instance: ComponentInstance::new(component, imports)? })
+    }
+
+    pub fn run(&self, name: String) -> Result<String, Trap> {
+        let mut results = self.instance.call("run", &[name.lower()])?.into_iter();
+        Marshal::lift(canonical::next_value(&mut results)?)
+    }
+}
@@ -14,7 +14,8 @@ pub mod op {
     op!(LOOP, 0x03);
     op!(IF, 0x04);
     op!(ELSE, 0x05);
-
+    op!(THROW, 0x08);
+    op!(THROW_REF, 0x0a);
     op!(END, 0x0b);
     op!(BR, 0x0c);
     op!(BR_IF, 0x0d);
@@ -22,10 +23,15 @@ pub mod op {
     op!(RETURN, 0x0f);
     op!(CALL, 0x10);
     op!(CALL_INDIRECT, 0x11);
+    op!(RETURN_CALL, 0x12);
+    op!(RETURN_CALL_INDIRECT, 0x13);
+    op!(CALL_REF, 0x14);
+    op!(RETURN_CALL_REF, 0x15);
 
     op!(DROP, 0x1a);
     op!(SELECT, 0x1b);
     op!(SELECT_T, 0x1c);
+    op!(TRY_TABLE, 0x1f);
 
     // Variables
     op!(LOCAL_GET, 0x20);
@@ -33,6 +39,8 @@ pub mod op {
     op!(LOCAL_TEE, 0x22);
     op!(GLOBAL_GET, 0x23);
     op!(GLOBAL_SET, 0x24);
+    op!(TABLE_GET, 0x25);
+    op!(TABLE_SET, 0x26);
 
     // Memory
     op!(I32_LOAD, 0x28);
@@ -75,7 +83,7 @@ pub mod op {
     op!(I32_LT_U, 0x49);
     op!(I32_GT_S, 0x4a);
     op!(I32_GT_U, 0x4b);
-    op!(I32_LE_S, 0x4a);    // Should this be 0x4c?
+    op!(I32_LE_S, 0x4c);
     op!(I32_LE_U, 0x4d);
     op!(I32_GE_S, 0x4e);
     op!(I32_GE_U, 0x4f);
@@ -86,7 +94,7 @@ pub mod op {
     op!(I64_LT_U, 0x54);
     op!(I64_GT_S, 0x55);
     op!(I64_GT_U, 0x56);
-    op!(I64_LE_S, 0x55);    // Should this be 0x57?
+    op!(I64_LE_S, 0x57);
     op!(I64_LE_U, 0x58);
     op!(I64_GE_S, 0x59);
     op!(I64_GE_U, 0x5a);
@@ -198,10 +206,420 @@ pub mod op {
     op!(I64_REINTERPRET_F64, 0xbd);
     op!(F32_REINTERPRET_I32, 0xbe);
     op!(F64_REINTERPRET_I64, 0xbf);
+
+    // Reference
+    op!(REF_NULL, 0xd0);
+    op!(REF_IS_NULL, 0xd1);
+    op!(REF_FUNC, 0xd2);
+    op!(REF_EQ, 0xd3);
+    op!(REF_AS_NON_NULL, 0xd4);
+    op!(BR_ON_NULL, 0xd5);
+    op!(BR_ON_NON_NULL, 0xd6);
+
+    // Prefixes of multi-byte opcodes, followed by a LEB128 sub-opcode
+    op!(GC_PREFIX, 0xfb);
+    op!(MISC_PREFIX, 0xfc);
+    op!(SIMD_PREFIX, 0xfd);
+    op!(ATOMIC_PREFIX, 0xfe);
+}
+
+/// Set in a memory immediate's alignment when an explicit memory index follows it.
+pub const MEMARG_INDEX_BIT: usize = 0x40;
+
+/// The kinds of clause in a [`op::TRY_TABLE`]'s catch list.
+pub mod catch {
+    pub const CATCH: u8 = 0x00;
+    pub const CATCH_REF: u8 = 0x01;
+    pub const CATCH_ALL: u8 = 0x02;
+    pub const CATCH_ALL_REF: u8 = 0x03;
+}
+
+/// Sub-opcodes following [`op::MISC_PREFIX`].
+pub mod misc {
+    pub const MEMORY_INIT: u32 = 8;
+    pub const DATA_DROP: u32 = 9;
+    pub const MEMORY_COPY: u32 = 10;
+    pub const MEMORY_FILL: u32 = 11;
+    pub const TABLE_INIT: u32 = 12;
+    pub const ELEM_DROP: u32 = 13;
+    pub const TABLE_COPY: u32 = 14;
+    pub const TABLE_GROW: u32 = 15;
+    pub const TABLE_SIZE: u32 = 16;
+    pub const TABLE_FILL: u32 = 17;
+}
+
+/// Sub-opcodes following [`op::GC_PREFIX`].
+pub mod gc {
+    pub const STRUCT_NEW: u32 = 0;
+    pub const STRUCT_NEW_DEFAULT: u32 = 1;
+    pub const STRUCT_GET: u32 = 2;
+    pub const STRUCT_GET_S: u32 = 3;
+    pub const STRUCT_GET_U: u32 = 4;
+    pub const STRUCT_SET: u32 = 5;
+    pub const ARRAY_NEW: u32 = 6;
+    pub const ARRAY_NEW_DEFAULT: u32 = 7;
+    pub const ARRAY_NEW_FIXED: u32 = 8;
+    pub const ARRAY_NEW_DATA: u32 = 9;
+    pub const ARRAY_NEW_ELEM: u32 = 10;
+    pub const ARRAY_GET: u32 = 11;
+    pub const ARRAY_GET_S: u32 = 12;
+    pub const ARRAY_GET_U: u32 = 13;
+    pub const ARRAY_SET: u32 = 14;
+    pub const ARRAY_LEN: u32 = 15;
+    pub const ARRAY_FILL: u32 = 16;
+    pub const ARRAY_COPY: u32 = 17;
+    pub const ARRAY_INIT_DATA: u32 = 18;
+    pub const ARRAY_INIT_ELEM: u32 = 19;
+    pub const REF_TEST: u32 = 20;
+    pub const REF_TEST_NULL: u32 = 21;
+    pub const REF_CAST: u32 = 22;
+    pub const REF_CAST_NULL: u32 = 23;
+    pub const BR_ON_CAST: u32 = 24;
+    pub const BR_ON_CAST_FAIL: u32 = 25;
+    pub const ANY_CONVERT_EXTERN: u32 = 26;
+    pub const EXTERN_CONVERT_ANY: u32 = 27;
+    pub const REF_I31: u32 = 28;
+    pub const I31_GET_S: u32 = 29;
+    pub const I31_GET_U: u32 = 30;
+
+    /// Set in the flags of [`BR_ON_CAST`] and [`BR_ON_CAST_FAIL`] when the operand's
+    /// type is nullable.
+    pub const CAST_SOURCE_NULL: u8 = 0x01;
+    /// Set when the type being cast to is nullable.
+    pub const CAST_TARGET_NULL: u8 = 0x02;
+}
+
+/// Sub-opcodes following [`op::SIMD_PREFIX`].
+pub mod simd {
+    pub const V128_LOAD: u32 = 0x00;
+    pub const V128_LOAD8X8_S: u32 = 0x01;
+    pub const V128_LOAD8X8_U: u32 = 0x02;
+    pub const V128_LOAD16X4_S: u32 = 0x03;
+    pub const V128_LOAD16X4_U: u32 = 0x04;
+    pub const V128_LOAD32X2_S: u32 = 0x05;
+    pub const V128_LOAD32X2_U: u32 = 0x06;
+    pub const V128_LOAD8_SPLAT: u32 = 0x07;
+    pub const V128_LOAD16_SPLAT: u32 = 0x08;
+    pub const V128_LOAD32_SPLAT: u32 = 0x09;
+    pub const V128_LOAD64_SPLAT: u32 = 0x0a;
+    pub const V128_STORE: u32 = 0x0b;
+    pub const V128_CONST: u32 = 0x0c;
+    pub const I8X16_SHUFFLE: u32 = 0x0d;
+    pub const I8X16_SWIZZLE: u32 = 0x0e;
+    pub const I8X16_SPLAT: u32 = 0x0f;
+    pub const I16X8_SPLAT: u32 = 0x10;
+    pub const I32X4_SPLAT: u32 = 0x11;
+    pub const I64X2_SPLAT: u32 = 0x12;
+    pub const F32X4_SPLAT: u32 = 0x13;
+    pub const F64X2_SPLAT: u32 = 0x14;
+    pub const I8X16_EXTRACT_LANE_S: u32 = 0x15;
+    pub const I8X16_EXTRACT_LANE_U: u32 = 0x16;
+    pub const I8X16_REPLACE_LANE: u32 = 0x17;
+    pub const I16X8_EXTRACT_LANE_S: u32 = 0x18;
+    pub const I16X8_EXTRACT_LANE_U: u32 = 0x19;
+    pub const I16X8_REPLACE_LANE: u32 = 0x1a;
+    pub const I32X4_EXTRACT_LANE: u32 = 0x1b;
+    pub const I32X4_REPLACE_LANE: u32 = 0x1c;
+    pub const I64X2_EXTRACT_LANE: u32 = 0x1d;
+    pub const I64X2_REPLACE_LANE: u32 = 0x1e;
+    pub const F32X4_EXTRACT_LANE: u32 = 0x1f;
+    pub const F32X4_REPLACE_LANE: u32 = 0x20;
+    pub const F64X2_EXTRACT_LANE: u32 = 0x21;
+    pub const F64X2_REPLACE_LANE: u32 = 0x22;
+    pub const I8X16_EQ: u32 = 0x23;
+    pub const I8X16_NE: u32 = 0x24;
+    pub const I8X16_LT_S: u32 = 0x25;
+    pub const I8X16_LT_U: u32 = 0x26;
+    pub const I8X16_GT_S: u32 = 0x27;
+    pub const I8X16_GT_U: u32 = 0x28;
+    pub const I8X16_LE_S: u32 = 0x29;
+    pub const I8X16_LE_U: u32 = 0x2a;
+    pub const I8X16_GE_S: u32 = 0x2b;
+    pub const I8X16_GE_U: u32 = 0x2c;
+    pub const I16X8_EQ: u32 = 0x2d;
+    pub const I16X8_NE: u32 = 0x2e;
+    pub const I16X8_LT_S: u32 = 0x2f;
+    pub const I16X8_LT_U: u32 = 0x30;
+    pub const I16X8_GT_S: u32 = 0x31;
+    pub const I16X8_GT_U: u32 = 0x32;
+    pub const I16X8_LE_S: u32 = 0x33;
+    pub const I16X8_LE_U: u32 = 0x34;
+    pub const I16X8_GE_S: u32 = 0x35;
+    pub const I16X8_GE_U: u32 = 0x36;
+    pub const I32X4_EQ: u32 = 0x37;
+    pub const I32X4_NE: u32 = 0x38;
+    pub const I32X4_LT_S: u32 = 0x39;
+    pub const I32X4_LT_U: u32 = 0x3a;
+    pub const I32X4_GT_S: u32 = 0x3b;
+    pub const I32X4_GT_U: u32 = 0x3c;
+    pub const I32X4_LE_S: u32 = 0x3d;
+    pub const I32X4_LE_U: u32 = 0x3e;
+    pub const I32X4_GE_S: u32 = 0x3f;
+    pub const I32X4_GE_U: u32 = 0x40;
+    pub const F32X4_EQ: u32 = 0x41;
+    pub const F32X4_NE: u32 = 0x42;
+    pub const F32X4_LT: u32 = 0x43;
+    pub const F32X4_GT: u32 = 0x44;
+    pub const F32X4_LE: u32 = 0x45;
+    pub const F32X4_GE: u32 = 0x46;
+    pub const F64X2_EQ: u32 = 0x47;
+    pub const F64X2_NE: u32 = 0x48;
+    pub const F64X2_LT: u32 = 0x49;
+    pub const F64X2_GT: u32 = 0x4a;
+    pub const F64X2_LE: u32 = 0x4b;
+    pub const F64X2_GE: u32 = 0x4c;
+    pub const V128_NOT: u32 = 0x4d;
+    pub const V128_AND: u32 = 0x4e;
+    pub const V128_ANDNOT: u32 = 0x4f;
+    pub const V128_OR: u32 = 0x50;
+    pub const V128_XOR: u32 = 0x51;
+    pub const V128_BITSELECT: u32 = 0x52;
+    pub const V128_ANY_TRUE: u32 = 0x53;
+    pub const V128_LOAD8_LANE: u32 = 0x54;
+    pub const V128_LOAD16_LANE: u32 = 0x55;
+    pub const V128_LOAD32_LANE: u32 = 0x56;
+    pub const V128_LOAD64_LANE: u32 = 0x57;
+    pub const V128_STORE8_LANE: u32 = 0x58;
+    pub const V128_STORE16_LANE: u32 = 0x59;
+    pub const V128_STORE32_LANE: u32 = 0x5a;
+    pub const V128_STORE64_LANE: u32 = 0x5b;
+    pub const V128_LOAD32_ZERO: u32 = 0x5c;
+    pub const V128_LOAD64_ZERO: u32 = 0x5d;
+    pub const F32X4_DEMOTE_F64X2_ZERO: u32 = 0x5e;
+    pub const F64X2_PROMOTE_LOW_F32X4: u32 = 0x5f;
+    pub const I8X16_ABS: u32 = 0x60;
+    pub const I8X16_NEG: u32 = 0x61;
+    pub const I8X16_POPCNT: u32 = 0x62;
+    pub const I8X16_ALL_TRUE: u32 = 0x63;
+    pub const I8X16_BITMASK: u32 = 0x64;
+    pub const I8X16_NARROW_I16X8_S: u32 = 0x65;
+    pub const I8X16_NARROW_I16X8_U: u32 = 0x66;
+    pub const F32X4_CEIL: u32 = 0x67;
+    pub const F32X4_FLOOR: u32 = 0x68;
+    pub const F32X4_TRUNC: u32 = 0x69;
+    pub const F32X4_NEAREST: u32 = 0x6a;
+    pub const I8X16_SHL: u32 = 0x6b;
+    pub const I8X16_SHR_S: u32 = 0x6c;
+    pub const I8X16_SHR_U: u32 = 0x6d;
+    pub const I8X16_ADD: u32 = 0x6e;
+    pub const I8X16_ADD_SAT_S: u32 = 0x6f;
+    pub const I8X16_ADD_SAT_U: u32 = 0x70;
+    pub const I8X16_SUB: u32 = 0x71;
+    pub const I8X16_SUB_SAT_S: u32 = 0x72;
+    pub const I8X16_SUB_SAT_U: u32 = 0x73;
+    pub const F64X2_CEIL: u32 = 0x74;
+    pub const F64X2_FLOOR: u32 = 0x75;
+    pub const I8X16_MIN_S: u32 = 0x76;
+    pub const I8X16_MIN_U: u32 = 0x77;
+    pub const I8X16_MAX_S: u32 = 0x78;
+    pub const I8X16_MAX_U: u32 = 0x79;
+    pub const F64X2_TRUNC: u32 = 0x7a;
+    pub const I8X16_AVGR_U: u32 = 0x7b;
+    pub const I16X8_EXTADD_PAIRWISE_I8X16_S: u32 = 0x7c;
+    pub const I16X8_EXTADD_PAIRWISE_I8X16_U: u32 = 0x7d;
+    pub const I32X4_EXTADD_PAIRWISE_I16X8_S: u32 = 0x7e;
+    pub const I32X4_EXTADD_PAIRWISE_I16X8_U: u32 = 0x7f;
+    pub const I16X8_ABS: u32 = 0x80;
+    pub const I16X8_NEG: u32 = 0x81;
+    pub const I16X8_Q15MULR_SAT_S: u32 = 0x82;
+    pub const I16X8_ALL_TRUE: u32 = 0x83;
+    pub const I16X8_BITMASK: u32 = 0x84;
+    pub const I16X8_NARROW_I32X4_S: u32 = 0x85;
+    pub const I16X8_NARROW_I32X4_U: u32 = 0x86;
+    pub const I16X8_EXTEND_LOW_I8X16_S: u32 = 0x87;
+    pub const I16X8_EXTEND_HIGH_I8X16_S: u32 = 0x88;
+    pub const I16X8_EXTEND_LOW_I8X16_U: u32 = 0x89;
+    pub const I16X8_EXTEND_HIGH_I8X16_U: u32 = 0x8a;
+    pub const I16X8_SHL: u32 = 0x8b;
+    pub const I16X8_SHR_S: u32 = 0x8c;
+    pub const I16X8_SHR_U: u32 = 0x8d;
+    pub const I16X8_ADD: u32 = 0x8e;
+    pub const I16X8_ADD_SAT_S: u32 = 0x8f;
+    pub const I16X8_ADD_SAT_U: u32 = 0x90;
+    pub const I16X8_SUB: u32 = 0x91;
+    pub const I16X8_SUB_SAT_S: u32 = 0x92;
+    pub const I16X8_SUB_SAT_U: u32 = 0x93;
+    pub const F64X2_NEAREST: u32 = 0x94;
+    pub const I16X8_MUL: u32 = 0x95;
+    pub const I16X8_MIN_S: u32 = 0x96;
+    pub const I16X8_MIN_U: u32 = 0x97;
+    pub const I16X8_MAX_S: u32 = 0x98;
+    pub const I16X8_MAX_U: u32 = 0x99;
+    pub const I16X8_AVGR_U: u32 = 0x9b;
+    pub const I16X8_EXTMUL_LOW_I8X16_S: u32 = 0x9c;
+    pub const I16X8_EXTMUL_HIGH_I8X16_S: u32 = 0x9d;
+    pub const I16X8_EXTMUL_LOW_I8X16_U: u32 = 0x9e;
+    pub const I16X8_EXTMUL_HIGH_I8X16_U: u32 = 0x9f;
+    pub const I32X4_ABS: u32 = 0xa0;
+    pub const I32X4_NEG: u32 = 0xa1;
+    pub const I32X4_ALL_TRUE: u32 = 0xa3;
+    pub const I32X4_BITMASK: u32 = 0xa4;
+    pub const I32X4_EXTEND_LOW_I16X8_S: u32 = 0xa7;
+    pub const I32X4_EXTEND_HIGH_I16X8_S: u32 = 0xa8;
+    pub const I32X4_EXTEND_LOW_I16X8_U: u32 = 0xa9;
+    pub const I32X4_EXTEND_HIGH_I16X8_U: u32 = 0xaa;
+    pub const I32X4_SHL: u32 = 0xab;
+    pub const I32X4_SHR_S: u32 = 0xac;
+    pub const I32X4_SHR_U: u32 = 0xad;
+    pub const I32X4_ADD: u32 = 0xae;
+    pub const I32X4_SUB: u32 = 0xb1;
+    pub const I32X4_MUL: u32 = 0xb5;
+    pub const I32X4_MIN_S: u32 = 0xb6;
+    pub const I32X4_MIN_U: u32 = 0xb7;
+    pub const I32X4_MAX_S: u32 = 0xb8;
+    pub const I32X4_MAX_U: u32 = 0xb9;
+    pub const I32X4_DOT_I16X8_S: u32 = 0xba;
+    pub const I32X4_EXTMUL_LOW_I16X8_S: u32 = 0xbc;
+    pub const I32X4_EXTMUL_HIGH_I16X8_S: u32 = 0xbd;
+    pub const I32X4_EXTMUL_LOW_I16X8_U: u32 = 0xbe;
+    pub const I32X4_EXTMUL_HIGH_I16X8_U: u32 = 0xbf;
+    pub const I64X2_ABS: u32 = 0xc0;
+    pub const I64X2_NEG: u32 = 0xc1;
+    pub const I64X2_ALL_TRUE: u32 = 0xc3;
+    pub const I64X2_BITMASK: u32 = 0xc4;
+    pub const I64X2_EXTEND_LOW_I32X4_S: u32 = 0xc7;
+    pub const I64X2_EXTEND_HIGH_I32X4_S: u32 = 0xc8;
+    pub const I64X2_EXTEND_LOW_I32X4_U: u32 = 0xc9;
+    pub const I64X2_EXTEND_HIGH_I32X4_U: u32 = 0xca;
+    pub const I64X2_SHL: u32 = 0xcb;
+    pub const I64X2_SHR_S: u32 = 0xcc;
+    pub const I64X2_SHR_U: u32 = 0xcd;
+    pub const I64X2_ADD: u32 = 0xce;
+    pub const I64X2_SUB: u32 = 0xd1;
+    pub const I64X2_MUL: u32 = 0xd5;
+    pub const I64X2_EQ: u32 = 0xd6;
+    pub const I64X2_NE: u32 = 0xd7;
+    pub const I64X2_LT_S: u32 = 0xd8;
+    pub const I64X2_GT_S: u32 = 0xd9;
+    pub const I64X2_LE_S: u32 = 0xda;
+    pub const I64X2_GE_S: u32 = 0xdb;
+    pub const I64X2_EXTMUL_LOW_I32X4_S: u32 = 0xdc;
+    pub const I64X2_EXTMUL_HIGH_I32X4_S: u32 = 0xdd;
+    pub const I64X2_EXTMUL_LOW_I32X4_U: u32 = 0xde;
+    pub const I64X2_EXTMUL_HIGH_I32X4_U: u32 = 0xdf;
+    pub const F32X4_ABS: u32 = 0xe0;
+    pub const F32X4_NEG: u32 = 0xe1;
+    pub const F32X4_SQRT: u32 = 0xe3;
+    pub const F32X4_ADD: u32 = 0xe4;
+    pub const F32X4_SUB: u32 = 0xe5;
+    pub const F32X4_MUL: u32 = 0xe6;
+    pub const F32X4_DIV: u32 = 0xe7;
+    pub const F32X4_MIN: u32 = 0xe8;
+    pub const F32X4_MAX: u32 = 0xe9;
+    pub const F32X4_PMIN: u32 = 0xea;
+    pub const F32X4_PMAX: u32 = 0xeb;
+    pub const F64X2_ABS: u32 = 0xec;
+    pub const F64X2_NEG: u32 = 0xed;
+    pub const F64X2_SQRT: u32 = 0xef;
+    pub const F64X2_ADD: u32 = 0xf0;
+    pub const F64X2_SUB: u32 = 0xf1;
+    pub const F64X2_MUL: u32 = 0xf2;
+    pub const F64X2_DIV: u32 = 0xf3;
+    pub const F64X2_MIN: u32 = 0xf4;
+    pub const F64X2_MAX: u32 = 0xf5;
+    pub const F64X2_PMIN: u32 = 0xf6;
+    pub const F64X2_PMAX: u32 = 0xf7;
+    pub const I32X4_TRUNC_SAT_F32X4_S: u32 = 0xf8;
+    pub const I32X4_TRUNC_SAT_F32X4_U: u32 = 0xf9;
+    pub const F32X4_CONVERT_I32X4_S: u32 = 0xfa;
+    pub const F32X4_CONVERT_I32X4_U: u32 = 0xfb;
+    pub const I32X4_TRUNC_SAT_F64X2_S_ZERO: u32 = 0xfc;
+    pub const I32X4_TRUNC_SAT_F64X2_U_ZERO: u32 = 0xfd;
+    pub const F64X2_CONVERT_LOW_I32X4_S: u32 = 0xfe;
+    pub const F64X2_CONVERT_LOW_I32X4_U: u32 = 0xff;
+}
+
+/// Sub-opcodes following [`op::ATOMIC_PREFIX`].
+pub mod atomic {
+    use crate::value::ValType;
+
+    pub const MEMORY_ATOMIC_NOTIFY: u32 = 0x00;
+    pub const MEMORY_ATOMIC_WAIT32: u32 = 0x01;
+    pub const MEMORY_ATOMIC_WAIT64: u32 = 0x02;
+    pub const ATOMIC_FENCE: u32 = 0x03;
+    pub const I32_ATOMIC_LOAD: u32 = 0x10;
+    pub const I64_ATOMIC_LOAD: u32 = 0x11;
+    pub const I32_ATOMIC_LOAD8_U: u32 = 0x12;
+    pub const I32_ATOMIC_LOAD16_U: u32 = 0x13;
+    pub const I64_ATOMIC_LOAD8_U: u32 = 0x14;
+    pub const I64_ATOMIC_LOAD16_U: u32 = 0x15;
+    pub const I64_ATOMIC_LOAD32_U: u32 = 0x16;
+    pub const I32_ATOMIC_STORE: u32 = 0x17;
+    pub const I64_ATOMIC_STORE: u32 = 0x18;
+    pub const I32_ATOMIC_STORE8: u32 = 0x19;
+    pub const I32_ATOMIC_STORE16: u32 = 0x1a;
+    pub const I64_ATOMIC_STORE8: u32 = 0x1b;
+    pub const I64_ATOMIC_STORE16: u32 = 0x1c;
+    pub const I64_ATOMIC_STORE32: u32 = 0x1d;
+    pub const I32_ATOMIC_RMW_ADD: u32 = 0x1e;
+    pub const I64_ATOMIC_RMW_ADD: u32 = 0x1f;
+    pub const I32_ATOMIC_RMW8_ADD_U: u32 = 0x20;
+    pub const I32_ATOMIC_RMW16_ADD_U: u32 = 0x21;
+    pub const I64_ATOMIC_RMW8_ADD_U: u32 = 0x22;
+    pub const I64_ATOMIC_RMW16_ADD_U: u32 = 0x23;
+    pub const I64_ATOMIC_RMW32_ADD_U: u32 = 0x24;
+    pub const I32_ATOMIC_RMW_SUB: u32 = 0x25;
+    pub const I64_ATOMIC_RMW_SUB: u32 = 0x26;
+    pub const I32_ATOMIC_RMW8_SUB_U: u32 = 0x27;
+    pub const I32_ATOMIC_RMW16_SUB_U: u32 = 0x28;
+    pub const I64_ATOMIC_RMW8_SUB_U: u32 = 0x29;
+    pub const I64_ATOMIC_RMW16_SUB_U: u32 = 0x2a;
+    pub const I64_ATOMIC_RMW32_SUB_U: u32 = 0x2b;
+    pub const I32_ATOMIC_RMW_AND: u32 = 0x2c;
+    pub const I64_ATOMIC_RMW_AND: u32 = 0x2d;
+    pub const I32_ATOMIC_RMW8_AND_U: u32 = 0x2e;
+    pub const I32_ATOMIC_RMW16_AND_U: u32 = 0x2f;
+    pub const I64_ATOMIC_RMW8_AND_U: u32 = 0x30;
+    pub const I64_ATOMIC_RMW16_AND_U: u32 = 0x31;
+    pub const I64_ATOMIC_RMW32_AND_U: u32 = 0x32;
+    pub const I32_ATOMIC_RMW_OR: u32 = 0x33;
+    pub const I64_ATOMIC_RMW_OR: u32 = 0x34;
+    pub const I32_ATOMIC_RMW8_OR_U: u32 = 0x35;
+    pub const I32_ATOMIC_RMW16_OR_U: u32 = 0x36;
+    pub const I64_ATOMIC_RMW8_OR_U: u32 = 0x37;
+    pub const I64_ATOMIC_RMW16_OR_U: u32 = 0x38;
+    pub const I64_ATOMIC_RMW32_OR_U: u32 = 0x39;
+    pub const I32_ATOMIC_RMW_XOR: u32 = 0x3a;
+    pub const I64_ATOMIC_RMW_XOR: u32 = 0x3b;
+    pub const I32_ATOMIC_RMW8_XOR_U: u32 = 0x3c;
+    pub const I32_ATOMIC_RMW16_XOR_U: u32 = 0x3d;
+    pub const I64_ATOMIC_RMW8_XOR_U: u32 = 0x3e;
+    pub const I64_ATOMIC_RMW16_XOR_U: u32 = 0x3f;
+    pub const I64_ATOMIC_RMW32_XOR_U: u32 = 0x40;
+    pub const I32_ATOMIC_RMW_XCHG: u32 = 0x41;
+    pub const I64_ATOMIC_RMW_XCHG: u32 = 0x42;
+    pub const I32_ATOMIC_RMW8_XCHG_U: u32 = 0x43;
+    pub const I32_ATOMIC_RMW16_XCHG_U: u32 = 0x44;
+    pub const I64_ATOMIC_RMW8_XCHG_U: u32 = 0x45;
+    pub const I64_ATOMIC_RMW16_XCHG_U: u32 = 0x46;
+    pub const I64_ATOMIC_RMW32_XCHG_U: u32 = 0x47;
+    pub const I32_ATOMIC_RMW_CMPXCHG: u32 = 0x48;
+    pub const I64_ATOMIC_RMW_CMPXCHG: u32 = 0x49;
+    pub const I32_ATOMIC_RMW8_CMPXCHG_U: u32 = 0x4a;
+    pub const I32_ATOMIC_RMW16_CMPXCHG_U: u32 = 0x4b;
+    pub const I64_ATOMIC_RMW8_CMPXCHG_U: u32 = 0x4c;
+    pub const I64_ATOMIC_RMW16_CMPXCHG_U: u32 = 0x4d;
+    pub const I64_ATOMIC_RMW32_CMPXCHG_U: u32 = 0x4e;
+
+    /// The value type and width in bytes of an atomic load, store or read-modify-write.
+    /// Each group of these instructions lists the same seven accesses in the same order.
+    pub fn access(op: u32) -> Option<(ValType, usize)> {
+        use ValType::*;
+        const ACCESSES: [(ValType, usize); 7] =
+            [(I32, 4), (I64, 8), (I32, 1), (I32, 2), (I64, 1), (I64, 2), (I64, 4)];
+
+        match op {
+            I32_ATOMIC_LOAD..=I64_ATOMIC_RMW32_CMPXCHG_U => {
+                Some(ACCESSES[((op - I32_ATOMIC_LOAD) % 7) as usize])
+            }
+            _ => None,
+        }
+    }
 }
 
 pub mod read {
     use super::leb128;
+    use crate::value::{HeapType, RefType, ValType};
 
     pub fn read_8(bytecode: &[u8], index: usize) -> u8 {
         bytecode[index]
@@ -237,57 +655,223 @@ pub mod read {
         result
     }
 
+    // The LEB128 readers below are for code that validation has already checked; a
+    // `Reader` checks the binaries that have not been
+
     pub fn read_size(bytecode: &[u8], index: usize) -> (u32, usize) {
-        let (val, size) = leb128::read_leb128(&bytecode, index);
-        let val = unsafe { std::mem::transmute(val as i32)};
-        (val, size)
+        let (val, size) = leb128::read_uleb128(bytecode, index, 32).expect(VALIDATED);
+        (val as u32, size)
+    }
+
+    pub fn read_u64(bytecode: &[u8], index: usize) -> (u64, usize) {
+        leb128::read_uleb128(bytecode, index, 64).expect(VALIDATED)
     }
 
     pub fn read_i32(bytecode: &[u8], index: usize) -> (i32, usize) {
-        let (val, size) = leb128::read_leb128(&bytecode, index);
+        let (val, size) = leb128::read_leb128(bytecode, index, 32).expect(VALIDATED);
         (val as i32, size)
     }
 
     pub fn read_i64(bytecode: &[u8], index: usize) -> (i64, usize) {
-        leb128::read_leb128(&bytecode, index)
+        leb128::read_leb128(bytecode, index, 64).expect(VALIDATED)
+    }
+
+    const VALIDATED: &str = "Malformed LEB128 in validated code";
+
+    /// A heap type: an abstract type's byte, or a non-negative s33 type index.
+    pub fn read_heap_type(bytecode: &[u8], index: usize) -> Option<(HeapType, usize)> {
+        if let Some(heap) = HeapType::from_byte(*bytecode.get(index)?) {
+            return Some((heap, 1));
+        }
+        let (idx, size) = leb128::read_leb128(bytecode, index, 33).ok()?;
+        let idx = u32::try_from(idx).ok()?;
+        Some((HeapType::Concrete(idx), size))
     }
 
+    /// A value type: a single byte, or `0x64`/`0x63` for a non-null/nullable reference
+    /// followed by its heap type.
+    pub fn read_val_type(bytecode: &[u8], index: usize) -> Option<(ValType, usize)> {
+        let byte = *bytecode.get(index)?;
+        let nullable = match byte {
+            0x64 => false,
+            0x63 => true,
+            _ => return ValType::from_byte(byte).map(|ty| (ty, 1)),
+        };
+        let (heap, size) = read_heap_type(bytecode, index + 1)?;
+        Some((ValType::Ref(RefType { nullable, heap }), size + 1))
+    }
+
+    // Float immediates are stored little-endian, unlike the header fields read above
     pub fn read_f32(bytecode: &[u8], index: usize) -> f32 {
-        let valu32 = read_32(bytecode, index);
-        unsafe { std::mem::transmute(valu32) }
+        let bytes = bytecode[index..index+4].try_into().unwrap();
+        f32::from_le_bytes(bytes)
     }
 
     pub fn read_f64(bytecode: &[u8], index: usize) -> f64 {
-        let valu64 = read_64(bytecode, index);
-        unsafe { std::mem::transmute(valu64) }
+        let bytes = bytecode[index..index+8].try_into().unwrap();
+        f64::from_le_bytes(bytes)
+    }
+
+    /// A cursor over a binary that has not been validated yet, whose reads fail rather
+    /// than panic on malformed or truncated input.
+    #[derive(Default)]
+    pub struct Reader<'a> {
+        pub bytes: &'a [u8],
+        pub pos: usize,
     }
+
+    impl<'a> Reader<'a> {
+        pub fn new(bytes: &'a [u8], pos: usize) -> Self {
+            Self { bytes, pos }
+        }
+
+        pub fn at_end(&self) -> bool {
+            self.pos >= self.bytes.len()
+        }
+
+        pub fn peek(&self) -> Option<u8> {
+            self.bytes.get(self.pos).copied()
+        }
+
+        pub fn u8(&mut self) -> Result<u8, String> {
+            let byte = self.peek().ok_or(UNEXPECTED_END)?;
+            self.pos += 1;
+            Ok(byte)
+        }
+
+        /// The next `len` bytes.
+        pub fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+            let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len())
+                .ok_or(UNEXPECTED_END)?;
+            let bytes = &self.bytes[self.pos..end];
+            self.pos = end;
+            Ok(bytes)
+        }
+
+        pub fn u32(&mut self) -> Result<u32, String> {
+            self.unsigned(32).map(|val| val as u32)
+        }
+
+        pub fn u64(&mut self) -> Result<u64, String> {
+            self.unsigned(64)
+        }
+
+        pub fn s32(&mut self) -> Result<i32, String> {
+            self.signed(32).map(|val| val as i32)
+        }
+
+        /// A signed 33-bit integer, which is how block and heap types encode indices.
+        pub fn s33(&mut self) -> Result<i64, String> {
+            self.signed(33)
+        }
+
+        pub fn s64(&mut self) -> Result<i64, String> {
+            self.signed(64)
+        }
+
+        pub fn f32(&mut self) -> Result<f32, String> {
+            Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        pub fn f64(&mut self) -> Result<f64, String> {
+            Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+        }
+
+        /// A UTF-8 string prefixed by its length.
+        pub fn name(&mut self) -> Result<String, String> {
+            let len = self.u32()? as usize;
+            let bytes = self.take(len)?;
+            std::str::from_utf8(bytes).map(str::to_string).map_err(|e| format!("{e}"))
+        }
+
+        fn unsigned(&mut self, bits: u32) -> Result<u64, String> {
+            let (val, size) = leb128::read_uleb128(self.bytes, self.pos, bits)?;
+            self.pos += size;
+            Ok(val)
+        }
+
+        fn signed(&mut self, bits: u32) -> Result<i64, String> {
+            let (val, size) = leb128::read_leb128(self.bytes, self.pos, bits)?;
+            self.pos += size;
+            Ok(val)
+        }
+    }
+
+    pub const UNEXPECTED_END: &str = "Unexpected end of input";
 }
 
 mod leb128 {
     const CONTINUATION_BIT: u8 = 0b1000_0000;
     const SIGN_BIT: u8 = 0b0100_0000;
     const LOW_BITS: u8 = 0b0111_1111;
-    const SIZE: usize = 64;
+    const SIZE: u32 = 64;
+    const TOO_LONG: &str = "Integer representation too long";
+    const TOO_LARGE: &str = "Integer too large";
 
-    pub fn read_leb128(bytecode: &[u8], index: usize) -> (i64, usize) {
+    use super::read::UNEXPECTED_END;
+
+    /// Decodes a signed integer of `bits` bits, returning it and its length. Its encoding
+    /// may be at most `ceil(bits / 7)` bytes long, and the unused bits of the last of
+    /// those must extend its sign.
+    pub fn read_leb128(bytecode: &[u8], index: usize, bits: u32) -> Result<(i64, usize), String> {
         let mut result = 0;
+        let mut shift = 0;
         let mut byte_count: usize = 0;
 
         loop {
-            let byte = bytecode[index + byte_count];
-
-            let low_bits = (byte & LOW_BITS) as i64;
-            let shift = 7 * byte_count;
-            result |= low_bits << shift;
-
+            let byte = *bytecode.get(index + byte_count).ok_or(UNEXPECTED_END)?;
             byte_count += 1;
 
+            let low_bits = byte & LOW_BITS;
+            if shift + 7 > bits {
+                if byte & CONTINUATION_BIT != 0 {
+                    return Err(String::from(TOO_LONG));
+                }
+                // The sign bit and the unused bits above it must all be the same
+                let high_bits = low_bits >> (bits - shift - 1);
+                if high_bits != 0 && high_bits != LOW_BITS >> (bits - shift - 1) {
+                    return Err(String::from(TOO_LARGE));
+                }
+            }
+            result |= (low_bits as i64) << shift;
+            shift += 7;
+
             if byte & CONTINUATION_BIT == 0 {
                 if shift < SIZE && (SIGN_BIT & byte) == SIGN_BIT {
                     // Sign extend the result.
-                    result |= !0 << (shift + 7);
+                    result |= !0 << shift;
+                }
+                return Ok((result, byte_count));
+            }
+        }
+    }
+
+    /// Decodes an unsigned integer of `bits` bits, returning it and its length. Its
+    /// encoding may be at most `ceil(bits / 7)` bytes long, and the unused bits of the
+    /// last of those must be zero.
+    pub fn read_uleb128(bytecode: &[u8], index: usize, bits: u32) -> Result<(u64, usize), String> {
+        let mut result = 0;
+        let mut shift = 0;
+        let mut byte_count: usize = 0;
+
+        loop {
+            let byte = *bytecode.get(index + byte_count).ok_or(UNEXPECTED_END)?;
+            byte_count += 1;
+
+            let low_bits = byte & LOW_BITS;
+            if shift + 7 > bits {
+                if byte & CONTINUATION_BIT != 0 {
+                    return Err(String::from(TOO_LONG));
+                }
+                if low_bits >> (bits - shift) != 0 {
+                    return Err(String::from(TOO_LARGE));
                 }
-                return (result, byte_count);
+            }
+            result |= (low_bits as u64) << shift;
+            shift += 7;
+
+            if byte & CONTINUATION_BIT == 0 {
+                return Ok((result, byte_count));
             }
         }
     }
@@ -295,12 +879,12 @@ mod leb128 {
     mod tests {
         #![allow(unused_imports)]
         // Not sure why the compiler thinks this import is unused
-        use super::read_leb128;
+        use super::{read_leb128, read_uleb128, TOO_LARGE, TOO_LONG};
 
         #[test]
         fn test_10() {
             let a = vec![0x0a];
-            let (num, size) = read_leb128(&a, 0);
+            let (num, size) = read_leb128(&a, 0, 64).unwrap();
             assert_eq!(10, num);
             assert_eq!(1, size);
         }
@@ -308,7 +892,7 @@ mod leb128 {
         #[test]
         fn test_10_large_slice() {
             let a = vec![0xff, 0xff, 0x0a, 0xff, 0xff];
-            let (num, size) = read_leb128(&a, 2);
+            let (num, size) = read_leb128(&a, 2, 64).unwrap();
             assert_eq!(10, num);
             assert_eq!(1, size);
         }
@@ -316,7 +900,7 @@ mod leb128 {
         #[test]
         fn test_10_neg() {
             let a = vec![0x76];
-            let (num, size) = read_leb128(&a, 0);
+            let (num, size) = read_leb128(&a, 0, 64).unwrap();
             assert_eq!(-10, num);
             assert_eq!(1, size);
         }
@@ -324,7 +908,7 @@ mod leb128 {
         #[test]
         fn test_200() {
             let a = vec![0xc8, 0x01];
-            let (num, size) = read_leb128(&a, 0);
+            let (num, size) = read_leb128(&a, 0, 64).unwrap();
             assert_eq!(200, num);
             assert_eq!(2, size);
         }
@@ -332,7 +916,7 @@ mod leb128 {
         #[test]
         fn test_200_neg() {
             let a = vec![0xb8, 0x7e];
-            let (num, size) = read_leb128(&a, 0);
+            let (num, size) = read_leb128(&a, 0, 64).unwrap();
             assert_eq!(-200, num);
             assert_eq!(2, size);
         }
@@ -340,7 +924,7 @@ mod leb128 {
         #[test]
         fn test_200_000() {
             let a = vec![0xc0, 0x9a, 0x0c];
-            let (num, size) = read_leb128(&a, 0);
+            let (num, size) = read_leb128(&a, 0, 64).unwrap();
             assert_eq!(200000, num);
             assert_eq!(3, size);
         }
@@ -348,16 +932,51 @@ mod leb128 {
         #[test]
         fn test_2_000_000_neg() {
             let a = vec![0x80, 0xf7, 0x85, 0x7f];
-            let (num, size) = read_leb128(&a, 0);
+            let (num, size) = read_leb128(&a, 0, 64).unwrap();
             assert_eq!(-2000000, num);
             assert_eq!(4, size);
         }
 
         #[test]
-        #[should_panic(expected = "index out of bounds: the len is 2 but the index is 2")]
+        fn test_unsigned_100() {
+            let a = vec![0xe4, 0x00];
+            let (num, size) = read_uleb128(&a, 0, 64).unwrap();
+            assert_eq!(100, num);
+            assert_eq!(2, size);
+            let (num, size) = read_uleb128(&[0x64], 0, 64).unwrap();
+            assert_eq!(100, num);
+            assert_eq!(1, size);
+        }
+
+        #[test]
         fn test_slice_too_small() {
             let a = vec![0x80, 0xf7];
-            read_leb128(&a, 0);
+            assert_eq!(Err(String::from("Unexpected end of input")), read_leb128(&a, 0, 64));
+        }
+
+        #[test]
+        fn test_length_limits() {
+            let u32_max = [0xff, 0xff, 0xff, 0xff, 0x0f];
+            assert_eq!(Ok((u32::MAX as u64, 5)), read_uleb128(&u32_max, 0, 32));
+            assert_eq!(TOO_LARGE, read_uleb128(&[0xff, 0xff, 0xff, 0xff, 0x1f], 0, 32).unwrap_err());
+            assert_eq!(TOO_LONG, read_uleb128(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00], 0, 32).unwrap_err());
+            assert_eq!(Ok((0, 5)), read_uleb128(&[0x80, 0x80, 0x80, 0x80, 0x00], 0, 32));
+            let u64_max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+            assert_eq!(Ok((u64::MAX, 10)), read_uleb128(&u64_max, 0, 64));
+            let u64_over = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+            assert_eq!(TOO_LARGE, read_uleb128(&u64_over, 0, 64).unwrap_err());
+
+            let i32_min = [0x80, 0x80, 0x80, 0x80, 0x78];
+            assert_eq!(Ok((i32::MIN as i64, 5)), read_leb128(&i32_min, 0, 32));
+            assert_eq!(Ok((-1, 5)), read_leb128(&[0xff, 0xff, 0xff, 0xff, 0x7f], 0, 32));
+            assert_eq!(TOO_LARGE, read_leb128(&[0x80, 0x80, 0x80, 0x80, 0x70], 0, 32).unwrap_err());
+            assert_eq!(TOO_LARGE, read_leb128(&u32_max, 0, 32).unwrap_err());
+            assert_eq!(Ok((u32::MAX as i64, 5)), read_leb128(&u32_max, 0, 33));
+            assert_eq!(TOO_LONG, read_leb128(&[0x80; 11], 0, 64).unwrap_err());
+            let i64_min = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f];
+            assert_eq!(Ok((i64::MIN, 10)), read_leb128(&i64_min, 0, 64));
+            let i64_over = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+            assert_eq!(TOO_LARGE, read_leb128(&i64_over, 0, 64).unwrap_err());
         }
     }
 }
\ No newline at end of file
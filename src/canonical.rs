@@ -0,0 +1,1229 @@
+#![allow(dead_code)]
+
+//! The canonical ABI: how component values are passed as core values, and laid out in
+//! linear memory when they don't fit in them.
+
+use crate::component::{ResourceId, StringEncoding, ValueType};
+use crate::value::{ValType, Value};
+use crate::vm::Trap;
+
+/// The most core parameters a function takes before they are passed in memory instead.
+pub const MAX_FLAT_PARAMS: usize = 16;
+/// The most core results a function returns before they are returned in memory instead.
+pub const MAX_FLAT_RESULTS: usize = 1;
+
+/// The bit set in the length of a UTF-16 string with [`StringEncoding::CompactUtf16`].
+const UTF16_TAG: u32 = 1 << 31;
+
+/// A value of a [`ValueType`]. Handles are passed to and from the host as the
+/// resource's representation: the host's own for imported resources, and the i32 the
+/// component chose for those it defines.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComponentValue {
+    Bool(bool),
+    S8(i8),
+    U8(u8),
+    S16(i16),
+    U16(u16),
+    S32(i32),
+    U32(u32),
+    S64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Char(char),
+    String(String),
+    List(Vec<ComponentValue>),
+    Record(Vec<(String, ComponentValue)>),
+    Tuple(Vec<ComponentValue>),
+    Variant(String, Option<Box<ComponentValue>>),
+    Enum(String),
+    Option(Option<Box<ComponentValue>>),
+    Result(Result<Option<Box<ComponentValue>>, Option<Box<ComponentValue>>>),
+    Flags(Vec<String>),
+    Own(u32),
+    Borrow(u32),
+}
+
+/// What lifting and lowering need from the instance whose memory holds the values.
+pub trait Guest {
+    fn read(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, Trap>;
+    fn write(&mut self, addr: u32, bytes: &[u8]) -> Result<(), Trap>;
+    /// The size of the memory in bytes.
+    fn memory_len(&mut self) -> u64;
+    /// Calls the instance's `realloc`, which allocates when `old_size` is 0.
+    fn realloc(&mut self, old: u32, old_size: u32, align: u32, new_size: u32)
+        -> Result<u32, Trap>;
+}
+
+/// A guest without a memory, for functions whose values all fit in core values.
+pub struct NoMemory;
+
+impl Guest for NoMemory {
+    fn read(&mut self, _: u32, _: u32) -> Result<Vec<u8>, Trap> {
+        Err(invalid("Values in memory need the memory option"))
+    }
+
+    fn write(&mut self, _: u32, _: &[u8]) -> Result<(), Trap> {
+        Err(invalid("Values in memory need the memory option"))
+    }
+
+    fn memory_len(&mut self) -> u64 {
+        0
+    }
+
+    fn realloc(&mut self, _: u32, _: u32, _: u32, _: u32) -> Result<u32, Trap> {
+        Err(invalid("Values in memory need the realloc option"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handle {
+    pub resource: ResourceId,
+    pub rep: u32,
+    pub own: bool,
+}
+
+/// The resource handles a component instance holds, which its core code refers to by
+/// index. Index 0 is never used, so it can stand for no handle.
+#[derive(Debug, Default)]
+pub struct HandleTable {
+    slots: Vec<Option<Handle>>,
+    free: Vec<u32>,
+}
+
+impl HandleTable {
+    pub fn insert(&mut self, handle: Handle) -> u32 {
+        if let Some(idx) = self.free.pop() {
+            self.slots[idx as usize] = Some(handle);
+            return idx;
+        }
+        if self.slots.is_empty() {
+            self.slots.push(None);
+        }
+        self.slots.push(Some(handle));
+        (self.slots.len() - 1) as u32
+    }
+
+    pub fn get(&self, idx: u32) -> Result<Handle, Trap> {
+        self.slots.get(idx as usize).copied().flatten()
+            .ok_or_else(|| invalid(&format!("Unknown handle {idx}")))
+    }
+
+    pub fn remove(&mut self, idx: u32) -> Result<Handle, Trap> {
+        let handle = self.get(idx)?;
+        self.slots[idx as usize] = None;
+        self.free.push(idx);
+        Ok(handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Lifts and lowers values for one call, through the memory of `guest`.
+pub struct Context<'c> {
+    pub guest: &'c mut dyn Guest,
+    pub encoding: StringEncoding,
+    pub handles: &'c mut HandleTable,
+    /// The borrow handles lowered during the call, which end with it.
+    pub borrows: Vec<u32>,
+}
+
+impl<'c> Context<'c> {
+    pub fn new(guest: &'c mut dyn Guest, encoding: StringEncoding, handles: &'c mut HandleTable)
+        -> Self
+    {
+        Self { guest, encoding, handles, borrows: Vec::new() }
+    }
+
+    /// Lifts values of `types` from the core values `flat`, which instead point to them
+    /// in memory if they would take more than `max_flat` core values.
+    pub fn lift_values(&mut self, flat: &[Value], types: &[ValueType], max_flat: usize)
+        -> Result<Vec<ComponentValue>, Trap>
+    {
+        if flatten_all(types).len() > max_flat {
+            let ptr = self.pointer(flat.first().copied())?;
+            let tuple = ValueType::Tuple(types.to_vec());
+            self.check_aligned(ptr, &tuple)?;
+            return match self.load(&tuple, ptr)? {
+                ComponentValue::Tuple(values) => Ok(values),
+                _ => unreachable!(),
+            };
+        }
+        let mut flat = flat.iter().copied();
+        types.iter().map(|ty| self.lift_flat(ty, &mut flat)).collect()
+    }
+
+    /// Lowers `values` of `types` to core values, or to a pointer to them in memory
+    /// allocated with `realloc` if they would take more than `max_flat`.
+    pub fn lower_values(
+        &mut self,
+        values: &[ComponentValue],
+        types: &[ValueType],
+        max_flat: usize,
+    ) -> Result<Vec<Value>, Trap> {
+        check_count(values, types)?;
+        if flatten_all(types).len() > max_flat {
+            let tuple = ValueType::Tuple(types.to_vec());
+            let ptr = self.allocate(alignment(&tuple), size(&tuple))?;
+            self.store_values(values, types, ptr)?;
+            return Ok(vec![Value::I32(ptr as i32)]);
+        }
+        let mut flat = Vec::new();
+        for (value, ty) in values.iter().zip(types) {
+            self.lower_flat(value, ty, &mut flat)?;
+        }
+        Ok(flat)
+    }
+
+    /// Stores `values` of `types` at `ptr`, laid out as a tuple.
+    pub fn store_values(&mut self, values: &[ComponentValue], types: &[ValueType], ptr: u32)
+        -> Result<(), Trap>
+    {
+        check_count(values, types)?;
+        let tuple = ValueType::Tuple(types.to_vec());
+        self.check_aligned(ptr, &tuple)?;
+        self.store(&ComponentValue::Tuple(values.to_vec()), &tuple, ptr)
+    }
+
+    pub fn lower_flat(&mut self, value: &ComponentValue, ty: &ValueType, out: &mut Vec<Value>)
+        -> Result<(), Trap>
+    {
+        use ComponentValue as V;
+        match (value, ty) {
+            (V::Bool(b), ValueType::Bool) => out.push(Value::I32(*b as i32)),
+            (V::S8(v), ValueType::S8) => out.push(Value::I32(*v as i32)),
+            (V::U8(v), ValueType::U8) => out.push(Value::I32(*v as i32)),
+            (V::S16(v), ValueType::S16) => out.push(Value::I32(*v as i32)),
+            (V::U16(v), ValueType::U16) => out.push(Value::I32(*v as i32)),
+            (V::S32(v), ValueType::S32) => out.push(Value::I32(*v)),
+            (V::U32(v), ValueType::U32) => out.push(Value::I32(*v as i32)),
+            (V::S64(v), ValueType::S64) => out.push(Value::I64(*v)),
+            (V::U64(v), ValueType::U64) => out.push(Value::I64(*v as i64)),
+            (V::F32(v), ValueType::F32) => out.push(Value::F32(*v)),
+            (V::F64(v), ValueType::F64) => out.push(Value::F64(*v)),
+            (V::Char(c), ValueType::Char) => out.push(Value::I32(*c as i32)),
+            (V::String(s), ValueType::String) => {
+                let (ptr, len) = self.store_string(s)?;
+                out.extend([Value::I32(ptr as i32), Value::I32(len as i32)]);
+            }
+            (V::List(elements), ValueType::List(elem)) => {
+                let (ptr, len) = self.store_list(elements, elem)?;
+                out.extend([Value::I32(ptr as i32), Value::I32(len as i32)]);
+            }
+            (V::Record(fields), ValueType::Record(types)) => {
+                for (value, ty) in record_fields(fields, types)? {
+                    self.lower_flat(value, ty, out)?;
+                }
+            }
+            (V::Tuple(values), ValueType::Tuple(types)) => {
+                check_count(values, types)?;
+                for (value, ty) in values.iter().zip(types) {
+                    self.lower_flat(value, ty, out)?;
+                }
+            }
+            (V::Flags(names), ValueType::Flags(all)) => {
+                let bits = flag_bits(names, all)?;
+                out.extend(bits.into_iter().map(|word| Value::I32(word as i32)));
+            }
+            (V::Own(rep), ValueType::Own(resource)) => {
+                let handle = Handle { resource: *resource, rep: *rep, own: true };
+                out.push(Value::I32(self.handles.insert(handle) as i32));
+            }
+            (V::Borrow(rep), ValueType::Borrow(resource)) => {
+                let handle = self.handles.insert(Handle { resource: *resource, rep: *rep, own: false });
+                self.borrows.push(handle);
+                out.push(Value::I32(handle as i32));
+            }
+            _ => {
+                let cases = cases(ty).ok_or_else(|| mismatch(value, ty))?;
+                let (case, payload) = case_of(value, ty)?;
+                out.push(Value::I32(case as i32));
+                let joined = join_cases(&cases);
+                let mut lowered = Vec::new();
+                if let (Some(payload), Some(payload_ty)) = (payload, cases[case]) {
+                    self.lower_flat(payload, payload_ty, &mut lowered)?;
+                }
+                for (idx, ty) in joined.iter().enumerate() {
+                    out.push(match lowered.get(idx) {
+                        Some(value) => widen(*value, *ty),
+                        None => zero(*ty),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn lift_flat(&mut self, ty: &ValueType, flat: &mut dyn Iterator<Item = Value>)
+        -> Result<ComponentValue, Trap>
+    {
+        use ComponentValue as V;
+        let mut next_i32 = || match flat.next() {
+            Some(Value::I32(v)) => Ok(v),
+            other => Err(invalid(&format!("Expected an i32, found {other:?}"))),
+        };
+        Ok(match ty {
+            ValueType::Bool => V::Bool(next_i32()? != 0),
+            ValueType::S8 => V::S8(next_i32()? as i8),
+            ValueType::U8 => V::U8(next_i32()? as u8),
+            ValueType::S16 => V::S16(next_i32()? as i16),
+            ValueType::U16 => V::U16(next_i32()? as u16),
+            ValueType::S32 => V::S32(next_i32()?),
+            ValueType::U32 => V::U32(next_i32()? as u32),
+            ValueType::Char => V::Char(to_char(next_i32()? as u32)?),
+            ValueType::String => {
+                let (ptr, len) = (next_i32()? as u32, next_i32()? as u32);
+                V::String(self.load_string(ptr, len)?)
+            }
+            ValueType::List(elem) => {
+                let (ptr, len) = (next_i32()? as u32, next_i32()? as u32);
+                V::List(self.load_list(elem, ptr, len)?)
+            }
+            ValueType::Own(resource) => V::Own(self.take_own(*resource, next_i32()? as u32)?),
+            ValueType::Borrow(resource) => V::Borrow(self.rep(*resource, next_i32()? as u32)?),
+            ValueType::Flags(all) => {
+                let words = (0..flag_words(all.len()))
+                    .map(|_| next_i32().map(|word| word as u32))
+                    .collect::<Result<Vec<_>, _>>()?;
+                V::Flags(flag_names(&words, all))
+            }
+            ValueType::S64 | ValueType::U64 => match flat.next() {
+                Some(Value::I64(v)) if *ty == ValueType::S64 => V::S64(v),
+                Some(Value::I64(v)) => V::U64(v as u64),
+                other => return Err(invalid(&format!("Expected an i64, found {other:?}"))),
+            },
+            ValueType::F32 => match flat.next() {
+                Some(Value::F32(v)) => V::F32(v),
+                other => return Err(invalid(&format!("Expected an f32, found {other:?}"))),
+            },
+            ValueType::F64 => match flat.next() {
+                Some(Value::F64(v)) => V::F64(v),
+                other => return Err(invalid(&format!("Expected an f64, found {other:?}"))),
+            },
+            ValueType::Record(types) => {
+                let mut fields = Vec::new();
+                for (name, ty) in types {
+                    fields.push((name.clone(), self.lift_flat(ty, flat)?));
+                }
+                V::Record(fields)
+            }
+            ValueType::Tuple(types) => {
+                V::Tuple(types.iter().map(|ty| self.lift_flat(ty, flat)).collect::<Result<_, _>>()?)
+            }
+            _ => {
+                let cases = cases(ty).unwrap();
+                let case = next_i32()? as u32 as usize;
+                let joined = join_cases(&cases);
+                let values: Vec<_> = flat.take(joined.len()).collect();
+                if values.len() != joined.len() {
+                    return Err(invalid("Too few core values for a variant"));
+                }
+                let Some(payload_ty) = cases.get(case) else {
+                    return Err(invalid(&format!("Invalid discriminant {case}")));
+                };
+                let payload = match payload_ty {
+                    Some(payload_ty) => {
+                        let flat_types = flatten(payload_ty);
+                        let mut narrowed = values.iter().zip(&flat_types)
+                            .map(|(value, ty)| narrow(*value, *ty));
+                        Some(Box::new(self.lift_flat(payload_ty, &mut narrowed)?))
+                    }
+                    None => None,
+                };
+                make_case(ty, case, payload)
+            }
+        })
+    }
+
+    pub fn store(&mut self, value: &ComponentValue, ty: &ValueType, ptr: u32) -> Result<(), Trap> {
+        use ComponentValue as V;
+        let bytes: Vec<u8> = match (value, ty) {
+            (V::Bool(b), ValueType::Bool) => vec![*b as u8],
+            (V::S8(v), ValueType::S8) => v.to_le_bytes().to_vec(),
+            (V::U8(v), ValueType::U8) => v.to_le_bytes().to_vec(),
+            (V::S16(v), ValueType::S16) => v.to_le_bytes().to_vec(),
+            (V::U16(v), ValueType::U16) => v.to_le_bytes().to_vec(),
+            (V::S32(v), ValueType::S32) => v.to_le_bytes().to_vec(),
+            (V::U32(v), ValueType::U32) => v.to_le_bytes().to_vec(),
+            (V::S64(v), ValueType::S64) => v.to_le_bytes().to_vec(),
+            (V::U64(v), ValueType::U64) => v.to_le_bytes().to_vec(),
+            (V::F32(v), ValueType::F32) => v.to_le_bytes().to_vec(),
+            (V::F64(v), ValueType::F64) => v.to_le_bytes().to_vec(),
+            (V::Char(c), ValueType::Char) => (*c as u32).to_le_bytes().to_vec(),
+            (V::Flags(names), ValueType::Flags(all)) => {
+                let bits = flag_bits(names, all)?;
+                let bytes: Vec<u8> = bits.iter().flat_map(|word| word.to_le_bytes()).collect();
+                bytes[..size(ty) as usize].to_vec()
+            }
+            (V::Record(_) | V::Tuple(_), ValueType::Record(_) | ValueType::Tuple(_)) => {
+                let fields: Vec<(&ComponentValue, &ValueType)> = match (value, ty) {
+                    (V::Record(fields), ValueType::Record(types)) => record_fields(fields, types)?,
+                    (V::Tuple(values), ValueType::Tuple(types)) => {
+                        check_count(values, types)?;
+                        values.iter().zip(types).collect()
+                    }
+                    _ => return Err(mismatch(value, ty)),
+                };
+                let mut offset = 0;
+                for (value, ty) in fields {
+                    offset = align_to(offset, alignment(ty));
+                    self.store(value, ty, at(ptr, offset)?)?;
+                    offset += size(ty);
+                }
+                return Ok(());
+            }
+            (V::String(_) | V::List(_), ValueType::String | ValueType::List(_))
+            | (V::Own(_), ValueType::Own(_))
+            | (V::Borrow(_), ValueType::Borrow(_)) => {
+                let mut flat = Vec::new();
+                self.lower_flat(value, ty, &mut flat)?;
+                flat.iter().flat_map(|value| match value {
+                    Value::I32(v) => v.to_le_bytes(),
+                    _ => unreachable!(),
+                }).collect()
+            }
+            _ => {
+                let cases = cases(ty).ok_or_else(|| mismatch(value, ty))?;
+                let (case, payload) = case_of(value, ty)?;
+                let disc_size = discriminant_size(cases.len());
+                self.guest.write(ptr, &(case as u32).to_le_bytes()[..disc_size as usize])?;
+                if let (Some(payload), Some(payload_ty)) = (payload, cases[case]) {
+                    let offset = align_to(disc_size, max_case_alignment(&cases));
+                    self.store(payload, payload_ty, at(ptr, offset)?)?;
+                }
+                return Ok(());
+            }
+        };
+        self.guest.write(ptr, &bytes)
+    }
+
+    pub fn load(&mut self, ty: &ValueType, ptr: u32) -> Result<ComponentValue, Trap> {
+        use ComponentValue as V;
+        let bytes = match ty {
+            ValueType::Record(_) | ValueType::Tuple(_) => Vec::new(),
+            ty if cases(ty).is_some() => Vec::new(),
+            ty => self.guest.read(ptr, size(ty))?,
+        };
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Ok(match ty {
+            ValueType::Bool => V::Bool(bytes[0] != 0),
+            ValueType::S8 => V::S8(bytes[0] as i8),
+            ValueType::U8 => V::U8(bytes[0]),
+            ValueType::S16 => V::S16(i16::from_le_bytes([bytes[0], bytes[1]])),
+            ValueType::U16 => V::U16(u16::from_le_bytes([bytes[0], bytes[1]])),
+            ValueType::S32 => V::S32(u32_at(0) as i32),
+            ValueType::U32 => V::U32(u32_at(0)),
+            ValueType::S64 => V::S64(i64::from_le_bytes(bytes[..8].try_into().unwrap())),
+            ValueType::U64 => V::U64(u64::from_le_bytes(bytes[..8].try_into().unwrap())),
+            ValueType::F32 => V::F32(f32::from_bits(u32_at(0))),
+            ValueType::F64 => V::F64(f64::from_le_bytes(bytes[..8].try_into().unwrap())),
+            ValueType::Char => V::Char(to_char(u32_at(0))?),
+            ValueType::String => V::String(self.load_string(u32_at(0), u32_at(4))?),
+            ValueType::List(elem) => V::List(self.load_list(elem, u32_at(0), u32_at(4))?),
+            ValueType::Own(resource) => V::Own(self.take_own(*resource, u32_at(0))?),
+            ValueType::Borrow(resource) => V::Borrow(self.rep(*resource, u32_at(0))?),
+            ValueType::Flags(all) => {
+                let mut padded = bytes.clone();
+                padded.resize(flag_words(all.len()) * 4, 0);
+                let words: Vec<u32> = padded.chunks(4)
+                    .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                    .collect();
+                V::Flags(flag_names(&words, all))
+            }
+            ValueType::Record(types) => {
+                let mut offset = 0;
+                let mut fields = Vec::new();
+                for (name, ty) in types {
+                    offset = align_to(offset, alignment(ty));
+                    fields.push((name.clone(), self.load(ty, at(ptr, offset)?)?));
+                    offset += size(ty);
+                }
+                V::Record(fields)
+            }
+            ValueType::Tuple(types) => {
+                let mut offset = 0;
+                let mut values = Vec::new();
+                for ty in types {
+                    offset = align_to(offset, alignment(ty));
+                    values.push(self.load(ty, at(ptr, offset)?)?);
+                    offset += size(ty);
+                }
+                V::Tuple(values)
+            }
+            _ => {
+                let cases = cases(ty).unwrap();
+                let disc_size = discriminant_size(cases.len());
+                let mut disc = self.guest.read(ptr, disc_size)?;
+                disc.resize(4, 0);
+                let case = u32::from_le_bytes(disc.try_into().unwrap()) as usize;
+                let Some(payload_ty) = cases.get(case) else {
+                    return Err(invalid(&format!("Invalid discriminant {case}")));
+                };
+                let payload = match payload_ty {
+                    Some(payload_ty) => {
+                        let offset = align_to(disc_size, max_case_alignment(&cases));
+                        Some(Box::new(self.load(payload_ty, at(ptr, offset)?)?))
+                    }
+                    None => None,
+                };
+                make_case(ty, case, payload)
+            }
+        })
+    }
+
+    /// Copies a string into memory allocated with `realloc`, returning its address and
+    /// its length in code units.
+    fn store_string(&mut self, s: &str) -> Result<(u32, u32), Trap> {
+        let (bytes, align, len) = match self.encoding {
+            StringEncoding::Utf8 => (s.as_bytes().to_vec(), 1, s.len()),
+            StringEncoding::CompactUtf16 if s.chars().all(|c| (c as u32) < 0x100) => {
+                let bytes: Vec<u8> = s.chars().map(|c| c as u8).collect();
+                let len = bytes.len();
+                (bytes, 2, len)
+            }
+            encoding => {
+                let units: Vec<u16> = s.encode_utf16().collect();
+                let tag = if encoding == StringEncoding::CompactUtf16 { UTF16_TAG } else { 0 };
+                let len = units.len() | tag as usize;
+                (units.iter().flat_map(|unit| unit.to_le_bytes()).collect(), 2, len)
+            }
+        };
+        let len = u32::try_from(len).map_err(|_| invalid("String too long"))?;
+        let ptr = self.allocate(align, bytes.len() as u32)?;
+        self.guest.write(ptr, &bytes)?;
+        Ok((ptr, len))
+    }
+
+    fn load_string(&mut self, ptr: u32, len: u32) -> Result<String, Trap> {
+        let (units16, len) = match self.encoding {
+            StringEncoding::Utf8 => {
+                let bytes = self.guest.read(ptr, len)?;
+                return String::from_utf8(bytes).map_err(|_| invalid("Invalid UTF-8 string"));
+            }
+            StringEncoding::Utf16 => (true, len),
+            StringEncoding::CompactUtf16 => (len & UTF16_TAG != 0, len & !UTF16_TAG),
+        };
+        if !ptr.is_multiple_of(2) {
+            return Err(invalid("Misaligned string"));
+        }
+        if !units16 {
+            let bytes = self.guest.read(ptr, len)?;
+            return Ok(bytes.iter().map(|byte| *byte as char).collect());
+        }
+        let bytes = self.guest.read(ptr, len.checked_mul(2).ok_or(Trap::MemoryOutOfBounds)?)?;
+        let units: Vec<u16> = bytes.chunks(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+        String::from_utf16(&units).map_err(|_| invalid("Invalid UTF-16 string"))
+    }
+
+    fn store_list(&mut self, elements: &[ComponentValue], elem: &ValueType)
+        -> Result<(u32, u32), Trap>
+    {
+        let elem_size = size(elem);
+        let total = (elements.len() as u64 * elem_size as u64).try_into()
+            .map_err(|_| invalid("List too long"))?;
+        let ptr = self.allocate(alignment(elem), total)?;
+        for (idx, element) in elements.iter().enumerate() {
+            self.store(element, elem, element_at(ptr, idx as u32, elem_size)?)?;
+        }
+        Ok((ptr, elements.len() as u32))
+    }
+
+    fn load_list(&mut self, elem: &ValueType, ptr: u32, len: u32)
+        -> Result<Vec<ComponentValue>, Trap>
+    {
+        if !ptr.is_multiple_of(alignment(elem)) {
+            return Err(invalid("Misaligned list"));
+        }
+        let elem_size = size(elem);
+        let end = ptr as u64 + len as u64 * elem_size as u64;
+        if end > u32::MAX as u64 + 1 {
+            return Err(Trap::MemoryOutOfBounds);
+        }
+        (0..len).map(|idx| self.load(elem, element_at(ptr, idx, elem_size)?)).collect()
+    }
+
+    /// Allocates `size` bytes with `realloc`, checking the pointer it returns is aligned
+    /// and that all of them are in memory.
+    fn allocate(&mut self, align: u32, size: u32) -> Result<u32, Trap> {
+        let ptr = self.guest.realloc(0, 0, align, size)?;
+        if !ptr.is_multiple_of(align) {
+            return Err(invalid("realloc returned a misaligned pointer"));
+        }
+        if ptr as u64 + size as u64 > self.guest.memory_len() {
+            return Err(Trap::MemoryOutOfBounds);
+        }
+        Ok(ptr)
+    }
+
+    fn take_own(&mut self, resource: ResourceId, idx: u32) -> Result<u32, Trap> {
+        match self.handles.get(idx)? {
+            Handle { resource: r, own: true, .. } if r == resource => {
+                Ok(self.handles.remove(idx)?.rep)
+            }
+            _ => Err(invalid(&format!("Handle {idx} is not an owned handle of its type"))),
+        }
+    }
+
+    fn rep(&mut self, resource: ResourceId, idx: u32) -> Result<u32, Trap> {
+        match self.handles.get(idx)? {
+            handle if handle.resource == resource => Ok(handle.rep),
+            _ => Err(invalid(&format!("Handle {idx} is not of its type"))),
+        }
+    }
+
+    fn pointer(&self, value: Option<Value>) -> Result<u32, Trap> {
+        match value {
+            Some(Value::I32(ptr)) => Ok(ptr as u32),
+            other => Err(invalid(&format!("Expected a pointer, found {other:?}"))),
+        }
+    }
+
+    fn check_aligned(&self, ptr: u32, ty: &ValueType) -> Result<(), Trap> {
+        match ptr % alignment(ty) {
+            0 => Ok(()),
+            _ => Err(invalid(&format!("Misaligned pointer {ptr:#x}"))),
+        }
+    }
+}
+
+/// The core types a value of `ty` is passed as.
+pub fn flatten(ty: &ValueType) -> Vec<ValType> {
+    match ty {
+        ValueType::S64 | ValueType::U64 => vec![ValType::I64],
+        ValueType::F32 => vec![ValType::F32],
+        ValueType::F64 => vec![ValType::F64],
+        ValueType::String | ValueType::List(_) => vec![ValType::I32, ValType::I32],
+        ValueType::Record(fields) => fields.iter().flat_map(|(_, ty)| flatten(ty)).collect(),
+        ValueType::Tuple(types) => flatten_all(types),
+        ValueType::Flags(names) => vec![ValType::I32; flag_words(names.len())],
+        ty => match cases(ty) {
+            Some(cases) => [vec![ValType::I32], join_cases(&cases)].concat(),
+            None => vec![ValType::I32],
+        },
+    }
+}
+
+pub fn flatten_all(types: &[ValueType]) -> Vec<ValType> {
+    types.iter().flat_map(flatten).collect()
+}
+
+/// The core types of a function taking `params` and returning `results`, as the
+/// canonical ABI lowers it for a caller in core code.
+pub fn lowered_signature(params: &[ValueType], results: &[ValueType]) -> (Vec<ValType>, Vec<ValType>) {
+    let mut flat_params = flatten_all(params);
+    if flat_params.len() > MAX_FLAT_PARAMS {
+        flat_params = vec![ValType::I32];
+    }
+    let mut flat_results = flatten_all(results);
+    if flat_results.len() > MAX_FLAT_RESULTS {
+        flat_params.push(ValType::I32);
+        flat_results = Vec::new();
+    }
+    (flat_params, flat_results)
+}
+
+pub fn alignment(ty: &ValueType) -> u32 {
+    match ty {
+        ValueType::Bool | ValueType::S8 | ValueType::U8 => 1,
+        ValueType::S16 | ValueType::U16 => 2,
+        ValueType::S64 | ValueType::U64 | ValueType::F64 => 8,
+        ValueType::Record(fields) => fields.iter().map(|(_, ty)| alignment(ty)).max().unwrap_or(1),
+        ValueType::Tuple(types) => types.iter().map(alignment).max().unwrap_or(1),
+        ValueType::Flags(names) => match names.len() {
+            0..=8 => 1,
+            9..=16 => 2,
+            _ => 4,
+        },
+        ty => match cases(ty) {
+            Some(cases) => discriminant_size(cases.len()).max(max_case_alignment(&cases)),
+            None => 4,
+        },
+    }
+}
+
+pub fn size(ty: &ValueType) -> u32 {
+    match ty {
+        ValueType::Bool | ValueType::S8 | ValueType::U8 => 1,
+        ValueType::S16 | ValueType::U16 => 2,
+        ValueType::S64 | ValueType::U64 | ValueType::F64 => 8,
+        ValueType::String | ValueType::List(_) => 8,
+        ValueType::Record(_) | ValueType::Tuple(_) => {
+            let types: Vec<&ValueType> = match ty {
+                ValueType::Record(fields) => fields.iter().map(|(_, ty)| ty).collect(),
+                ValueType::Tuple(types) => types.iter().collect(),
+                _ => unreachable!(),
+            };
+            let mut offset = 0;
+            for ty in types {
+                offset = align_to(offset, alignment(ty)) + size(ty);
+            }
+            align_to(offset, alignment(ty))
+        }
+        ValueType::Flags(names) => match names.len() {
+            0 => 0,
+            1..=8 => 1,
+            9..=16 => 2,
+            n => 4 * flag_words(n) as u32,
+        },
+        ty => match cases(ty) {
+            Some(cases) => {
+                let payload = cases.iter().flatten().map(|ty| size(ty)).max().unwrap_or(0);
+                let offset = align_to(discriminant_size(cases.len()), max_case_alignment(&cases));
+                align_to(offset + payload, alignment(ty))
+            }
+            None => 4,
+        },
+    }
+}
+
+/// A Rust type passed to and from components as values of a component type, which
+/// generated bindings implement for the types a WIT world defines.
+pub trait Marshal: Sized {
+    fn lower(self) -> ComponentValue;
+    fn lift(value: ComponentValue) -> Result<Self, Trap>;
+
+    /// The value as the payload of a case, which `()` leaves out.
+    fn lower_payload(self) -> Option<Box<ComponentValue>> {
+        Some(Box::new(self.lower()))
+    }
+
+    fn lift_payload(payload: Option<Box<ComponentValue>>) -> Result<Self, Trap> {
+        match payload {
+            Some(payload) => Self::lift(*payload),
+            None => Err(invalid("Expected a payload")),
+        }
+    }
+}
+
+/// A handle to a resource, which is passed as its representation.
+pub trait Resource: From<u32> {
+    fn rep(&self) -> u32;
+}
+
+/// A borrowed handle to the resource `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Borrow<T>(pub T);
+
+impl<T: Resource> Marshal for Borrow<T> {
+    fn lower(self) -> ComponentValue {
+        ComponentValue::Borrow(self.0.rep())
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Borrow(rep) => Ok(Borrow(T::from(rep))),
+            other => Err(unexpected(&other, "a borrowed handle")),
+        }
+    }
+}
+
+macro_rules! marshal_primitive {
+    ($($ty:ty => $variant:ident),*) => {$(
+        impl Marshal for $ty {
+            fn lower(self) -> ComponentValue {
+                ComponentValue::$variant(self)
+            }
+
+            fn lift(value: ComponentValue) -> Result<Self, Trap> {
+                match value {
+                    ComponentValue::$variant(value) => Ok(value),
+                    other => Err(unexpected(&other, stringify!($ty))),
+                }
+            }
+        }
+    )*};
+}
+
+marshal_primitive!(
+    bool => Bool, i8 => S8, u8 => U8, i16 => S16, u16 => U16, i32 => S32, u32 => U32,
+    i64 => S64, u64 => U64, f32 => F32, f64 => F64, char => Char, String => String
+);
+
+impl Marshal for () {
+    fn lower(self) -> ComponentValue {
+        ComponentValue::Tuple(Vec::new())
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Tuple(values) if values.is_empty() => Ok(()),
+            other => Err(unexpected(&other, "()")),
+        }
+    }
+
+    fn lower_payload(self) -> Option<Box<ComponentValue>> {
+        None
+    }
+
+    fn lift_payload(payload: Option<Box<ComponentValue>>) -> Result<Self, Trap> {
+        match payload {
+            None => Ok(()),
+            Some(payload) => Err(unexpected(&payload, "no payload")),
+        }
+    }
+}
+
+impl<T: Marshal> Marshal for Vec<T> {
+    fn lower(self) -> ComponentValue {
+        ComponentValue::List(self.into_iter().map(T::lower).collect())
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::List(values) => values.into_iter().map(T::lift).collect(),
+            other => Err(unexpected(&other, "a list")),
+        }
+    }
+}
+
+impl<T: Marshal> Marshal for Option<T> {
+    fn lower(self) -> ComponentValue {
+        ComponentValue::Option(self.map(|value| Box::new(value.lower())))
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Option(value) => value.map(|value| T::lift(*value)).transpose(),
+            other => Err(unexpected(&other, "an option")),
+        }
+    }
+}
+
+impl<T: Marshal, E: Marshal> Marshal for Result<T, E> {
+    fn lower(self) -> ComponentValue {
+        ComponentValue::Result(match self {
+            Ok(value) => Ok(value.lower_payload()),
+            Err(err) => Err(err.lower_payload()),
+        })
+    }
+
+    fn lift(value: ComponentValue) -> Result<Self, Trap> {
+        match value {
+            ComponentValue::Result(Ok(value)) => Ok(Ok(T::lift_payload(value)?)),
+            ComponentValue::Result(Err(err)) => Ok(Err(E::lift_payload(err)?)),
+            other => Err(unexpected(&other, "a result")),
+        }
+    }
+}
+
+macro_rules! marshal_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: Marshal),*> Marshal for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn lower(self) -> ComponentValue {
+                let ($($name,)*) = self;
+                ComponentValue::Tuple(vec![$($name.lower()),*])
+            }
+
+            fn lift(value: ComponentValue) -> Result<Self, Trap> {
+                let mut values = fields(value, marshal_tuple!(@count $($name)*))?.into_iter();
+                Ok(($($name::lift(values.next().unwrap())?,)*))
+            }
+        }
+    };
+    (@count $($name:ident)*) => { [$(stringify!($name)),*].len() };
+}
+
+marshal_tuple!(A);
+marshal_tuple!(A, B);
+marshal_tuple!(A, B, C);
+marshal_tuple!(A, B, C, D);
+marshal_tuple!(A, B, C, D, E);
+marshal_tuple!(A, B, C, D, E, F);
+
+/// The `len` values of a record or tuple, in order.
+pub fn fields(value: ComponentValue, len: usize) -> Result<Vec<ComponentValue>, Trap> {
+    let values = match value {
+        ComponentValue::Record(fields) => fields.into_iter().map(|(_, value)| value).collect(),
+        ComponentValue::Tuple(values) => values,
+        other => return Err(unexpected(&other, "a record or tuple")),
+    };
+    match values.len() == len {
+        true => Ok(values),
+        false => Err(invalid(&format!("Expected {len} fields, found {}", values.len()))),
+    }
+}
+
+/// The next of a function's arguments or results.
+pub fn next_value(values: &mut impl Iterator<Item = ComponentValue>) -> Result<ComponentValue, Trap> {
+    values.next().ok_or_else(|| invalid("Too few values"))
+}
+
+pub fn unexpected(value: &ComponentValue, expected: &str) -> Trap {
+    invalid(&format!("Expected {expected}, found {value:?}"))
+}
+
+/// The payload type of each case of a variant-like type, or `None` if it isn't one.
+fn cases(ty: &ValueType) -> Option<Vec<Option<&ValueType>>> {
+    match ty {
+        ValueType::Variant(cases) => Some(cases.iter().map(|(_, ty)| ty.as_ref()).collect()),
+        ValueType::Enum(names) => Some(vec![None; names.len()]),
+        ValueType::Option(ty) => Some(vec![None, Some(ty)]),
+        ValueType::Result(ok, err) => Some(vec![ok.as_deref(), err.as_deref()]),
+        _ => None,
+    }
+}
+
+/// The case a value of a variant-like type is, and its payload.
+fn case_of<'v>(value: &'v ComponentValue, ty: &ValueType)
+    -> Result<(usize, Option<&'v ComponentValue>), Trap>
+{
+    use ComponentValue as V;
+    let named = |name: &str, names: Vec<&String>| {
+        names.iter().position(|case| *case == name)
+            .ok_or_else(|| invalid(&format!("{name} is not a case of {ty:?}")))
+    };
+    let (case, payload) = match (value, ty) {
+        (V::Variant(name, payload), ValueType::Variant(cases)) => {
+            (named(name, cases.iter().map(|(name, _)| name).collect())?, payload.as_deref())
+        }
+        (V::Enum(name), ValueType::Enum(names)) => (named(name, names.iter().collect())?, None),
+        (V::Option(None), ValueType::Option(_)) => (0, None),
+        (V::Option(Some(payload)), ValueType::Option(_)) => (1, Some(&**payload)),
+        (V::Result(Ok(payload)), ValueType::Result(..)) => (0, payload.as_deref()),
+        (V::Result(Err(payload)), ValueType::Result(..)) => (1, payload.as_deref()),
+        _ => return Err(mismatch(value, ty)),
+    };
+    if payload.is_some() != cases(ty).unwrap()[case].is_some() {
+        return Err(mismatch(value, ty));
+    }
+    Ok((case, payload))
+}
+
+fn make_case(ty: &ValueType, case: usize, payload: Option<Box<ComponentValue>>) -> ComponentValue {
+    match ty {
+        ValueType::Variant(cases) => ComponentValue::Variant(cases[case].0.clone(), payload),
+        ValueType::Enum(names) => ComponentValue::Enum(names[case].clone()),
+        ValueType::Option(_) => ComponentValue::Option(payload),
+        ValueType::Result(..) if case == 0 => ComponentValue::Result(Ok(payload)),
+        ValueType::Result(..) => ComponentValue::Result(Err(payload)),
+        _ => unreachable!(),
+    }
+}
+
+/// The core types that can hold the flattened payload of any of the cases.
+fn join_cases(cases: &[Option<&ValueType>]) -> Vec<ValType> {
+    let mut joined: Vec<ValType> = Vec::new();
+    for flat in cases.iter().flatten().map(|ty| flatten(ty)) {
+        for (idx, ty) in flat.into_iter().enumerate() {
+            match joined.get_mut(idx) {
+                Some(existing) if *existing == ty => {}
+                Some(existing) => {
+                    let small = [ValType::I32, ValType::F32];
+                    *existing = if small.contains(existing) && small.contains(&ty) {
+                        ValType::I32
+                    } else {
+                        ValType::I64
+                    };
+                }
+                None => joined.push(ty),
+            }
+        }
+    }
+    joined
+}
+
+/// Reinterprets a core value as the joined type of a variant's payloads.
+fn widen(value: Value, ty: ValType) -> Value {
+    match (value, ty) {
+        (Value::F32(v), ValType::I32) => Value::I32(v.to_bits() as i32),
+        (Value::I32(v), ValType::I64) => Value::I64(v as u32 as i64),
+        (Value::F32(v), ValType::I64) => Value::I64(v.to_bits() as i64),
+        (Value::F64(v), ValType::I64) => Value::I64(v.to_bits() as i64),
+        (value, _) => value,
+    }
+}
+
+/// Reinterprets a core value of a variant's joined type as one of a case's payload.
+fn narrow(value: Value, ty: ValType) -> Value {
+    match (value, ty) {
+        (Value::I32(v), ValType::F32) => Value::F32(f32::from_bits(v as u32)),
+        (Value::I64(v), ValType::I32) => Value::I32(v as i32),
+        (Value::I64(v), ValType::F32) => Value::F32(f32::from_bits(v as u32)),
+        (Value::I64(v), ValType::F64) => Value::F64(f64::from_bits(v as u64)),
+        (value, _) => value,
+    }
+}
+
+fn zero(ty: ValType) -> Value {
+    match ty {
+        ValType::I64 => Value::I64(0),
+        ValType::F32 => Value::F32(0.0),
+        ValType::F64 => Value::F64(0.0),
+        _ => Value::I32(0),
+    }
+}
+
+fn discriminant_size(num_cases: usize) -> u32 {
+    match num_cases {
+        0..=0x100 => 1,
+        0x101..=0x1_0000 => 2,
+        _ => 4,
+    }
+}
+
+fn max_case_alignment(cases: &[Option<&ValueType>]) -> u32 {
+    cases.iter().flatten().map(|ty| alignment(ty)).max().unwrap_or(1)
+}
+
+fn flag_words(num_flags: usize) -> usize {
+    num_flags.div_ceil(32)
+}
+
+fn flag_bits(names: &[String], all: &[String]) -> Result<Vec<u32>, Trap> {
+    let mut words = vec![0u32; flag_words(all.len())];
+    for name in names {
+        let bit = all.iter().position(|flag| flag == name)
+            .ok_or_else(|| invalid(&format!("Unknown flag {name}")))?;
+        words[bit / 32] |= 1 << (bit % 32);
+    }
+    Ok(words)
+}
+
+fn flag_names(words: &[u32], all: &[String]) -> Vec<String> {
+    all.iter().enumerate()
+        .filter(|(bit, _)| words[bit / 32] & (1 << (bit % 32)) != 0)
+        .map(|(_, name)| name.clone())
+        .collect()
+}
+
+fn record_fields<'v, 't>(fields: &'v [(String, ComponentValue)], types: &'t [(String, ValueType)])
+    -> Result<Vec<(&'v ComponentValue, &'t ValueType)>, Trap>
+{
+    if fields.len() != types.len() {
+        return Err(invalid(&format!("Expected fields {types:?}, found {fields:?}")));
+    }
+    types.iter().map(|(name, ty)| {
+        fields.iter().find(|(field, _)| field == name)
+            .map(|(_, value)| (value, ty))
+            .ok_or_else(|| invalid(&format!("Missing field {name}")))
+    }).collect()
+}
+
+fn check_count<T>(values: &[T], types: &[ValueType]) -> Result<(), Trap> {
+    match values.len() == types.len() {
+        true => Ok(()),
+        false => Err(invalid(&format!("Expected {} values, found {}", types.len(), values.len()))),
+    }
+}
+
+fn to_char(code: u32) -> Result<char, Trap> {
+    char::from_u32(code).ok_or_else(|| invalid(&format!("Invalid char {code:#x}")))
+}
+
+/// The address `offset` bytes past `ptr`, trapping if it is past the end of the address
+/// space.
+fn at(ptr: u32, offset: u32) -> Result<u32, Trap> {
+    ptr.checked_add(offset).ok_or(Trap::MemoryOutOfBounds)
+}
+
+/// The address of the element at `idx` of a list at `ptr`.
+fn element_at(ptr: u32, idx: u32, elem_size: u32) -> Result<u32, Trap> {
+    at(ptr, idx.checked_mul(elem_size).ok_or(Trap::MemoryOutOfBounds)?)
+}
+
+fn align_to(offset: u32, align: u32) -> u32 {
+    offset.div_ceil(align) * align
+}
+
+fn mismatch(value: &ComponentValue, ty: &ValueType) -> Trap {
+    invalid(&format!("Expected a value of {ty:?}, found {value:?}"))
+}
+
+fn invalid(msg: &str) -> Trap {
+    Trap::InvalidValue(msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ComponentValue as V;
+
+    /// A memory with a bump allocator.
+    #[derive(Default)]
+    struct Bump {
+        memory: Vec<u8>,
+    }
+
+    impl Guest for Bump {
+        fn read(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, Trap> {
+            let range = addr as usize..addr as usize + len as usize;
+            self.memory.get(range).map(<[u8]>::to_vec).ok_or(Trap::MemoryOutOfBounds)
+        }
+
+        fn write(&mut self, addr: u32, bytes: &[u8]) -> Result<(), Trap> {
+            let range = addr as usize..addr as usize + bytes.len();
+            self.memory.get_mut(range).ok_or(Trap::MemoryOutOfBounds)?.copy_from_slice(bytes);
+            Ok(())
+        }
+
+        fn memory_len(&mut self) -> u64 {
+            self.memory.len() as u64
+        }
+
+        fn realloc(&mut self, _: u32, _: u32, align: u32, new_size: u32) -> Result<u32, Trap> {
+            let ptr = align_to(self.memory.len() as u32, align);
+            self.memory.resize((ptr + new_size) as usize, 0);
+            Ok(ptr)
+        }
+    }
+
+    /// A 4 GiB memory of zeros, whose `realloc` always returns the same pointer.
+    struct Zeros {
+        ptr: u32,
+    }
+
+    impl Guest for Zeros {
+        fn read(&mut self, _: u32, len: u32) -> Result<Vec<u8>, Trap> {
+            Ok(vec![0; len as usize])
+        }
+
+        fn write(&mut self, _: u32, _: &[u8]) -> Result<(), Trap> {
+            Ok(())
+        }
+
+        fn memory_len(&mut self) -> u64 {
+            1 << 32
+        }
+
+        fn realloc(&mut self, _: u32, _: u32, _: u32, _: u32) -> Result<u32, Trap> {
+            Ok(self.ptr)
+        }
+    }
+
+    fn point() -> ValueType {
+        ValueType::Record(vec![
+            (String::from("x"), ValueType::U8),
+            (String::from("y"), ValueType::F64),
+        ])
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!((16, 8), (size(&point()), alignment(&point())));
+        let option = ValueType::Option(Box::new(ValueType::U32));
+        assert_eq!((8, 4), (size(&option), alignment(&option)));
+        let flags = ValueType::Flags((0..33).map(|i| i.to_string()).collect());
+        assert_eq!((8, 4), (size(&flags), alignment(&flags)));
+        let result = ValueType::Result(Some(Box::new(ValueType::F32)), Some(Box::new(ValueType::U64)));
+        assert_eq!(vec![ValType::I32, ValType::I64], flatten(&result));
+        let variant = ValueType::Variant(vec![
+            (String::from("a"), Some(ValueType::F32)),
+            (String::from("b"), Some(ValueType::S32)),
+            (String::from("c"), None),
+        ]);
+        assert_eq!(vec![ValType::I32, ValType::I32], flatten(&variant));
+    }
+
+    #[test]
+    fn round_trips() {
+        let types = vec![
+            ValueType::String,
+            ValueType::List(Box::new(point())),
+            ValueType::Result(Some(Box::new(ValueType::F32)), Some(Box::new(ValueType::U64))),
+            ValueType::Option(Box::new(ValueType::Char)),
+            ValueType::Enum(vec![String::from("red"), String::from("green")]),
+            ValueType::Flags(vec![String::from("read"), String::from("write")]),
+            ValueType::Tuple(vec![ValueType::S8, ValueType::S16]),
+        ];
+        let point = |x, y| V::Record(vec![(String::from("x"), V::U8(x)), (String::from("y"), V::F64(y))]);
+        let values = vec![
+            V::String(String::from("héllo")),
+            V::List(vec![point(1, 0.5), point(2, -1.0)]),
+            V::Result(Ok(Some(Box::new(V::F32(1.5))))),
+            V::Option(Some(Box::new(V::Char('λ')))),
+            V::Enum(String::from("green")),
+            V::Flags(vec![String::from("write")]),
+            V::Tuple(vec![V::S8(-1), V::S16(-300)]),
+        ];
+        for encoding in [StringEncoding::Utf8, StringEncoding::Utf16, StringEncoding::CompactUtf16] {
+            let mut guest = Bump::default();
+            let mut handles = HandleTable::default();
+            let mut cx = Context::new(&mut guest, encoding, &mut handles);
+            for max_flat in [MAX_FLAT_PARAMS, MAX_FLAT_RESULTS] {
+                let flat = cx.lower_values(&values, &types, max_flat).unwrap();
+                assert_eq!(values, cx.lift_values(&flat, &types, max_flat).unwrap());
+            }
+        }
+
+        // Results that aren't flat point into memory
+        let mut guest = Bump::default();
+        let mut handles = HandleTable::default();
+        let mut cx = Context::new(&mut guest, StringEncoding::Utf8, &mut handles);
+        let flat = cx.lower_values(&values[..1], &types[..1], MAX_FLAT_RESULTS).unwrap();
+        assert_eq!(vec![Value::I32(0)], flat);
+        assert_eq!([8, 0, 0, 0, 6, 0, 0, 0], guest.memory[..8]);
+        assert_eq!(b"h\xc3\xa9llo", &guest.memory[8..14]);
+    }
+
+    #[test]
+    fn invalid_values() {
+        let mut guest = Bump { memory: vec![0xff; 16] };
+        let mut handles = HandleTable::default();
+        let mut cx = Context::new(&mut guest, StringEncoding::Utf8, &mut handles);
+        let string = [ValueType::String];
+        let err = cx.lift_values(&[Value::I32(0), Value::I32(4)], &string, MAX_FLAT_PARAMS);
+        assert_eq!(Err(invalid("Invalid UTF-8 string")), err);
+        let err = cx.lift_values(&[Value::I32(8), Value::I32(9)], &string, MAX_FLAT_PARAMS);
+        assert_eq!(Err(Trap::MemoryOutOfBounds), err);
+        let err = cx.lift_values(&[Value::I32(0x11_0000)], &[ValueType::Char], MAX_FLAT_PARAMS);
+        assert!(err.is_err());
+        let option = [ValueType::Option(Box::new(ValueType::U8))];
+        let err = cx.lift_values(&[Value::I32(2), Value::I32(0)], &option, MAX_FLAT_PARAMS);
+        assert_eq!(Err(invalid("Invalid discriminant 2")), err);
+        assert!(cx.lower_values(&[V::U32(1)], &[ValueType::S32], MAX_FLAT_PARAMS).is_err());
+    }
+
+    #[test]
+    fn end_of_address_space() {
+        let mut guest = Zeros { ptr: 0xffff_fff8 };
+        let mut handles = HandleTable::default();
+        let mut cx = Context::new(&mut guest, StringEncoding::Utf8, &mut handles);
+        let tuple = ValueType::Tuple(vec![ValueType::U32; 3]);
+        assert_eq!(Err(Trap::MemoryOutOfBounds), cx.load(&tuple, 0xffff_fffc));
+        let list = [ValueType::List(Box::new(ValueType::U32))];
+        let values = [V::List(vec![V::U32(1); 4])];
+        assert_eq!(Err(Trap::MemoryOutOfBounds), cx.lower_values(&values, &list, MAX_FLAT_PARAMS));
+        let values = [V::List(vec![V::U32(1); 2])];
+        assert!(cx.lower_values(&values, &list, MAX_FLAT_PARAMS).is_ok());
+    }
+
+    #[test]
+    fn marshal() {
+        let value: Result<Vec<(u8, String)>, ()> = Ok(vec![(1, String::from("a"))]);
+        let tuple = V::Tuple(vec![V::U8(1), V::String(String::from("a"))]);
+        assert_eq!(V::Result(Ok(Some(Box::new(V::List(vec![tuple]))))), value.clone().lower());
+        assert_eq!(Ok(value.clone()), Marshal::lift(value.lower()));
+        assert_eq!(V::Result(Err(None)), Err::<(), ()>(()).lower());
+        assert!(<Option<u32>>::lift(V::U32(1)).is_err());
+        assert!(<(u8, u8)>::lift(V::Tuple(vec![V::U8(1)])).is_err());
+    }
+
+    #[test]
+    fn handles() {
+        let mut handles = HandleTable::default();
+        let mut guest = NoMemory;
+        let mut cx = Context::new(&mut guest, StringEncoding::Utf8, &mut handles);
+        let types = [ValueType::Own(0), ValueType::Borrow(0)];
+        let flat = cx.lower_values(&[V::Own(7), V::Borrow(9)], &types, MAX_FLAT_PARAMS).unwrap();
+        assert_eq!(vec![Value::I32(1), Value::I32(2)], flat);
+        assert_eq!(vec![2], cx.borrows);
+
+        // Lifting an owned handle moves it out of the table, so it can't be used again
+        let own = [ValueType::Own(0)];
+        assert_eq!(Ok(vec![V::Own(7)]), cx.lift_values(&flat[..1], &own, MAX_FLAT_PARAMS));
+        assert!(cx.lift_values(&flat[..1], &own, MAX_FLAT_PARAMS).is_err());
+        assert!(cx.lift_values(&flat[1..], &own, MAX_FLAT_PARAMS).is_err());
+        let borrow = [ValueType::Borrow(0)];
+        assert_eq!(Ok(vec![V::Borrow(9)]), cx.lift_values(&flat[1..], &borrow, MAX_FLAT_PARAMS));
+        assert!(cx.lift_values(&flat[1..], &[ValueType::Borrow(1)], MAX_FLAT_PARAMS).is_err());
+        assert_eq!(1, handles.len());
+    }
+}
@@ -0,0 +1,1090 @@
+#![allow(dead_code)]
+
+//! The component binary format: core modules wrapped in typed interfaces, with the
+//! index spaces that link them together.
+//!
+//! Types are resolved as they are read, so a [`ValueType`] never refers to a type
+//! index. Resources are the exception: each is told apart by its [`ResourceId`], an
+//! index into [`Component::resources`] shared by every component nested in the binary.
+
+use crate::bytecode;
+use crate::value::{FuncType as CoreFuncType, ValType};
+use crate::wasm_module::{self, WasmLoadError, WasmModule, COMPONENT_LAYER};
+
+pub fn load(bytecode: &[u8]) -> Result<Component<'_>, WasmLoadError> {
+    match wasm_module::preamble(bytecode) {
+        Ok((_, COMPONENT_LAYER)) => {}
+        Ok(_) => return Err(WasmLoadError::at(6, "This is a core module, not a component")),
+        Err(msg) => return Err(WasmLoadError::at(0, &msg)),
+    }
+    let mut parser = Parser { reader: Reader { bytes: bytecode, pos: 0 }, ..Parser::default() };
+    let mut component = parser.component(bytecode.len()).map_err(|msg| {
+        let at = parser.reader.pos.saturating_sub(1);
+        parser.module_error.take().unwrap_or_else(|| WasmLoadError::at(at, &msg))
+    })?;
+    component.resources = parser.resources;
+    Ok(component)
+}
+
+#[derive(Debug, Default)]
+pub struct Component<'a> {
+    pub modules: Vec<ModuleDef<'a>>,
+    pub core_instances: Vec<CoreInstanceDef>,
+    pub core_types: Vec<CoreType>,
+    pub core_funcs: Vec<CoreFunc>,
+    pub core_tables: Vec<CoreExport>,
+    pub core_memories: Vec<CoreExport>,
+    pub core_globals: Vec<CoreExport>,
+    pub components: Vec<ComponentDef<'a>>,
+    pub instances: Vec<InstanceDef>,
+    pub funcs: Vec<Func>,
+    pub types: Vec<Type>,
+    pub imports: Vec<ComponentImport>,
+    pub exports: Vec<ComponentExport>,
+    /// Every resource type in the binary, including those of nested components. Only
+    /// the outermost component has them.
+    pub resources: Vec<Resource>,
+}
+
+impl Component<'_> {
+    /// The export called `name`, if there is one.
+    pub fn export(&self, name: &str) -> Option<&ComponentExport> {
+        self.exports.iter().find(|export| export.name == name)
+    }
+
+    /// The type of the function at `idx`.
+    pub fn func_type(&self, idx: u32) -> Option<&FuncType> {
+        match self.funcs.get(idx as usize)? {
+            Func::Import { ty, .. } | Func::Lift { ty, .. } | Func::Alias { ty, .. } => Some(ty),
+        }
+    }
+}
+
+/// A core module, defined in the component or imported.
+#[derive(Debug)]
+pub enum ModuleDef<'a> {
+    Defined(Box<WasmModule<'a>>),
+    Imported(usize),
+}
+
+/// A nested component, defined in the component or imported.
+#[derive(Debug)]
+pub enum ComponentDef<'a> {
+    Defined(Box<Component<'a>>),
+    Imported(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreInstanceDef {
+    /// A module instantiated with the named core instances as its imports.
+    Instantiate { module: u32, args: Vec<(String, u32)> },
+    /// A bundle of core definitions, such as lowered functions for a module to import.
+    Exports(Vec<(String, CoreSort, u32)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstanceDef {
+    Import { import: usize, ty: InstanceType },
+    Instantiate { component: u32, args: Vec<(String, Sort, u32)> },
+    Exports(Vec<(String, Sort, u32)>),
+    /// An exported instance, which is another index for the instance `idx`.
+    Reexport(u32),
+}
+
+/// The definition in a core instance exported as `name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreExport {
+    pub instance: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreFunc {
+    Alias(CoreExport),
+    /// A component function lowered to take and return core values.
+    Lower { func: u32, options: CanonOptions },
+    ResourceNew(ResourceId),
+    ResourceDrop(ResourceId),
+    ResourceRep(ResourceId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Func {
+    Import { import: usize, ty: FuncType },
+    /// A core function lifted to take and return component values.
+    Lift { core_func: u32, options: CanonOptions, ty: FuncType },
+    Alias { instance: u32, name: String, ty: FuncType },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreSort {
+    Func,
+    Table,
+    Memory,
+    Global,
+    Tag,
+    Type,
+    Module,
+    Instance,
+}
+
+impl CoreSort {
+    pub fn from_byte(byte: u8) -> Option<CoreSort> {
+        match byte {
+            0x00 => Some(CoreSort::Func),
+            0x01 => Some(CoreSort::Table),
+            0x02 => Some(CoreSort::Memory),
+            0x03 => Some(CoreSort::Global),
+            0x04 => Some(CoreSort::Tag),
+            0x10 => Some(CoreSort::Type),
+            0x11 => Some(CoreSort::Module),
+            0x12 => Some(CoreSort::Instance),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    Core(CoreSort),
+    Func,
+    Value,
+    Type,
+    Component,
+    Instance,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringEncoding {
+    #[default]
+    Utf8,
+    Utf16,
+    /// Latin-1 if every character fits, otherwise UTF-16 with the top bit of the
+    /// length set.
+    CompactUtf16,
+}
+
+/// How a lifted or lowered function reaches the linear memory holding its values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CanonOptions {
+    pub encoding: StringEncoding,
+    /// Core memory index.
+    pub memory: Option<u32>,
+    /// Core function index of `realloc(old_ptr, old_size, align, new_size) -> ptr`.
+    pub realloc: Option<u32>,
+    /// Core function index called with a lifted function's results once the caller
+    /// has read them.
+    pub post_return: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentImport {
+    pub name: String,
+    pub ty: ExternType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentExport {
+    pub name: String,
+    pub sort: Sort,
+    pub index: u32,
+}
+
+pub type ResourceId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resource {
+    /// A resource whose representation the host chooses.
+    Imported,
+    /// A resource the component defines, represented by an i32, with the core function
+    /// to call when an owned handle to it is dropped.
+    Defined { dtor: Option<u32> },
+}
+
+/// A type definition.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Value(ValueType),
+    Func(FuncType),
+    Component(ComponentType),
+    Instance(InstanceType),
+    Resource(ResourceId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueType {
+    Bool,
+    S8,
+    U8,
+    S16,
+    U16,
+    S32,
+    U32,
+    S64,
+    U64,
+    F32,
+    F64,
+    Char,
+    String,
+    List(Box<ValueType>),
+    Record(Vec<(String, ValueType)>),
+    Tuple(Vec<ValueType>),
+    Variant(Vec<(String, Option<ValueType>)>),
+    Enum(Vec<String>),
+    Option(Box<ValueType>),
+    Result(Option<Box<ValueType>>, Option<Box<ValueType>>),
+    Flags(Vec<String>),
+    Own(ResourceId),
+    Borrow(ResourceId),
+}
+
+impl ValueType {
+    fn primitive(byte: u8) -> Option<ValueType> {
+        use ValueType::*;
+        Some(match byte {
+            0x7f => Bool,
+            0x7e => S8,
+            0x7d => U8,
+            0x7c => S16,
+            0x7b => U16,
+            0x7a => S32,
+            0x79 => U32,
+            0x78 => S64,
+            0x77 => U64,
+            0x76 => F32,
+            0x75 => F64,
+            0x74 => Char,
+            0x73 => String,
+            _ => return None,
+        })
+    }
+}
+
+/// A component function type. Results may be named in the binary, but only their
+/// types matter once lifted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FuncType {
+    pub params: Vec<(String, ValueType)>,
+    pub results: Vec<ValueType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternType {
+    Module(ModuleType),
+    Func(FuncType),
+    Value(ValueType),
+    Type(Type),
+    Component(ComponentType),
+    Instance(InstanceType),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ComponentType {
+    pub imports: Vec<(String, ExternType)>,
+    pub exports: Vec<(String, ExternType)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstanceType {
+    pub exports: Vec<(String, ExternType)>,
+}
+
+impl InstanceType {
+    pub fn export(&self, name: &str) -> Option<&ExternType> {
+        self.exports.iter().find(|(export, _)| export == name).map(|(_, ty)| ty)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreType {
+    Func(CoreFuncType),
+    Module(ModuleType),
+}
+
+/// The names a core module type imports and exports.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleType {
+    pub imports: Vec<(String, String)>,
+    pub exports: Vec<String>,
+}
+
+/// The type index spaces of a component or type declaration, which outer aliases
+/// refer to by how many scopes out they are.
+#[derive(Default)]
+struct Scope {
+    types: Vec<Type>,
+    core_types: Vec<CoreType>,
+}
+
+#[derive(Default)]
+struct Parser<'a> {
+    reader: Reader<'a>,
+    scopes: Vec<Scope>,
+    resources: Vec<Resource>,
+    /// The error in a nested core module, located in the whole binary.
+    module_error: Option<WasmLoadError>,
+}
+
+impl<'a> Parser<'a> {
+    /// Reads a component from its preamble up to `end`.
+    fn component(&mut self, end: usize) -> Result<Component<'a>, String> {
+        self.reader.pos += 8;
+        self.scopes.push(Scope::default());
+        let mut component = Component::default();
+        while self.reader.pos < end {
+            let id = self.reader.u8()?;
+            let size = self.reader.u32()? as usize;
+            let section_end = self.reader.pos.checked_add(size)
+                .filter(|section_end| *section_end <= end)
+                .ok_or("Section extends past the end of the component")?;
+            match id {
+                0x00 => self.reader.pos = section_end,
+                0x01 => {
+                    let bytes = &self.reader.bytes[self.reader.pos..section_end];
+                    match wasm_module::load(bytes) {
+                        Ok(module) => component.modules.push(ModuleDef::Defined(Box::new(module))),
+                        Err(err) => {
+                            let msg = err.msg().to_string();
+                            self.module_error = Some(err.offset_by(self.reader.pos));
+                            return Err(msg);
+                        }
+                    }
+                    self.reader.pos = section_end;
+                }
+                0x02 => self.vec(|p| p.core_instance(&mut component))?,
+                0x03 => self.vec(|p| {
+                    let ty = p.core_type()?;
+                    p.scope().core_types.push(ty);
+                    Ok(())
+                })?,
+                0x04 => {
+                    let nested = self.component(section_end)?;
+                    component.components.push(ComponentDef::Defined(Box::new(nested)));
+                }
+                0x05 => self.vec(|p| p.instance(&mut component))?,
+                0x06 => self.vec(|p| p.alias(&mut component))?,
+                0x07 => self.vec(|p| {
+                    let ty = p.def_type()?;
+                    p.scope().types.push(ty);
+                    Ok(())
+                })?,
+                0x08 => self.vec(|p| p.canon(&mut component))?,
+                0x09 => return Err(String::from("Component start functions are unsupported")),
+                0x0a => self.vec(|p| p.import(&mut component))?,
+                0x0b => self.vec(|p| p.export(&mut component))?,
+                0x0c => return Err(String::from("Component values are unsupported")),
+                id => return Err(format!("Invalid component section code {id:#04x}")),
+            }
+            if self.reader.pos != section_end {
+                return Err(format!("Section {id:#04x} does not end where its size says"));
+            }
+        }
+        let scope = self.scopes.pop().unwrap();
+        component.types = scope.types;
+        component.core_types = scope.core_types;
+        Ok(component)
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    /// Reads a vector, calling `item` for each of its elements.
+    fn vec(&mut self, mut item: impl FnMut(&mut Self) -> Result<(), String>) -> Result<(), String> {
+        for _ in 0..self.reader.u32()? {
+            item(self)?;
+        }
+        Ok(())
+    }
+
+    fn core_instance(&mut self, component: &mut Component) -> Result<(), String> {
+        let instance = match self.reader.u8()? {
+            0x00 => {
+                let module = self.reader.u32()?;
+                let mut args = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    let name = self.reader.name()?;
+                    if self.reader.u8()? != 0x12 {
+                        return Err(String::from("Core instantiation arguments must be instances"));
+                    }
+                    args.push((name, self.reader.u32()?));
+                }
+                CoreInstanceDef::Instantiate { module, args }
+            }
+            0x01 => {
+                let mut exports = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    let name = self.reader.name()?;
+                    let sort = self.core_sort()?;
+                    exports.push((name, sort, self.reader.u32()?));
+                }
+                CoreInstanceDef::Exports(exports)
+            }
+            other => return Err(format!("Invalid core instance {other:#04x}")),
+        };
+        component.core_instances.push(instance);
+        Ok(())
+    }
+
+    fn instance(&mut self, component: &mut Component) -> Result<(), String> {
+        let instance = match self.reader.u8()? {
+            0x00 => {
+                let nested = self.reader.u32()?;
+                let mut args = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    let name = self.reader.name()?;
+                    let sort = self.sort()?;
+                    args.push((name, sort, self.reader.u32()?));
+                }
+                InstanceDef::Instantiate { component: nested, args }
+            }
+            0x01 => {
+                let mut exports = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    let name = self.extern_name()?;
+                    let sort = self.sort()?;
+                    exports.push((name, sort, self.reader.u32()?));
+                }
+                InstanceDef::Exports(exports)
+            }
+            other => return Err(format!("Invalid instance {other:#04x}")),
+        };
+        component.instances.push(instance);
+        Ok(())
+    }
+
+    fn alias(&mut self, component: &mut Component) -> Result<(), String> {
+        let sort = self.sort()?;
+        match self.reader.u8()? {
+            0x00 => {
+                let instance = self.reader.u32()?;
+                let name = self.reader.name()?;
+                let ty = instance_export(component, instance, &name)?;
+                match (sort, ty) {
+                    (Sort::Func, ExternType::Func(ty)) => {
+                        component.funcs.push(Func::Alias { instance, name, ty });
+                    }
+                    (Sort::Type, ExternType::Type(ty)) => self.scope().types.push(ty),
+                    _ => return Err(format!("Export {name} is not of sort {sort:?}")),
+                }
+            }
+            0x01 => {
+                let Sort::Core(core_sort) = sort else {
+                    return Err(String::from("Core export aliases must be of a core sort"));
+                };
+                let instance = self.reader.u32()?;
+                if instance as usize >= component.core_instances.len() {
+                    return Err(format!("Unknown core instance {instance}"));
+                }
+                let export = CoreExport { instance, name: self.reader.name()? };
+                match core_sort {
+                    CoreSort::Func => component.core_funcs.push(CoreFunc::Alias(export)),
+                    CoreSort::Table => component.core_tables.push(export),
+                    CoreSort::Memory => component.core_memories.push(export),
+                    CoreSort::Global => component.core_globals.push(export),
+                    sort => return Err(format!("Aliasing core {sort:?} exports is unsupported")),
+                }
+            }
+            0x02 => self.outer_alias(sort)?,
+            other => return Err(format!("Invalid alias target {other:#04x}")),
+        }
+        Ok(())
+    }
+
+    /// Copies a type from an enclosing scope, `count` scopes out from this one.
+    fn outer_alias(&mut self, sort: Sort) -> Result<(), String> {
+        let count = self.reader.u32()? as usize;
+        let idx = self.reader.u32()? as usize;
+        let Some(outer) = self.scopes.len().checked_sub(count + 1) else {
+            return Err(format!("Outer alias to scope {count} out of range"));
+        };
+        match sort {
+            Sort::Type => {
+                let ty = self.scopes[outer].types.get(idx).cloned()
+                    .ok_or_else(|| format!("Unknown outer type {idx}"))?;
+                self.scope().types.push(ty);
+            }
+            Sort::Core(CoreSort::Type) => {
+                let ty = self.scopes[outer].core_types.get(idx).cloned()
+                    .ok_or_else(|| format!("Unknown outer core type {idx}"))?;
+                self.scope().core_types.push(ty);
+            }
+            sort => return Err(format!("Outer aliases of {sort:?} are unsupported")),
+        }
+        Ok(())
+    }
+
+    fn canon(&mut self, component: &mut Component) -> Result<(), String> {
+        match self.reader.u8()? {
+            0x00 => {
+                self.expect(0x00, "lift")?;
+                let core_func = self.reader.u32()?;
+                let options = self.canon_options()?;
+                let ty = match self.type_ref()? {
+                    Type::Func(ty) => ty,
+                    _ => return Err(String::from("Lifted functions must have a function type")),
+                };
+                component.funcs.push(Func::Lift { core_func, options, ty });
+            }
+            0x01 => {
+                self.expect(0x00, "lower")?;
+                let func = self.reader.u32()?;
+                if func as usize >= component.funcs.len() {
+                    return Err(format!("Unknown function {func}"));
+                }
+                let options = self.canon_options()?;
+                component.core_funcs.push(CoreFunc::Lower { func, options });
+            }
+            op @ (0x02..=0x04) => {
+                let resource = match self.type_ref()? {
+                    Type::Resource(resource) => resource,
+                    _ => return Err(String::from("Expected a resource type")),
+                };
+                component.core_funcs.push(match op {
+                    0x02 => CoreFunc::ResourceNew(resource),
+                    0x03 => CoreFunc::ResourceDrop(resource),
+                    _ => CoreFunc::ResourceRep(resource),
+                });
+            }
+            other => return Err(format!("Unsupported canonical function {other:#04x}")),
+        }
+        Ok(())
+    }
+
+    fn canon_options(&mut self) -> Result<CanonOptions, String> {
+        let mut options = CanonOptions::default();
+        for _ in 0..self.reader.u32()? {
+            match self.reader.u8()? {
+                0x00 => options.encoding = StringEncoding::Utf8,
+                0x01 => options.encoding = StringEncoding::Utf16,
+                0x02 => options.encoding = StringEncoding::CompactUtf16,
+                0x03 => options.memory = Some(self.reader.u32()?),
+                0x04 => options.realloc = Some(self.reader.u32()?),
+                0x05 => options.post_return = Some(self.reader.u32()?),
+                other => return Err(format!("Unsupported canonical option {other:#04x}")),
+            }
+        }
+        Ok(options)
+    }
+
+    fn import(&mut self, component: &mut Component) -> Result<(), String> {
+        let name = self.extern_name()?;
+        let ty = self.extern_type()?;
+        let import = component.imports.len();
+        match &ty {
+            ExternType::Module(_) => component.modules.push(ModuleDef::Imported(import)),
+            ExternType::Func(ty) => component.funcs.push(Func::Import { import, ty: ty.clone() }),
+            ExternType::Value(_) => return Err(String::from("Component values are unsupported")),
+            ExternType::Type(ty) => self.scope().types.push(ty.clone()),
+            ExternType::Component(_) => component.components.push(ComponentDef::Imported(import)),
+            ExternType::Instance(ty) => {
+                component.instances.push(InstanceDef::Import { import, ty: ty.clone() });
+            }
+        }
+        component.imports.push(ComponentImport { name, ty });
+        Ok(())
+    }
+
+    /// Reads an export, which also gives the exported definition a new index.
+    fn export(&mut self, component: &mut Component) -> Result<(), String> {
+        let name = self.extern_name()?;
+        let sort = self.sort()?;
+        let index = self.reader.u32()?;
+        if self.reader.u8()? == 0x01 {
+            self.extern_type()?;        // the type it is exported as, which must match
+        }
+        let unknown = || format!("Export {name} of unknown {sort:?} {index}");
+        match sort {
+            Sort::Func => {
+                let func = component.funcs.get(index as usize).ok_or_else(unknown)?.clone();
+                component.funcs.push(func);
+            }
+            Sort::Type => {
+                let ty = self.type_at(index)?;
+                self.scope().types.push(ty);
+            }
+            Sort::Instance => {
+                if index as usize >= component.instances.len() {
+                    return Err(unknown());
+                }
+                component.instances.push(InstanceDef::Reexport(index));
+            }
+            Sort::Core(CoreSort::Module) if (index as usize) < component.modules.len() => {}
+            Sort::Component if (index as usize) < component.components.len() => {}
+            _ => return Err(unknown()),
+        }
+        component.exports.push(ComponentExport { name, sort, index });
+        Ok(())
+    }
+
+    fn extern_type(&mut self) -> Result<ExternType, String> {
+        Ok(match self.reader.u8()? {
+            0x00 => {
+                self.expect(0x11, "core module type")?;
+                let idx = self.reader.u32()? as usize;
+                match self.scope().core_types.get(idx) {
+                    Some(CoreType::Module(ty)) => ExternType::Module(ty.clone()),
+                    _ => return Err(format!("Core type {idx} is not a module type")),
+                }
+            }
+            0x01 => match self.type_ref()? {
+                Type::Func(ty) => ExternType::Func(ty),
+                _ => return Err(String::from("Expected a function type")),
+            },
+            0x02 => match self.reader.u8()? {
+                0x01 => ExternType::Value(self.val_type()?),
+                _ => return Err(String::from("Component values are unsupported")),
+            },
+            0x03 => match self.reader.u8()? {
+                0x00 => ExternType::Type(self.type_ref()?),
+                0x01 => {
+                    self.resources.push(Resource::Imported);
+                    ExternType::Type(Type::Resource(self.resources.len() - 1))
+                }
+                other => return Err(format!("Invalid type bound {other:#04x}")),
+            },
+            0x04 => match self.type_ref()? {
+                Type::Component(ty) => ExternType::Component(ty),
+                _ => return Err(String::from("Expected a component type")),
+            },
+            0x05 => match self.type_ref()? {
+                Type::Instance(ty) => ExternType::Instance(ty),
+                _ => return Err(String::from("Expected an instance type")),
+            },
+            other => return Err(format!("Invalid extern type {other:#04x}")),
+        })
+    }
+
+    fn def_type(&mut self) -> Result<Type, String> {
+        let byte = self.reader.u8()?;
+        if let Some(ty) = ValueType::primitive(byte) {
+            return Ok(Type::Value(ty));
+        }
+        Ok(match byte {
+            0x72 => {
+                let mut fields = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    fields.push((self.reader.name()?, self.val_type()?));
+                }
+                Type::Value(ValueType::Record(fields))
+            }
+            0x71 => {
+                let mut cases = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    let name = self.reader.name()?;
+                    let payload = self.optional(Self::val_type)?;
+                    if self.reader.u8()? != 0x00 {
+                        return Err(String::from("Variant cases cannot refine others"));
+                    }
+                    cases.push((name, payload));
+                }
+                Type::Value(ValueType::Variant(cases))
+            }
+            0x70 => Type::Value(ValueType::List(Box::new(self.val_type()?))),
+            0x6f => {
+                let mut types = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    types.push(self.val_type()?);
+                }
+                Type::Value(ValueType::Tuple(types))
+            }
+            0x6e | 0x6d => {
+                let mut names = Vec::new();
+                for _ in 0..self.reader.u32()? {
+                    names.push(self.reader.name()?);
+                }
+                Type::Value(match byte {
+                    0x6e => ValueType::Flags(names),
+                    _ => ValueType::Enum(names),
+                })
+            }
+            0x6b => Type::Value(ValueType::Option(Box::new(self.val_type()?))),
+            0x6a => {
+                let ok = self.optional(Self::val_type)?.map(Box::new);
+                let err = self.optional(Self::val_type)?.map(Box::new);
+                Type::Value(ValueType::Result(ok, err))
+            }
+            0x69 | 0x68 => {
+                let Type::Resource(resource) = self.type_ref()? else {
+                    return Err(String::from("Handles must refer to a resource type"));
+                };
+                Type::Value(match byte {
+                    0x69 => ValueType::Own(resource),
+                    _ => ValueType::Borrow(resource),
+                })
+            }
+            0x40 => Type::Func(self.func_type()?),
+            0x41 => {
+                let mut ty = ComponentType::default();
+                self.scopes.push(Scope::default());
+                for _ in 0..self.reader.u32()? {
+                    if self.reader.bytes.get(self.reader.pos) == Some(&0x03) {
+                        self.reader.pos += 1;
+                        let name = self.extern_name()?;
+                        let import = self.extern_type()?;
+                        self.declare(&import);
+                        ty.imports.push((name, import));
+                    } else if let Some(export) = self.instance_decl()? {
+                        ty.exports.push(export);
+                    }
+                }
+                self.scopes.pop();
+                Type::Component(ty)
+            }
+            0x42 => {
+                let mut ty = InstanceType::default();
+                self.scopes.push(Scope::default());
+                for _ in 0..self.reader.u32()? {
+                    if let Some(export) = self.instance_decl()? {
+                        ty.exports.push(export);
+                    }
+                }
+                self.scopes.pop();
+                Type::Instance(ty)
+            }
+            0x3f => {
+                if self.reader.u8()? != 0x7f {
+                    return Err(String::from("Resources must be represented by an i32"));
+                }
+                let dtor = self.optional(|p| p.reader.u32())?;
+                self.resources.push(Resource::Defined { dtor });
+                Type::Resource(self.resources.len() - 1)
+            }
+            other => return Err(format!("Invalid type {other:#04x}")),
+        })
+    }
+
+    fn func_type(&mut self) -> Result<FuncType, String> {
+        let mut ty = FuncType::default();
+        for _ in 0..self.reader.u32()? {
+            ty.params.push((self.reader.name()?, self.val_type()?));
+        }
+        match self.reader.u8()? {
+            0x00 => ty.results.push(self.val_type()?),
+            0x01 => {
+                for _ in 0..self.reader.u32()? {
+                    self.reader.name()?;
+                    ty.results.push(self.val_type()?);
+                }
+            }
+            other => return Err(format!("Invalid result list {other:#04x}")),
+        }
+        Ok(ty)
+    }
+
+    /// Reads a declaration of an instance type, returning it if it is an export.
+    fn instance_decl(&mut self) -> Result<Option<(String, ExternType)>, String> {
+        match self.reader.u8()? {
+            0x00 => {
+                let ty = self.core_type()?;
+                self.scope().core_types.push(ty);
+            }
+            0x01 => {
+                let ty = self.def_type()?;
+                self.scope().types.push(ty);
+            }
+            0x02 => {
+                let sort = self.sort()?;
+                if self.reader.u8()? != 0x02 {
+                    return Err(String::from("Type declarations may only alias outer types"));
+                }
+                self.outer_alias(sort)?;
+            }
+            0x04 => {
+                let name = self.extern_name()?;
+                let ty = self.extern_type()?;
+                self.declare(&ty);
+                return Ok(Some((name, ty)));
+            }
+            other => return Err(format!("Invalid declaration {other:#04x}")),
+        }
+        Ok(None)
+    }
+
+    /// Adds the type imported or exported by a declaration to the current scope.
+    fn declare(&mut self, ty: &ExternType) {
+        if let ExternType::Type(ty) = ty {
+            self.scope().types.push(ty.clone());
+        }
+    }
+
+    fn core_type(&mut self) -> Result<CoreType, String> {
+        let mut form = self.reader.u8()?;
+        if form == 0x00 {
+            form = self.reader.u8()?;
+            if form != 0x50 {
+                return Err(format!("Invalid core type {form:#04x}"));
+            }
+        }
+        match form {
+            0x60 => {
+                let mut ty = CoreFuncType::default();
+                for _ in 0..self.reader.u32()? {
+                    ty.params.push(self.core_val_type()?);
+                }
+                for _ in 0..self.reader.u32()? {
+                    ty.results.push(self.core_val_type()?);
+                }
+                Ok(CoreType::Func(ty))
+            }
+            0x50 => {
+                let mut ty = ModuleType::default();
+                self.scopes.push(Scope::default());
+                for _ in 0..self.reader.u32()? {
+                    match self.reader.u8()? {
+                        0x00 => {
+                            let names = (self.reader.name()?, self.reader.name()?);
+                            self.core_import_desc()?;
+                            ty.imports.push(names);
+                        }
+                        0x01 => {
+                            let ty = self.core_type()?;
+                            self.scope().core_types.push(ty);
+                        }
+                        0x02 => {
+                            let sort = self.core_sort()?;
+                            self.expect(0x01, "outer alias")?;
+                            self.outer_alias(Sort::Core(sort))?;
+                        }
+                        0x03 => {
+                            ty.exports.push(self.reader.name()?);
+                            self.core_import_desc()?;
+                        }
+                        other => return Err(format!("Invalid module declaration {other:#04x}")),
+                    }
+                }
+                self.scopes.pop();
+                Ok(CoreType::Module(ty))
+            }
+            other => Err(format!("Unsupported core type {other:#04x}")),
+        }
+    }
+
+    /// Skips the type of a core import or export, which only matters to the module.
+    fn core_import_desc(&mut self) -> Result<(), String> {
+        let limits = |p: &mut Self| -> Result<(), String> {
+            let flags = p.reader.u8()?;
+            p.reader.u64()?;
+            if flags & 0x01 != 0 {
+                p.reader.u64()?;
+            }
+            Ok(())
+        };
+        match self.reader.u8()? {
+            0x00 => {
+                self.reader.u32()?;
+            }
+            0x01 => {
+                self.core_val_type()?;
+                limits(self)?;
+            }
+            0x02 => limits(self)?,
+            0x03 => {
+                self.core_val_type()?;
+                self.reader.u8()?;
+            }
+            0x04 => {
+                self.expect(0x00, "tag")?;
+                self.reader.u32()?;
+            }
+            other => return Err(format!("Invalid import kind {other:#04x}")),
+        }
+        Ok(())
+    }
+
+    fn core_val_type(&mut self) -> Result<ValType, String> {
+        let byte = self.reader.bytes.get(self.reader.pos).copied().unwrap_or_default();
+        let (ty, size) = bytecode::read::read_val_type(self.reader.bytes, self.reader.pos)
+            .ok_or_else(|| format!("Invalid value type {byte:#04x}"))?;
+        self.reader.pos += size;
+        Ok(ty)
+    }
+
+    /// A value type: a primitive type's byte, or the index of a defined value type.
+    /// Read as an s33, the primitives are negative.
+    fn val_type(&mut self) -> Result<ValueType, String> {
+        let byte = self.reader.bytes.get(self.reader.pos).copied().unwrap_or_default();
+        if let Some(ty) = ValueType::primitive(byte) {
+            self.reader.pos += 1;
+            return Ok(ty);
+        }
+        match self.type_ref()? {
+            Type::Value(ty) => Ok(ty),
+            _ => Err(String::from("Expected a value type")),
+        }
+    }
+
+    /// Reads a type index, returning the type it refers to.
+    fn type_ref(&mut self) -> Result<Type, String> {
+        let idx = self.reader.u32()?;
+        self.type_at(idx)
+    }
+
+    fn type_at(&mut self, idx: u32) -> Result<Type, String> {
+        self.scope().types.get(idx as usize).cloned()
+            .ok_or_else(|| format!("Unknown type {idx}"))
+    }
+
+    fn sort(&mut self) -> Result<Sort, String> {
+        Ok(match self.reader.u8()? {
+            0x00 => Sort::Core(self.core_sort()?),
+            0x01 => Sort::Func,
+            0x02 => Sort::Value,
+            0x03 => Sort::Type,
+            0x04 => Sort::Component,
+            0x05 => Sort::Instance,
+            other => return Err(format!("Invalid sort {other:#04x}")),
+        })
+    }
+
+    fn core_sort(&mut self) -> Result<CoreSort, String> {
+        let byte = self.reader.u8()?;
+        CoreSort::from_byte(byte).ok_or_else(|| format!("Invalid core sort {byte:#04x}"))
+    }
+
+    /// An import or export name, which may be followed by a version suffix.
+    fn extern_name(&mut self) -> Result<String, String> {
+        match self.reader.u8()? {
+            0x00 => self.reader.name(),
+            0x01 => {
+                let name = self.reader.name()?;
+                self.reader.name()?;
+                Ok(name)
+            }
+            other => Err(format!("Invalid name {other:#04x}")),
+        }
+    }
+
+    fn optional<T>(
+        &mut self,
+        item: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<Option<T>, String> {
+        match self.reader.u8()? {
+            0x00 => Ok(None),
+            0x01 => item(self).map(Some),
+            other => Err(format!("Invalid optional {other:#04x}")),
+        }
+    }
+
+    fn expect(&mut self, byte: u8, what: &str) -> Result<(), String> {
+        match self.reader.u8()? {
+            found if found == byte => Ok(()),
+            found => Err(format!("Expected {byte:#04x} in {what}, found {found:#04x}")),
+        }
+    }
+}
+
+/// The type of the export `name` of instance `idx`.
+fn instance_export(component: &Component, idx: u32, name: &str) -> Result<ExternType, String> {
+    let mut idx = idx;
+    loop {
+        let instance = component.instances.get(idx as usize)
+            .ok_or_else(|| format!("Unknown instance {idx}"))?;
+        let missing = || format!("Instance {idx} has no export {name}");
+        return match instance {
+            InstanceDef::Import { ty, .. } => ty.export(name).cloned().ok_or_else(missing),
+            InstanceDef::Exports(exports) => {
+                let (_, sort, item) = exports.iter().find(|(export, ..)| export == name)
+                    .ok_or_else(missing)?;
+                match sort {
+                    Sort::Func => component.func_type(*item).cloned().map(ExternType::Func)
+                        .ok_or_else(|| format!("Unknown function {item}")),
+                    _ => Err(format!("Aliasing {sort:?} exports of instances is unsupported")),
+                }
+            }
+            InstanceDef::Instantiate { .. } => {
+                Err(String::from("Aliasing exports of nested components is unsupported"))
+            }
+            InstanceDef::Reexport(original) => {
+                idx = *original;
+                continue;
+            }
+        };
+    }
+}
+
+#[derive(Default)]
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.pos).ok_or("Unexpected end of the component")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        u32::try_from(self.u64()?).map_err(|_| String::from("Integer too large"))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut result = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(String::from("Integer representation too long"))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len())
+            .ok_or("Unexpected end of the component")?;
+        let name = std::str::from_utf8(&self.bytes[self.pos..end]).map_err(|e| format!("{e}"))?;
+        self.pos = end;
+        Ok(name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{name, ComponentBuilder, ModuleBuilder};
+
+    #[test]
+    fn types_and_errors() {
+        // A record type and a function taking a list of it, imported and exported again
+        let mut builder = ComponentBuilder::new();
+        builder.items(0x07, &[
+            &[&[0x72, 0x02][..], &name("x"), &[0x7d], &name("y"), &[0x76]].concat(),
+            &[0x70, 0x00],
+            &[&[0x40, 0x01][..], &name("points"), &[0x01, 0x01, 0x00]].concat(),
+        ]);
+        builder.items(0x0a, &[&[&[0x00][..], &name("sum"), &[0x01, 0x02]].concat()]);
+        builder.items(0x0b, &[&[&[0x00][..], &name("total"), &[0x01, 0x00, 0x00]].concat()]);
+        let bytes = builder.build();
+        let component = load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        let point = ValueType::Record(vec![
+            (String::from("x"), ValueType::U8),
+            (String::from("y"), ValueType::F32),
+        ]);
+        let ty = FuncType {
+            params: vec![(String::from("points"), ValueType::List(Box::new(point)))],
+            results: Vec::new(),
+        };
+        assert_eq!(Some(&ty), component.func_type(1));
+        assert_eq!(Some(&ComponentExport { name: String::from("total"), sort: Sort::Func, index: 0 }),
+            component.export("total"));
+
+        assert_eq!("This is a core module, not a component",
+            load(&ModuleBuilder::new().build()).err().unwrap().msg());
+
+        // Errors in nested modules are located in the whole binary
+        let mut builder = ComponentBuilder::new();
+        builder.module(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x7f]);
+        let err = load(&builder.build()).err().unwrap();
+        assert_eq!("Error at byte 0x11: Invalid section code 0x7f.", err.formatted());
+
+        let mut bytes = builder.build();
+        bytes.truncate(8);
+        bytes.extend_from_slice(&[0x07, 0x04, 0x01, 0x70, 0x7f, 0x00]);
+        let err = load(&bytes).err().unwrap();
+        assert_eq!("Section 0x07 does not end where its size says", err.msg());
+    }
+}
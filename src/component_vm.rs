@@ -0,0 +1,723 @@
+#![allow(dead_code)]
+
+//! Running components: instantiating their core modules, linking them to each other
+//! and to the host through the canonical ABI, and calling their exports.
+//!
+//! Every core instance is behind a mutex, since the functions linking them may be
+//! called from any of them, on any thread. Other threads wait for a running instance,
+//! but the thread running it can't enter it again, except by a function it imports
+//! calling back into the instance that called it, which goes through the caller's [`Vm`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::canonical::{
+    self, ComponentValue, Context, Guest, Handle, HandleTable, NoMemory, MAX_FLAT_PARAMS,
+    MAX_FLAT_RESULTS,
+};
+use crate::component::{
+    CanonOptions, Component, CoreExport, CoreFunc, CoreInstanceDef, CoreSort, Func, FuncType,
+    InstanceDef, ModuleDef, Resource, Sort, StringEncoding, ValueType,
+};
+use crate::shared_memory::SharedMemory;
+use crate::value::{ExternalKind, Value};
+use crate::vm::{HostFunc, Imports, Trap, Vm};
+use crate::wasm_module::WasmModule;
+
+/// A component function, which the host provides for an import or calls as an export.
+pub type ComponentFunc<'a> =
+    Arc<dyn Fn(&[ComponentValue]) -> Result<Vec<ComponentValue>, Trap> + Send + Sync + 'a>;
+
+/// What the host provides for a component's imports. Functions are found by their
+/// import name, or by `instance#function` for those of an imported instance.
+#[derive(Default)]
+pub struct ComponentImports<'a> {
+    pub functions: HashMap<String, ComponentFunc<'a>>,
+}
+
+pub struct ComponentInstance<'a> {
+    /// The exported functions, including those of exported instances as
+    /// `instance#function`.
+    exports: HashMap<String, ComponentFunc<'a>>,
+    handles: Arc<Mutex<HandleTable>>,
+}
+
+impl<'a> ComponentInstance<'a> {
+    pub fn new(component: &'a Component<'a>, imports: ComponentImports<'a>) -> Result<Self, Trap> {
+        let mut linker = Linker {
+            component,
+            imports,
+            core_instances: Vec::new(),
+            handles: Arc::default(),
+        };
+        if component.instances.iter().any(|i| matches!(i, InstanceDef::Instantiate { .. })) {
+            return Err(unsupported("Instantiating nested components"));
+        }
+        for def in &component.core_instances {
+            let instance = linker.core_instance(def)?;
+            linker.core_instances.push(instance);
+        }
+
+        let mut exports = HashMap::new();
+        for export in &component.exports {
+            match export.sort {
+                Sort::Func => {
+                    exports.insert(export.name.clone(), linker.func(export.index)?);
+                }
+                Sort::Instance => {
+                    let InstanceDef::Exports(items) = linker.instance(export.index)? else {
+                        continue;
+                    };
+                    for (name, sort, idx) in items {
+                        if *sort == Sort::Func {
+                            exports.insert(format!("{}#{name}", export.name), linker.func(*idx)?);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { exports, handles: linker.handles })
+    }
+
+    /// Calls the exported function `name` with `args`.
+    pub fn call(&self, name: &str, args: &[ComponentValue]) -> Result<Vec<ComponentValue>, Trap> {
+        let func = self.exports.get(name)
+            .ok_or_else(|| Trap::InvalidArguments(format!("No exported function {name}")))?;
+        func(args)
+    }
+
+    /// The number of resource handles the instance holds.
+    pub fn handle_count(&self) -> usize {
+        self.handles.lock().unwrap().len()
+    }
+}
+
+/// An instantiated core module.
+struct ModuleInstance<'a> {
+    module: &'a WasmModule<'a>,
+    vm: Mutex<Vm<'a>>,
+}
+
+impl ModuleInstance<'_> {
+    /// Runs `f` on this instance: on `caller` if that is it, otherwise by locking it.
+    fn with_vm<R>(
+        &self,
+        caller: Option<&mut Vm<'_>>,
+        f: impl FnOnce(&mut Vm<'_>) -> Result<R, Trap>,
+    ) -> Result<R, Trap> {
+        match lock(&self.vm) {
+            Ok(mut vm) => f(&mut vm),
+            Err(running) => match caller {
+                Some(caller) if (caller as *const Vm).cast::<()>() as usize == running => {
+                    f(caller)
+                }
+                _ => Err(Trap::Host(String::from("Cannot reenter a running core instance"))),
+            },
+        }
+    }
+}
+
+thread_local! {
+    /// The mutexes this thread holds through [`lock`], each with the address of the value
+    /// it guards.
+    static LOCKED: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A guard of a mutex locked with [`lock`].
+struct Locked<'m, T> {
+    guard: MutexGuard<'m, T>,
+    mutex: usize,
+}
+
+impl<T> Deref for Locked<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for Locked<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for Locked<'_, T> {
+    fn drop(&mut self) {
+        LOCKED.with(|locked| {
+            let mut locked = locked.borrow_mut();
+            if let Some(idx) = locked.iter().rposition(|(mutex, _)| *mutex == self.mutex) {
+                locked.remove(idx);
+            }
+        });
+    }
+}
+
+/// Locks `mutex`, waiting for any other thread holding it. If this thread holds it,
+/// which waiting would never end, returns the address of the value it guards instead.
+fn lock<T>(mutex: &Mutex<T>) -> Result<Locked<'_, T>, usize> {
+    let key = mutex as *const Mutex<T> as usize;
+    let held = LOCKED.with(|locked| {
+        locked.borrow().iter().find(|(mutex, _)| *mutex == key).map(|(_, value)| *value)
+    });
+    if let Some(value) = held {
+        return Err(value);
+    }
+    let guard = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+    LOCKED.with(|locked| locked.borrow_mut().push((key, &*guard as *const T as usize)));
+    Ok(Locked { guard, mutex: key })
+}
+
+enum CoreInstance<'a> {
+    Module(Arc<ModuleInstance<'a>>),
+    Exports(Vec<(String, CoreItem<'a>)>),
+}
+
+#[derive(Clone)]
+enum CoreItem<'a> {
+    Func(HostFunc<'a>),
+    Memory(SharedMemory),
+    Global(Value),
+}
+
+/// The memory and functions a lifted or lowered function reaches its values through.
+#[derive(Clone)]
+struct GuestOptions<'a> {
+    encoding: StringEncoding,
+    /// The instance whose memory holds the values, and that memory's index in it.
+    memory: Option<(Arc<ModuleInstance<'a>>, usize)>,
+    /// Function indices in the memory's instance.
+    realloc: Option<usize>,
+    post_return: Option<usize>,
+}
+
+impl GuestOptions<'_> {
+    /// Runs `f` with the instance's memory as the guest, and its handles.
+    fn with_guest<R>(
+        &self,
+        caller: Option<&mut Vm<'_>>,
+        handles: &Mutex<HandleTable>,
+        f: impl FnOnce(&mut Context) -> Result<R, Trap>,
+    ) -> Result<R, Trap> {
+        let mut handles = lock_handles(handles)?;
+        let Some((instance, memory)) = &self.memory else {
+            return f(&mut Context::new(&mut NoMemory, self.encoding, &mut handles));
+        };
+        instance.with_vm(caller, |vm| {
+            let mut guest = VmGuest { vm, memory: *memory, realloc: self.realloc };
+            f(&mut Context::new(&mut guest, self.encoding, &mut handles))
+        })
+    }
+}
+
+/// The memory of a core instance, allocated in with its `realloc`.
+struct VmGuest<'v, 'a> {
+    vm: &'v mut Vm<'a>,
+    memory: usize,
+    realloc: Option<usize>,
+}
+
+impl Guest for VmGuest<'_, '_> {
+    fn read(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, Trap> {
+        self.vm.memory(self.memory).and_then(|memory| memory.read(addr as u64, len as u64))
+            .ok_or(Trap::MemoryOutOfBounds)
+    }
+
+    fn write(&mut self, addr: u32, bytes: &[u8]) -> Result<(), Trap> {
+        match self.vm.memory_mut(self.memory).is_some_and(|m| m.write(addr as u64, bytes)) {
+            true => Ok(()),
+            false => Err(Trap::MemoryOutOfBounds),
+        }
+    }
+
+    fn memory_len(&mut self) -> u64 {
+        self.vm.memory(self.memory).map_or(0, |memory| memory.len() as u64)
+    }
+
+    fn realloc(&mut self, old: u32, old_size: u32, align: u32, new_size: u32)
+        -> Result<u32, Trap>
+    {
+        let Some(realloc) = self.realloc else {
+            return Err(Trap::InvalidValue(String::from("Values in memory need the realloc option")));
+        };
+        let args = [old, old_size, align, new_size].map(|arg| Value::I32(arg as i32));
+        match self.vm.invoke(realloc, &args)?.as_slice() {
+            [Value::I32(ptr)] => Ok(*ptr as u32),
+            results => Err(Trap::InvalidValue(format!("realloc returned {results:?}"))),
+        }
+    }
+}
+
+/// Resolves a component's index spaces to running instances and functions.
+struct Linker<'a> {
+    component: &'a Component<'a>,
+    imports: ComponentImports<'a>,
+    core_instances: Vec<CoreInstance<'a>>,
+    handles: Arc<Mutex<HandleTable>>,
+}
+
+impl<'a> Linker<'a> {
+    fn core_instance(&self, def: &CoreInstanceDef) -> Result<CoreInstance<'a>, Trap> {
+        let (module_idx, args) = match def {
+            CoreInstanceDef::Instantiate { module, args } => (*module, args),
+            CoreInstanceDef::Exports(items) => {
+                let mut exports = Vec::new();
+                for (name, sort, idx) in items {
+                    let item = match sort {
+                        CoreSort::Func => CoreItem::Func(self.core_func(*idx)?),
+                        CoreSort::Memory => self.core_export(self.core_memories(*idx)?)?,
+                        CoreSort::Global => self.core_export(self.core_globals(*idx)?)?,
+                        sort => return Err(unsupported(&format!("Core {sort:?} exports"))),
+                    };
+                    exports.push((name.clone(), item));
+                }
+                return Ok(CoreInstance::Exports(exports));
+            }
+        };
+        let module = match self.component.modules.get(module_idx as usize) {
+            Some(ModuleDef::Defined(module)) => module,
+            Some(ModuleDef::Imported(_)) => return Err(unsupported("Importing core modules")),
+            None => return Err(unknown("core module", module_idx)),
+        };
+
+        let mut imports = Imports::default();
+        for import in &module.imports {
+            let (_, instance) = args.iter().find(|(name, _)| *name == import.module)
+                .ok_or_else(|| missing(&import.module))?;
+            let export = CoreExport { instance: *instance, name: import.name.clone() };
+            match (import.kind, self.core_export(&export)?) {
+                (ExternalKind::Func, CoreItem::Func(func)) => imports.functions.push(func),
+                (ExternalKind::Memory, CoreItem::Memory(memory)) => imports.memories.push(memory),
+                (ExternalKind::Global, CoreItem::Global(value)) => imports.globals.push(value),
+                (kind, _) => {
+                    let msg = format!("Import {}.{} is not a {kind:?}", import.module, import.name);
+                    return Err(Trap::InvalidArguments(msg));
+                }
+            }
+        }
+        // Defined memories are shared too, so they can be exported to other instances
+        for memory in &module.memories[imports.memories.len()..] {
+            let shared = SharedMemory::try_new(memory.limits).ok_or_else(|| {
+                let msg = format!("Memory of {} pages could not be allocated", memory.limits.min);
+                Trap::ResourceLimitExceeded(msg)
+            })?;
+            imports.memories.push(shared);
+        }
+        let mut vm = Vm::with_imports(module, imports)?;
+        if let Some(start) = module.start_function {
+            vm.invoke(start, &[])?;
+        }
+        Ok(CoreInstance::Module(Arc::new(ModuleInstance { module, vm: Mutex::new(vm) })))
+    }
+
+    /// The definition a core instance exports.
+    fn core_export(&self, export: &CoreExport) -> Result<CoreItem<'a>, Trap> {
+        let missing = || {
+            let msg = format!("Core instance {} has no export {}", export.instance, export.name);
+            Trap::InvalidArguments(msg)
+        };
+        match self.core_instances.get(export.instance as usize) {
+            Some(CoreInstance::Module(instance)) => {
+                let found = instance.module.exports.iter().find(|e| e.name == export.name)
+                    .ok_or_else(missing)?;
+                let vm = instance.vm.lock().unwrap();
+                Ok(match found.kind {
+                    ExternalKind::Func => {
+                        let (instance, func) = (instance.clone(), found.index);
+                        CoreItem::Func(Arc::new(move |caller: &mut Vm, args: &[Value]| {
+                            instance.with_vm(Some(caller), |vm| vm.invoke(func, args))
+                        }))
+                    }
+                    ExternalKind::Memory => {
+                        let memory = vm.memory(found.index).and_then(|memory| memory.shared());
+                        CoreItem::Memory(memory.cloned().ok_or_else(missing)?)
+                    }
+                    ExternalKind::Global => CoreItem::Global(vm.globals()[found.index]),
+                    kind => return Err(unsupported(&format!("Exporting a core {kind:?}"))),
+                })
+            }
+            Some(CoreInstance::Exports(items)) => {
+                let (_, item) = items.iter().find(|(name, _)| *name == export.name)
+                    .ok_or_else(missing)?;
+                Ok(item.clone())
+            }
+            None => Err(unknown("core instance", export.instance)),
+        }
+    }
+
+    fn core_func(&self, idx: u32) -> Result<HostFunc<'a>, Trap> {
+        let handles = self.handles.clone();
+        let func: HostFunc = match self.component.core_funcs.get(idx as usize) {
+            Some(CoreFunc::Alias(export)) => match self.core_export(export)? {
+                CoreItem::Func(func) => return Ok(func),
+                _ => return Err(Trap::InvalidArguments(format!("Core function {idx} is not one"))),
+            },
+            Some(CoreFunc::Lower { func, options }) => {
+                let callee = self.func(*func)?;
+                let ty = self.component.func_type(*func).unwrap().clone();
+                let options = self.options(options)?;
+                Arc::new(move |caller: &mut Vm, args: &[Value]| {
+                    lowered(caller, &callee, &ty, &options, &handles, args)
+                })
+            }
+            Some(CoreFunc::ResourceNew(resource)) => {
+                let resource = *resource;
+                Arc::new(move |_: &mut Vm, args: &[Value]| {
+                    let handle = Handle { resource, rep: i32_arg(args)?, own: true };
+                    Ok(vec![Value::I32(lock_handles(&handles)?.insert(handle) as i32)])
+                })
+            }
+            Some(CoreFunc::ResourceRep(resource)) => {
+                let resource = *resource;
+                Arc::new(move |_: &mut Vm, args: &[Value]| {
+                    let handle = lock_handles(&handles)?.get(i32_arg(args)?)?;
+                    check_resource(handle, resource)?;
+                    Ok(vec![Value::I32(handle.rep as i32)])
+                })
+            }
+            Some(CoreFunc::ResourceDrop(resource)) => {
+                let resource = *resource;
+                let dtor = match self.component.resources.get(resource) {
+                    Some(Resource::Defined { dtor: Some(dtor) }) => Some(self.core_func(*dtor)?),
+                    _ => None,
+                };
+                Arc::new(move |caller: &mut Vm, args: &[Value]| {
+                    let idx = i32_arg(args)?;
+                    let handle = {
+                        let mut handles = lock_handles(&handles)?;
+                        check_resource(handles.get(idx)?, resource)?;
+                        handles.remove(idx)?
+                    };
+                    if let (true, Some(dtor)) = (handle.own, &dtor) {
+                        dtor(caller, &[Value::I32(handle.rep as i32)])?;
+                    }
+                    Ok(Vec::new())
+                })
+            }
+            None => return Err(unknown("core function", idx)),
+        };
+        Ok(func)
+    }
+
+    fn func(&self, idx: u32) -> Result<ComponentFunc<'a>, Trap> {
+        match self.component.funcs.get(idx as usize) {
+            Some(Func::Import { import, .. }) => self.host_func(&self.component.imports[*import].name),
+            Some(Func::Alias { instance, name, .. }) => match self.instance(*instance)? {
+                InstanceDef::Import { import, .. } => {
+                    self.host_func(&format!("{}#{name}", self.component.imports[*import].name))
+                }
+                InstanceDef::Exports(items) => {
+                    match items.iter().find(|(item, ..)| item == name) {
+                        Some((_, Sort::Func, idx)) => self.func(*idx),
+                        _ => Err(missing(&format!("instance {instance} function {name}"))),
+                    }
+                }
+                _ => Err(unsupported("Instantiating nested components")),
+            },
+            Some(Func::Lift { core_func, options, ty }) => {
+                let Some(CoreFunc::Alias(export)) = self.component.core_funcs.get(*core_func as usize)
+                else {
+                    return Err(unsupported("Lifting functions not exported by a core module"));
+                };
+                let Some(CoreInstance::Module(instance)) =
+                    self.core_instances.get(export.instance as usize)
+                else {
+                    return Err(unsupported("Lifting functions not exported by a core module"));
+                };
+                let func = instance.module.exports.iter()
+                    .find(|e| e.name == export.name && e.kind == ExternalKind::Func)
+                    .ok_or_else(|| missing(&format!("core function {}", export.name)))?
+                    .index;
+                let (instance, ty, options) = (instance.clone(), ty.clone(), self.options(options)?);
+                let handles = self.handles.clone();
+                Ok(Arc::new(move |args: &[ComponentValue]| {
+                    lifted(&instance, func, &ty, &options, &handles, args)
+                }))
+            }
+            None => Err(unknown("function", idx)),
+        }
+    }
+
+    fn host_func(&self, name: &str) -> Result<ComponentFunc<'a>, Trap> {
+        self.imports.functions.get(name).cloned().ok_or_else(|| missing(name))
+    }
+
+    /// The instance at `idx`, looking through reexports.
+    fn instance(&self, mut idx: u32) -> Result<&'a InstanceDef, Trap> {
+        loop {
+            match self.component.instances.get(idx as usize) {
+                Some(InstanceDef::Reexport(original)) => idx = *original,
+                Some(instance) => return Ok(instance),
+                None => return Err(unknown("instance", idx)),
+            }
+        }
+    }
+
+    /// Resolves the memory and functions of `options`, which must all be exported by the
+    /// same core instance.
+    fn options(&self, options: &CanonOptions) -> Result<GuestOptions<'a>, Trap> {
+        let mut guest = GuestOptions {
+            encoding: options.encoding,
+            memory: None,
+            realloc: None,
+            post_return: None,
+        };
+        let Some(memory) = options.memory else {
+            return Ok(guest);
+        };
+        let export = self.core_memories(memory)?;
+        let Some(CoreInstance::Module(instance)) = self.core_instances.get(export.instance as usize)
+        else {
+            return Err(unsupported("Memories not exported by a core module"));
+        };
+        let index_of = |name: &str, kind| {
+            instance.module.exports.iter().find(|e| e.name == name && e.kind == kind)
+                .map(|e| e.index)
+                .ok_or_else(|| missing(&format!("core export {name}")))
+        };
+        let func_of = |idx: Option<u32>| -> Result<Option<usize>, Trap> {
+            let Some(idx) = idx else {
+                return Ok(None);
+            };
+            match self.component.core_funcs.get(idx as usize) {
+                Some(CoreFunc::Alias(func)) if func.instance == export.instance => {
+                    index_of(&func.name, ExternalKind::Func).map(Some)
+                }
+                _ => Err(unsupported("Options from different core instances")),
+            }
+        };
+        guest.realloc = func_of(options.realloc)?;
+        guest.post_return = func_of(options.post_return)?;
+        guest.memory = Some((instance.clone(), index_of(&export.name, ExternalKind::Memory)?));
+        Ok(guest)
+    }
+
+    fn core_memories(&self, idx: u32) -> Result<&'a CoreExport, Trap> {
+        self.component.core_memories.get(idx as usize).ok_or_else(|| unknown("core memory", idx))
+    }
+
+    fn core_globals(&self, idx: u32) -> Result<&'a CoreExport, Trap> {
+        self.component.core_globals.get(idx as usize).ok_or_else(|| unknown("core global", idx))
+    }
+}
+
+/// Calls the core function `func` of `instance` with `args` lowered, lifting its results.
+fn lifted(
+    instance: &ModuleInstance,
+    func: usize,
+    ty: &FuncType,
+    options: &GuestOptions,
+    handles: &Mutex<HandleTable>,
+    args: &[ComponentValue],
+) -> Result<Vec<ComponentValue>, Trap> {
+    let params: Vec<ValueType> = ty.params.iter().map(|(_, ty)| ty.clone()).collect();
+    instance.with_vm(None, |vm| {
+        let (flat, borrows) = options.with_guest(Some(&mut *vm), handles, |cx| {
+            let flat = cx.lower_values(args, &params, MAX_FLAT_PARAMS)?;
+            Ok((flat, std::mem::take(&mut cx.borrows)))
+        })?;
+        let results = vm.invoke(func, &flat);
+        // Borrows lent for the call end with it
+        let mut table = lock_handles(handles)?;
+        for idx in borrows {
+            table.remove(idx)?;
+        }
+        drop(table);
+        let results = results?;
+        let values = options.with_guest(Some(&mut *vm), handles, |cx| {
+            cx.lift_values(&results, &ty.results, MAX_FLAT_RESULTS)
+        })?;
+        if let Some(post_return) = options.post_return {
+            vm.invoke(post_return, &results)?;
+        }
+        Ok(values)
+    })
+}
+
+/// Calls `callee` for core code in `caller`, lifting `args` and lowering the results.
+fn lowered(
+    caller: &mut Vm,
+    callee: &ComponentFunc,
+    ty: &FuncType,
+    options: &GuestOptions,
+    handles: &Mutex<HandleTable>,
+    args: &[Value],
+) -> Result<Vec<Value>, Trap> {
+    let params: Vec<ValueType> = ty.params.iter().map(|(_, ty)| ty.clone()).collect();
+    // Results that don't fit in core values are stored where the last argument points
+    let (args, retptr) = match canonical::flatten_all(&ty.results).len() > MAX_FLAT_RESULTS {
+        true => (&args[..args.len() - 1], Some(i32_arg(&args[args.len() - 1..])?)),
+        false => (args, None),
+    };
+    let values = options.with_guest(Some(&mut *caller), handles, |cx| {
+        cx.lift_values(args, &params, MAX_FLAT_PARAMS)
+    })?;
+    let results = callee(&values)?;
+    options.with_guest(Some(caller), handles, |cx| match retptr {
+        Some(ptr) => cx.store_values(&results, &ty.results, ptr).map(|_| Vec::new()),
+        None => cx.lower_values(&results, &ty.results, MAX_FLAT_RESULTS),
+    })
+}
+
+fn lock_handles(handles: &Mutex<HandleTable>) -> Result<Locked<'_, HandleTable>, Trap> {
+    lock(handles).map_err(|_| Trap::Host(String::from("The handle table is in use")))
+}
+
+fn check_resource(handle: Handle, resource: usize) -> Result<(), Trap> {
+    match handle.resource == resource {
+        true => Ok(()),
+        false => Err(Trap::InvalidValue(String::from("Handle of the wrong resource type"))),
+    }
+}
+
+fn i32_arg(args: &[Value]) -> Result<u32, Trap> {
+    match args {
+        [Value::I32(arg)] => Ok(*arg as u32),
+        _ => Err(Trap::InvalidArguments(format!("Expected an i32, got {args:?}"))),
+    }
+}
+
+fn missing(name: &str) -> Trap {
+    Trap::InvalidArguments(format!("Import {name} not provided"))
+}
+
+fn unknown(what: &str, idx: u32) -> Trap {
+    Trap::InvalidArguments(format!("Unknown {what} {idx}"))
+}
+
+fn unsupported(what: &str) -> Trap {
+    Trap::InvalidArguments(format!("{what} is unsupported"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, name, ComponentBuilder, ModuleBuilder};
+    use crate::{component, wasm_module};
+    use crate::value::ValType::I32;
+    use ComponentValue as V;
+
+    #[test]
+    fn strings_through_host() {
+        let bytes = test_util::greeter();
+        let component = component::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+
+        let mut imports = ComponentImports::default();
+        let greet: ComponentFunc = Arc::new(|args| match args {
+            [V::String(name)] => Ok(vec![V::String(format!("Hello, {name}!"))]),
+            _ => Err(Trap::Host(String::from("Expected a string"))),
+        });
+        imports.functions.insert(String::from("greet"), greet);
+        let instance = ComponentInstance::new(&component, imports).unwrap();
+        for who in ["wasm", "wörld", ""] {
+            let results = instance.call("run", &[V::String(who.to_string())]);
+            assert_eq!(Ok(vec![V::String(format!("Hello, {who}!"))]), results);
+        }
+        assert!(instance.call("run", &[V::U32(1)]).is_err());
+        assert!(instance.call("walk", &[]).is_err());
+
+        let missing = ComponentInstance::new(&component, ComponentImports::default());
+        assert_eq!(Some(Trap::InvalidArguments(String::from("Import greet not provided"))), missing.err());
+    }
+
+    #[test]
+    fn calls_from_several_threads() {
+        let bytes = test_util::greeter();
+        let component = component::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        let mut imports = ComponentImports::default();
+        let greet: ComponentFunc = Arc::new(|args| {
+            // Keeps the instance running while the other threads call it
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            match args {
+                [V::String(name)] => Ok(vec![V::String(format!("Hello, {name}!"))]),
+                _ => Err(Trap::Host(String::from("Expected a string"))),
+            }
+        });
+        imports.functions.insert(String::from("greet"), greet);
+        let instance = ComponentInstance::new(&component, imports).unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let instance = &instance;
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        let results = instance.call("run", &[V::String(thread.to_string())]);
+                        assert_eq!(Ok(vec![V::String(format!("Hello, {thread}!"))]), results);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn reentry_goes_to_the_same_instance() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[0x0b]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+        let instance = || {
+            ModuleInstance { module: &module, vm: Mutex::new(Vm::new(&module).unwrap()) }
+        };
+        let (a, b) = (instance(), instance());
+
+        // Only the instance's own Vm may enter it while it runs
+        let result = a.with_vm(None, |vm_a| {
+            let own = (&*vm_a as *const Vm).cast::<()>();
+            let reentered =
+                a.with_vm(Some(&mut *vm_a), |vm| Ok(std::ptr::eq(own, (vm as *const Vm).cast())));
+            assert_eq!(Ok(true), reentered);
+            b.with_vm(Some(vm_a), |vm_b| a.with_vm(Some(vm_b), |_| Ok(())))
+        });
+        let reentry = Trap::Host(String::from("Cannot reenter a running core instance"));
+        assert_eq!(Err(reentry), result);
+        assert!(LOCKED.with(|locked| locked.borrow().is_empty()));
+    }
+
+    #[test]
+    fn resources() {
+        // make() returns a new handle to 42; rep(self) returns its handle's representation
+        let mut builder = ModuleBuilder::new();
+        let new = builder.import_func("res", "new", &[I32], &[I32]);
+        let rep = builder.import_func("res", "rep", &[I32], &[I32]);
+        let make = builder.func(&[], &[I32], &[], &[0x41, 0x2a, 0x10, new as u8, 0x0b]);
+        let get = builder.func(&[I32], &[I32], &[], &[0x20, 0x00, 0x10, rep as u8, 0x0b]);
+        builder.export("make", ExternalKind::Func, make);
+        builder.export("rep", ExternalKind::Func, get);
+
+        let mut component = ComponentBuilder::new();
+        component.module(&builder.build());
+        component.items(0x07, &[
+            &[0x3f, 0x7f, 0x00],
+            &[0x69, 0x00],
+            &[0x68, 0x00],
+            &[0x40, 0x00, 0x00, 0x01],
+            &[&[0x40, 0x01][..], &name("self"), &[0x02, 0x00, 0x79]].concat(),
+        ]);
+        component.items(0x08, &[&[0x02, 0x00], &[0x04, 0x00]]);
+        component.items(0x02, &[
+            &[&[0x01, 0x02][..], &name("new"), &[0x00, 0x00], &name("rep"), &[0x00, 0x01]].concat(),
+            &[&[0x00, 0x00, 0x01][..], &name("res"), &[0x12, 0x00]].concat(),
+        ]);
+        component.items(0x06, &[
+            &[&[0x00, 0x00, 0x01, 0x01][..], &name("make")].concat(),
+            &[&[0x00, 0x00, 0x01, 0x01][..], &name("rep")].concat(),
+        ]);
+        component.items(0x08, &[&[0x00, 0x00, 0x02, 0x00, 0x03], &[0x00, 0x00, 0x03, 0x00, 0x04]]);
+        component.items(0x0b, &[
+            &[&[0x00][..], &name("make"), &[0x01, 0x00, 0x00]].concat(),
+            &[&[0x00][..], &name("rep"), &[0x01, 0x01, 0x00]].concat(),
+        ]);
+        let bytes = component.build();
+        let component = component::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        assert_eq!(vec![Resource::Defined { dtor: None }], component.resources);
+
+        let instance = ComponentInstance::new(&component, ComponentImports::default()).unwrap();
+        assert_eq!(Ok(vec![V::Own(42)]), instance.call("make", &[]));
+        assert_eq!(Ok(vec![V::U32(7)]), instance.call("rep", &[V::Borrow(7)]));
+        // Neither the returned handle nor the lent one stays in the table
+        assert_eq!(0, instance.handle_count());
+    }
+}
@@ -0,0 +1,101 @@
+#![allow(dead_code)]
+
+use crate::gc;
+use crate::value::{AnyRef, Value};
+
+/// An instruction allowed in a constant expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConstOp {
+    /// A `*.const`, `ref.null` or `ref.func`.
+    Value(Value),
+    GlobalGet(u32),
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    /// `ref.i31`, whose operand is an i32.
+    RefI31,
+}
+
+/// The initializer of a global, or the offset or an element of a segment, computed
+/// during instantiation. Expressions are validated when the module is loaded, so they
+/// always produce a single value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstExpr(pub Vec<ConstOp>);
+
+impl ConstExpr {
+    /// An expression that is just `value`.
+    pub fn value(value: Value) -> Self {
+        Self(vec![ConstOp::Value(value)])
+    }
+
+    /// The expression's result, reading globals from `globals`.
+    pub fn evaluate(&self, globals: &[Value]) -> Value {
+        let mut stack = Vec::with_capacity(self.0.len());
+        for op in &self.0 {
+            let value = match *op {
+                ConstOp::Value(value) => value,
+                ConstOp::GlobalGet(idx) => globals[idx as usize],
+                ConstOp::RefI31 => match stack.pop() {
+                    Some(Value::I32(value)) => Value::AnyRef(AnyRef(gc::i31(value))),
+                    _ => unreachable!("Constant expressions are validated"),
+                },
+                op => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    binary(op, a, b)
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().unwrap()
+    }
+
+    /// The expression's result if it doesn't read any globals.
+    pub fn as_value(&self) -> Option<Value> {
+        match self.0[..] {
+            [ConstOp::Value(value)] => Some(value),
+            _ => None,
+        }
+    }
+}
+
+fn binary(op: ConstOp, a: Value, b: Value) -> Value {
+    match (op, a, b) {
+        (ConstOp::I32Add, Value::I32(a), Value::I32(b)) => Value::I32(a.wrapping_add(b)),
+        (ConstOp::I32Sub, Value::I32(a), Value::I32(b)) => Value::I32(a.wrapping_sub(b)),
+        (ConstOp::I32Mul, Value::I32(a), Value::I32(b)) => Value::I32(a.wrapping_mul(b)),
+        (ConstOp::I64Add, Value::I64(a), Value::I64(b)) => Value::I64(a.wrapping_add(b)),
+        (ConstOp::I64Sub, Value::I64(a), Value::I64(b)) => Value::I64(a.wrapping_sub(b)),
+        (ConstOp::I64Mul, Value::I64(a), Value::I64(b)) => Value::I64(a.wrapping_mul(b)),
+        _ => unreachable!("Constant expressions are validated"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_arithmetic_wraps() {
+        // (global.get 0 * 3) - 1
+        let expr = ConstExpr(vec![
+            ConstOp::GlobalGet(0),
+            ConstOp::Value(Value::I32(3)),
+            ConstOp::I32Mul,
+            ConstOp::Value(Value::I32(1)),
+            ConstOp::I32Sub,
+        ]);
+        assert_eq!(Value::I32(29), expr.evaluate(&[Value::I32(10)]));
+        assert_eq!(Value::I32(i32::MAX - 3), expr.evaluate(&[Value::I32(i32::MAX)]));
+
+        let expr = ConstExpr(vec![
+            ConstOp::Value(Value::I64(i64::MAX)),
+            ConstOp::Value(Value::I64(1)),
+            ConstOp::I64Add,
+        ]);
+        assert_eq!(Value::I64(i64::MIN), expr.evaluate(&[]));
+    }
+}
@@ -0,0 +1,234 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::disasm;
+use crate::dwarf::LineTable;
+use crate::hook::{Hook, HookAction, Location};
+use crate::vm::Vm;
+use crate::wasm_module::WasmModule;
+
+/// A [`Hook`] that counts how often each instruction executes.
+///
+/// Counts accumulate across invocations, so one `Coverage` can record a whole test
+/// suite. Install it on a [`Vm`] behind an `Arc<Mutex<_>>` to read the results.
+pub struct Coverage {
+    /// Execution counts per function, indexed by instruction offset.
+    counts: Vec<Vec<u64>>,
+    calls: Vec<u64>,
+}
+
+#[derive(Default)]
+struct SourceFile {
+    /// The line, name and call count of each function starting in the file.
+    functions: Vec<(u32, String, u64)>,
+    lines: BTreeMap<u32, u64>,
+}
+
+impl Coverage {
+    pub fn new(module: &WasmModule) -> Self {
+        let counts = (0..module.functions.len())
+            .map(|func| vec![0; module.function_code(func).len()])
+            .collect();
+        Self { counts, calls: vec![0; module.functions.len()] }
+    }
+
+    /// How many times the instruction at `location` executed.
+    pub fn count(&self, location: Location) -> u64 {
+        self.counts[location.func][location.offset]
+    }
+
+    pub fn calls(&self, func: usize) -> u64 {
+        self.calls[func]
+    }
+
+    /// The number of executed instructions in a function, and its total.
+    pub fn instructions_covered(&self, module: &WasmModule, func: usize) -> (usize, usize) {
+        let instructions = disasm::disassemble(module.function_code(func));
+        let covered = instructions.iter().filter(|(offset, _)| self.counts[func][*offset] > 0);
+        (covered.count(), instructions.len())
+    }
+
+    /// The coverage as an lcov tracefile.
+    ///
+    /// With a line table, records are per source file and line. Without one there is no
+    /// source to refer to, so the lines are those of [`Coverage::annotated`] and the
+    /// record is named `source`.
+    pub fn lcov(&self, module: &WasmModule, lines: Option<&LineTable>, source: &str) -> String {
+        let mut files: BTreeMap<String, SourceFile> = BTreeMap::new();
+        match lines {
+            Some(lines) => {
+                for func in 0..self.counts.len() {
+                    let code_start = module.functions[func].code_start as u64;
+                    let instructions = disasm::disassemble(module.function_code(func));
+                    for (idx, (offset, _)) in instructions.iter().enumerate() {
+                        let Some(line) = lines.lookup(code_start + *offset as u64) else {
+                            continue;
+                        };
+                        let file = files.entry(line.file.to_string()).or_default();
+                        if idx == 0 {
+                            let name = module.function_label(func);
+                            file.functions.push((line.line, name, self.calls[func]));
+                        }
+                        let hits = file.lines.entry(line.line).or_default();
+                        *hits = (*hits).max(self.counts[func][*offset]);
+                    }
+                }
+            }
+            None => {
+                let file = files.entry(source.to_string()).or_default();
+                let mut line = 0;
+                for func in 0..self.counts.len() {
+                    if module.functions[func].imported {
+                        continue;
+                    }
+                    line += 1;
+                    file.functions.push((line + 1, module.function_label(func), self.calls[func]));
+                    for (offset, _) in disasm::disassemble(module.function_code(func)) {
+                        line += 1;
+                        file.lines.insert(line, self.counts[func][offset]);
+                    }
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for (name, file) in files {
+            writeln!(out, "SF:{name}").unwrap();
+            for (line, function, _) in &file.functions {
+                writeln!(out, "FN:{line},{function}").unwrap();
+            }
+            for (_, function, calls) in &file.functions {
+                writeln!(out, "FNDA:{calls},{function}").unwrap();
+            }
+            let functions_hit = file.functions.iter().filter(|(_, _, calls)| *calls > 0).count();
+            writeln!(out, "FNF:{}\nFNH:{functions_hit}", file.functions.len()).unwrap();
+            for (line, hits) in &file.lines {
+                writeln!(out, "DA:{line},{hits}").unwrap();
+            }
+            let lines_hit = file.lines.values().filter(|hits| **hits > 0).count();
+            writeln!(out, "LF:{}\nLH:{lines_hit}\nend_of_record", file.lines.len()).unwrap();
+        }
+        out
+    }
+
+    /// The disassembly of every function with each instruction's execution count.
+    /// Instructions that never ran are marked `#####`, and with a line table each
+    /// change of source line is noted.
+    pub fn annotated(&self, module: &WasmModule, lines: Option<&LineTable>) -> String {
+        let mut out = String::new();
+        for func in 0..self.counts.len() {
+            if module.functions[func].imported {
+                continue;
+            }
+            let (covered, total) = self.instructions_covered(module, func);
+            let name = module.function_label(func);
+            writeln!(out, "{name}: {covered}/{total} instructions executed").unwrap();
+
+            let code_start = module.functions[func].code_start as u64;
+            let mut last_line = None;
+            for (offset, text) in disasm::disassemble(module.function_code(func)) {
+                let count = match self.counts[func][offset] {
+                    0 => String::from("#####"),
+                    count => count.to_string(),
+                };
+                let line = lines.and_then(|lines| lines.lookup(code_start + offset as u64));
+                let instruction = format!("{count:>10}  {offset:6}: {text}");
+                match line {
+                    Some(line) if Some(line) != last_line => {
+                        writeln!(out, "{instruction:<52}  ; {}:{}", line.file, line.line).unwrap();
+                    }
+                    _ => writeln!(out, "{instruction}").unwrap(),
+                }
+                last_line = line;
+            }
+        }
+        out
+    }
+}
+
+impl Hook for Coverage {
+    fn instruction(&mut self, _vm: &Vm, location: Location) -> HookAction {
+        self.counts[location.func][location.offset] += 1;
+        HookAction::Continue
+    }
+
+    fn enter(&mut self, _vm: &Vm, func: usize) {
+        self.calls[func] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::bytecode::op::*;
+    use crate::dwarf;
+    use crate::test_util::ModuleBuilder;
+    use crate::value::{ValType::*, Value};
+    use crate::wasm_module;
+
+    fn abs_module(debug_line: Option<Vec<u8>>) -> WasmModule<'static> {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, I32_CONST, 0, I32_LT_S,
+            IF, 0x7f,
+                I32_CONST, 0, LOCAL_GET, 0, I32_SUB,
+            ELSE,
+                LOCAL_GET, 0,
+            END,
+            END,
+        ]);
+        if let Some(debug_line) = debug_line {
+            builder.custom(".debug_line", &debug_line);
+        }
+        wasm_module::load_bytes(builder.build().into()).ok().unwrap()
+    }
+
+    fn cover(module: &WasmModule, args: &[Value]) -> Coverage {
+        let coverage = Arc::new(Mutex::new(Coverage::new(module)));
+        let mut vm = Vm::new(module).unwrap();
+        vm.set_hook(Some(Box::new(coverage.clone())));
+        for arg in args {
+            vm.invoke(0, &[*arg]).unwrap();
+        }
+        drop(vm);
+        Arc::into_inner(coverage).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn annotates_unexecuted_instructions() {
+        let module = abs_module(None);
+        let coverage = cover(&module, &[Value::I32(5), Value::I32(7)]);
+        assert_eq!(2, coverage.calls(0));
+        assert_eq!((7, 11), coverage.instructions_covered(&module, 0));
+
+        let annotated = coverage.annotated(&module, None);
+        let lines: Vec<_> = annotated.lines().collect();
+        assert_eq!("func[0]: 7/11 instructions executed", lines[0]);
+        assert_eq!("     #####       7: i32.const 0", lines[5]);
+        assert_eq!("         2      13: local.get 0", lines[9]);
+
+        let lcov = coverage.lcov(&module, None, "abs.wasm");
+        assert!(lcov.starts_with("SF:abs.wasm\nFN:2,func[0]\nFNDA:2,func[0]\nFNF:1\nFNH:1\n"));
+        assert!(lcov.contains("\nDA:6,0\n"));
+        assert!(lcov.ends_with("\nLF:11\nLH:7\nend_of_record\n"));
+    }
+
+    #[test]
+    fn maps_to_source_lines() {
+        // The body starts 3 bytes into the code section: the function count, body size
+        // and local declaration count come first
+        let debug_line = dwarf::tests::line_program(&[(3, 4), (10, 5), (16, 7), (19, 8)]);
+        let module = abs_module(Some(debug_line));
+        let lines = LineTable::from_module(&module).unwrap().unwrap();
+        let coverage = cover(&module, &[Value::I32(5)]);
+
+        let lcov = coverage.lcov(&module, Some(&lines), "abs.wasm");
+        assert_eq!("SF:src/lib.c\nFN:4,func[0]\nFNDA:1,func[0]\nFNF:1\nFNH:1\n\
+            DA:4,1\nDA:5,0\nDA:7,1\nDA:8,1\nLF:4\nLH:3\nend_of_record\n", lcov);
+        assert!(coverage.annotated(&module, Some(&lines)).contains("  ; src/lib.c:5\n"));
+    }
+}
@@ -0,0 +1,447 @@
+#![allow(dead_code)]
+
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::disasm;
+use crate::hook::{Hook, HookAction, Location};
+use crate::value::{HeapType, ValType, Value};
+use crate::vm::{Trap, Vm};
+use crate::wasm_module::WasmModule;
+
+/// How far execution continues before pausing again.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Continue,
+    Into,
+    /// Pause at the next instruction at or above this call depth.
+    Over(usize),
+    /// Pause at the next instruction above this call depth.
+    Out(usize),
+}
+
+struct DebugHook {
+    breakpoints: Vec<Location>,
+    step: Step,
+}
+
+impl Hook for DebugHook {
+    fn instruction(&mut self, vm: &Vm, location: Location) -> HookAction {
+        let depth = vm.call_depth();
+        let stop = match self.step {
+            Step::Continue => false,
+            Step::Into => true,
+            Step::Over(d) => depth <= d,
+            Step::Out(d) => depth < d,
+        };
+        if stop || self.breakpoints.contains(&location) {
+            HookAction::Pause
+        } else {
+            HookAction::Continue
+        }
+    }
+}
+
+/// Why the debugger returned control.
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    Paused(Location),
+    Finished(Vec<Value>),
+}
+
+/// Runs a module under a per-instruction hook that pauses at breakpoints and steps.
+/// The VM runs in checked mode, so that operand values can be inspected.
+pub struct Debugger<'a> {
+    vm: Vm<'a>,
+    hook: Arc<Mutex<DebugHook>>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(module: &'a WasmModule<'a>) -> Result<Self, Trap> {
+        let mut vm = Vm::new(module)?;
+        vm.set_checked(true);
+        let hook = Arc::new(Mutex::new(DebugHook {
+            breakpoints: Vec::new(),
+            step: Step::Continue,
+        }));
+        vm.set_hook(Some(Box::new(hook.clone())));
+        Ok(Self { vm, hook })
+    }
+
+    pub fn vm(&self) -> &Vm<'a> {
+        &self.vm
+    }
+
+    pub fn add_breakpoint(&mut self, location: Location) {
+        let mut hook = self.hook.lock().unwrap();
+        if !hook.breakpoints.contains(&location) {
+            hook.breakpoints.push(location);
+        }
+    }
+
+    /// Removes a breakpoint, returning whether it existed.
+    pub fn remove_breakpoint(&mut self, location: Location) -> bool {
+        let mut hook = self.hook.lock().unwrap();
+        let len = hook.breakpoints.len();
+        hook.breakpoints.retain(|b| *b != location);
+        hook.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> Vec<Location> {
+        self.hook.lock().unwrap().breakpoints.clone()
+    }
+
+    /// Invokes a function, running until a breakpoint or the end.
+    pub fn run(&mut self, func_idx: usize, args: &[Value]) -> Result<Stop, Trap> {
+        self.set_step(Step::Continue);
+        let result = self.vm.invoke(func_idx, args);
+        self.stop(result)
+    }
+
+    /// Invokes a function, pausing before its first instruction.
+    pub fn start(&mut self, func_idx: usize, args: &[Value]) -> Result<Stop, Trap> {
+        self.set_step(Step::Into);
+        let result = self.vm.invoke(func_idx, args);
+        self.stop(result)
+    }
+
+    pub fn continue_(&mut self) -> Result<Stop, Trap> {
+        self.resume(Step::Continue)
+    }
+
+    /// Executes one instruction, following calls into the callee.
+    pub fn step_into(&mut self) -> Result<Stop, Trap> {
+        self.resume(Step::Into)
+    }
+
+    /// Executes one instruction, running any call it makes to completion.
+    pub fn step_over(&mut self) -> Result<Stop, Trap> {
+        let depth = self.vm.call_depth();
+        self.resume(Step::Over(depth))
+    }
+
+    /// Runs until the current function returns.
+    pub fn step_out(&mut self) -> Result<Stop, Trap> {
+        let depth = self.vm.call_depth();
+        self.resume(Step::Out(depth))
+    }
+
+    /// Where execution is paused, if it is.
+    pub fn location(&self) -> Option<Location> {
+        if !self.vm.is_suspended() {
+            return None;
+        }
+        self.vm.call_stack().last().copied()
+    }
+
+    /// The instructions of the paused function within `context` instructions of the
+    /// current one, with their offsets.
+    pub fn disassemble_around(&self, context: usize) -> Vec<(usize, String)> {
+        let Some(location) = self.location() else {
+            return Vec::new();
+        };
+        let code = self.vm.module().function_code(location.func);
+        let instructions = disasm::disassemble(code);
+        let current = instructions.iter().position(|(offset, _)| *offset == location.offset);
+        let current = current.unwrap_or(0);
+        let start = current.saturating_sub(context);
+        let end = (current + context + 1).min(instructions.len());
+        instructions[start..end].to_vec()
+    }
+
+    /// `len` bytes of a memory starting at `addr`, if they are in bounds.
+    pub fn read_memory(&self, memory: usize, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let data = self.vm.memory(memory)?.data();
+        Some(data.get(addr..addr.checked_add(len)?)?.to_vec())
+    }
+
+    fn resume(&mut self, step: Step) -> Result<Stop, Trap> {
+        self.set_step(step);
+        let result = self.vm.resume();
+        self.stop(result)
+    }
+
+    fn set_step(&mut self, step: Step) {
+        self.hook.lock().unwrap().step = step;
+    }
+
+    fn stop(&self, result: Result<Vec<Value>, Trap>) -> Result<Stop, Trap> {
+        match result {
+            Ok(values) => Ok(Stop::Finished(values)),
+            Err(Trap::Paused) => Ok(Stop::Paused(self.location().unwrap())),
+            Err(trap) => Err(trap),
+        }
+    }
+}
+
+const HELP: &str = "\
+Commands:
+  run <func> [args...]      invoke a function, stopping at breakpoints
+  start <func> [args...]    invoke a function, stopping at its first instruction
+  break <func>[@offset]     set a breakpoint by function name or index
+  delete <func>[@offset]    remove a breakpoint
+  breakpoints               list breakpoints
+  continue | c              continue to the next breakpoint
+  step | s                  step one instruction, into calls
+  next | n                  step one instruction, over calls
+  finish                    run until the current function returns
+  backtrace | bt            show the call stack
+  stack                     show the operand stack
+  locals [depth]            show the locals of a frame
+  globals                   show the globals
+  memory <addr> [len]       show bytes of memory 0
+  disas [context]           disassemble around the current instruction
+  quit | q                  exit";
+
+/// Runs the interactive debugger for `wavm debug`, reading commands from stdin.
+pub fn repl(module: &WasmModule) {
+    let mut debugger = match Debugger::new(module) {
+        Ok(debugger) => debugger,
+        Err(trap) => return eprintln!("Instantiation failed: {trap:?}"),
+    };
+
+    println!("Type 'help' for a list of commands.");
+    let stdin = io::stdin();
+    loop {
+        print!("(wavm) ");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = words.split_first() else {
+            continue;
+        };
+        match command_line(&mut debugger, command, args) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(msg) => println!("{msg}"),
+        }
+    }
+}
+
+/// Executes one REPL command, returning `false` to quit.
+fn command_line(debugger: &mut Debugger, command: &str, args: &[&str]) -> Result<bool, String> {
+    let module = debugger.vm().module();
+    let stop = match command {
+        "help" | "h" => {
+            println!("{HELP}");
+            return Ok(true);
+        }
+        "quit" | "q" => return Ok(false),
+        "run" | "r" | "start" => {
+            let (func_idx, values) = invocation(module, args)?;
+            if command == "start" {
+                debugger.start(func_idx, &values)
+            } else {
+                debugger.run(func_idx, &values)
+            }
+        }
+        "break" | "b" => {
+            let location = location(module, args)?;
+            debugger.add_breakpoint(location);
+            println!("Breakpoint at {}", describe(module, location));
+            return Ok(true);
+        }
+        "delete" | "d" => {
+            let location = location(module, args)?;
+            if !debugger.remove_breakpoint(location) {
+                println!("No breakpoint at {}", describe(module, location));
+            }
+            return Ok(true);
+        }
+        "breakpoints" => {
+            for location in debugger.breakpoints() {
+                println!("  {}", describe(module, location));
+            }
+            return Ok(true);
+        }
+        "continue" | "c" | "step" | "s" | "next" | "n" | "finish" => {
+            if debugger.location().is_none() {
+                return Err(String::from("Not running"));
+            }
+            match command {
+                "continue" | "c" => debugger.continue_(),
+                "step" | "s" => debugger.step_into(),
+                "next" | "n" => debugger.step_over(),
+                _ => debugger.step_out(),
+            }
+        }
+        "backtrace" | "bt" => {
+            for (depth, location) in debugger.vm().call_stack().iter().rev().enumerate() {
+                println!("  #{depth} {}", describe(module, *location));
+            }
+            return Ok(true);
+        }
+        "stack" => {
+            for value in debugger.vm().operands().unwrap_or_default() {
+                println!("  {value:?}");
+            }
+            return Ok(true);
+        }
+        "locals" => {
+            let depth = args.first().map_or(Ok(0), |d| d.parse().map_err(|_| "Invalid depth"))?;
+            let locals = debugger.vm().locals(depth).ok_or("No such frame")?;
+            for (idx, value) in locals.iter().enumerate() {
+                println!("  {idx}: {value:?}");
+            }
+            return Ok(true);
+        }
+        "globals" => {
+            for (idx, value) in debugger.vm().globals().iter().enumerate() {
+                println!("  {idx}: {value:?}");
+            }
+            return Ok(true);
+        }
+        "memory" | "x" => {
+            let addr = parse_number(args.first().ok_or("Expected an address")?)?;
+            let len = args.get(1).map_or(Ok(64), |l| parse_number(l))?;
+            let bytes = debugger.read_memory(0, addr, len).ok_or("No such memory range")?;
+            for (row, chunk) in bytes.chunks(16).enumerate() {
+                let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+                println!("  {:#010x}: {}", addr + row * 16, hex.join(" "));
+            }
+            return Ok(true);
+        }
+        "disas" => {
+            let context = args.first().map_or(Ok(5), |c| parse_number(c))?;
+            print_disassembly(debugger, context);
+            return Ok(true);
+        }
+        other => return Err(format!("Unknown command '{other}'. Type 'help' for a list.")),
+    };
+
+    match stop {
+        Ok(Stop::Paused(location)) => {
+            println!("Paused at {}", describe(module, location));
+            print_disassembly(debugger, 0);
+        }
+        Ok(Stop::Finished(values)) => println!("Finished: {values:?}"),
+        Err(trap) => println!("Trap: {trap:?}"),
+    }
+    Ok(true)
+}
+
+fn print_disassembly(debugger: &Debugger, context: usize) {
+    let current = debugger.location().map(|l| l.offset);
+    for (offset, text) in debugger.disassemble_around(context) {
+        let marker = if Some(offset) == current { "=>" } else { "  " };
+        println!("{marker} {offset:6}: {text}");
+    }
+}
+
+fn describe(module: &WasmModule, location: Location) -> String {
+    match module.function_name(location.func) {
+        Some(name) => format!("{name} (func {}) @ {}", location.func, location.offset),
+        None => format!("func {} @ {}", location.func, location.offset),
+    }
+}
+
+fn function(module: &WasmModule, name: &str) -> Result<usize, String> {
+    let idx = match name.parse::<usize>() {
+        Ok(idx) => idx,
+        Err(_) => module.function_by_name(name).ok_or(format!("Unknown function '{name}'"))?,
+    };
+    if idx >= module.functions.len() {
+        return Err(format!("Unknown function {idx}"));
+    }
+    Ok(idx)
+}
+
+/// Parses `<func>[@offset]`.
+fn location(module: &WasmModule, args: &[&str]) -> Result<Location, String> {
+    let arg = args.first().ok_or("Expected a function")?;
+    let (name, offset) = match arg.split_once('@') {
+        Some((name, offset)) => (name, parse_number(offset)?),
+        None => (*arg, 0),
+    };
+    Ok(Location { func: function(module, name)?, offset })
+}
+
+/// Parses `<func> [args...]` into a function index and arguments of its parameter types.
+pub fn invocation(module: &WasmModule, args: &[&str]) -> Result<(usize, Vec<Value>), String> {
+    let (name, args) = args.split_first().ok_or("Expected a function")?;
+    let func_idx = function(module, name)?;
+    let params = &module.func_type(module.functions[func_idx].functype).params;
+    if params.len() != args.len() {
+        return Err(format!("Expected {} arguments", params.len()));
+    }
+    let values = params.iter().zip(args).map(|(ty, arg)| parse_value(module, *ty, arg));
+    Ok((func_idx, values.collect::<Result<_, _>>()?))
+}
+
+fn parse_value(module: &WasmModule, ty: ValType, arg: &str) -> Result<Value, String> {
+    let invalid = || format!("Invalid {ty:?} '{arg}'");
+    Ok(match ty {
+        ValType::I32 => Value::I32(arg.parse().map_err(|_| invalid())?),
+        ValType::I64 => Value::I64(arg.parse().map_err(|_| invalid())?),
+        ValType::F32 => Value::F32(arg.parse().map_err(|_| invalid())?),
+        ValType::F64 => Value::F64(arg.parse().map_err(|_| invalid())?),
+        // Vectors are given as a single 128-bit integer, in hex with a 0x prefix
+        ValType::V128 => Value::V128(match arg.strip_prefix("0x") {
+            Some(hex) => u128::from_str_radix(hex, 16).map_err(|_| invalid())? as i128,
+            None => arg.parse().map_err(|_| invalid())?,
+        }),
+        ValType::Ref(ty) if arg == "null" && ty.nullable => Value::RefNull(ty.heap),
+        ValType::Ref(ty) if module.top(ty.heap) == HeapType::Func => {
+            Value::FuncRef(arg.parse().map_err(|_| invalid())?)
+        }
+        // Host objects and exceptions can't be named on the command line
+        ValType::Ref(_) => return Err(invalid()),
+    })
+}
+
+fn parse_number(arg: &str) -> Result<usize, String> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid number '{arg}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::op::*;
+    use crate::test_util::ModuleBuilder;
+    use crate::value::ValType::*;
+    use crate::wasm_module;
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut builder = ModuleBuilder::new();
+        // double(x) = x + x
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, LOCAL_GET, 0, I32_ADD, END]);
+        // main(x) = double(x) + 1
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, CALL, 0, I32_CONST, 1, I32_ADD, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let mut debugger = Debugger::new(&module).unwrap();
+        debugger.add_breakpoint(Location { func: 0, offset: 4 });
+        let stop = debugger.run(1, &[Value::I32(5)]).unwrap();
+        assert_eq!(Stop::Paused(Location { func: 0, offset: 4 }), stop);
+        assert_eq!(Some(vec![Value::I32(5), Value::I32(5)]), debugger.vm().operands());
+        assert_eq!(Some(vec![Value::I32(5)]), debugger.vm().locals(1));
+        assert_eq!(2, debugger.vm().call_stack().len());
+
+        // Stepping out lands after the call in the caller
+        let stop = debugger.step_out().unwrap();
+        assert_eq!(Stop::Paused(Location { func: 1, offset: 4 }), stop);
+        assert_eq!(Some(vec![Value::I32(10)]), debugger.vm().operands());
+
+        assert!(debugger.remove_breakpoint(Location { func: 0, offset: 4 }));
+        assert_eq!(Stop::Finished(vec![Value::I32(11)]), debugger.continue_().unwrap());
+
+        // Stepping over the call never pauses inside the callee
+        let stop = debugger.start(1, &[Value::I32(1)]).unwrap();
+        assert_eq!(Stop::Paused(Location { func: 1, offset: 0 }), stop);
+        assert_eq!(Stop::Paused(Location { func: 1, offset: 2 }), debugger.step_over().unwrap());
+        assert_eq!(Stop::Paused(Location { func: 1, offset: 4 }), debugger.step_over().unwrap());
+        let stop = debugger.step_into().unwrap();
+        assert_eq!(Stop::Paused(Location { func: 1, offset: 6 }), stop);
+        let around: Vec<_> = debugger.disassemble_around(1).into_iter().map(|(_, t)| t).collect();
+        assert_eq!(vec!["i32.const 1", "i32.add", "end"], around);
+    }
+}
@@ -0,0 +1,1022 @@
+#![allow(dead_code)]
+
+use crate::bytecode::{self, atomic, catch, gc, misc, op::*, simd};
+use crate::v128::{self, Kind};
+use crate::value::{HeapType, RefType, ValType};
+
+/// The text format name of an instruction.
+pub fn mnemonic(op: u8) -> Option<&'static str> {
+    let name = match op {
+        UNREACHABLE => "unreachable",
+        NOP => "nop",
+        BLOCK => "block",
+        LOOP => "loop",
+        IF => "if",
+        ELSE => "else",
+        THROW => "throw",
+        THROW_REF => "throw_ref",
+        END => "end",
+        BR => "br",
+        BR_IF => "br_if",
+        BR_TABLE => "br_table",
+        RETURN => "return",
+        CALL => "call",
+        CALL_INDIRECT => "call_indirect",
+        RETURN_CALL => "return_call",
+        RETURN_CALL_INDIRECT => "return_call_indirect",
+        CALL_REF => "call_ref",
+        RETURN_CALL_REF => "return_call_ref",
+        DROP => "drop",
+        SELECT => "select",
+        SELECT_T => "select",
+        TRY_TABLE => "try_table",
+        LOCAL_GET => "local.get",
+        LOCAL_SET => "local.set",
+        LOCAL_TEE => "local.tee",
+        GLOBAL_GET => "global.get",
+        GLOBAL_SET => "global.set",
+        TABLE_GET => "table.get",
+        TABLE_SET => "table.set",
+        I32_LOAD => "i32.load",
+        I64_LOAD => "i64.load",
+        F32_LOAD => "f32.load",
+        F64_LOAD => "f64.load",
+        I32_LOAD8_S => "i32.load8_s",
+        I32_LOAD8_U => "i32.load8_u",
+        I32_LOAD16_S => "i32.load16_s",
+        I32_LOAD16_U => "i32.load16_u",
+        I64_LOAD8_S => "i64.load8_s",
+        I64_LOAD8_U => "i64.load8_u",
+        I64_LOAD16_S => "i64.load16_s",
+        I64_LOAD16_U => "i64.load16_u",
+        I64_LOAD32_S => "i64.load32_s",
+        I64_LOAD32_U => "i64.load32_u",
+        I32_STORE => "i32.store",
+        I64_STORE => "i64.store",
+        F32_STORE => "f32.store",
+        F64_STORE => "f64.store",
+        I32_STORE8 => "i32.store8",
+        I32_STORE16 => "i32.store16",
+        I64_STORE8 => "i64.store8",
+        I64_STORE16 => "i64.store16",
+        I64_STORE32 => "i64.store32",
+        MEMORY_SIZE => "memory.size",
+        MEMORY_GROW => "memory.grow",
+        I32_CONST => "i32.const",
+        I64_CONST => "i64.const",
+        F32_CONST => "f32.const",
+        F64_CONST => "f64.const",
+        I32_EQZ => "i32.eqz",
+        I32_EQ => "i32.eq",
+        I32_NE => "i32.ne",
+        I32_LT_S => "i32.lt_s",
+        I32_LT_U => "i32.lt_u",
+        I32_GT_S => "i32.gt_s",
+        I32_GT_U => "i32.gt_u",
+        I32_LE_S => "i32.le_s",
+        I32_LE_U => "i32.le_u",
+        I32_GE_S => "i32.ge_s",
+        I32_GE_U => "i32.ge_u",
+        I64_EQZ => "i64.eqz",
+        I64_EQ => "i64.eq",
+        I64_NE => "i64.ne",
+        I64_LT_S => "i64.lt_s",
+        I64_LT_U => "i64.lt_u",
+        I64_GT_S => "i64.gt_s",
+        I64_GT_U => "i64.gt_u",
+        I64_LE_S => "i64.le_s",
+        I64_LE_U => "i64.le_u",
+        I64_GE_S => "i64.ge_s",
+        I64_GE_U => "i64.ge_u",
+        F32_EQ => "f32.eq",
+        F32_NE => "f32.ne",
+        F32_LT => "f32.lt",
+        F32_GT => "f32.gt",
+        F32_LE => "f32.le",
+        F32_GE => "f32.ge",
+        F64_EQ => "f64.eq",
+        F64_NE => "f64.ne",
+        F64_LT => "f64.lt",
+        F64_GT => "f64.gt",
+        F64_LE => "f64.le",
+        F64_GE => "f64.ge",
+        I32_CLZ => "i32.clz",
+        I32_CTZ => "i32.ctz",
+        I32_POPCNT => "i32.popcnt",
+        I32_ADD => "i32.add",
+        I32_SUB => "i32.sub",
+        I32_MUL => "i32.mul",
+        I32_DIV_S => "i32.div_s",
+        I32_DIV_U => "i32.div_u",
+        I32_REM_S => "i32.rem_s",
+        I32_REM_U => "i32.rem_u",
+        I32_AND => "i32.and",
+        I32_OR => "i32.or",
+        I32_XOR => "i32.xor",
+        I32_SHL => "i32.shl",
+        I32_SHR_S => "i32.shr_s",
+        I32_SHR_U => "i32.shr_u",
+        I32_ROTL => "i32.rotl",
+        I32_ROTR => "i32.rotr",
+        I64_CLZ => "i64.clz",
+        I64_CTZ => "i64.ctz",
+        I64_POPCNT => "i64.popcnt",
+        I64_ADD => "i64.add",
+        I64_SUB => "i64.sub",
+        I64_MUL => "i64.mul",
+        I64_DIV_S => "i64.div_s",
+        I64_DIV_U => "i64.div_u",
+        I64_REM_S => "i64.rem_s",
+        I64_REM_U => "i64.rem_u",
+        I64_AND => "i64.and",
+        I64_OR => "i64.or",
+        I64_XOR => "i64.xor",
+        I64_SHL => "i64.shl",
+        I64_SHR_S => "i64.shr_s",
+        I64_SHR_U => "i64.shr_u",
+        I64_ROTL => "i64.rotl",
+        I64_ROTR => "i64.rotr",
+        F32_ABS => "f32.abs",
+        F32_NEG => "f32.neg",
+        F32_CEIL => "f32.ceil",
+        F32_FLOOR => "f32.floor",
+        F32_TRUNC => "f32.trunc",
+        F32_NEAREST => "f32.nearest",
+        F32_SQRT => "f32.sqrt",
+        F32_ADD => "f32.add",
+        F32_SUB => "f32.sub",
+        F32_MUL => "f32.mul",
+        F32_DIV => "f32.div",
+        F32_MIN => "f32.min",
+        F32_MAX => "f32.max",
+        F32_COPYSIGN => "f32.copysign",
+        F64_ABS => "f64.abs",
+        F64_NEG => "f64.neg",
+        F64_CEIL => "f64.ceil",
+        F64_FLOOR => "f64.floor",
+        F64_TRUNC => "f64.trunc",
+        F64_NEAREST => "f64.nearest",
+        F64_SQRT => "f64.sqrt",
+        F64_ADD => "f64.add",
+        F64_SUB => "f64.sub",
+        F64_MUL => "f64.mul",
+        F64_DIV => "f64.div",
+        F64_MIN => "f64.min",
+        F64_MAX => "f64.max",
+        F64_COPYSIGN => "f64.copysign",
+        I32_WRAP_I64 => "i32.wrap_i64",
+        I32_TRUNC_F32_S => "i32.trunc_f32_s",
+        I32_TRUNC_F32_U => "i32.trunc_f32_u",
+        I32_TRUNC_F64_S => "i32.trunc_f64_s",
+        I32_TRUNC_F64_U => "i32.trunc_f64_u",
+        I64_EXTEND_I32_S => "i64.extend_i32_s",
+        I64_EXTEND_I32_U => "i64.extend_i32_u",
+        I64_TRUNC_F32_S => "i64.trunc_f32_s",
+        I64_TRUNC_F32_U => "i64.trunc_f32_u",
+        I64_TRUNC_F64_S => "i64.trunc_f64_s",
+        I64_TRUNC_F64_U => "i64.trunc_f64_u",
+        F32_CONVERT_I32_S => "f32.convert_i32_s",
+        F32_CONVERT_I32_U => "f32.convert_i32_u",
+        F32_CONVERT_I64_S => "f32.convert_i64_s",
+        F32_CONVERT_I64_U => "f32.convert_i64_u",
+        F32_DEMOTE_F64 => "f32.demote_f64",
+        F64_CONVERT_I32_S => "f64.convert_i32_s",
+        F64_CONVERT_I32_U => "f64.convert_i32_u",
+        F64_CONVERT_I64_S => "f64.convert_i64_s",
+        F64_CONVERT_I64_U => "f64.convert_i64_u",
+        F64_PROMOTE_F32 => "f64.promote_f32",
+        I32_REINTERPRET_F32 => "i32.reinterpret_f32",
+        I64_REINTERPRET_F64 => "i64.reinterpret_f64",
+        F32_REINTERPRET_I32 => "f32.reinterpret_i32",
+        F64_REINTERPRET_I64 => "f64.reinterpret_i64",
+        REF_NULL => "ref.null",
+        REF_IS_NULL => "ref.is_null",
+        REF_FUNC => "ref.func",
+        REF_EQ => "ref.eq",
+        REF_AS_NON_NULL => "ref.as_non_null",
+        BR_ON_NULL => "br_on_null",
+        BR_ON_NON_NULL => "br_on_non_null",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The text format name of the instruction with sub-opcode `op` after `prefix`.
+pub fn prefixed_mnemonic(prefix: u8, op: u32) -> Option<&'static str> {
+    match prefix {
+        MISC_PREFIX => misc_mnemonic(op),
+        SIMD_PREFIX => simd_mnemonic(op),
+        ATOMIC_PREFIX => atomic_mnemonic(op),
+        GC_PREFIX => gc_mnemonic(op),
+        _ => None,
+    }
+}
+
+/// The text format name of an instruction with the [`MISC_PREFIX`] prefix.
+pub fn misc_mnemonic(op: u32) -> Option<&'static str> {
+    let name = match op {
+        misc::MEMORY_INIT => "memory.init",
+        misc::DATA_DROP => "data.drop",
+        misc::MEMORY_COPY => "memory.copy",
+        misc::MEMORY_FILL => "memory.fill",
+        misc::TABLE_INIT => "table.init",
+        misc::ELEM_DROP => "elem.drop",
+        misc::TABLE_COPY => "table.copy",
+        misc::TABLE_GROW => "table.grow",
+        misc::TABLE_SIZE => "table.size",
+        misc::TABLE_FILL => "table.fill",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The text format name of an instruction with the [`SIMD_PREFIX`] prefix.
+pub fn simd_mnemonic(op: u32) -> Option<&'static str> {
+    let name = match op {
+        simd::V128_LOAD => "v128.load",
+        simd::V128_LOAD8X8_S => "v128.load8x8_s",
+        simd::V128_LOAD8X8_U => "v128.load8x8_u",
+        simd::V128_LOAD16X4_S => "v128.load16x4_s",
+        simd::V128_LOAD16X4_U => "v128.load16x4_u",
+        simd::V128_LOAD32X2_S => "v128.load32x2_s",
+        simd::V128_LOAD32X2_U => "v128.load32x2_u",
+        simd::V128_LOAD8_SPLAT => "v128.load8_splat",
+        simd::V128_LOAD16_SPLAT => "v128.load16_splat",
+        simd::V128_LOAD32_SPLAT => "v128.load32_splat",
+        simd::V128_LOAD64_SPLAT => "v128.load64_splat",
+        simd::V128_STORE => "v128.store",
+        simd::V128_CONST => "v128.const",
+        simd::I8X16_SHUFFLE => "i8x16.shuffle",
+        simd::I8X16_SWIZZLE => "i8x16.swizzle",
+        simd::I8X16_SPLAT => "i8x16.splat",
+        simd::I16X8_SPLAT => "i16x8.splat",
+        simd::I32X4_SPLAT => "i32x4.splat",
+        simd::I64X2_SPLAT => "i64x2.splat",
+        simd::F32X4_SPLAT => "f32x4.splat",
+        simd::F64X2_SPLAT => "f64x2.splat",
+        simd::I8X16_EXTRACT_LANE_S => "i8x16.extract_lane_s",
+        simd::I8X16_EXTRACT_LANE_U => "i8x16.extract_lane_u",
+        simd::I8X16_REPLACE_LANE => "i8x16.replace_lane",
+        simd::I16X8_EXTRACT_LANE_S => "i16x8.extract_lane_s",
+        simd::I16X8_EXTRACT_LANE_U => "i16x8.extract_lane_u",
+        simd::I16X8_REPLACE_LANE => "i16x8.replace_lane",
+        simd::I32X4_EXTRACT_LANE => "i32x4.extract_lane",
+        simd::I32X4_REPLACE_LANE => "i32x4.replace_lane",
+        simd::I64X2_EXTRACT_LANE => "i64x2.extract_lane",
+        simd::I64X2_REPLACE_LANE => "i64x2.replace_lane",
+        simd::F32X4_EXTRACT_LANE => "f32x4.extract_lane",
+        simd::F32X4_REPLACE_LANE => "f32x4.replace_lane",
+        simd::F64X2_EXTRACT_LANE => "f64x2.extract_lane",
+        simd::F64X2_REPLACE_LANE => "f64x2.replace_lane",
+        simd::I8X16_EQ => "i8x16.eq",
+        simd::I8X16_NE => "i8x16.ne",
+        simd::I8X16_LT_S => "i8x16.lt_s",
+        simd::I8X16_LT_U => "i8x16.lt_u",
+        simd::I8X16_GT_S => "i8x16.gt_s",
+        simd::I8X16_GT_U => "i8x16.gt_u",
+        simd::I8X16_LE_S => "i8x16.le_s",
+        simd::I8X16_LE_U => "i8x16.le_u",
+        simd::I8X16_GE_S => "i8x16.ge_s",
+        simd::I8X16_GE_U => "i8x16.ge_u",
+        simd::I16X8_EQ => "i16x8.eq",
+        simd::I16X8_NE => "i16x8.ne",
+        simd::I16X8_LT_S => "i16x8.lt_s",
+        simd::I16X8_LT_U => "i16x8.lt_u",
+        simd::I16X8_GT_S => "i16x8.gt_s",
+        simd::I16X8_GT_U => "i16x8.gt_u",
+        simd::I16X8_LE_S => "i16x8.le_s",
+        simd::I16X8_LE_U => "i16x8.le_u",
+        simd::I16X8_GE_S => "i16x8.ge_s",
+        simd::I16X8_GE_U => "i16x8.ge_u",
+        simd::I32X4_EQ => "i32x4.eq",
+        simd::I32X4_NE => "i32x4.ne",
+        simd::I32X4_LT_S => "i32x4.lt_s",
+        simd::I32X4_LT_U => "i32x4.lt_u",
+        simd::I32X4_GT_S => "i32x4.gt_s",
+        simd::I32X4_GT_U => "i32x4.gt_u",
+        simd::I32X4_LE_S => "i32x4.le_s",
+        simd::I32X4_LE_U => "i32x4.le_u",
+        simd::I32X4_GE_S => "i32x4.ge_s",
+        simd::I32X4_GE_U => "i32x4.ge_u",
+        simd::F32X4_EQ => "f32x4.eq",
+        simd::F32X4_NE => "f32x4.ne",
+        simd::F32X4_LT => "f32x4.lt",
+        simd::F32X4_GT => "f32x4.gt",
+        simd::F32X4_LE => "f32x4.le",
+        simd::F32X4_GE => "f32x4.ge",
+        simd::F64X2_EQ => "f64x2.eq",
+        simd::F64X2_NE => "f64x2.ne",
+        simd::F64X2_LT => "f64x2.lt",
+        simd::F64X2_GT => "f64x2.gt",
+        simd::F64X2_LE => "f64x2.le",
+        simd::F64X2_GE => "f64x2.ge",
+        simd::V128_NOT => "v128.not",
+        simd::V128_AND => "v128.and",
+        simd::V128_ANDNOT => "v128.andnot",
+        simd::V128_OR => "v128.or",
+        simd::V128_XOR => "v128.xor",
+        simd::V128_BITSELECT => "v128.bitselect",
+        simd::V128_ANY_TRUE => "v128.any_true",
+        simd::V128_LOAD8_LANE => "v128.load8_lane",
+        simd::V128_LOAD16_LANE => "v128.load16_lane",
+        simd::V128_LOAD32_LANE => "v128.load32_lane",
+        simd::V128_LOAD64_LANE => "v128.load64_lane",
+        simd::V128_STORE8_LANE => "v128.store8_lane",
+        simd::V128_STORE16_LANE => "v128.store16_lane",
+        simd::V128_STORE32_LANE => "v128.store32_lane",
+        simd::V128_STORE64_LANE => "v128.store64_lane",
+        simd::V128_LOAD32_ZERO => "v128.load32_zero",
+        simd::V128_LOAD64_ZERO => "v128.load64_zero",
+        simd::F32X4_DEMOTE_F64X2_ZERO => "f32x4.demote_f64x2_zero",
+        simd::F64X2_PROMOTE_LOW_F32X4 => "f64x2.promote_low_f32x4",
+        simd::I8X16_ABS => "i8x16.abs",
+        simd::I8X16_NEG => "i8x16.neg",
+        simd::I8X16_POPCNT => "i8x16.popcnt",
+        simd::I8X16_ALL_TRUE => "i8x16.all_true",
+        simd::I8X16_BITMASK => "i8x16.bitmask",
+        simd::I8X16_NARROW_I16X8_S => "i8x16.narrow_i16x8_s",
+        simd::I8X16_NARROW_I16X8_U => "i8x16.narrow_i16x8_u",
+        simd::F32X4_CEIL => "f32x4.ceil",
+        simd::F32X4_FLOOR => "f32x4.floor",
+        simd::F32X4_TRUNC => "f32x4.trunc",
+        simd::F32X4_NEAREST => "f32x4.nearest",
+        simd::I8X16_SHL => "i8x16.shl",
+        simd::I8X16_SHR_S => "i8x16.shr_s",
+        simd::I8X16_SHR_U => "i8x16.shr_u",
+        simd::I8X16_ADD => "i8x16.add",
+        simd::I8X16_ADD_SAT_S => "i8x16.add_sat_s",
+        simd::I8X16_ADD_SAT_U => "i8x16.add_sat_u",
+        simd::I8X16_SUB => "i8x16.sub",
+        simd::I8X16_SUB_SAT_S => "i8x16.sub_sat_s",
+        simd::I8X16_SUB_SAT_U => "i8x16.sub_sat_u",
+        simd::F64X2_CEIL => "f64x2.ceil",
+        simd::F64X2_FLOOR => "f64x2.floor",
+        simd::I8X16_MIN_S => "i8x16.min_s",
+        simd::I8X16_MIN_U => "i8x16.min_u",
+        simd::I8X16_MAX_S => "i8x16.max_s",
+        simd::I8X16_MAX_U => "i8x16.max_u",
+        simd::F64X2_TRUNC => "f64x2.trunc",
+        simd::I8X16_AVGR_U => "i8x16.avgr_u",
+        simd::I16X8_EXTADD_PAIRWISE_I8X16_S => "i16x8.extadd_pairwise_i8x16_s",
+        simd::I16X8_EXTADD_PAIRWISE_I8X16_U => "i16x8.extadd_pairwise_i8x16_u",
+        simd::I32X4_EXTADD_PAIRWISE_I16X8_S => "i32x4.extadd_pairwise_i16x8_s",
+        simd::I32X4_EXTADD_PAIRWISE_I16X8_U => "i32x4.extadd_pairwise_i16x8_u",
+        simd::I16X8_ABS => "i16x8.abs",
+        simd::I16X8_NEG => "i16x8.neg",
+        simd::I16X8_Q15MULR_SAT_S => "i16x8.q15mulr_sat_s",
+        simd::I16X8_ALL_TRUE => "i16x8.all_true",
+        simd::I16X8_BITMASK => "i16x8.bitmask",
+        simd::I16X8_NARROW_I32X4_S => "i16x8.narrow_i32x4_s",
+        simd::I16X8_NARROW_I32X4_U => "i16x8.narrow_i32x4_u",
+        simd::I16X8_EXTEND_LOW_I8X16_S => "i16x8.extend_low_i8x16_s",
+        simd::I16X8_EXTEND_HIGH_I8X16_S => "i16x8.extend_high_i8x16_s",
+        simd::I16X8_EXTEND_LOW_I8X16_U => "i16x8.extend_low_i8x16_u",
+        simd::I16X8_EXTEND_HIGH_I8X16_U => "i16x8.extend_high_i8x16_u",
+        simd::I16X8_SHL => "i16x8.shl",
+        simd::I16X8_SHR_S => "i16x8.shr_s",
+        simd::I16X8_SHR_U => "i16x8.shr_u",
+        simd::I16X8_ADD => "i16x8.add",
+        simd::I16X8_ADD_SAT_S => "i16x8.add_sat_s",
+        simd::I16X8_ADD_SAT_U => "i16x8.add_sat_u",
+        simd::I16X8_SUB => "i16x8.sub",
+        simd::I16X8_SUB_SAT_S => "i16x8.sub_sat_s",
+        simd::I16X8_SUB_SAT_U => "i16x8.sub_sat_u",
+        simd::F64X2_NEAREST => "f64x2.nearest",
+        simd::I16X8_MUL => "i16x8.mul",
+        simd::I16X8_MIN_S => "i16x8.min_s",
+        simd::I16X8_MIN_U => "i16x8.min_u",
+        simd::I16X8_MAX_S => "i16x8.max_s",
+        simd::I16X8_MAX_U => "i16x8.max_u",
+        simd::I16X8_AVGR_U => "i16x8.avgr_u",
+        simd::I16X8_EXTMUL_LOW_I8X16_S => "i16x8.extmul_low_i8x16_s",
+        simd::I16X8_EXTMUL_HIGH_I8X16_S => "i16x8.extmul_high_i8x16_s",
+        simd::I16X8_EXTMUL_LOW_I8X16_U => "i16x8.extmul_low_i8x16_u",
+        simd::I16X8_EXTMUL_HIGH_I8X16_U => "i16x8.extmul_high_i8x16_u",
+        simd::I32X4_ABS => "i32x4.abs",
+        simd::I32X4_NEG => "i32x4.neg",
+        simd::I32X4_ALL_TRUE => "i32x4.all_true",
+        simd::I32X4_BITMASK => "i32x4.bitmask",
+        simd::I32X4_EXTEND_LOW_I16X8_S => "i32x4.extend_low_i16x8_s",
+        simd::I32X4_EXTEND_HIGH_I16X8_S => "i32x4.extend_high_i16x8_s",
+        simd::I32X4_EXTEND_LOW_I16X8_U => "i32x4.extend_low_i16x8_u",
+        simd::I32X4_EXTEND_HIGH_I16X8_U => "i32x4.extend_high_i16x8_u",
+        simd::I32X4_SHL => "i32x4.shl",
+        simd::I32X4_SHR_S => "i32x4.shr_s",
+        simd::I32X4_SHR_U => "i32x4.shr_u",
+        simd::I32X4_ADD => "i32x4.add",
+        simd::I32X4_SUB => "i32x4.sub",
+        simd::I32X4_MUL => "i32x4.mul",
+        simd::I32X4_MIN_S => "i32x4.min_s",
+        simd::I32X4_MIN_U => "i32x4.min_u",
+        simd::I32X4_MAX_S => "i32x4.max_s",
+        simd::I32X4_MAX_U => "i32x4.max_u",
+        simd::I32X4_DOT_I16X8_S => "i32x4.dot_i16x8_s",
+        simd::I32X4_EXTMUL_LOW_I16X8_S => "i32x4.extmul_low_i16x8_s",
+        simd::I32X4_EXTMUL_HIGH_I16X8_S => "i32x4.extmul_high_i16x8_s",
+        simd::I32X4_EXTMUL_LOW_I16X8_U => "i32x4.extmul_low_i16x8_u",
+        simd::I32X4_EXTMUL_HIGH_I16X8_U => "i32x4.extmul_high_i16x8_u",
+        simd::I64X2_ABS => "i64x2.abs",
+        simd::I64X2_NEG => "i64x2.neg",
+        simd::I64X2_ALL_TRUE => "i64x2.all_true",
+        simd::I64X2_BITMASK => "i64x2.bitmask",
+        simd::I64X2_EXTEND_LOW_I32X4_S => "i64x2.extend_low_i32x4_s",
+        simd::I64X2_EXTEND_HIGH_I32X4_S => "i64x2.extend_high_i32x4_s",
+        simd::I64X2_EXTEND_LOW_I32X4_U => "i64x2.extend_low_i32x4_u",
+        simd::I64X2_EXTEND_HIGH_I32X4_U => "i64x2.extend_high_i32x4_u",
+        simd::I64X2_SHL => "i64x2.shl",
+        simd::I64X2_SHR_S => "i64x2.shr_s",
+        simd::I64X2_SHR_U => "i64x2.shr_u",
+        simd::I64X2_ADD => "i64x2.add",
+        simd::I64X2_SUB => "i64x2.sub",
+        simd::I64X2_MUL => "i64x2.mul",
+        simd::I64X2_EQ => "i64x2.eq",
+        simd::I64X2_NE => "i64x2.ne",
+        simd::I64X2_LT_S => "i64x2.lt_s",
+        simd::I64X2_GT_S => "i64x2.gt_s",
+        simd::I64X2_LE_S => "i64x2.le_s",
+        simd::I64X2_GE_S => "i64x2.ge_s",
+        simd::I64X2_EXTMUL_LOW_I32X4_S => "i64x2.extmul_low_i32x4_s",
+        simd::I64X2_EXTMUL_HIGH_I32X4_S => "i64x2.extmul_high_i32x4_s",
+        simd::I64X2_EXTMUL_LOW_I32X4_U => "i64x2.extmul_low_i32x4_u",
+        simd::I64X2_EXTMUL_HIGH_I32X4_U => "i64x2.extmul_high_i32x4_u",
+        simd::F32X4_ABS => "f32x4.abs",
+        simd::F32X4_NEG => "f32x4.neg",
+        simd::F32X4_SQRT => "f32x4.sqrt",
+        simd::F32X4_ADD => "f32x4.add",
+        simd::F32X4_SUB => "f32x4.sub",
+        simd::F32X4_MUL => "f32x4.mul",
+        simd::F32X4_DIV => "f32x4.div",
+        simd::F32X4_MIN => "f32x4.min",
+        simd::F32X4_MAX => "f32x4.max",
+        simd::F32X4_PMIN => "f32x4.pmin",
+        simd::F32X4_PMAX => "f32x4.pmax",
+        simd::F64X2_ABS => "f64x2.abs",
+        simd::F64X2_NEG => "f64x2.neg",
+        simd::F64X2_SQRT => "f64x2.sqrt",
+        simd::F64X2_ADD => "f64x2.add",
+        simd::F64X2_SUB => "f64x2.sub",
+        simd::F64X2_MUL => "f64x2.mul",
+        simd::F64X2_DIV => "f64x2.div",
+        simd::F64X2_MIN => "f64x2.min",
+        simd::F64X2_MAX => "f64x2.max",
+        simd::F64X2_PMIN => "f64x2.pmin",
+        simd::F64X2_PMAX => "f64x2.pmax",
+        simd::I32X4_TRUNC_SAT_F32X4_S => "i32x4.trunc_sat_f32x4_s",
+        simd::I32X4_TRUNC_SAT_F32X4_U => "i32x4.trunc_sat_f32x4_u",
+        simd::F32X4_CONVERT_I32X4_S => "f32x4.convert_i32x4_s",
+        simd::F32X4_CONVERT_I32X4_U => "f32x4.convert_i32x4_u",
+        simd::I32X4_TRUNC_SAT_F64X2_S_ZERO => "i32x4.trunc_sat_f64x2_s_zero",
+        simd::I32X4_TRUNC_SAT_F64X2_U_ZERO => "i32x4.trunc_sat_f64x2_u_zero",
+        simd::F64X2_CONVERT_LOW_I32X4_S => "f64x2.convert_low_i32x4_s",
+        simd::F64X2_CONVERT_LOW_I32X4_U => "f64x2.convert_low_i32x4_u",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The text format name of an instruction with the [`ATOMIC_PREFIX`] prefix.
+pub fn atomic_mnemonic(op: u32) -> Option<&'static str> {
+    let name = match op {
+        atomic::MEMORY_ATOMIC_NOTIFY => "memory.atomic.notify",
+        atomic::MEMORY_ATOMIC_WAIT32 => "memory.atomic.wait32",
+        atomic::MEMORY_ATOMIC_WAIT64 => "memory.atomic.wait64",
+        atomic::ATOMIC_FENCE => "atomic.fence",
+        atomic::I32_ATOMIC_LOAD => "i32.atomic.load",
+        atomic::I64_ATOMIC_LOAD => "i64.atomic.load",
+        atomic::I32_ATOMIC_LOAD8_U => "i32.atomic.load8_u",
+        atomic::I32_ATOMIC_LOAD16_U => "i32.atomic.load16_u",
+        atomic::I64_ATOMIC_LOAD8_U => "i64.atomic.load8_u",
+        atomic::I64_ATOMIC_LOAD16_U => "i64.atomic.load16_u",
+        atomic::I64_ATOMIC_LOAD32_U => "i64.atomic.load32_u",
+        atomic::I32_ATOMIC_STORE => "i32.atomic.store",
+        atomic::I64_ATOMIC_STORE => "i64.atomic.store",
+        atomic::I32_ATOMIC_STORE8 => "i32.atomic.store8",
+        atomic::I32_ATOMIC_STORE16 => "i32.atomic.store16",
+        atomic::I64_ATOMIC_STORE8 => "i64.atomic.store8",
+        atomic::I64_ATOMIC_STORE16 => "i64.atomic.store16",
+        atomic::I64_ATOMIC_STORE32 => "i64.atomic.store32",
+        atomic::I32_ATOMIC_RMW_ADD => "i32.atomic.rmw.add",
+        atomic::I64_ATOMIC_RMW_ADD => "i64.atomic.rmw.add",
+        atomic::I32_ATOMIC_RMW8_ADD_U => "i32.atomic.rmw8.add_u",
+        atomic::I32_ATOMIC_RMW16_ADD_U => "i32.atomic.rmw16.add_u",
+        atomic::I64_ATOMIC_RMW8_ADD_U => "i64.atomic.rmw8.add_u",
+        atomic::I64_ATOMIC_RMW16_ADD_U => "i64.atomic.rmw16.add_u",
+        atomic::I64_ATOMIC_RMW32_ADD_U => "i64.atomic.rmw32.add_u",
+        atomic::I32_ATOMIC_RMW_SUB => "i32.atomic.rmw.sub",
+        atomic::I64_ATOMIC_RMW_SUB => "i64.atomic.rmw.sub",
+        atomic::I32_ATOMIC_RMW8_SUB_U => "i32.atomic.rmw8.sub_u",
+        atomic::I32_ATOMIC_RMW16_SUB_U => "i32.atomic.rmw16.sub_u",
+        atomic::I64_ATOMIC_RMW8_SUB_U => "i64.atomic.rmw8.sub_u",
+        atomic::I64_ATOMIC_RMW16_SUB_U => "i64.atomic.rmw16.sub_u",
+        atomic::I64_ATOMIC_RMW32_SUB_U => "i64.atomic.rmw32.sub_u",
+        atomic::I32_ATOMIC_RMW_AND => "i32.atomic.rmw.and",
+        atomic::I64_ATOMIC_RMW_AND => "i64.atomic.rmw.and",
+        atomic::I32_ATOMIC_RMW8_AND_U => "i32.atomic.rmw8.and_u",
+        atomic::I32_ATOMIC_RMW16_AND_U => "i32.atomic.rmw16.and_u",
+        atomic::I64_ATOMIC_RMW8_AND_U => "i64.atomic.rmw8.and_u",
+        atomic::I64_ATOMIC_RMW16_AND_U => "i64.atomic.rmw16.and_u",
+        atomic::I64_ATOMIC_RMW32_AND_U => "i64.atomic.rmw32.and_u",
+        atomic::I32_ATOMIC_RMW_OR => "i32.atomic.rmw.or",
+        atomic::I64_ATOMIC_RMW_OR => "i64.atomic.rmw.or",
+        atomic::I32_ATOMIC_RMW8_OR_U => "i32.atomic.rmw8.or_u",
+        atomic::I32_ATOMIC_RMW16_OR_U => "i32.atomic.rmw16.or_u",
+        atomic::I64_ATOMIC_RMW8_OR_U => "i64.atomic.rmw8.or_u",
+        atomic::I64_ATOMIC_RMW16_OR_U => "i64.atomic.rmw16.or_u",
+        atomic::I64_ATOMIC_RMW32_OR_U => "i64.atomic.rmw32.or_u",
+        atomic::I32_ATOMIC_RMW_XOR => "i32.atomic.rmw.xor",
+        atomic::I64_ATOMIC_RMW_XOR => "i64.atomic.rmw.xor",
+        atomic::I32_ATOMIC_RMW8_XOR_U => "i32.atomic.rmw8.xor_u",
+        atomic::I32_ATOMIC_RMW16_XOR_U => "i32.atomic.rmw16.xor_u",
+        atomic::I64_ATOMIC_RMW8_XOR_U => "i64.atomic.rmw8.xor_u",
+        atomic::I64_ATOMIC_RMW16_XOR_U => "i64.atomic.rmw16.xor_u",
+        atomic::I64_ATOMIC_RMW32_XOR_U => "i64.atomic.rmw32.xor_u",
+        atomic::I32_ATOMIC_RMW_XCHG => "i32.atomic.rmw.xchg",
+        atomic::I64_ATOMIC_RMW_XCHG => "i64.atomic.rmw.xchg",
+        atomic::I32_ATOMIC_RMW8_XCHG_U => "i32.atomic.rmw8.xchg_u",
+        atomic::I32_ATOMIC_RMW16_XCHG_U => "i32.atomic.rmw16.xchg_u",
+        atomic::I64_ATOMIC_RMW8_XCHG_U => "i64.atomic.rmw8.xchg_u",
+        atomic::I64_ATOMIC_RMW16_XCHG_U => "i64.atomic.rmw16.xchg_u",
+        atomic::I64_ATOMIC_RMW32_XCHG_U => "i64.atomic.rmw32.xchg_u",
+        atomic::I32_ATOMIC_RMW_CMPXCHG => "i32.atomic.rmw.cmpxchg",
+        atomic::I64_ATOMIC_RMW_CMPXCHG => "i64.atomic.rmw.cmpxchg",
+        atomic::I32_ATOMIC_RMW8_CMPXCHG_U => "i32.atomic.rmw8.cmpxchg_u",
+        atomic::I32_ATOMIC_RMW16_CMPXCHG_U => "i32.atomic.rmw16.cmpxchg_u",
+        atomic::I64_ATOMIC_RMW8_CMPXCHG_U => "i64.atomic.rmw8.cmpxchg_u",
+        atomic::I64_ATOMIC_RMW16_CMPXCHG_U => "i64.atomic.rmw16.cmpxchg_u",
+        atomic::I64_ATOMIC_RMW32_CMPXCHG_U => "i64.atomic.rmw32.cmpxchg_u",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The text format name of an instruction with the [`GC_PREFIX`] prefix.
+pub fn gc_mnemonic(op: u32) -> Option<&'static str> {
+    let name = match op {
+        gc::STRUCT_NEW => "struct.new",
+        gc::STRUCT_NEW_DEFAULT => "struct.new_default",
+        gc::STRUCT_GET => "struct.get",
+        gc::STRUCT_GET_S => "struct.get_s",
+        gc::STRUCT_GET_U => "struct.get_u",
+        gc::STRUCT_SET => "struct.set",
+        gc::ARRAY_NEW => "array.new",
+        gc::ARRAY_NEW_DEFAULT => "array.new_default",
+        gc::ARRAY_NEW_FIXED => "array.new_fixed",
+        gc::ARRAY_NEW_DATA => "array.new_data",
+        gc::ARRAY_NEW_ELEM => "array.new_elem",
+        gc::ARRAY_GET => "array.get",
+        gc::ARRAY_GET_S => "array.get_s",
+        gc::ARRAY_GET_U => "array.get_u",
+        gc::ARRAY_SET => "array.set",
+        gc::ARRAY_LEN => "array.len",
+        gc::ARRAY_FILL => "array.fill",
+        gc::ARRAY_COPY => "array.copy",
+        gc::ARRAY_INIT_DATA => "array.init_data",
+        gc::ARRAY_INIT_ELEM => "array.init_elem",
+        gc::REF_TEST | gc::REF_TEST_NULL => "ref.test",
+        gc::REF_CAST | gc::REF_CAST_NULL => "ref.cast",
+        gc::BR_ON_CAST => "br_on_cast",
+        gc::BR_ON_CAST_FAIL => "br_on_cast_fail",
+        gc::ANY_CONVERT_EXTERN => "any.convert_extern",
+        gc::EXTERN_CONVERT_ANY => "extern.convert_any",
+        gc::REF_I31 => "ref.i31",
+        gc::I31_GET_S => "i31.get_s",
+        gc::I31_GET_U => "i31.get_u",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Decodes the instruction at `offset`, returning its text and length in bytes.
+pub fn decode(code: &[u8], offset: usize) -> (String, usize) {
+    let mut reader = Immediates { code, ip: offset + 1 };
+    let op = code[offset];
+    if op == MISC_PREFIX {
+        return decode_misc(code, offset);
+    }
+    if op == SIMD_PREFIX {
+        return decode_simd(code, offset);
+    }
+    if op == ATOMIC_PREFIX {
+        return decode_atomic(code, offset);
+    }
+    if op == GC_PREFIX {
+        return decode_gc(code, offset);
+    }
+    let Some(name) = mnemonic(op) else {
+        return (format!("<unknown {op:#04x}>"), 1);
+    };
+
+    let immediates = match op {
+        BLOCK | LOOP | IF => reader.block_type(),
+        BR | BR_IF | CALL | RETURN_CALL | THROW | REF_FUNC => reader.index(),
+        BR_ON_NULL | BR_ON_NON_NULL => reader.index(),
+        CALL_REF | RETURN_CALL_REF => reader.index(),
+        LOCAL_GET..=TABLE_SET => reader.index(),
+        REF_NULL => reader.heap_type(),
+        TRY_TABLE => {
+            let block_type = reader.block_type();
+            let num_catches = reader.size();
+            let mut parts: Vec<_> = (0..num_catches).map(|_| reader.catch_clause()).collect();
+            if !block_type.is_empty() {
+                parts.insert(0, block_type);
+            }
+            parts.join(" ")
+        }
+        BR_TABLE => {
+            let num_labels = reader.size();
+            let labels: Vec<_> = (0..=num_labels).map(|_| reader.index()).collect();
+            labels.join(" ")
+        }
+        CALL_INDIRECT | RETURN_CALL_INDIRECT => {
+            let type_idx = reader.size();
+            let table_idx = reader.size();
+            format!("{table_idx} (type {type_idx})")
+        }
+        SELECT_T => {
+            let num_types = reader.size();
+            let types: Vec<_> = (0..num_types).map(|_| reader.val_type()).collect();
+            format!("(result {})", types.join(" "))
+        }
+        I32_LOAD..=I64_STORE32 => reader.memarg(),
+        MEMORY_SIZE | MEMORY_GROW => reader.memory_index(),
+        I32_CONST => {
+            let (val, len) = bytecode::read::read_i32(code, reader.ip);
+            reader.ip += len;
+            val.to_string()
+        }
+        I64_CONST => {
+            let (val, len) = bytecode::read::read_i64(code, reader.ip);
+            reader.ip += len;
+            val.to_string()
+        }
+        F32_CONST => {
+            reader.ip += 4;
+            bytecode::read::read_f32(code, reader.ip - 4).to_string()
+        }
+        F64_CONST => {
+            reader.ip += 8;
+            bytecode::read::read_f64(code, reader.ip - 8).to_string()
+        }
+        _ => String::new(),
+    };
+
+    (text(name, immediates), reader.ip - offset)
+}
+
+fn decode_misc(code: &[u8], offset: usize) -> (String, usize) {
+    let mut reader = Immediates { code, ip: offset + 1 };
+    let op = reader.size() as u32;
+    let Some(name) = misc_mnemonic(op) else {
+        return (format!("<unknown 0xfc {op}>"), reader.ip - offset);
+    };
+    let immediates = match op {
+        misc::MEMORY_INIT => {
+            let data_idx = reader.index();
+            match reader.memory_index() {
+                mem if mem.is_empty() => data_idx,
+                mem => format!("{mem} {data_idx}"),
+            }
+        }
+        misc::MEMORY_COPY => {
+            let (dst, src) = (reader.size(), reader.size());
+            if dst == 0 && src == 0 { String::new() } else { format!("{dst} {src}") }
+        }
+        misc::MEMORY_FILL => reader.memory_index(),
+        misc::TABLE_INIT => {
+            // The text format puts the table first
+            let elem_idx = reader.index();
+            format!("{} {elem_idx}", reader.index())
+        }
+        misc::TABLE_COPY => format!("{} {}", reader.index(), reader.index()),
+        misc::DATA_DROP | misc::ELEM_DROP => reader.index(),
+        misc::TABLE_GROW | misc::TABLE_SIZE | misc::TABLE_FILL => reader.index(),
+        _ => String::new(),
+    };
+    (text(name, immediates), reader.ip - offset)
+}
+
+fn decode_simd(code: &[u8], offset: usize) -> (String, usize) {
+    let mut reader = Immediates { code, ip: offset + 1 };
+    let op = reader.size() as u32;
+    let Some(name) = simd_mnemonic(op) else {
+        return (format!("<unknown 0xfd {op}>"), reader.ip - offset);
+    };
+    let immediates = match v128::kind(op) {
+        Some(Kind::Load { .. } | Kind::Store) => reader.memarg(),
+        Some(Kind::LoadLane { .. } | Kind::StoreLane { .. }) => {
+            let memarg = reader.memarg();
+            format!("{memarg} {}", reader.lane())
+        }
+        Some(Kind::ExtractLane { .. } | Kind::ReplaceLane { .. }) => reader.lane(),
+        Some(Kind::Const) => {
+            let lanes: Vec<_> = (0..4).map(|_| reader.bytes::<4>()).collect();
+            let lanes = lanes.iter().map(|lane| format!("{:#010x}", u32::from_le_bytes(*lane)));
+            format!("i32x4 {}", lanes.collect::<Vec<_>>().join(" "))
+        }
+        Some(Kind::Shuffle) => {
+            let lanes: Vec<_> = (0..16).map(|_| reader.lane()).collect();
+            lanes.join(" ")
+        }
+        _ => String::new(),
+    };
+    (text(name, immediates), reader.ip - offset)
+}
+
+fn decode_atomic(code: &[u8], offset: usize) -> (String, usize) {
+    let mut reader = Immediates { code, ip: offset + 1 };
+    let op = reader.size() as u32;
+    let Some(name) = atomic_mnemonic(op) else {
+        return (format!("<unknown 0xfe {op}>"), reader.ip - offset);
+    };
+    let immediates = match op {
+        atomic::ATOMIC_FENCE => {
+            reader.ip += 1;
+            String::new()
+        }
+        _ => reader.memarg(),
+    };
+    (text(name, immediates), reader.ip - offset)
+}
+
+fn decode_gc(code: &[u8], offset: usize) -> (String, usize) {
+    let mut reader = Immediates { code, ip: offset + 1 };
+    let op = reader.size() as u32;
+    let Some(name) = gc_mnemonic(op) else {
+        return (format!("<unknown 0xfb {op}>"), reader.ip - offset);
+    };
+    let immediates = match op {
+        gc::STRUCT_GET..=gc::STRUCT_SET | gc::ARRAY_NEW_FIXED..=gc::ARRAY_NEW_ELEM => {
+            format!("{} {}", reader.index(), reader.index())
+        }
+        gc::ARRAY_COPY | gc::ARRAY_INIT_DATA | gc::ARRAY_INIT_ELEM => {
+            format!("{} {}", reader.index(), reader.index())
+        }
+        gc::ARRAY_LEN | gc::ANY_CONVERT_EXTERN..=gc::I31_GET_U => String::new(),
+        gc::REF_TEST | gc::REF_CAST => format!("(ref {})", reader.heap_type()),
+        gc::REF_TEST_NULL | gc::REF_CAST_NULL => format!("(ref null {})", reader.heap_type()),
+        gc::BR_ON_CAST | gc::BR_ON_CAST_FAIL => {
+            let flags = reader.bytes::<1>()[0];
+            let label = reader.index();
+            let ref_type = |reader: &mut Immediates, nullable: bool| match nullable {
+                true => format!("(ref null {})", reader.heap_type()),
+                false => format!("(ref {})", reader.heap_type()),
+            };
+            let source = ref_type(&mut reader, flags & gc::CAST_SOURCE_NULL != 0);
+            let target = ref_type(&mut reader, flags & gc::CAST_TARGET_NULL != 0);
+            format!("{label} {source} {target}")
+        }
+        _ => reader.index(),
+    };
+    (text(name, immediates), reader.ip - offset)
+}
+
+fn text(name: &str, immediates: String) -> String {
+    if immediates.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name} {immediates}")
+    }
+}
+
+/// Decodes a whole function body into each instruction's offset and text.
+pub fn disassemble(code: &[u8]) -> Vec<(usize, String)> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let (text, len) = decode(code, offset);
+        instructions.push((offset, text));
+        offset += len;
+    }
+    instructions
+}
+
+struct Immediates<'a> {
+    code: &'a [u8],
+    ip: usize,
+}
+
+impl Immediates<'_> {
+    fn size(&mut self) -> usize {
+        let (val, len) = bytecode::read::read_size(self.code, self.ip);
+        self.ip += len;
+        val as usize
+    }
+
+    fn index(&mut self) -> String {
+        self.size().to_string()
+    }
+
+    fn lane(&mut self) -> String {
+        self.ip += 1;
+        self.code[self.ip - 1].to_string()
+    }
+
+    fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        self.ip += N;
+        self.code[self.ip - N..self.ip].try_into().unwrap()
+    }
+
+    fn memarg(&mut self) -> String {
+        let align = self.size();
+        let mem = if align & bytecode::MEMARG_INDEX_BIT != 0 { self.size() } else { 0 };
+        let align = align & !bytecode::MEMARG_INDEX_BIT;
+        let (offset, len) = bytecode::read::read_u64(self.code, self.ip);
+        self.ip += len;
+        let memarg = format!("offset={offset} align={}", 1u64 << align.min(63));
+        if mem == 0 { memarg } else { format!("{mem} {memarg}") }
+    }
+
+    /// A memory index, which the text format leaves out when it is 0.
+    fn memory_index(&mut self) -> String {
+        match self.size() {
+            0 => String::new(),
+            mem => mem.to_string(),
+        }
+    }
+
+    fn val_type(&mut self) -> String {
+        match bytecode::read::read_val_type(self.code, self.ip) {
+            Some((ty, len)) => {
+                self.ip += len;
+                val_type_name(ty)
+            }
+            None => {
+                self.ip += 1;
+                format!("<type {:#04x}>", self.code[self.ip - 1])
+            }
+        }
+    }
+
+    fn heap_type(&mut self) -> String {
+        match bytecode::read::read_heap_type(self.code, self.ip) {
+            Some((heap, len)) => {
+                self.ip += len;
+                heap_type_name(heap)
+            }
+            None => {
+                self.ip += 1;
+                format!("<type {:#04x}>", self.code[self.ip - 1])
+            }
+        }
+    }
+
+    fn catch_clause(&mut self) -> String {
+        self.ip += 1;
+        match self.code[self.ip - 1] {
+            catch::CATCH => format!("(catch {} {})", self.index(), self.index()),
+            catch::CATCH_REF => format!("(catch_ref {} {})", self.index(), self.index()),
+            catch::CATCH_ALL => format!("(catch_all {})", self.index()),
+            catch::CATCH_ALL_REF => format!("(catch_all_ref {})", self.index()),
+            kind => format!("(<catch {kind:#04x}>)"),
+        }
+    }
+
+    fn block_type(&mut self) -> String {
+        match self.code[self.ip] {
+            0x40 => {
+                self.ip += 1;
+                String::new()
+            }
+            0x63 | 0x64 => format!("(result {})", self.val_type()),
+            byte if ValType::from_byte(byte).is_some() => format!("(result {})", self.val_type()),
+            _ => {
+                let (idx, len) = bytecode::read::read_i64(self.code, self.ip);
+                self.ip += len;
+                format!("(type {idx})")
+            }
+        }
+    }
+}
+
+/// The text format of a value type, using the shorthands such as `funcref` for
+/// nullable abstract references.
+pub fn val_type_name(ty: ValType) -> String {
+    match ty {
+        ValType::Ref(RefType::FUNCREF) => String::from("funcref"),
+        ValType::Ref(RefType::EXTERNREF) => String::from("externref"),
+        ValType::Ref(RefType::EXNREF) => String::from("exnref"),
+        ValType::Ref(RefType::ANYREF) => String::from("anyref"),
+        ValType::Ref(RefType { nullable: true, heap }) => {
+            format!("(ref null {})", heap_type_name(heap))
+        }
+        ValType::Ref(RefType { nullable: false, heap }) => {
+            format!("(ref {})", heap_type_name(heap))
+        }
+        ty => format!("{ty:?}").to_lowercase(),
+    }
+}
+
+fn heap_type_name(heap: HeapType) -> String {
+    match heap {
+        HeapType::Concrete(idx) => idx.to_string(),
+        heap => format!("{heap:?}").to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_function() {
+        let code = [
+            BLOCK, 0x7f, LOCAL_GET, 0, I32_LOAD, 2, 8, I32_CONST, 0x7f, BR_TABLE, 1, 0, 0,
+            END, F32_CONST, 0, 0, 0xc0, 0x3f, DROP, END,
+        ];
+        let text: Vec<_> = disassemble(&code)
+            .into_iter()
+            .map(|(offset, text)| format!("{offset}: {text}"))
+            .collect();
+        assert_eq!(vec![
+            "0: block (result i32)",
+            "2: local.get 0",
+            "4: i32.load offset=8 align=4",
+            "7: i32.const -1",
+            "9: br_table 0 0",
+            "13: end",
+            "14: f32.const 1.5",
+            "19: drop",
+            "20: end",
+        ], text);
+    }
+
+    #[test]
+    fn disassemble_simd() {
+        let mut code = vec![SIMD_PREFIX, 0x0c];
+        code.extend((1..=16).collect::<Vec<u8>>());
+        code.extend([SIMD_PREFIX, 0x55, 1, 8, 3, SIMD_PREFIX, 0xba, 0x01, END]);
+        let text: Vec<_> = disassemble(&code).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(vec![
+            "v128.const i32x4 0x04030201 0x08070605 0x0c0b0a09 0x100f0e0d",
+            "v128.load16_lane offset=8 align=2 3",
+            "i32x4.dot_i16x8_s",
+            "end",
+        ], text);
+    }
+
+    #[test]
+    fn disassemble_try_table() {
+        let code = [
+            TRY_TABLE, 0x7f, 2, catch::CATCH, 0, 1, catch::CATCH_ALL_REF, 2,
+            I32_CONST, 1, THROW, 0, END, END,
+        ];
+        let text: Vec<_> = disassemble(&code).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(vec![
+            "try_table (result i32) (catch 0 1) (catch_all_ref 2)",
+            "i32.const 1",
+            "throw 0",
+            "end",
+            "end",
+        ], text);
+    }
+
+    #[test]
+    fn disassemble_gc_instructions() {
+        let code = [
+            GC_PREFIX, 0, 3, GC_PREFIX, 4, 3, 1, GC_PREFIX, 8, 2, 5, GC_PREFIX, 23, 0x6c,
+            GC_PREFIX, 24, 1, 0, 0x6e, 3, GC_PREFIX, 28, REF_EQ, END,
+        ];
+        let text: Vec<_> = disassemble(&code).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(vec![
+            "struct.new 3",
+            "struct.get_u 3 1",
+            "array.new_fixed 2 5",
+            "ref.cast (ref null i31)",
+            "br_on_cast 0 (ref null any) (ref 3)",
+            "ref.i31",
+            "ref.eq",
+            "end",
+        ], text);
+    }
+
+    #[test]
+    fn disassemble_typed_references() {
+        let code = [
+            BLOCK, 0x64, 2, REF_NULL, 0x73, BR_ON_NULL, 0, SELECT_T, 1, 0x63, 0x70, END,
+            REF_AS_NON_NULL, CALL_REF, 2, END,
+        ];
+        let text: Vec<_> = disassemble(&code).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(vec![
+            "block (result (ref 2))",
+            "ref.null nofunc",
+            "br_on_null 0",
+            "select (result funcref)",
+            "end",
+            "ref.as_non_null",
+            "call_ref 2",
+            "end",
+        ], text);
+    }
+
+    #[test]
+    fn disassemble_atomics() {
+        let code = [ATOMIC_PREFIX, 0x48, 2, 4, ATOMIC_PREFIX, 0x03, 0, ATOMIC_PREFIX, 0x4f, END];
+        let text: Vec<_> = disassemble(&code).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(vec![
+            "i32.atomic.rmw.cmpxchg offset=4 align=4",
+            "atomic.fence",
+            "<unknown 0xfe 79>",
+            "end",
+        ], text);
+    }
+}
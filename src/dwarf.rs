@@ -0,0 +1,453 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use crate::wasm_module::WasmModule;
+
+/// A source position from the DWARF line table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine<'a> {
+    pub file: &'a str,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    address: u64,
+    file: usize,
+    line: u32,
+    end_sequence: bool,
+}
+
+/// The address to source line mapping from a module's `.debug_line` section.
+///
+/// Addresses in WebAssembly DWARF are offsets from the start of the code section's
+/// contents, which is how [`WasmModule::code_section`] is laid out.
+#[derive(Debug, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    rows: Vec<Row>,
+}
+
+impl LineTable {
+    /// Parses the module's line table, or returns `None` if it has no DWARF.
+    pub fn from_module(module: &WasmModule) -> Option<Result<Self, String>> {
+        let debug_line = module.custom_section(".debug_line")?;
+        let strings = |name: &str| module.custom_section(name).unwrap_or(&[]);
+        Some(Self::parse(debug_line, strings(".debug_str"), strings(".debug_line_str")))
+    }
+
+    pub fn parse(debug_line: &[u8], debug_str: &[u8], debug_line_str: &[u8])
+        -> Result<Self, String> {
+        let mut table = Self::default();
+        let mut reader = DwarfReader { data: debug_line, pos: 0 };
+        while reader.pos < debug_line.len() {
+            table.unit(&mut reader, debug_str, debug_line_str)?;
+        }
+        // An end of sequence sorts before a row starting another sequence at its address
+        table.rows.sort_by_key(|row| (row.address, !row.end_sequence));
+        Ok(table)
+    }
+
+    /// The source line of the instruction at `address`, if the line table covers it.
+    pub fn lookup(&self, address: u64) -> Option<SourceLine<'_>> {
+        let idx = self.rows.partition_point(|row| row.address <= address).checked_sub(1)?;
+        let row = &self.rows[idx];
+        if row.end_sequence {
+            return None;
+        }
+        Some(SourceLine { file: &self.files[row.file], line: row.line })
+    }
+
+    /// Parses one line number program: a header followed by its opcodes.
+    fn unit(&mut self, reader: &mut DwarfReader, debug_str: &[u8], debug_line_str: &[u8])
+        -> Result<(), String> {
+        let (length, offset_size) = match reader.u32()? {
+            0xffff_ffff => (reader.u64()?, 8),
+            length => (length as u64, 4),
+        };
+        let end = reader.pos.checked_add(length as usize)
+            .filter(|end| *end <= reader.data.len())
+            .ok_or("Line table unit extends past the end of .debug_line")?;
+
+        let version = reader.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(format!("Unsupported line table version {version}"));
+        }
+        if version >= 5 {
+            reader.u8()?;       // address size
+            reader.u8()?;       // segment selector size
+        }
+        let header_length = reader.offset(offset_size)?;
+        let program_start = reader.pos.saturating_add(header_length as usize);
+        let min_instruction_length = reader.u8()? as u64;
+        if version >= 4 {
+            reader.u8()?;       // maximum operations per instruction
+        }
+        let default_is_stmt = reader.u8()? != 0;
+        let line_base = reader.u8()? as i8 as i64;
+        let line_range = reader.u8()?;
+        let opcode_base = reader.u8()?;
+        if line_range == 0 || opcode_base == 0 {
+            return Err(String::from("Invalid line table header"));
+        }
+        let mut opcode_lengths = Vec::new();
+        for _ in 1..opcode_base {
+            opcode_lengths.push(reader.u8()?);
+        }
+
+        // Files are numbered from 1 before version 5, and from 0 since
+        let file_base = self.files.len();
+        let first_file = if version >= 5 {
+            self.entries_v5(reader, offset_size, debug_str, debug_line_str)?;
+            0
+        } else {
+            self.entries_v4(reader)?;
+            1
+        };
+        let file = |idx: u64| (idx as usize).checked_add(file_base)?
+            .checked_sub(first_file)
+            .filter(|file| *file < self.files.len());
+
+        reader.pos = program_start;
+        let start = State { address: 0, file: 1, line: 1, is_stmt: default_is_stmt };
+        let mut state = start;
+        let mut rows = Vec::new();
+        let mut emit = |state: &State, end_sequence: bool| -> Result<(), String> {
+            let file = file(state.file).ok_or("Line table refers to an undefined file")?;
+            rows.push(Row { address: state.address, file, line: state.line as u32, end_sequence });
+            Ok(())
+        };
+        while reader.pos < end {
+            match reader.u8()? {
+                0 => {
+                    let len = reader.uleb()? as usize;
+                    if len == 0 {
+                        return Err(String::from("Empty extended opcode in line table"));
+                    }
+                    let next = reader.pos.saturating_add(len);
+                    match reader.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            emit(&state, true)?;
+                            state = start;
+                        }
+                        DW_LNE_SET_ADDRESS => state.address = reader.offset(len - 1)?,
+                        _ => {}
+                    }
+                    reader.pos = next;
+                }
+                DW_LNS_COPY => emit(&state, false)?,
+                DW_LNS_ADVANCE_PC => {
+                    let advance = reader.uleb()?.wrapping_mul(min_instruction_length);
+                    state.address = state.address.wrapping_add(advance);
+                }
+                DW_LNS_ADVANCE_LINE => state.line = state.line.wrapping_add(reader.sleb()?),
+                DW_LNS_SET_FILE => state.file = reader.uleb()?,
+                DW_LNS_NEGATE_STMT => state.is_stmt = !state.is_stmt,
+                DW_LNS_CONST_ADD_PC => {
+                    let adjusted = (255 - opcode_base) / line_range;
+                    let advance = adjusted as u64 * min_instruction_length;
+                    state.address = state.address.wrapping_add(advance);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => {
+                    state.address = state.address.wrapping_add(reader.u16()? as u64);
+                }
+                op if op < opcode_base => {
+                    // Column, basic block, prologue and other operands we don't track
+                    for _ in 0..opcode_lengths[op as usize - 1] {
+                        reader.uleb()?;
+                    }
+                }
+                op => {
+                    let adjusted = op - opcode_base;
+                    let advance = (adjusted / line_range) as u64 * min_instruction_length;
+                    state.address = state.address.wrapping_add(advance);
+                    let advance = line_base + (adjusted % line_range) as i64;
+                    state.line = state.line.wrapping_add(advance);
+                    emit(&state, false)?;
+                }
+            }
+        }
+        self.rows.extend(rows);
+        reader.pos = end;
+        Ok(())
+    }
+
+    /// Reads the directory and file tables of a version 2 to 4 header.
+    fn entries_v4(&mut self, reader: &mut DwarfReader) -> Result<(), String> {
+        let mut directories = vec![String::new()];
+        loop {
+            let dir = reader.str()?;
+            if dir.is_empty() {
+                break;
+            }
+            directories.push(dir);
+        }
+        loop {
+            let name = reader.str()?;
+            if name.is_empty() {
+                return Ok(());
+            }
+            let dir = reader.uleb()? as usize;
+            reader.uleb()?;     // modification time
+            reader.uleb()?;     // file length
+            let dir = directories.get(dir).ok_or("Line table refers to an undefined directory")?;
+            self.files.push(join_path(dir, &name));
+        }
+    }
+
+    /// Reads the self-describing directory and file tables of a version 5 header.
+    fn entries_v5(&mut self, reader: &mut DwarfReader, offset_size: usize, debug_str: &[u8],
+        debug_line_str: &[u8]) -> Result<(), String> {
+        let mut entries = || -> Result<Vec<HashMap<u64, Attribute>>, String> {
+            let mut format = Vec::new();
+            for _ in 0..reader.u8()? {
+                format.push((reader.uleb()?, reader.uleb()?));
+            }
+            let mut entries = Vec::new();
+            for _ in 0..reader.uleb()? {
+                let mut entry = HashMap::new();
+                for (content, form) in &format {
+                    let value = reader.attribute(*form, offset_size, debug_str, debug_line_str)?;
+                    entry.insert(*content, value);
+                }
+                entries.push(entry);
+            }
+            Ok(entries)
+        };
+        let path = |entry: &HashMap<u64, Attribute>| match entry.get(&DW_LNCT_PATH) {
+            Some(Attribute::String(path)) => Ok(path.clone()),
+            _ => Err(String::from("Line table entry has no path")),
+        };
+
+        let directories = entries()?.iter().map(path).collect::<Result<Vec<_>, _>>()?;
+        for file in entries()? {
+            let name = path(&file)?;
+            let dir = match file.get(&DW_LNCT_DIRECTORY_INDEX) {
+                Some(Attribute::Number(idx)) => directories.get(*idx as usize)
+                    .ok_or("Line table refers to an undefined directory")?
+                    .as_str(),
+                _ => "",
+            };
+            self.files.push(join_path(dir, &name));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    address: u64,
+    file: u64,
+    line: i64,
+    is_stmt: bool,
+}
+
+enum Attribute {
+    String(String),
+    Number(u64),
+}
+
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_NEGATE_STMT: u8 = 0x06;
+const DW_LNS_CONST_ADD_PC: u8 = 0x08;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 0x09;
+
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+
+const DW_LNCT_PATH: u64 = 0x1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 0x2;
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() || name.starts_with('/') {
+        String::from(name)
+    } else {
+        format!("{}/{name}", dir.trim_end_matches('/'))
+    }
+}
+
+/// A bounds checked little-endian reader, since DWARF comes from the guest toolchain
+/// and is not validated like the rest of the module.
+struct DwarfReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl DwarfReader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or("Unexpected end of DWARF data")?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(self.offset(2)? as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(self.offset(4)? as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        self.offset(8)
+    }
+
+    /// A little-endian integer of `size` bytes, as used for addresses and offsets.
+    fn offset(&mut self, size: usize) -> Result<u64, String> {
+        if size > 8 {
+            return Err(format!("Unsupported DWARF value size {size}"));
+        }
+        let bytes = self.bytes(size)?;
+        Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64))
+    }
+
+    fn uleb(&mut self) -> Result<u64, String> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Result<i64, String> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= !0 << shift;
+                }
+                return Ok(result);
+            }
+        }
+    }
+
+    /// A null terminated string.
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.data[self.pos.min(self.data.len())..].iter().position(|b| *b == 0)
+            .ok_or("Unterminated string in DWARF data")?;
+        let s = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.pos += 1;
+        Ok(s)
+    }
+
+    fn attribute(&mut self, form: u64, offset_size: usize, debug_str: &[u8], debug_line_str: &[u8])
+        -> Result<Attribute, String> {
+        let value = match form {
+            DW_FORM_STRING => return Ok(Attribute::String(self.str()?)),
+            DW_FORM_STRP | DW_FORM_LINE_STRP => {
+                let strings = if form == DW_FORM_STRP { debug_str } else { debug_line_str };
+                let pos = self.offset(offset_size)? as usize;
+                let mut reader = DwarfReader { data: strings, pos };
+                return Ok(Attribute::String(reader.str()?));
+            }
+            DW_FORM_UDATA => self.uleb()?,
+            DW_FORM_DATA1 => self.offset(1)?,
+            DW_FORM_DATA2 => self.offset(2)?,
+            DW_FORM_DATA4 => self.offset(4)?,
+            DW_FORM_DATA8 => self.offset(8)?,
+            DW_FORM_DATA16 => {
+                self.bytes(16)?;
+                0
+            }
+            DW_FORM_BLOCK => {
+                let len = self.uleb()? as usize;
+                self.bytes(len)?;
+                0
+            }
+            _ => return Err(format!("Unsupported DWARF form {form:#x} in line table header")),
+        };
+        Ok(Attribute::Number(value))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A version 4 line program mapping code addresses to lines of "src/lib.c".
+    pub fn line_program(lines: &[(u8, u8)]) -> Vec<u8> {
+        let mut header = vec![
+            1,              // minimum instruction length
+            1,              // maximum operations per instruction
+            1,              // default is_stmt
+            0xfb,           // line base -5
+            14,             // line range
+            13,             // opcode base
+            0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1,
+        ];
+        header.extend_from_slice(b"src\0\0lib.c\0\x01\0\0\0");
+
+        let mut program = Vec::new();
+        let mut line = 1;
+        for (address, to) in lines {
+            program.extend_from_slice(&[0, 5, DW_LNE_SET_ADDRESS, *address, 0, 0, 0]);
+            program.extend_from_slice(&[DW_LNS_ADVANCE_LINE, (*to as i8 - line) as u8 & 0x7f]);
+            program.push(DW_LNS_COPY);
+            line = *to as i8;
+        }
+        let last = lines.last().map_or(0, |(address, _)| *address) + 1;
+        program.extend_from_slice(&[0, 5, DW_LNE_SET_ADDRESS, last, 0, 0, 0]);
+        program.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+
+        let mut unit = 4u16.to_le_bytes().to_vec();
+        unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        unit.extend(header);
+        unit.extend(program);
+        let mut section = (unit.len() as u32).to_le_bytes().to_vec();
+        section.extend(unit);
+        section
+    }
+
+    #[test]
+    fn looks_up_lines() {
+        let section = line_program(&[(4, 10), (7, 12), (9, 11)]);
+        let table = LineTable::parse(&section, &[], &[]).unwrap();
+        assert_eq!(None, table.lookup(3));
+        assert_eq!(Some(SourceLine { file: "src/lib.c", line: 10 }), table.lookup(4));
+        assert_eq!(Some(SourceLine { file: "src/lib.c", line: 10 }), table.lookup(6));
+        assert_eq!(Some(SourceLine { file: "src/lib.c", line: 12 }), table.lookup(7));
+        assert_eq!(Some(SourceLine { file: "src/lib.c", line: 11 }), table.lookup(9));
+        assert_eq!(None, table.lookup(10));
+    }
+
+    #[test]
+    fn rejects_truncated_units() {
+        let section = line_program(&[(4, 10)]);
+        assert!(LineTable::parse(&section[..section.len() - 2], &[], &[]).is_err());
+    }
+}
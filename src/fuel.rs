@@ -0,0 +1,71 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use crate::bytecode::op::*;
+use crate::bytecode::{gc, misc};
+
+/// The fuel charged for executing each instruction, indexed by opcode, or by prefix and
+/// sub-opcode for prefixed instructions. A prefixed instruction without a cost of its own
+/// costs what its prefix does.
+#[derive(Clone)]
+pub struct CostTable {
+    costs: [u64; 256],
+    prefixed: HashMap<(u8, u32), u64>,
+    /// Charged for each byte or element a bulk instruction processes, on top of its cost.
+    per_element: u64,
+}
+
+impl Default for CostTable {
+    /// Every instruction costs 1, except those that do no work at runtime, plus 1 for each
+    /// byte or element a bulk instruction processes.
+    fn default() -> Self {
+        let mut table = Self::uniform(1);
+        for op in [NOP, BLOCK, LOOP, END, DROP] {
+            table.set(op, 0);
+        }
+        table
+    }
+}
+
+impl CostTable {
+    pub fn uniform(cost: u64) -> Self {
+        Self { costs: [cost; 256], prefixed: HashMap::new(), per_element: cost }
+    }
+
+    pub fn set(&mut self, op: u8, cost: u64) {
+        self.costs[op as usize] = cost;
+    }
+
+    pub fn set_prefixed(&mut self, prefix: u8, op: u32, cost: u64) {
+        self.prefixed.insert((prefix, op), cost);
+    }
+
+    /// Sets the fuel charged for each byte or element of a bulk instruction.
+    pub fn set_per_element(&mut self, cost: u64) {
+        self.per_element = cost;
+    }
+
+    #[inline]
+    pub fn cost(&self, op: u8) -> u64 {
+        self.costs[op as usize]
+    }
+
+    pub fn prefixed_cost(&self, prefix: u8, op: u32) -> u64 {
+        self.prefixed.get(&(prefix, op)).copied().unwrap_or(self.costs[prefix as usize])
+    }
+
+    /// The extra fuel for a bulk instruction processing `len` bytes or elements, or 0 if
+    /// the instruction isn't one. Every bulk instruction takes the length last.
+    pub fn bulk_cost(&self, prefix: u8, op: u32, len: u64) -> u64 {
+        let bulk = match prefix {
+            MISC_PREFIX => matches!(op, misc::MEMORY_INIT | misc::MEMORY_COPY
+                | misc::MEMORY_FILL | misc::TABLE_INIT | misc::TABLE_COPY | misc::TABLE_FILL),
+            GC_PREFIX => matches!(op, gc::ARRAY_NEW | gc::ARRAY_NEW_DEFAULT
+                | gc::ARRAY_NEW_DATA | gc::ARRAY_NEW_ELEM | gc::ARRAY_FILL | gc::ARRAY_COPY
+                | gc::ARRAY_INIT_DATA | gc::ARRAY_INIT_ELEM),
+            _ => false,
+        };
+        if bulk { self.per_element.saturating_mul(len) } else { 0 }
+    }
+}
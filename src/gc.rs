@@ -0,0 +1,226 @@
+#![allow(dead_code)]
+
+//! The heap of struct and array objects, and how references in the `any` hierarchy
+//! are stored in a slot.
+//!
+//! Slots are untagged, so the collector is conservative: any slot that looks like an
+//! object reference keeps that object alive. An integer that happens to match the
+//! pattern can only delay freeing an object, never free a live one.
+
+use crate::stack::NULL_REF;
+use crate::value::HeapType;
+
+/// Set in the slot of an `i31ref`, whose value is in the low 31 bits.
+pub const I31_TAG: u64 = 1 << 32;
+/// Set in the slot of a struct or array, whose index in the heap is in the low 32 bits.
+pub const OBJECT_TAG: u64 = 1 << 33;
+/// Toggled by `any.convert_extern` and `extern.convert_any`, so that converting a
+/// reference back gives the original.
+pub const CONVERTED_TAG: u64 = 1 << 34;
+
+/// The number of live objects at which the first collection happens.
+const INITIAL_THRESHOLD: usize = 1024;
+/// The most fields or elements an object may have, so that an array's length alone
+/// can't exhaust the host's memory.
+pub const MAX_OBJECT_SLOTS: usize = 1 << 26;
+
+/// The slot of the `i31ref` holding the low 31 bits of `value`.
+pub fn i31(value: i32) -> u64 {
+    I31_TAG | (value as u32 & 0x7fff_ffff) as u64
+}
+
+/// The value of an `i31ref`, sign or zero extended from 31 bits.
+pub fn i31_value(raw: u64, signed: bool) -> i32 {
+    let value = raw as u32 & 0x7fff_ffff;
+    if signed { ((value << 1) as i32) >> 1 } else { value as i32 }
+}
+
+/// Converts a reference between the `any` and `extern` hierarchies. Converting it back
+/// gives the original, and null stays null.
+pub fn convert(raw: u64) -> u64 {
+    if raw == NULL_REF { raw } else { raw ^ CONVERTED_TAG }
+}
+
+/// Space for `len` fields, or `None` if that is too many or can't be allocated.
+pub fn slots(len: usize) -> Option<Vec<u128>> {
+    if len > MAX_OBJECT_SLOTS {
+        return None;
+    }
+    let mut slots = Vec::new();
+    slots.try_reserve_exact(len).ok()?;
+    Some(slots)
+}
+
+/// The heap index a slot refers to if it looks like a reference to an object, possibly
+/// converted to an `externref`.
+pub fn object_index(raw: u128) -> Option<u32> {
+    let raw = u64::try_from(raw).ok()?;
+    let tags = raw >> 32;
+    (tags & !(CONVERTED_TAG >> 32) == OBJECT_TAG >> 32).then_some(raw as u32)
+}
+
+/// A struct's fields or an array's elements, as slots. Packed fields keep only their
+/// low 8 or 16 bits.
+#[derive(Debug)]
+pub struct Object {
+    pub type_idx: u32,
+    pub fields: Vec<u128>,
+}
+
+/// The objects allocated by a VM, freed by a mark and sweep collection once enough
+/// have been allocated since the last one.
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    free: Vec<u32>,
+    live: usize,
+    threshold: usize,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            free: Vec::new(),
+            live: 0,
+            threshold: INITIAL_THRESHOLD,
+        }
+    }
+
+    /// The number of objects not yet freed.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Whether the heap has grown enough since the last collection to collect again.
+    pub fn should_collect(&self) -> bool {
+        self.live >= self.threshold
+    }
+
+    /// Adds an object, returning the slot referring to it.
+    pub fn allocate(&mut self, object: Object) -> u64 {
+        self.live += 1;
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.objects[idx as usize] = Some(object);
+                idx
+            }
+            None => {
+                self.objects.push(Some(object));
+                (self.objects.len() - 1) as u32
+            }
+        };
+        OBJECT_TAG | idx as u64
+    }
+
+    /// The object a non-null struct or array reference refers to.
+    pub fn get(&self, raw: u64) -> &Object {
+        self.object(raw).expect("Reference to a freed object")
+    }
+
+    /// The object `raw` refers to, if it is a reference to one in this heap.
+    pub fn object(&self, raw: u64) -> Option<&Object> {
+        let idx = object_index(raw as u128).filter(|_| raw & CONVERTED_TAG == 0)?;
+        self.objects.get(idx as usize)?.as_ref()
+    }
+
+    /// The exact type of a non-null reference in the `any` hierarchy: the defined type
+    /// of an object, `i31`, or just `any` for an external reference converted with
+    /// `any.convert_extern`. `None` if it refers to no object in this heap.
+    pub fn type_of(&self, raw: u64) -> Option<HeapType> {
+        if raw & CONVERTED_TAG != 0 {
+            Some(HeapType::Any)
+        } else if raw & I31_TAG != 0 {
+            Some(HeapType::I31)
+        } else {
+            self.object(raw).map(|object| HeapType::Concrete(object.type_idx))
+        }
+    }
+
+    /// The fields of every object not yet freed.
+    pub fn fields(&self) -> impl Iterator<Item = &u128> {
+        self.objects.iter().flatten().flat_map(|object| &object.fields)
+    }
+
+    pub fn get_mut(&mut self, raw: u64) -> &mut Object {
+        self.objects[raw as u32 as usize].as_mut().expect("Reference to a freed object")
+    }
+
+    /// Frees every object that can't be reached from `roots`, the slots of the stack,
+    /// globals, tables and anywhere else wasm can read a reference from.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = u128>) {
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<u32> = roots.into_iter().filter_map(object_index).collect();
+        while let Some(idx) = pending.pop() {
+            // A root may only look like a reference
+            let Some(Some(object)) = self.objects.get(idx as usize) else {
+                continue;
+            };
+            if !std::mem::replace(&mut marked[idx as usize], true) {
+                pending.extend(object.fields.iter().filter_map(|raw| object_index(*raw)));
+            }
+        }
+
+        for (idx, (object, marked)) in self.objects.iter_mut().zip(marked).enumerate() {
+            if object.is_some() && !marked {
+                *object = None;
+                self.free.push(idx as u32);
+                self.live -= 1;
+            }
+        }
+        self.threshold = (self.live * 2).max(INITIAL_THRESHOLD);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_unreachable_objects() {
+        let mut heap = Heap::new();
+        let leaf = heap.allocate(Object { type_idx: 0, fields: vec![7] });
+        let root = heap.allocate(Object { type_idx: 1, fields: vec![leaf as u128] });
+        let garbage = heap.allocate(Object { type_idx: 1, fields: vec![root as u128] });
+        // Cycles are collected too
+        heap.get_mut(garbage).fields.push(garbage as u128);
+
+        heap.collect([root as u128, i31(3) as u128, 42]);
+        assert_eq!(2, heap.len());
+        assert_eq!(vec![7], heap.get(leaf).fields);
+
+        // Converting to an externref keeps the object alive
+        heap.collect([(root | CONVERTED_TAG) as u128]);
+        assert_eq!(2, heap.len());
+        heap.collect([]);
+        assert!(heap.is_empty());
+        // Freed indices are reused
+        let reused = heap.allocate(Object { type_idx: 0, fields: Vec::new() });
+        assert!(object_index(reused as u128).is_some_and(|idx| idx < 3));
+    }
+
+    #[test]
+    fn i31_round_trips() {
+        assert_eq!(-1, i31_value(i31(-1), true));
+        assert_eq!(0x7fff_ffff, i31_value(i31(-1), false));
+        assert_eq!(-0x4000_0000, i31_value(i31(0x4000_0000), true));
+        assert_eq!(None, object_index(i31(5) as u128));
+        assert_eq!(None, object_index(NULL_REF as u128));
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let mut heap = Heap::new();
+        let object = heap.allocate(Object { type_idx: 2, fields: Vec::new() });
+        let converted = convert(object);
+        assert_eq!(object, convert(converted));
+        assert_eq!(NULL_REF, convert(NULL_REF));
+        assert_eq!(Some(HeapType::Concrete(2)), heap.type_of(object));
+        assert_eq!(Some(HeapType::Any), heap.type_of(converted));
+        assert_eq!(Some(HeapType::I31), heap.type_of(i31(9)));
+        assert!(heap.object(converted).is_none());
+    }
+}
@@ -0,0 +1,47 @@
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+
+use crate::vm::Vm;
+
+/// A position in the code: a function and the offset of an instruction within its body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub func: usize,
+    pub offset: usize,
+}
+
+pub enum HookAction {
+    Continue,
+    /// Stops before the instruction with a `Paused` trap. Resuming executes the
+    /// instruction without calling the hook for it again.
+    Pause,
+}
+
+/// Observes execution one instruction at a time. Install with [`Vm::set_hook`].
+pub trait Hook: Send {
+    /// Called before the instruction at `location` executes.
+    fn instruction(&mut self, vm: &Vm, location: Location) -> HookAction;
+
+    /// Called when a function's frame has been entered, before its first instruction.
+    fn enter(&mut self, _vm: &Vm, _func: usize) {}
+
+    /// Called when a function's frame is about to be left, by returning or by a trap
+    /// unwinding it.
+    fn exit(&mut self, _vm: &Vm, _func: usize) {}
+}
+
+/// Lets a hook be shared, so its owner can inspect or change it between invocations.
+impl<H: Hook> Hook for Arc<Mutex<H>> {
+    fn instruction(&mut self, vm: &Vm, location: Location) -> HookAction {
+        self.lock().unwrap().instruction(vm, location)
+    }
+
+    fn enter(&mut self, vm: &Vm, func: usize) {
+        self.lock().unwrap().enter(vm, func)
+    }
+
+    fn exit(&mut self, vm: &Vm, func: usize) {
+        self.lock().unwrap().exit(vm, func)
+    }
+}
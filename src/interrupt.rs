@@ -0,0 +1,26 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A handle for stopping a running [`Vm`](crate::vm::Vm) from another thread.
+///
+/// The interpreter polls the handle at function entries and loop back-edges, and while
+/// parked in `memory.atomic.wait`, and traps with `Interrupted` once it is set. The
+/// request is consumed by that trap, so an interrupt made while nothing is running stops
+/// the next invocation instead.
+#[derive(Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn take(&self) -> bool {
+        self.flag.load(Ordering::Relaxed) && self.flag.swap(false, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,126 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const DEFAULT_CALL_DEPTH: usize = 1024;
+pub const DEFAULT_STACK_SLOTS: usize = 1 << 20;
+
+/// Decides how much a module may allocate.
+///
+/// Memory and table creation at instantiation and every later growth are checked
+/// against the limiter. Denying creation fails instantiation, while denying growth
+/// makes the growing instruction return -1 as if the declared maximum was reached.
+/// A single limiter may be shared by several instances to bound them together.
+pub trait ResourceLimiter: Send + Sync {
+    /// A memory is growing from `current` to `desired` bytes. `maximum` is the declared
+    /// maximum in bytes, if any.
+    fn memory_growing(&self, current: usize, desired: usize, maximum: Option<usize>) -> bool;
+
+    /// A table is growing from `current` to `desired` elements.
+    fn table_growing(&self, current: u32, desired: u32, maximum: Option<u32>) -> bool;
+
+    /// An instance is being created. Returning `false` fails instantiation.
+    fn instance_creating(&self) -> bool {
+        true
+    }
+
+    /// An instance created after `instance_creating` was dropped.
+    fn instance_dropped(&self) {}
+
+    /// The deepest call stack allowed, in frames.
+    fn max_call_depth(&self) -> usize {
+        DEFAULT_CALL_DEPTH
+    }
+
+    /// The largest value stack allowed, in slots, including locals.
+    fn max_stack_slots(&self) -> usize {
+        DEFAULT_STACK_SLOTS
+    }
+}
+
+/// A [`ResourceLimiter`] with fixed caps. Every cap defaults to unlimited, except the
+/// call depth and stack size, which default to the interpreter's usual limits.
+pub struct BasicLimiter {
+    memory_size: Option<usize>,
+    table_elements: Option<u32>,
+    instances: Option<usize>,
+    call_depth: usize,
+    stack_slots: usize,
+    instance_count: AtomicUsize,
+}
+
+impl Default for BasicLimiter {
+    fn default() -> Self {
+        Self {
+            memory_size: None,
+            table_elements: None,
+            instances: None,
+            call_depth: DEFAULT_CALL_DEPTH,
+            stack_slots: DEFAULT_STACK_SLOTS,
+            instance_count: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl BasicLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caps each memory at `bytes`.
+    pub fn memory_size(mut self, bytes: usize) -> Self {
+        self.memory_size = Some(bytes);
+        self
+    }
+
+    /// Caps each table at `elements`.
+    pub fn table_elements(mut self, elements: u32) -> Self {
+        self.table_elements = Some(elements);
+        self
+    }
+
+    /// Caps the number of live instances using this limiter.
+    pub fn instances(mut self, instances: usize) -> Self {
+        self.instances = Some(instances);
+        self
+    }
+
+    pub fn call_depth(mut self, frames: usize) -> Self {
+        self.call_depth = frames;
+        self
+    }
+
+    pub fn stack_slots(mut self, slots: usize) -> Self {
+        self.stack_slots = slots;
+        self
+    }
+}
+
+impl ResourceLimiter for BasicLimiter {
+    fn memory_growing(&self, _current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        self.memory_size.is_none_or(|limit| desired <= limit)
+    }
+
+    fn table_growing(&self, _current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        self.table_elements.is_none_or(|limit| desired <= limit)
+    }
+
+    fn instance_creating(&self) -> bool {
+        let limit = self.instances.unwrap_or(usize::MAX);
+        self.instance_count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < limit).then_some(n + 1))
+            .is_ok()
+    }
+
+    fn instance_dropped(&self) {
+        self.instance_count.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn max_call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    fn max_stack_slots(&self) -> usize {
+        self.stack_slots
+    }
+}
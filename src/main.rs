@@ -1,14 +1,51 @@
+mod bindgen;
 mod bytecode;
+mod canonical;
+mod component;
+mod component_vm;
+mod const_expr;
+mod coverage;
+mod debugger;
+mod disasm;
+mod dwarf;
+mod fuel;
+mod gc;
+mod hook;
+mod interrupt;
+mod limits;
+mod memory;
+mod metadata;
+mod profiler;
+mod shared_memory;
+mod stack;
+mod stream;
+mod v128;
+mod validate;
 mod value;
 mod wasm_module;
+mod wit;
 mod vm;
 
+#[cfg(test)]
+mod bindgen_app;
+#[cfg(test)]
+mod bindgen_greeter;
+#[cfg(test)]
+mod test_util;
+
+use std::sync::{Arc, Mutex};
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(command) = args.get(1) {
+        return run_command(command, &args[2..]);
+    }
+
     let _simple_two_func =
         "AGFzbQEAAAABCwJgAn9/AX9gAAF9AwMCAAEKFQILAEGZBhogACABagsHAEPD9UhACwAMBG5hbWUCBQIAAAEA";
     let simple_one_func_start =
         "AGFzbQEAAAABBAFgAAADAgEACAEACgoBCABBCkECbBoLABMEbmFtZQEHAQAEbWFpbgIDAQAA";
-    
+
     let wasm_test = simple_one_func_start;
     
     let bytecode = base64::decode(wasm_test).unwrap();
@@ -24,3 +61,163 @@ fn main() {
         }
     }
 }
+
+fn run_command(command: &str, args: &[String]) {
+    match (command, args) {
+        ("debug", [path]) => {
+            if let Some(module) = load_file(path) {
+                debugger::repl(&module);
+            }
+        }
+        ("profile", [flag, folded, path, rest @ ..]) if flag == "--folded" => {
+            profile(path, rest, Some(folded));
+        }
+        ("profile", [path, rest @ ..]) => profile(path, rest, None),
+        ("coverage", [flag, lcov, path, rest @ ..]) if flag == "--lcov" => {
+            coverage(path, rest, Some(lcov));
+        }
+        ("coverage", [path, rest @ ..]) => coverage(path, rest, None),
+        ("info", [path]) => info(path),
+        ("bindgen", [path]) => bindgen(path, None),
+        ("bindgen", [path, world]) => bindgen(path, Some(world)),
+        _ => {
+            eprintln!("Usage: wavm debug <file.wasm>");
+            eprintln!("       wavm profile [--folded <out.folded>] <file.wasm> <func> [args...]");
+            eprintln!("       wavm coverage [--lcov <out.info>] <file.wasm> <func> [args...]");
+            eprintln!("       wavm info <file.wasm>");
+            eprintln!("       wavm bindgen <file.wit> [<world>]");
+        }
+    }
+}
+
+/// Prints a module's custom sections, and what built it and which features it needs
+/// when it says.
+fn info(path: &str) {
+    let Some(module) = load_file(path) else {
+        return;
+    };
+    for section in &module.custom_sections {
+        let size = section.contents.len();
+        println!("Custom section {:?} at {:#x}, {size} bytes", section.name, section.offset);
+    }
+    match metadata::Producers::from_module(&module) {
+        Some(Ok(producers)) => print!("\nProducers\n{producers}"),
+        Some(Err(err)) => eprintln!("Malformed producers section: {err}"),
+        None => {}
+    }
+    match metadata::TargetFeatures::from_module(&module) {
+        Some(Ok(features)) => print!("\nTarget features\n{features}"),
+        Some(Err(err)) => eprintln!("Malformed target_features section: {err}"),
+        None => {}
+    }
+}
+
+fn profile(path: &str, args: &[String], folded: Option<&str>) {
+    let Some(module) = load_file(path) else {
+        return;
+    };
+    let args: Vec<_> = args.iter().map(String::as_str).collect();
+    let (func_idx, values) = match debugger::invocation(&module, &args) {
+        Ok(invocation) => invocation,
+        Err(err) => return eprintln!("{err}"),
+    };
+    let mut vm = match vm::Vm::new(&module) {
+        Ok(vm) => vm,
+        Err(trap) => return eprintln!("Instantiation failed: {trap:?}"),
+    };
+
+    let profiler = Arc::new(Mutex::new(profiler::Profiler::new(&module)));
+    vm.set_hook(Some(Box::new(profiler.clone())));
+    match vm.invoke(func_idx, &values) {
+        Ok(results) => println!("Result: {results:?}\n"),
+        Err(trap) => println!("Trapped: {trap:?}\n"),
+    }
+    drop(vm);
+
+    let profiler = profiler.lock().unwrap();
+    print!("{}", profiler.summary(&module));
+    if let Some(folded) = folded {
+        if let Err(err) = std::fs::write(folded, profiler.folded_stacks(&module)) {
+            eprintln!("Cannot write {folded}: {err}");
+        }
+    }
+}
+
+fn coverage(path: &str, args: &[String], lcov: Option<&str>) {
+    let Some(module) = load_file(path) else {
+        return;
+    };
+    let args: Vec<_> = args.iter().map(String::as_str).collect();
+    let (func_idx, values) = match debugger::invocation(&module, &args) {
+        Ok(invocation) => invocation,
+        Err(err) => return eprintln!("{err}"),
+    };
+    let lines = match dwarf::LineTable::from_module(&module) {
+        Some(Ok(lines)) => Some(lines),
+        Some(Err(err)) => {
+            eprintln!("Ignoring DWARF line table: {err}");
+            None
+        }
+        None => None,
+    };
+    let mut vm = match vm::Vm::new(&module) {
+        Ok(vm) => vm,
+        Err(trap) => return eprintln!("Instantiation failed: {trap:?}"),
+    };
+
+    let coverage = Arc::new(Mutex::new(coverage::Coverage::new(&module)));
+    vm.set_hook(Some(Box::new(coverage.clone())));
+    match vm.invoke(func_idx, &values) {
+        Ok(results) => println!("Result: {results:?}\n"),
+        Err(trap) => println!("Trapped: {trap:?}\n"),
+    }
+    drop(vm);
+
+    let coverage = coverage.lock().unwrap();
+    print!("{}", coverage.annotated(&module, lines.as_ref()));
+    if let Some(lcov) = lcov {
+        if let Err(err) = std::fs::write(lcov, coverage.lcov(&module, lines.as_ref(), path)) {
+            eprintln!("Cannot write {lcov}: {err}");
+        }
+    }
+}
+
+/// Prints the bindings of a world of a WIT file, which may be left out if it is the only
+/// one.
+fn bindgen(path: &str, world: Option<&str>) {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => return eprintln!("Cannot read {path}: {err}"),
+    };
+    let package = match wit::parse(&source) {
+        Ok(package) => package,
+        Err(err) => return eprintln!("{path}: {err}"),
+    };
+    let world = match (world, package.worlds.as_slice()) {
+        (Some(world), _) => world,
+        (None, [world]) => &world.name,
+        (None, _) => return eprintln!("{path} has several worlds or none; name one"),
+    };
+    match bindgen::generate(&package, world) {
+        Ok(bindings) => print!("{bindings}"),
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Loads a module from a file, which is mapped into memory rather than read.
+fn load_file(path: &str) -> Option<wasm_module::WasmModule<'static>> {
+    let bytes = match std::fs::File::open(path).and_then(|file| wasm_module::Bytes::map(&file)) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Cannot read {path}: {err}");
+            return None;
+        }
+    };
+    match wasm_module::load_bytes(bytes) {
+        Ok(module) => Some(module),
+        Err(err) => {
+            eprintln!("{}", err.formatted());
+            None
+        }
+    }
+}
@@ -0,0 +1,381 @@
+#![allow(dead_code)]
+
+use std::borrow::Cow;
+
+use crate::shared_memory::SharedMemory;
+use crate::stack::NULL_REF;
+use crate::value::{Limits, MemoryType, RefType};
+
+pub const PAGE_SIZE: usize = 0x1_0000;
+pub const MAX_PAGES: u64 = 0x1_0000;
+/// The most pages a 64-bit memory may have, so its size in bytes fits a `u64`.
+pub const MAX_PAGES_64: u64 = 1 << 48;
+
+/// A linear memory instance.
+pub struct Memory {
+    data: Data,
+    max: Option<u64>,
+    memory64: bool,
+    shared: bool,
+}
+
+/// The bytes of a memory: owned by one instance, or shared between several.
+enum Data {
+    Owned(Vec<u8>),
+    Shared(SharedMemory),
+}
+
+impl Memory {
+    /// A memory of the type's minimum size, or `None` if that can't be allocated.
+    pub fn new(ty: MemoryType) -> Option<Self> {
+        if ty.shared {
+            return Some(Self::from_shared(SharedMemory::try_new(ty.limits)?, ty));
+        }
+        let mut data = Vec::new();
+        let len = page_bytes(ty.limits.min)?;
+        data.try_reserve_exact(len).ok()?;
+        data.resize(len, 0);
+        Some(Self {
+            data: Data::Owned(data),
+            max: ty.limits.max,
+            memory64: ty.memory64,
+            shared: false,
+        })
+    }
+
+    /// A memory of type `ty` backed by `memory`, whose contents the host or other
+    /// instances may also access.
+    pub fn from_shared(memory: SharedMemory, ty: MemoryType) -> Self {
+        Self {
+            max: Some(memory.max()),
+            data: Data::Shared(memory),
+            memory64: ty.memory64,
+            shared: ty.shared,
+        }
+    }
+
+    /// Whether the memory is addressed with i64.
+    pub fn is_64(&self) -> bool {
+        self.memory64
+    }
+
+    /// Whether the memory's type is shared, so threads may wait on it. An imported
+    /// memory may be backed by a [`SharedMemory`] without being of a shared type.
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// The shared memory backing this one, if it is shared.
+    pub fn shared(&self) -> Option<&SharedMemory> {
+        match &self.data {
+            Data::Owned(_) => None,
+            Data::Shared(memory) => Some(memory),
+        }
+    }
+
+    /// The current size in pages.
+    pub fn size(&self) -> u64 {
+        (self.len() / PAGE_SIZE) as u64
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.data {
+            Data::Owned(data) => data.len(),
+            Data::Shared(memory) => memory.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.max
+    }
+
+    /// The largest size in pages this memory may grow to.
+    pub fn max_pages(&self) -> u64 {
+        let limit = if self.memory64 { MAX_PAGES_64 } else { MAX_PAGES };
+        self.max.unwrap_or(limit).min(limit)
+    }
+
+    /// Grows the memory by `delta` pages, returning the previous size. Growth past the
+    /// maximum, or that can't be allocated, returns `None` and leaves the memory
+    /// unchanged.
+    pub fn grow(&mut self, delta: u64) -> Option<u64> {
+        let max = self.max_pages();
+        match &mut self.data {
+            Data::Owned(data) => {
+                let old = (data.len() / PAGE_SIZE) as u64;
+                let new = old.checked_add(delta)?;
+                if new > max {
+                    return None;
+                }
+                let len = page_bytes(new)?;
+                let additional = len - data.len();
+                data.try_reserve_exact(additional).ok()?;
+                data.resize(len, 0);
+                Some(old)
+            }
+            Data::Shared(memory) => memory.grow(delta),
+        }
+    }
+
+    /// The `N` bytes at `addr + offset`, or `None` if any are out of bounds.
+    #[inline]
+    pub fn load<const N: usize>(&self, addr: u64, offset: u64) -> Option<[u8; N]> {
+        let start = effective_address(addr, offset)?;
+        match &self.data {
+            Data::Owned(data) => Some(data.get(start..start.checked_add(N)?)?.try_into().unwrap()),
+            Data::Shared(memory) => memory.read(start),
+        }
+    }
+
+    /// Writes `bytes` at `addr + offset`, returning `false` if any are out of bounds.
+    #[inline]
+    pub fn store<const N: usize>(&mut self, addr: u64, offset: u64, bytes: [u8; N]) -> bool {
+        let Some(start) = effective_address(addr, offset) else {
+            return false;
+        };
+        let Some(end) = start.checked_add(N) else {
+            return false;
+        };
+        match &mut self.data {
+            Data::Owned(data) => match data.get_mut(start..end) {
+                Some(dest) => {
+                    dest.copy_from_slice(&bytes);
+                    true
+                }
+                None => false,
+            },
+            Data::Shared(memory) => memory.write(start, &bytes),
+        }
+    }
+
+    /// Reads the `N` bytes at `start` as a single atomic access.
+    pub fn atomic_load<const N: usize>(&self, start: usize) -> Option<[u8; N]> {
+        match &self.data {
+            Data::Owned(data) => Some(data.get(start..start + N)?.try_into().unwrap()),
+            Data::Shared(memory) => memory.atomic_read(start),
+        }
+    }
+
+    /// Writes the `N` bytes at `start` as a single atomic access, returning `false` if
+    /// any are out of bounds.
+    pub fn atomic_store<const N: usize>(&mut self, start: usize, bytes: [u8; N]) -> bool {
+        match &mut self.data {
+            Data::Owned(data) => match data.get_mut(start..start + N) {
+                Some(dest) => {
+                    dest.copy_from_slice(&bytes);
+                    true
+                }
+                None => false,
+            },
+            Data::Shared(memory) => memory.atomic_write(start, bytes),
+        }
+    }
+
+    /// Replaces the `N` bytes at `start` with `f` of them as a single atomic access,
+    /// returning the original bytes, or `None` if any are out of bounds.
+    pub fn atomic_rmw<const N: usize>(
+        &mut self,
+        start: usize,
+        f: impl FnOnce([u8; N]) -> [u8; N],
+    ) -> Option<[u8; N]> {
+        match &mut self.data {
+            Data::Owned(data) => {
+                let dest: &mut [u8; N] = data.get_mut(start..start + N)?.try_into().unwrap();
+                let old = *dest;
+                *dest = f(old);
+                Some(old)
+            }
+            Data::Shared(memory) => memory.atomic_rmw(start, f),
+        }
+    }
+
+    /// A copy of the `len` bytes starting at `src`, or `None` if any are out of bounds.
+    pub fn read(&self, src: u64, len: u64) -> Option<Vec<u8>> {
+        let end = src.checked_add(len).filter(|end| *end <= self.len() as u64)?;
+        let (src, end) = (src as usize, end as usize);
+        match &self.data {
+            Data::Owned(data) => Some(data[src..end].to_vec()),
+            Data::Shared(memory) => memory.read_bytes(src, end - src),
+        }
+    }
+
+    /// Copies `len` bytes from `src` to `dst`; the ranges may overlap. Returns `false`,
+    /// writing nothing, if either range is out of bounds.
+    pub fn copy(&mut self, dst: u64, src: u64, len: u64) -> bool {
+        let size = self.len() as u64;
+        if src.checked_add(len).is_none_or(|end| end > size)
+            || dst.checked_add(len).is_none_or(|end| end > size)
+        {
+            return false;
+        }
+        // All three are within the memory's length, so fit a usize
+        let (dst, src, len) = (dst as usize, src as usize, len as usize);
+        match &mut self.data {
+            Data::Owned(data) => data.copy_within(src..src + len, dst),
+            Data::Shared(memory) => {
+                memory.copy(dst, src, len);
+            }
+        }
+        true
+    }
+
+    /// Sets `len` bytes starting at `dst` to `value`. Returns `false`, writing nothing,
+    /// if the range is out of bounds.
+    pub fn fill(&mut self, dst: u64, value: u8, len: u64) -> bool {
+        if dst.checked_add(len).is_none_or(|end| end > self.len() as u64) {
+            return false;
+        }
+        let (dst, len) = (dst as usize, len as usize);
+        match &mut self.data {
+            Data::Owned(data) => match data.get_mut(dst..dst + len) {
+                Some(dest) => {
+                    dest.fill(value);
+                    true
+                }
+                None => false,
+            },
+            Data::Shared(memory) => memory.fill(dst, value, len),
+        }
+    }
+
+    /// Writes `bytes` starting at `dst`. Returns `false`, writing nothing, if the range
+    /// is out of bounds.
+    pub fn write(&mut self, dst: u64, bytes: &[u8]) -> bool {
+        let Some(end) = dst.checked_add(bytes.len() as u64).filter(|end| *end <= self.len() as u64)
+        else {
+            return false;
+        };
+        let (dst, end) = (dst as usize, end as usize);
+        match &mut self.data {
+            Data::Owned(data) => match data.get_mut(dst..end) {
+                Some(dest) => {
+                    dest.copy_from_slice(bytes);
+                    true
+                }
+                None => false,
+            },
+            Data::Shared(memory) => memory.write(dst, bytes),
+        }
+    }
+
+    /// The memory's contents; a snapshot if it is shared, since other threads may be
+    /// writing to it.
+    pub fn data(&self) -> Cow<'_, [u8]> {
+        match &self.data {
+            Data::Owned(data) => Cow::Borrowed(data),
+            Data::Shared(memory) => Cow::Owned(memory.to_vec()),
+        }
+    }
+
+    /// The memory's contents, unless it is shared.
+    pub fn data_mut(&mut self) -> Option<&mut [u8]> {
+        match &mut self.data {
+            Data::Owned(data) => Some(data),
+            Data::Shared(_) => None,
+        }
+    }
+}
+
+/// The size in bytes of `pages` pages, or `None` if it doesn't fit a `usize`.
+pub fn page_bytes(pages: u64) -> Option<usize> {
+    usize::try_from(pages).ok()?.checked_mul(PAGE_SIZE)
+}
+
+/// `addr + offset` as an index into a memory's bytes, or `None` if it is beyond any
+/// memory this host could hold.
+#[inline]
+fn effective_address(addr: u64, offset: u64) -> Option<usize> {
+    usize::try_from(addr.checked_add(offset)?).ok()
+}
+
+/// A table instance. Elements are stored as stack slots, with [`NULL_REF`] for null.
+pub struct Table {
+    pub elem: RefType,
+    elements: Vec<u64>,
+    max: Option<u32>,
+}
+
+impl Table {
+    /// A table of null elements of the minimum size, or `None` if that can't be allocated.
+    pub fn new(elem: RefType, limits: Limits) -> Option<Self> {
+        let mut elements = Vec::new();
+        elements.try_reserve_exact(limits.min as usize).ok()?;
+        elements.resize(limits.min as usize, NULL_REF);
+        Some(Self {
+            elem,
+            elements,
+            max: limits.max.map(|max| max as u32),
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.elements.len() as u32
+    }
+
+    pub fn max(&self) -> Option<u32> {
+        self.max
+    }
+
+    pub fn get(&self, idx: u32) -> Option<u64> {
+        self.elements.get(idx as usize).copied()
+    }
+
+    /// Sets the element at `idx`, returning `false` if it is out of bounds.
+    pub fn set(&mut self, idx: u32, raw: u64) -> bool {
+        match self.elements.get_mut(idx as usize) {
+            Some(element) => {
+                *element = raw;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Grows the table by `delta` elements set to `init`, returning the previous size,
+    /// or `None` if that would exceed its maximum or can't be allocated.
+    pub fn grow(&mut self, delta: u32, init: u64) -> Option<u32> {
+        let old = self.size();
+        let new = old.checked_add(delta)?;
+        if new > self.max.unwrap_or(u32::MAX) {
+            return None;
+        }
+        self.elements.try_reserve_exact(delta as usize).ok()?;
+        self.elements.resize(new as usize, init);
+        Some(old)
+    }
+
+    /// The `len` elements starting at `start`, or `None` if any are out of bounds.
+    pub fn slice(&self, start: u32, len: u32) -> Option<&[u64]> {
+        let start = start as usize;
+        self.elements.get(start..start + len as usize)
+    }
+
+    /// Writes `elements` starting at `dst`. Returns `false`, writing nothing, if the
+    /// range is out of bounds.
+    pub fn write(&mut self, dst: u32, elements: &[u64]) -> bool {
+        let dst = dst as usize;
+        match self.elements.get_mut(dst..dst + elements.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(elements);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets `len` elements starting at `start`, returning `false` if any are out of bounds.
+    pub fn fill(&mut self, start: u32, raw: u64, len: u32) -> bool {
+        let start = start as usize;
+        match self.elements.get_mut(start..start + len as usize) {
+            Some(elements) => {
+                elements.fill(raw);
+                true
+            }
+            None => false,
+        }
+    }
+}
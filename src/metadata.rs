@@ -0,0 +1,198 @@
+#![allow(dead_code)]
+
+//! The tool conventions' `producers` and `target_features` custom sections, which say
+//! what built a module and which proposals it relies on.
+
+use std::fmt;
+
+use crate::bytecode::read::Reader;
+use crate::wasm_module::WasmModule;
+
+/// A tool and its version, as listed in the `producers` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Producer {
+    pub name: String,
+    pub version: String,
+}
+
+/// The `producers` section: for each field, such as `language`, `processed-by` or
+/// `sdk`, the tools that contributed to the module.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Producers {
+    pub fields: Vec<(String, Vec<Producer>)>,
+}
+
+impl Producers {
+    /// Parses the module's producers, or returns `None` if it has no such section.
+    pub fn from_module(module: &WasmModule) -> Option<Result<Self, String>> {
+        module.custom_section("producers").map(Self::parse)
+    }
+
+    pub fn parse(section: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(section, 0);
+        let mut producers = Self::default();
+        for _ in 0..reader.u32()? {
+            let field = reader.name()?;
+            if producers.field(&field).is_some() {
+                return Err(format!("Duplicate producers field {field}"));
+            }
+            let mut values = Vec::new();
+            for _ in 0..reader.u32()? {
+                let name = reader.name()?;
+                let version = reader.name()?;
+                values.push(Producer { name, version });
+            }
+            producers.fields.push((field, values));
+        }
+        if !reader.at_end() {
+            return Err(String::from("Unexpected bytes after the producers section"));
+        }
+        Ok(producers)
+    }
+
+    /// The tools listed under `field`, if the section has it.
+    pub fn field(&self, field: &str) -> Option<&[Producer]> {
+        let (_, values) = self.fields.iter().find(|(name, _)| name == field)?;
+        Some(values)
+    }
+}
+
+impl fmt::Display for Producers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (field, values) in &self.fields {
+            write!(f, "{field}:")?;
+            for producer in values {
+                match producer.version.as_str() {
+                    "" => write!(f, " {}", producer.name)?,
+                    version => write!(f, " {} {version}", producer.name)?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a module uses a feature, requires every module it links with to, or must not
+/// be linked with one that does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeaturePolicy {
+    Used,
+    Required,
+    Disallowed,
+}
+
+/// The `target_features` section: the features the module was compiled for, such as
+/// `bulk-memory` or `simd128`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TargetFeatures {
+    pub features: Vec<(FeaturePolicy, String)>,
+}
+
+impl TargetFeatures {
+    /// Parses the module's target features, or returns `None` if it has no such section.
+    pub fn from_module(module: &WasmModule) -> Option<Result<Self, String>> {
+        module.custom_section("target_features").map(Self::parse)
+    }
+
+    pub fn parse(section: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(section, 0);
+        let mut features = Vec::new();
+        for _ in 0..reader.u32()? {
+            let policy = match reader.u8()? {
+                b'+' => FeaturePolicy::Used,
+                b'=' => FeaturePolicy::Required,
+                b'-' => FeaturePolicy::Disallowed,
+                prefix => return Err(format!("Invalid target feature prefix {prefix:#04x}")),
+            };
+            features.push((policy, reader.name()?));
+        }
+        if !reader.at_end() {
+            return Err(String::from("Unexpected bytes after the target_features section"));
+        }
+        Ok(Self { features })
+    }
+
+    /// The features the module needs the engine to support.
+    pub fn needed(&self) -> impl Iterator<Item = &str> {
+        self.features.iter()
+            .filter(|(policy, _)| *policy != FeaturePolicy::Disallowed)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn policy(&self, feature: &str) -> Option<FeaturePolicy> {
+        self.features.iter().find(|(_, name)| name == feature).map(|(policy, _)| *policy)
+    }
+}
+
+impl fmt::Display for TargetFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (policy, name) in &self.features {
+            let prefix = match policy {
+                FeaturePolicy::Used => '+',
+                FeaturePolicy::Required => '=',
+                FeaturePolicy::Disallowed => '-',
+            };
+            writeln!(f, "{prefix}{name}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::op::*;
+    use crate::test_util::{name, ModuleBuilder};
+    use crate::wasm_module::load;
+
+    #[test]
+    fn producers() {
+        let section = [
+            &[0x02][..], &name("language"), &[0x01], &name("Rust"), &name(""),
+            &name("processed-by"), &[0x02], &name("rustc"), &name("1.80.0"),
+            &name("wasm-bindgen"), &name("0.2.92"),
+        ].concat();
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[END]);
+        builder.custom("producers", &section);
+        let bytecode = builder.build();
+        let module = load(&bytecode).ok().unwrap();
+
+        let producers = Producers::from_module(&module).unwrap().unwrap();
+        let rustc = Producer { name: "rustc".into(), version: "1.80.0".into() };
+        assert_eq!(Some("Rust"), producers.field("language").map(|p| p[0].name.as_str()));
+        assert_eq!(Some(&rustc), producers.field("processed-by").and_then(|p| p.first()));
+        assert_eq!(None, producers.field("sdk"));
+        assert_eq!(
+            "language: Rust\nprocessed-by: rustc 1.80.0 wasm-bindgen 0.2.92\n",
+            producers.to_string()
+        );
+
+        let duplicate = [&[0x02][..], &name("sdk"), &[0x00], &name("sdk"), &[0x00]].concat();
+        assert_eq!(Err("Duplicate producers field sdk".into()), Producers::parse(&duplicate));
+        assert_eq!(
+            Err("Unexpected end of input".into()),
+            Producers::parse(&section[..section.len() - 1])
+        );
+    }
+
+    #[test]
+    fn target_features() {
+        let section = [
+            &[0x03, b'+'][..], &name("simd128"), b"=", &name("bulk-memory"),
+            b"-", &name("atomics"),
+        ].concat();
+        let features = TargetFeatures::parse(&section).unwrap();
+        assert_eq!(vec!["simd128", "bulk-memory"], features.needed().collect::<Vec<_>>());
+        assert_eq!(Some(FeaturePolicy::Disallowed), features.policy("atomics"));
+        assert_eq!(None, features.policy("gc"));
+        assert_eq!("+simd128\n=bulk-memory\n-atomics\n", features.to_string());
+
+        let invalid = [&[0x01, b'?'][..], &name("gc")].concat();
+        assert_eq!(
+            Err("Invalid target feature prefix 0x3f".into()),
+            TargetFeatures::parse(&invalid)
+        );
+    }
+}
@@ -0,0 +1,295 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::bytecode::op::*;
+use crate::bytecode::read::read_size;
+use crate::disasm;
+use crate::hook::{Hook, HookAction, Location};
+use crate::vm::Vm;
+use crate::wasm_module::WasmModule;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    /// Instructions executed by the function and everything it called.
+    pub inclusive_instructions: u64,
+    /// Instructions executed by the function itself.
+    pub exclusive_instructions: u64,
+    pub inclusive_time: Duration,
+    pub exclusive_time: Duration,
+}
+
+/// An instruction's opcode: its first byte, and for a prefixed instruction the
+/// sub-opcode that follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Opcode {
+    pub op: u8,
+    pub sub_op: Option<u32>,
+}
+
+impl Opcode {
+    /// The opcode of the instruction at `offset` in `code`.
+    pub fn at(code: &[u8], offset: usize) -> Self {
+        let op = code[offset];
+        let sub_op = matches!(op, MISC_PREFIX | SIMD_PREFIX | ATOMIC_PREFIX | GC_PREFIX)
+            .then(|| read_size(code, offset + 1).0);
+        Self { op, sub_op }
+    }
+
+    /// The instruction's name in the text format, or its bytes if it has none.
+    pub fn name(&self) -> String {
+        let name = match self.sub_op {
+            None => disasm::mnemonic(self.op),
+            Some(sub_op) => disasm::prefixed_mnemonic(self.op, sub_op),
+        };
+        match (name, self.sub_op) {
+            (Some(name), _) => String::from(name),
+            (None, None) => format!("{:#04x}", self.op),
+            (None, Some(sub_op)) => format!("{:#04x} {sub_op}", self.op),
+        }
+    }
+}
+
+struct ProfileFrame {
+    func: usize,
+    entered: Instant,
+    instructions_at_entry: u64,
+    child_time: Duration,
+    /// Instructions executed in this frame since its stack was last recorded.
+    pending: u64,
+}
+
+/// A [`Hook`] that records where execution goes, per function and per opcode.
+///
+/// Install it on a [`Vm`] behind an `Arc<Mutex<_>>` to read the results afterwards.
+/// Inclusive counts of a recursive function only include its outermost activation, so
+/// they are not counted twice.
+pub struct Profiler {
+    functions: Vec<FunctionStats>,
+    opcodes: HashMap<Opcode, u64>,
+    stacks: HashMap<Vec<usize>, u64>,
+    frames: Vec<ProfileFrame>,
+    active: Vec<u32>,
+    instructions: u64,
+}
+
+impl Profiler {
+    pub fn new(module: &WasmModule) -> Self {
+        let num_funcs = module.functions.len();
+        Self {
+            functions: vec![FunctionStats::default(); num_funcs],
+            opcodes: HashMap::new(),
+            stacks: HashMap::new(),
+            frames: Vec::new(),
+            active: vec![0; num_funcs],
+            instructions: 0,
+        }
+    }
+
+    pub fn function_stats(&self, func: usize) -> &FunctionStats {
+        &self.functions[func]
+    }
+
+    /// How many times each opcode was executed, for those that were.
+    pub fn opcode_counts(&self) -> &HashMap<Opcode, u64> {
+        &self.opcodes
+    }
+
+    pub fn opcode_count(&self, op: Opcode) -> u64 {
+        self.opcodes.get(&op).copied().unwrap_or(0)
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The instructions executed at each call stack, in the collapsed-stack format read
+    /// by flamegraph tools: one line per stack, outermost function first.
+    pub fn folded_stacks(&self, module: &WasmModule) -> String {
+        let mut lines: Vec<_> = self.stacks.iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(stack, count)| {
+                let names: Vec<_> = stack.iter().map(|func| module.function_label(*func)).collect();
+                format!("{} {count}", names.join(";"))
+            })
+            .collect();
+        lines.sort();
+        lines.iter().fold(String::new(), |out, line| out + line + "\n")
+    }
+
+    /// A table of the called functions, most expensive first, followed by the most
+    /// frequently executed opcodes.
+    pub fn summary(&self, module: &WasmModule) -> String {
+        let mut out = String::new();
+        let mut funcs: Vec<_> = (0..self.functions.len())
+            .filter(|func| self.functions[*func].calls > 0)
+            .collect();
+        funcs.sort_by_key(|func| std::cmp::Reverse(self.functions[*func].exclusive_instructions));
+
+        writeln!(out, "{:>8} {:>12} {:>12} {:>12} {:>12}  function",
+            "calls", "incl instrs", "excl instrs", "incl time", "excl time").unwrap();
+        for func in funcs {
+            let stats = &self.functions[func];
+            writeln!(out, "{:>8} {:>12} {:>12} {:>12.3?} {:>12.3?}  {}",
+                stats.calls,
+                stats.inclusive_instructions,
+                stats.exclusive_instructions,
+                stats.inclusive_time,
+                stats.exclusive_time,
+                module.function_label(func)).unwrap();
+        }
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by_key(|(op, count)| (std::cmp::Reverse(**count), **op));
+        writeln!(out, "\n{:>12}  opcode", "count").unwrap();
+        for (op, count) in opcodes.into_iter().take(20) {
+            writeln!(out, "{count:>12}  {}", op.name()).unwrap();
+        }
+        out
+    }
+
+    /// Adds the instructions pending in the innermost frame to its stack's total.
+    fn record_stack(&mut self) {
+        let Some(top) = self.frames.last_mut() else {
+            return;
+        };
+        let pending = std::mem::take(&mut top.pending);
+        if pending > 0 {
+            let stack: Vec<_> = self.frames.iter().map(|frame| frame.func).collect();
+            *self.stacks.entry(stack).or_default() += pending;
+        }
+    }
+}
+
+impl Hook for Profiler {
+    fn instruction(&mut self, vm: &Vm, location: Location) -> HookAction {
+        let op = Opcode::at(vm.module().function_code(location.func), location.offset);
+        *self.opcodes.entry(op).or_default() += 1;
+        self.functions[location.func].exclusive_instructions += 1;
+        self.instructions += 1;
+        if let Some(top) = self.frames.last_mut() {
+            top.pending += 1;
+        }
+        HookAction::Continue
+    }
+
+    fn enter(&mut self, _vm: &Vm, func: usize) {
+        self.record_stack();
+        self.functions[func].calls += 1;
+        self.active[func] += 1;
+        self.frames.push(ProfileFrame {
+            func,
+            entered: Instant::now(),
+            instructions_at_entry: self.instructions,
+            child_time: Duration::ZERO,
+            pending: 0,
+        });
+    }
+
+    fn exit(&mut self, _vm: &Vm, func: usize) {
+        self.record_stack();
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        debug_assert_eq!(frame.func, func);
+
+        let elapsed = frame.entered.elapsed();
+        let stats = &mut self.functions[func];
+        stats.exclusive_time += elapsed.saturating_sub(frame.child_time);
+        self.active[func] -= 1;
+        if self.active[func] == 0 {
+            stats.inclusive_time += elapsed;
+            stats.inclusive_instructions += self.instructions - frame.instructions_at_entry;
+        }
+        if let Some(parent) = self.frames.last_mut() {
+            parent.child_time += elapsed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::bytecode::misc;
+    use crate::test_util::ModuleBuilder;
+    use crate::value::{RefType, ValType::*, Value};
+    use crate::wasm_module;
+
+    #[test]
+    fn counts_calls_and_instructions() {
+        let mut builder = ModuleBuilder::new();
+        // fib(n) = n < 2 ? n : fib(n - 1) + fib(n - 2)
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, I32_CONST, 2, I32_LT_S,
+            IF, 0x7f,
+                LOCAL_GET, 0,
+            ELSE,
+                LOCAL_GET, 0, I32_CONST, 1, I32_SUB, CALL, 0,
+                LOCAL_GET, 0, I32_CONST, 2, I32_SUB, CALL, 0,
+                I32_ADD,
+            END,
+            END,
+        ]);
+        builder.func(&[], &[I32], &[], &[I32_CONST, 5, CALL, 0, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let profiler = Arc::new(Mutex::new(Profiler::new(&module)));
+        let mut vm = Vm::new(&module).unwrap();
+        vm.set_hook(Some(Box::new(profiler.clone())));
+        assert_eq!(Ok(vec![Value::I32(5)]), vm.invoke(1, &[]));
+
+        let profiler = profiler.lock().unwrap();
+        let main = profiler.function_stats(1);
+        let fib = profiler.function_stats(0);
+        assert_eq!(1, main.calls);
+        assert_eq!(15, fib.calls);
+        assert_eq!(3, main.exclusive_instructions);
+        assert_eq!(profiler.total_instructions(), main.inclusive_instructions);
+        assert_eq!(fib.exclusive_instructions, fib.inclusive_instructions);
+        assert_eq!(15, profiler.opcode_count(Opcode { op: IF, sub_op: None }));
+        assert!(main.inclusive_time >= fib.inclusive_time);
+
+        // Leaf calls of fib(1) and fib(0) at depth 5 run 7 instructions each
+        let folded = profiler.folded_stacks(&module);
+        assert!(folded.starts_with("func[1] 3\nfunc[1];func[0] "));
+        assert!(folded.contains("\nfunc[1];func[0];func[0];func[0];func[0];func[0] 14\n"));
+        let total: u64 = folded.lines()
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .sum();
+        assert_eq!(profiler.total_instructions(), total);
+    }
+
+    #[test]
+    fn counts_prefixed_opcodes_apart() {
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, None);
+        builder.table(RefType::FUNCREF, 1, None);
+        builder.func(&[], &[], &[], &[
+            I32_CONST, 0, I32_CONST, 0, I32_CONST, 4, MISC_PREFIX, 11, 0,
+            MISC_PREFIX, 16, 0, DROP, MISC_PREFIX, 16, 0, DROP, END,
+        ]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let profiler = Arc::new(Mutex::new(Profiler::new(&module)));
+        let mut vm = Vm::new(&module).unwrap();
+        vm.set_hook(Some(Box::new(profiler.clone())));
+        assert_eq!(Ok(vec![]), vm.invoke(0, &[]));
+
+        let profiler = profiler.lock().unwrap();
+        let fill = Opcode { op: MISC_PREFIX, sub_op: Some(misc::MEMORY_FILL) };
+        let size = Opcode { op: MISC_PREFIX, sub_op: Some(misc::TABLE_SIZE) };
+        assert_eq!(1, profiler.opcode_count(fill));
+        assert_eq!(2, profiler.opcode_count(size));
+        assert_eq!(0, profiler.opcode_count(Opcode { op: MISC_PREFIX, sub_op: None }));
+        let summary = profiler.summary(&module);
+        assert!(summary.contains("           2  table.size\n"));
+        assert!(summary.contains("           1  memory.fill\n"));
+    }
+}
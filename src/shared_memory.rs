@@ -0,0 +1,267 @@
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::memory::{page_bytes, MAX_PAGES_64, PAGE_SIZE};
+use crate::value::Limits;
+
+/// The longest a waiter blocks between asking whether to give up.
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+
+/// A linear memory that several instances, possibly on different threads, use at once.
+/// Clones are handles to the same memory.
+///
+/// Every byte is accessed atomically, so racing guests can't cause undefined behaviour
+/// in the host, though like wasm only atomic instructions are atomic as a whole. Those
+/// take a lock that makes them atomic with respect to each other.
+#[derive(Clone)]
+pub struct SharedMemory(Arc<Shared>);
+
+struct Shared {
+    /// Only locked for writing to grow the memory.
+    data: RwLock<Vec<AtomicU8>>,
+    max: u64,
+    atomics: Mutex<()>,
+    /// The threads blocked in `memory.atomic.wait` on each address, in arrival order.
+    waiters: Mutex<HashMap<usize, VecDeque<Arc<Waiter>>>>,
+}
+
+#[derive(Default)]
+struct Waiter {
+    woken: Mutex<bool>,
+    condvar: Condvar,
+}
+
+/// The outcome of `memory.atomic.wait`, whose result is the discriminant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitResult {
+    Woken = 0,
+    NotEqual = 1,
+    TimedOut = 2,
+    /// The waiter gave up early when asked to, which isn't a result of the instruction.
+    Stopped = 3,
+}
+
+impl SharedMemory {
+    /// A memory of `limits.min` pages. Shared memories must declare a maximum; without
+    /// one the largest possible size is used.
+    pub fn new(limits: Limits) -> Self {
+        Self::try_new(limits).expect("Shared memory too large to allocate")
+    }
+
+    /// Like [`new`](SharedMemory::new), but `None` if the memory can't be allocated.
+    pub fn try_new(limits: Limits) -> Option<Self> {
+        let len = page_bytes(limits.min)?;
+        let mut data = Vec::new();
+        data.try_reserve_exact(len).ok()?;
+        data.resize_with(len, || AtomicU8::new(0));
+        Some(Self(Arc::new(Shared {
+            data: RwLock::new(data),
+            max: limits.max.unwrap_or(MAX_PAGES_64).min(MAX_PAGES_64),
+            atomics: Mutex::new(()),
+            waiters: Mutex::new(HashMap::new()),
+        })))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.data.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The current size in pages.
+    pub fn size(&self) -> u64 {
+        (self.len() / PAGE_SIZE) as u64
+    }
+
+    pub fn max(&self) -> u64 {
+        self.0.max
+    }
+
+    /// Grows the memory by `delta` pages, returning the previous size, or `None` if that
+    /// would exceed its maximum.
+    pub fn grow(&self, delta: u64) -> Option<u64> {
+        let mut data = self.0.data.write().unwrap();
+        let old = (data.len() / PAGE_SIZE) as u64;
+        let new = old.checked_add(delta)?;
+        if new > self.0.max {
+            return None;
+        }
+        let len = page_bytes(new)?;
+        let additional = len - data.len();
+        data.try_reserve_exact(additional).ok()?;
+        data.resize_with(len, || AtomicU8::new(0));
+        Some(old)
+    }
+
+    /// The `N` bytes at `start`, or `None` if any are out of bounds.
+    pub fn read<const N: usize>(&self, start: usize) -> Option<[u8; N]> {
+        let data = self.0.data.read().unwrap();
+        let bytes = data.get(start..start.checked_add(N)?)?;
+        Some(std::array::from_fn(|i| bytes[i].load(Ordering::Relaxed)))
+    }
+
+    /// A copy of the `len` bytes at `start`, or `None` if any are out of bounds.
+    pub fn read_bytes(&self, start: usize, len: usize) -> Option<Vec<u8>> {
+        let data = self.0.data.read().unwrap();
+        let bytes = data.get(start..start.checked_add(len)?)?;
+        Some(bytes.iter().map(|byte| byte.load(Ordering::Relaxed)).collect())
+    }
+
+    /// Writes `bytes` at `dst`. Returns `false`, writing nothing, if any are out of bounds.
+    pub fn write(&self, dst: usize, bytes: &[u8]) -> bool {
+        let data = self.0.data.read().unwrap();
+        let Some(dest) = dst.checked_add(bytes.len()).and_then(|end| data.get(dst..end)) else {
+            return false;
+        };
+        for (byte, value) in dest.iter().zip(bytes) {
+            byte.store(*value, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// Copies `len` bytes from `src` to `dst`; the ranges may overlap. Returns `false`,
+    /// writing nothing, if either range is out of bounds.
+    pub fn copy(&self, dst: usize, src: usize, len: usize) -> bool {
+        let data = self.0.data.read().unwrap();
+        let (Some(src), Some(dest)) = (data.get(src..src + len), data.get(dst..dst + len)) else {
+            return false;
+        };
+        let bytes: Vec<u8> = src.iter().map(|byte| byte.load(Ordering::Relaxed)).collect();
+        for (byte, value) in dest.iter().zip(bytes) {
+            byte.store(value, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// Sets `len` bytes starting at `dst` to `value`. Returns `false`, writing nothing,
+    /// if the range is out of bounds.
+    pub fn fill(&self, dst: usize, value: u8, len: usize) -> bool {
+        let data = self.0.data.read().unwrap();
+        let Some(dest) = data.get(dst..dst + len) else {
+            return false;
+        };
+        for byte in dest {
+            byte.store(value, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// A copy of the memory's current contents.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let data = self.0.data.read().unwrap();
+        data.iter().map(|byte| byte.load(Ordering::Relaxed)).collect()
+    }
+
+    /// Reads the `N` bytes at `start` atomically.
+    pub fn atomic_read<const N: usize>(&self, start: usize) -> Option<[u8; N]> {
+        let _atomics = self.0.atomics.lock().unwrap();
+        self.read(start)
+    }
+
+    /// Writes the `N` bytes at `start` atomically, returning `false` if any are out of
+    /// bounds.
+    pub fn atomic_write<const N: usize>(&self, start: usize, bytes: [u8; N]) -> bool {
+        let _atomics = self.0.atomics.lock().unwrap();
+        self.write(start, &bytes)
+    }
+
+    /// Replaces the `N` bytes at `start` with `f` of them atomically, returning the
+    /// original bytes, or `None` if any are out of bounds.
+    pub fn atomic_rmw<const N: usize>(
+        &self,
+        start: usize,
+        f: impl FnOnce([u8; N]) -> [u8; N],
+    ) -> Option<[u8; N]> {
+        let _atomics = self.0.atomics.lock().unwrap();
+        let old = self.read(start)?;
+        self.write(start, &f(old));
+        Some(old)
+    }
+
+    /// Blocks until a [`notify`] of `start` if the `N` bytes there equal `expected`,
+    /// giving up after `timeout` if there is one, or once `stop` returns true. `stop` is
+    /// asked at least every [`WAIT_SLICE`]. `None` if the bytes are out of bounds.
+    ///
+    /// [`notify`]: SharedMemory::notify
+    pub fn wait<const N: usize>(
+        &self,
+        start: usize,
+        expected: [u8; N],
+        timeout: Option<Duration>,
+        mut stop: impl FnMut() -> bool,
+    ) -> Option<WaitResult> {
+        let waiter = {
+            // Comparing with the queue locked means a notify after a store that changed
+            // the value either happens first, or finds this waiter queued
+            let mut waiters = self.0.waiters.lock().unwrap();
+            if self.atomic_read::<N>(start)? != expected {
+                return Some(WaitResult::NotEqual);
+            }
+            let waiter = Arc::new(Waiter::default());
+            waiters.entry(start).or_default().push_back(waiter.clone());
+            waiter
+        };
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut woken = waiter.woken.lock().unwrap();
+        let mut result = WaitResult::TimedOut;
+        while !*woken {
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                break;
+            }
+            if stop() {
+                result = WaitResult::Stopped;
+                break;
+            }
+            let slice = deadline.map_or(WAIT_SLICE, |deadline| (deadline - now).min(WAIT_SLICE));
+            woken = waiter.condvar.wait_timeout(woken, slice).unwrap().0;
+        }
+        if *woken {
+            return Some(WaitResult::Woken);
+        }
+        drop(woken);
+
+        // Gave up, unless a notify dequeued this waiter in the meantime
+        let mut waiters = self.0.waiters.lock().unwrap();
+        let queue = waiters.get_mut(&start)?;
+        match queue.iter().position(|queued| Arc::ptr_eq(queued, &waiter)) {
+            Some(idx) => {
+                queue.remove(idx);
+                if queue.is_empty() {
+                    waiters.remove(&start);
+                }
+                Some(result)
+            }
+            None => Some(WaitResult::Woken),
+        }
+    }
+
+    /// Wakes up to `count` threads waiting on `start`, oldest first, returning how many
+    /// were woken.
+    pub fn notify(&self, start: usize, count: u32) -> u32 {
+        let mut waiters = self.0.waiters.lock().unwrap();
+        let Some(queue) = waiters.get_mut(&start) else {
+            return 0;
+        };
+        let mut woken = 0;
+        while woken < count {
+            let Some(waiter) = queue.pop_front() else {
+                break;
+            };
+            *waiter.woken.lock().unwrap() = true;
+            waiter.condvar.notify_one();
+            woken += 1;
+        }
+        if queue.is_empty() {
+            waiters.remove(&start);
+        }
+        woken
+    }
+}
@@ -0,0 +1,402 @@
+#![allow(dead_code)]
+
+use crate::value::{AnyRef, ExnRef, ExternRef, HeapType, RefType, ValType, Value};
+
+/// The slot holding a null reference. Function indices and host object handles are
+/// 32-bit, so it can't be confused with a non-null reference.
+pub const NULL_REF: u64 = u64::MAX;
+
+/// The interpreter's value stack.
+///
+/// Validation guarantees that every instruction finds operands of the right type, so
+/// values are stored as untagged 64-bit slots, with narrower values leaving the upper
+/// bits zero. A `v128` takes two adjacent slots, its low half first. Function locals
+/// live in the same stack, at the base of each call frame.
+///
+/// In checked mode a parallel stack of types is maintained and every access is
+/// verified against it. A mismatch means the validator and interpreter disagree, so
+/// it panics rather than trapping. References are tagged with the top of their type's
+/// family, since the interpreter doesn't track their exact types, and both slots of a
+/// `v128` are tagged with it.
+pub struct Stack {
+    slots: Vec<u64>,
+    tags: Option<Vec<ValType>>,
+    /// The top of each concrete type's family, by type index, for tagging references
+    /// to them.
+    tops: Vec<HeapType>,
+}
+
+impl Stack {
+    pub fn new(checked: bool) -> Self {
+        Self {
+            slots: Vec::new(),
+            tags: if checked { Some(Vec::new()) } else { None },
+            tops: Vec::new(),
+        }
+    }
+
+    /// Sets the family of each of the module's types, which is needed to tag references
+    /// to concrete types in checked mode.
+    pub fn set_type_tops(&mut self, tops: Vec<HeapType>) {
+        self.tops = tops;
+    }
+
+    /// The slots, from the bottom of the stack.
+    pub fn slots(&self) -> &[u64] {
+        &self.slots
+    }
+
+    fn erase(&self, ty: ValType) -> ValType {
+        erase(&self.tops, ty)
+    }
+
+    pub fn is_checked(&self) -> bool {
+        self.tags.is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        if let Some(tags) = &mut self.tags {
+            tags.clear();
+        }
+    }
+
+    /// The raw slot at absolute position `index`.
+    pub fn get(&self, index: usize) -> u64 {
+        self.slots[index]
+    }
+
+    /// The value of type `ty` starting at absolute position `index`, widened to a
+    /// `u128` as globals and object fields hold it.
+    pub fn read(&self, index: usize, ty: ValType) -> u128 {
+        let low = self.slots[index] as u128;
+        if ty == ValType::V128 { low | (self.slots[index + 1] as u128) << 64 } else { low }
+    }
+
+    /// The values from absolute position `start` to the top, if their types are known.
+    /// Types are only tracked in checked mode.
+    pub fn typed_values(&self, start: usize) -> Option<Vec<Value>> {
+        let tags = self.tags.as_ref()?;
+        let mut values = Vec::new();
+        let mut index = start;
+        while index < self.slots.len() {
+            let ty = tags[index];
+            values.push(raw_to_value(self.read(index, ty), ty));
+            index += ty.slots();
+        }
+        Some(values)
+    }
+
+    /// Pushes a value of a type that takes a single slot.
+    #[inline]
+    pub fn push_raw(&mut self, raw: u64, ty: ValType) {
+        self.slots.push(raw);
+        if let Some(tags) = &mut self.tags {
+            tags.push(erase(&self.tops, ty));
+        }
+    }
+
+    /// Pops a value of a type that takes a single slot.
+    #[inline]
+    pub fn pop_raw(&mut self, ty: ValType) -> u64 {
+        if let Some(tags) = &mut self.tags {
+            let tag = erase(&self.tops, ty);
+            match tags.pop() {
+                Some(t) if t == tag => {}
+                found => panic!("Checked stack: expected {ty:?}, found {found:?}"),
+            }
+        }
+        self.slots.pop().expect("Checked stack: empty stack")
+    }
+
+    /// Pops a slot of any type. Used by parametric instructions such as `drop`.
+    #[inline]
+    pub fn pop_any(&mut self) -> u64 {
+        if let Some(tags) = &mut self.tags {
+            tags.pop();
+        }
+        self.slots.pop().expect("Checked stack: empty stack")
+    }
+
+    /// Reads the slot at absolute position `index` and pushes a copy of it.
+    #[inline]
+    pub fn push_copy(&mut self, index: usize) {
+        let raw = self.slots[index];
+        self.slots.push(raw);
+        if let Some(tags) = &mut self.tags {
+            let ty = tags[index];
+            tags.push(ty);
+        }
+    }
+
+    /// Pops the top slot into the slot at absolute position `index`.
+    #[inline]
+    pub fn pop_into(&mut self, index: usize) {
+        if let Some(tags) = &mut self.tags {
+            match tags.pop() {
+                Some(t) if t == tags[index] => {}
+                found => panic!("Checked stack: expected {:?}, found {found:?}", tags[index]),
+            }
+        }
+        self.slots[index] = self.slots.pop().expect("Checked stack: empty stack");
+    }
+
+    /// Copies the slot at absolute position `from` into the one at `index`.
+    #[inline]
+    pub fn copy_into(&mut self, from: usize, index: usize) {
+        if let Some(tags) = &self.tags {
+            if tags[from] != tags[index] {
+                panic!("Checked stack: expected {:?}, found {:?}", tags[index], tags[from]);
+            }
+        }
+        self.slots[index] = self.slots[from];
+    }
+
+    /// Removes the slots between `base` and the top `keep` slots, shifting those down.
+    pub fn unwind(&mut self, base: usize, keep: usize) {
+        let top = self.slots.len() - keep;
+        self.slots.copy_within(top.., base);
+        self.slots.truncate(base + keep);
+        if let Some(tags) = &mut self.tags {
+            tags.copy_within(top.., base);
+            tags.truncate(base + keep);
+        }
+    }
+
+    #[inline]
+    pub fn push_i32(&mut self, v: i32) {
+        self.push_raw(v as u32 as u64, ValType::I32);
+    }
+
+    #[inline]
+    pub fn push_u32(&mut self, v: u32) {
+        self.push_raw(v as u64, ValType::I32);
+    }
+
+    #[inline]
+    pub fn push_i64(&mut self, v: i64) {
+        self.push_raw(v as u64, ValType::I64);
+    }
+
+    #[inline]
+    pub fn push_u64(&mut self, v: u64) {
+        self.push_raw(v, ValType::I64);
+    }
+
+    #[inline]
+    pub fn push_f32(&mut self, v: f32) {
+        self.push_raw(v.to_bits() as u64, ValType::F32);
+    }
+
+    #[inline]
+    pub fn push_f64(&mut self, v: f64) {
+        self.push_raw(v.to_bits(), ValType::F64);
+    }
+
+    #[inline]
+    pub fn push_v128(&mut self, v: u128) {
+        self.push_raw(v as u64, ValType::V128);
+        self.push_raw((v >> 64) as u64, ValType::V128);
+    }
+
+    #[inline]
+    pub fn pop_i32(&mut self) -> i32 {
+        self.pop_raw(ValType::I32) as u32 as i32
+    }
+
+    #[inline]
+    pub fn pop_u32(&mut self) -> u32 {
+        self.pop_raw(ValType::I32) as u32
+    }
+
+    #[inline]
+    pub fn pop_i64(&mut self) -> i64 {
+        self.pop_raw(ValType::I64) as i64
+    }
+
+    #[inline]
+    pub fn pop_u64(&mut self) -> u64 {
+        self.pop_raw(ValType::I64)
+    }
+
+    #[inline]
+    pub fn pop_f32(&mut self) -> f32 {
+        f32::from_bits(self.pop_raw(ValType::F32) as u32)
+    }
+
+    #[inline]
+    pub fn pop_f64(&mut self) -> f64 {
+        f64::from_bits(self.pop_raw(ValType::F64))
+    }
+
+    #[inline]
+    pub fn pop_v128(&mut self) -> u128 {
+        let high = self.pop_raw(ValType::V128) as u128;
+        let low = self.pop_raw(ValType::V128) as u128;
+        high << 64 | low
+    }
+
+    /// Pushes a value of any type, given as a `u128` as globals and object fields
+    /// hold it.
+    #[inline]
+    pub fn push_typed(&mut self, raw: u128, ty: ValType) {
+        if ty == ValType::V128 {
+            self.push_v128(raw);
+        } else {
+            self.push_raw(raw as u64, ty);
+        }
+    }
+
+    /// Pops a value of any type as a `u128`, the inverse of [`Stack::push_typed`].
+    #[inline]
+    pub fn pop_typed(&mut self, ty: ValType) -> u128 {
+        if ty == ValType::V128 { self.pop_v128() } else { self.pop_raw(ty) as u128 }
+    }
+
+    pub fn push_value(&mut self, value: Value) {
+        let ty = value.ty().expect("Value not yet supported on the stack");
+        self.push_typed(value_to_raw(value), ty);
+    }
+
+    pub fn pop_value(&mut self, ty: ValType) -> Value {
+        match ty {
+            ValType::I32 => Value::I32(self.pop_i32()),
+            ValType::I64 => Value::I64(self.pop_i64()),
+            ValType::F32 => Value::F32(self.pop_f32()),
+            ValType::F64 => Value::F64(self.pop_f64()),
+            ValType::V128 => Value::V128(self.pop_v128() as i128),
+            ValType::Ref(_) => {
+                let raw = self.pop_raw(ty);
+                // Nulls keep their declared type
+                raw_to_value(raw as u128, if raw == NULL_REF { ty } else { self.erase(ty) })
+            }
+        }
+    }
+
+    /// Prints the stack on a single line. Slots are only shown as typed values in
+    /// checked mode, since otherwise their types are not known.
+    pub fn dump(&self) {
+        match self.typed_values(0) {
+            Some(values) => {
+                for value in values {
+                    print!("[{value:?}]");
+                }
+            }
+            None => {
+                for raw in &self.slots {
+                    print!("[{raw:#x}]");
+                }
+            }
+        }
+        println!();
+    }
+}
+
+/// The tag a value of type `ty` is checked against: references are widened to the
+/// nullable top of their family, looking up concrete types' families in `tops`.
+fn erase(tops: &[HeapType], ty: ValType) -> ValType {
+    match ty {
+        ValType::Ref(ty) => {
+            let top = match ty.heap {
+                HeapType::Concrete(idx) => tops[idx as usize],
+                heap => heap.top().unwrap(),
+            };
+            ValType::Ref(RefType::null(top))
+        }
+        ty => ty,
+    }
+}
+
+pub fn value_to_raw(value: Value) -> u128 {
+    match value {
+        Value::I32(v) => v as u32 as u128,
+        Value::I64(v) => v as u64 as u128,
+        Value::F32(v) => v.to_bits() as u128,
+        Value::F64(v) => v.to_bits() as u128,
+        Value::V128(v) => v as u128,
+        Value::RefNull(_) => NULL_REF as u128,
+        Value::FuncRef(idx) => idx as u128,
+        Value::ExternRef(handle) => handle.0 as u128,
+        Value::ExnRef(handle) => handle.0 as u128,
+        Value::AnyRef(reference) => reference.0 as u128,
+    }
+}
+
+/// The value of a slot of type `ty`. A non-null reference to a concrete type must be
+/// given with its type erased, as its family isn't known here.
+pub fn raw_to_value(raw: u128, ty: ValType) -> Value {
+    let null = raw == NULL_REF as u128;
+    match ty {
+        ValType::I32 => Value::I32(raw as u32 as i32),
+        ValType::I64 => Value::I64(raw as u64 as i64),
+        ValType::F32 => Value::F32(f32::from_bits(raw as u32)),
+        ValType::F64 => Value::F64(f64::from_bits(raw as u64)),
+        ValType::V128 => Value::V128(raw as i128),
+        ValType::Ref(ty) if null => Value::RefNull(ty.heap),
+        ValType::Ref(ty) => match ty.heap.top().expect("Concrete types must be erased") {
+            HeapType::Extern => Value::ExternRef(ExternRef(raw as u64)),
+            HeapType::Exn => Value::ExnRef(ExnRef(raw as u32)),
+            HeapType::Any => Value::AnyRef(AnyRef(raw as u64)),
+            _ => Value::FuncRef(raw as u32),
+        },
+    }
+}
+
+/// The initial slot of a local of type `ty`: zero, or null for references.
+pub fn default_raw(ty: ValType) -> u128 {
+    if ty.is_ref() { NULL_REF as u128 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_values() {
+        let mut stack = Stack::new(false);
+        stack.push_i32(-7);
+        stack.push_i64(i64::MIN);
+        stack.push_f32(1.5);
+        stack.push_f64(-2.25);
+        stack.push_v128(u128::MAX - 1);
+        // A v128 takes two slots, low half first
+        assert_eq!(6, stack.len());
+        assert_eq!([u64::MAX - 1, u64::MAX], stack.slots()[4..]);
+        assert_eq!(u128::MAX - 1, stack.pop_v128());
+        assert_eq!(-2.25, stack.pop_f64());
+        assert_eq!(1.5, stack.pop_f32());
+        assert_eq!(i64::MIN, stack.pop_i64());
+        assert_eq!(-7, stack.pop_i32());
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn unwind_keeps_top_slots() {
+        let mut stack = Stack::new(true);
+        stack.push_i32(1);
+        stack.push_i64(2);
+        stack.push_i32(3);
+        stack.push_f32(4.0);
+        stack.unwind(1, 2);
+        assert_eq!(3, stack.len());
+        assert_eq!(4.0, stack.pop_f32());
+        assert_eq!(3, stack.pop_i32());
+        assert_eq!(1, stack.pop_i32());
+    }
+
+    #[test]
+    #[should_panic(expected = "Checked stack: expected I64, found Some(I32)")]
+    fn checked_mode_catches_mismatch() {
+        let mut stack = Stack::new(true);
+        stack.push_i32(1);
+        stack.pop_i64();
+    }
+}
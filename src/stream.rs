@@ -0,0 +1,342 @@
+#![allow(dead_code)]
+
+//! Decodes a module from a reader as its bytes arrive. Each section is decoded once it
+//! has been read in full, and each function body is validated as soon as it arrives, so
+//! a module can be checked while the rest of it is still downloading.
+
+use std::io::Read;
+
+use crate::bytecode::read::Reader;
+use crate::validate;
+use crate::wasm_module::{
+    self, Bytes, WasmLoadError, WasmModule, COMPONENT_LAYER, MODULE_LAYER,
+};
+
+/// What a `ModuleStream` decoded from the bytes it read.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// The preamble, with the module's version.
+    Version(u32),
+    /// A section other than the code section, which starts `offset` bytes into the
+    /// binary and has now been decoded into the module.
+    Section { id: u8, offset: usize, size: usize },
+    /// The code section started, and `count` function bodies follow.
+    CodeStart { count: usize },
+    /// The body of function `idx` arrived and was validated, so its code and branch
+    /// targets are ready.
+    FunctionBody { idx: usize },
+    /// The module ended and passed validation.
+    End,
+}
+
+enum State {
+    Preamble,
+    Sections,
+    /// Reading the bodies of functions `next` up to `end`, which should finish at
+    /// `section_end`.
+    Bodies { next: usize, end: usize, section_end: usize },
+    Done,
+}
+
+/// Reads a module from `reader`, an event at a time. The module keeps the bytes it has
+/// read, which its code and custom sections refer to.
+pub struct ModuleStream<R> {
+    reader: R,
+    module: WasmModule<'static>,
+    /// Whether the sections before the code have been validated.
+    declared: bool,
+    state: State,
+}
+
+/// Reads a whole module from `reader`.
+pub fn load<R: Read>(reader: R) -> Result<WasmModule<'static>, WasmLoadError> {
+    let mut stream = ModuleStream::new(reader);
+    for event in stream.by_ref() {
+        event?;
+    }
+    Ok(stream.into_module())
+}
+
+impl<R: Read> ModuleStream<R> {
+    pub fn new(reader: R) -> Self {
+        let mut module = WasmModule::default();
+        module.bytes = Bytes::Owned(Vec::new());
+        Self {
+            reader,
+            module,
+            declared: false,
+            state: State::Preamble,
+        }
+    }
+
+    /// The module decoded so far.
+    pub fn module(&self) -> &WasmModule<'static> {
+        &self.module
+    }
+
+    pub fn into_module(self) -> WasmModule<'static> {
+        self.module
+    }
+
+    fn next_event(&mut self) -> Result<Event, WasmLoadError> {
+        loop {
+            match self.state {
+                State::Preamble => return self.preamble(),
+                State::Sections => return self.section(),
+                State::Bodies { next, end, .. } if next < end => return self.function_body(next),
+                State::Bodies { section_end, .. } if self.offset() != section_end => {
+                    let msg = "Section 0x0a does not end where its size says";
+                    return Err(WasmLoadError::at(self.offset() - 1, msg));
+                }
+                State::Bodies { .. } => self.state = State::Sections,
+                State::Done => unreachable!("Read past the end of the module"),
+            }
+        }
+    }
+
+    fn preamble(&mut self) -> Result<Event, WasmLoadError> {
+        self.read(8).map_err(|_| WasmLoadError::at(0, "Magic Number not found"))?;
+        let error = |msg: &str| WasmLoadError::at(6, msg);
+        match wasm_module::preamble(&self.module.bytes) {
+            Ok((version, MODULE_LAYER)) => {
+                self.module.version = version as u32;
+                self.state = State::Sections;
+                Ok(Event::Version(version as u32))
+            }
+            Ok((_, COMPONENT_LAYER)) => {
+                Err(error("This is a component, which must be loaded with component::load"))
+            }
+            Ok((_, layer)) => Err(error(&format!("Unknown layer {layer:#06x}"))),
+            Err(msg) => Err(error(&msg)),
+        }
+    }
+
+    fn section(&mut self) -> Result<Event, WasmLoadError> {
+        let start = self.offset();
+        if self.read_up_to(1)? == 0 {
+            return self.end();
+        }
+        let id = self.module.bytes[start];
+        let size = self.read_size()?;
+        if id == 0x0a {
+            return self.code_start(size);
+        }
+
+        self.read(size)?;
+        wasm_module::load_section(&mut self.module, start)?;
+        Ok(Event::Section { id, offset: start, size })
+    }
+
+    /// Starts the code section, once the sections it depends on are validated.
+    fn code_start(&mut self, size: usize) -> Result<Event, WasmLoadError> {
+        let code_start = self.offset();
+        let section_end = code_start + size;
+        let num_funcs = self.read_size()?;
+        self.module.code_range = code_start..self.offset();
+
+        // Imported functions come first and have no body
+        let num_imported = self.module.functions.iter().filter(|f| f.imported).count();
+        if num_funcs != self.module.functions.len() - num_imported {
+            let msg = "Function and code section have inconsistent lengths";
+            return Err(WasmLoadError::at(self.offset() - 1, msg));
+        }
+        self.declare()?;
+        let end = num_imported + num_funcs;
+        self.state = State::Bodies { next: num_imported, end, section_end };
+        Ok(Event::CodeStart { count: num_funcs })
+    }
+
+    fn function_body(&mut self, idx: usize) -> Result<Event, WasmLoadError> {
+        let start = self.offset();
+        let size = self.read_size()?;
+        self.read(size)?;
+        self.module.code_range.end = self.offset();
+
+        wasm_module::load_function_body(&mut self.module, idx, start)?;
+        validate::validate_function(&mut self.module, idx)
+            .map_err(|err| wasm_module::validation_error(err, self.module.code_range.start))?;
+        if let State::Bodies { next, .. } = &mut self.state {
+            *next += 1;
+        }
+        Ok(Event::FunctionBody { idx })
+    }
+
+    /// Validates the rest of the module once it has all arrived.
+    fn end(&mut self) -> Result<Event, WasmLoadError> {
+        let has_bodies = self.module.functions.iter().any(|f| !f.imported);
+        if has_bodies && !self.declared {
+            let msg = "Function and code section have inconsistent lengths";
+            return Err(WasmLoadError::at(self.offset() - 1, msg));
+        }
+        self.declare()?;
+        validate::validate_data(&self.module)
+            .map_err(|err| wasm_module::validation_error(err, self.module.code_range.start))?;
+        self.state = State::Done;
+        Ok(Event::End)
+    }
+
+    fn declare(&mut self) -> Result<(), WasmLoadError> {
+        if !self.declared {
+            validate::validate_declarations(&self.module)
+                .map_err(|err| wasm_module::validation_error(err, self.module.code_range.start))?;
+            self.declared = true;
+        }
+        Ok(())
+    }
+
+    /// The number of bytes read so far.
+    fn offset(&self) -> usize {
+        self.module.bytes.len()
+    }
+
+    /// Reads a u32 LEB128, which is at most five bytes long.
+    fn read_size(&mut self) -> Result<usize, WasmLoadError> {
+        let start = self.offset();
+        while self.offset() - start < 5 {
+            self.read(1)?;
+            if self.module.bytes[self.offset() - 1] & 0x80 == 0 {
+                break;
+            }
+        }
+        match Reader::new(&self.module.bytes, start).u32() {
+            Ok(size) => Ok(size as usize),
+            Err(msg) => Err(WasmLoadError::at(self.offset() - 1, &msg)),
+        }
+    }
+
+    /// Reads `len` more bytes into the module.
+    fn read(&mut self, len: usize) -> Result<(), WasmLoadError> {
+        if self.read_up_to(len)? < len {
+            return Err(WasmLoadError::at(self.offset(), "Unexpected end of input"));
+        }
+        Ok(())
+    }
+
+    /// Reads up to `len` more bytes into the module, fewer if the input ends first.
+    fn read_up_to(&mut self, len: usize) -> Result<usize, WasmLoadError> {
+        let Bytes::Owned(bytes) = &mut self.module.bytes else {
+            unreachable!("Streamed modules own their bytes");
+        };
+        let read = self.reader.by_ref().take(len as u64).read_to_end(bytes);
+        read.map_err(|err| WasmLoadError::at(bytes.len(), &err.to_string()))
+    }
+}
+
+impl<R: Read> Iterator for ModuleStream<R> {
+    type Item = Result<Event, WasmLoadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let State::Done = self.state {
+            return None;
+        }
+        let event = self.next_event();
+        if event.is_err() {
+            self.state = State::Done;
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Arc;
+    use crate::bytecode::op::*;
+    use crate::test_util::ModuleBuilder;
+    use crate::value::{ExternalKind, ValType::*, Value};
+    use crate::vm::{HostFunc, Imports, Vm};
+
+    /// A reader that hands out a few bytes at a time, as a network would.
+    struct Trickle {
+        bytes: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(3).min(self.bytes.len() - self.pos);
+            buf[..len].copy_from_slice(&self.bytes[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    fn trickle(bytes: Vec<u8>) -> ModuleStream<Trickle> {
+        ModuleStream::new(Trickle { bytes, pos: 0 })
+    }
+
+    #[test]
+    fn events() {
+        let mut builder = ModuleBuilder::new();
+        builder.import_func("env", "log", &[I32], &[]);
+        let double = builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, LOCAL_GET, 0, I32_ADD, END]);
+        let body = [LOCAL_GET, 0, CALL, double as u8, I32_CONST, 1, I32_ADD, END];
+        let main = builder.func(&[I32], &[I32], &[I64], &body);
+        builder.export("main", ExternalKind::Func, main);
+        builder.memory(1, None);
+        builder.data(Some(0), b"hi");
+        let bytecode = builder.build();
+
+        let mut stream = trickle(bytecode.clone());
+        assert_eq!(Event::Version(1), stream.next().unwrap().ok().unwrap());
+        let mut ids = Vec::new();
+        let code_start = loop {
+            match stream.next().unwrap().ok().unwrap() {
+                Event::Section { id, offset, size } => {
+                    assert_eq!(id, bytecode[offset]);
+                    assert!(offset + size < bytecode.len());
+                    ids.push(id);
+                }
+                event => break event,
+            }
+        };
+        assert_eq!(vec![0x01, 0x02, 0x03, 0x05, 0x07, 0x0c], ids);
+        assert_eq!(Event::CodeStart { count: 2 }, code_start);
+
+        // Each body is validated before the next one is read
+        assert_eq!(Event::FunctionBody { idx: 1 }, stream.next().unwrap().ok().unwrap());
+        assert!(stream.module().functions[1].max_stack > 0);
+        assert!(stream.module().functions[2].max_stack == 0);
+        assert_eq!(Event::FunctionBody { idx: 2 }, stream.next().unwrap().ok().unwrap());
+        assert_eq!(vec![I64], stream.module().functions[2].locals);
+        assert_eq!(&body, stream.module().function_code(2));
+
+        assert!(matches!(stream.next(), Some(Ok(Event::Section { id: 0x0b, .. }))));
+        assert_eq!(Event::End, stream.next().unwrap().ok().unwrap());
+        assert!(stream.next().is_none());
+
+        let module = stream.into_module();
+        let loaded = wasm_module::load(&bytecode).ok().unwrap();
+        assert_eq!(loaded.code_section(), module.code_section());
+        assert_eq!(loaded.data[0].init, module.data[0].init);
+
+        let log: HostFunc = Arc::new(|_, _| Ok(Vec::new()));
+        let imports = Imports { functions: vec![log], ..Imports::default() };
+        let mut vm = Vm::with_imports(&module, imports).ok().unwrap();
+        assert_eq!(Ok(vec![Value::I32(9)]), vm.invoke(2, &[Value::I32(4)]));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |bytecode: Vec<u8>| match load(Trickle { bytes: bytecode, pos: 0 }) {
+            Ok(_) => panic!("Loaded an invalid module"),
+            Err(err) => err.formatted(),
+        };
+
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[I32], &[], &[I64_CONST, 1, END]);
+        let bytecode = builder.build();
+        let expected = wasm_module::load(&bytecode).err().unwrap().formatted();
+        assert_eq!(expected, error(bytecode.clone()));
+
+        let truncated = bytecode[..bytecode.len() - 2].to_vec();
+        let expected = format!("Error at byte {:#04x}: Unexpected end of input", truncated.len());
+        assert_eq!(expected, error(truncated));
+
+        assert_eq!("Error at byte 0x00: Magic Number not found", error(b"\0as".to_vec()));
+        let mut component = bytecode[..8].to_vec();
+        component[6] = 1;
+        assert!(error(component).ends_with("must be loaded with component::load"));
+    }
+}
@@ -0,0 +1,623 @@
+//! Helpers for assembling small wasm binaries in tests.
+#![allow(dead_code)]
+
+use crate::value::{
+    CompositeType, ExternalKind, FieldType, FuncType, HeapType, Limits, RefType, StorageType,
+    SubType, ValType,
+};
+
+pub fn uleb(mut value: u64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn sleb(mut value: i64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Encodes an instruction with the SIMD prefix.
+pub fn simd(op: u32) -> Vec<u8> {
+    let mut out = vec![0xfd];
+    uleb(op as u64, &mut out);
+    out
+}
+
+/// Encodes an instruction with the GC prefix.
+pub fn gc(op: u32) -> Vec<u8> {
+    let mut out = vec![0xfb];
+    uleb(op as u64, &mut out);
+    out
+}
+
+/// Encodes an instruction with the atomic prefix.
+pub fn atomic(op: u32) -> Vec<u8> {
+    let mut out = vec![0xfe];
+    uleb(op as u64, &mut out);
+    out
+}
+
+struct Func {
+    type_idx: u32,
+    locals: Vec<ValType>,
+    body: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct ModuleBuilder {
+    /// The recursion groups of the type section.
+    types: Vec<Vec<SubType>>,
+    /// Each import's module and name, kind, and encoded type.
+    imports: Vec<(String, String, ExternalKind, Vec<u8>)>,
+    funcs: Vec<Func>,
+    tables: Vec<(RefType, Limits, Option<Vec<u8>>)>,
+    memories: Vec<(Limits, u8)>,
+    tags: Vec<u32>,
+    globals: Vec<(ValType, bool, Vec<u8>)>,
+    exports: Vec<(String, ExternalKind, u32)>,
+    start: Option<u32>,
+    elements: Vec<(Option<i32>, Vec<u32>)>,
+    data: Vec<(Option<Vec<u8>>, Vec<u8>)>,
+    customs: Vec<(String, Vec<u8>)>,
+}
+
+impl ModuleBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_type(&mut self, params: &[ValType], results: &[ValType]) -> u32 {
+        let functype = FuncType { params: params.to_vec(), results: results.to_vec() };
+        let ty = vec![SubType::plain(CompositeType::Func(functype))];
+        match self.types.iter().position(|group| *group == ty) {
+            Some(group) => self.types[..group].iter().map(Vec::len).sum::<usize>() as u32,
+            None => self.rec_group(ty),
+        }
+    }
+
+    /// Adds a final struct type of its own recursion group.
+    pub fn struct_type(&mut self, fields: &[FieldType]) -> u32 {
+        self.rec_group(vec![SubType::plain(CompositeType::Struct(fields.to_vec()))])
+    }
+
+    /// Adds a final array type of its own recursion group.
+    pub fn array_type(&mut self, elem: FieldType) -> u32 {
+        self.rec_group(vec![SubType::plain(CompositeType::Array(elem))])
+    }
+
+    /// Adds a recursion group, returning the index of its first type.
+    pub fn rec_group(&mut self, types: Vec<SubType>) -> u32 {
+        let idx = self.types.iter().map(Vec::len).sum::<usize>() as u32;
+        self.types.push(types);
+        idx
+    }
+
+    /// Adds a function whose `body` is its instructions, including the final `end`.
+    pub fn func(
+        &mut self,
+        params: &[ValType],
+        results: &[ValType],
+        locals: &[ValType],
+        body: &[u8],
+    ) -> u32 {
+        let type_idx = self.add_type(params, results);
+        self.funcs.push(Func {
+            type_idx,
+            locals: locals.to_vec(),
+            body: body.to_vec(),
+        });
+        self.func_count() - 1
+    }
+
+    pub fn table(&mut self, elem: RefType, min: u32, max: Option<u32>) -> u32 {
+        let limits = Limits { min: min as u64, max: max.map(|max| max as u64) };
+        self.tables.push((elem, limits, None));
+        (self.tables.len() - 1) as u32
+    }
+
+    /// A table whose elements start as the value of the constant expression `init`,
+    /// which includes its `end`.
+    pub fn table_with_init(&mut self, elem: RefType, min: u32, init: &[u8]) -> u32 {
+        let limits = Limits { min: min as u64, max: None };
+        self.tables.push((elem, limits, Some(init.to_vec())));
+        (self.tables.len() - 1) as u32
+    }
+
+    pub fn memory(&mut self, min: u32, max: Option<u32>) -> u32 {
+        let limits = Limits { min: min as u64, max: max.map(|max| max as u64) };
+        self.memories.push((limits, 0x00));
+        self.memory_count() - 1
+    }
+
+    pub fn shared_memory(&mut self, min: u32, max: u32) -> u32 {
+        self.memories.push((Limits { min: min as u64, max: Some(max as u64) }, 0x02));
+        self.memory_count() - 1
+    }
+
+    /// Adds a memory addressed with i64.
+    pub fn memory64(&mut self, min: u64, max: Option<u64>) -> u32 {
+        self.memories.push((Limits { min, max }, 0x04));
+        self.memory_count() - 1
+    }
+
+    /// Imports a function, which comes before every defined function in the index space.
+    pub fn import_func(
+        &mut self,
+        module: &str,
+        name: &str,
+        params: &[ValType],
+        results: &[ValType],
+    ) -> u32 {
+        let mut ty = Vec::new();
+        uleb(self.add_type(params, results) as u64, &mut ty);
+        self.import(module, name, ExternalKind::Func, ty);
+        self.func_count() - 1
+    }
+
+    /// Imports a memory, which comes before every defined memory in the index space.
+    pub fn import_memory(&mut self, module: &str, name: &str, min: u32, max: Option<u32>) -> u32 {
+        let mut ty = Vec::new();
+        encode_limits(&Limits { min: min as u64, max: max.map(|max| max as u64) }, &mut ty);
+        self.import(module, name, ExternalKind::Memory, ty);
+        self.memory_count() - 1
+    }
+
+    /// Imports an immutable global, which comes before every defined global.
+    pub fn import_global(&mut self, module: &str, name: &str, ty: ValType) -> u32 {
+        let mut encoded = Vec::new();
+        encode_val_type(ty, &mut encoded);
+        encoded.push(0x00);
+        self.import(module, name, ExternalKind::Global, encoded);
+        self.global_count() - 1
+    }
+
+    fn import(&mut self, module: &str, name: &str, kind: ExternalKind, ty: Vec<u8>) {
+        self.imports.push((module.to_string(), name.to_string(), kind, ty));
+    }
+
+    fn imported(&self, kind: ExternalKind) -> usize {
+        self.imports.iter().filter(|import| import.2 == kind).count()
+    }
+
+    fn func_count(&self) -> u32 {
+        (self.imported(ExternalKind::Func) + self.funcs.len()) as u32
+    }
+
+    fn memory_count(&self) -> u32 {
+        (self.imported(ExternalKind::Memory) + self.memories.len()) as u32
+    }
+
+    fn global_count(&self) -> u32 {
+        (self.imported(ExternalKind::Global) + self.globals.len()) as u32
+    }
+
+    /// Adds a global whose initializer is the constant expression `init`, including the
+    /// final `end`.
+    pub fn global(&mut self, ty: ValType, mutable: bool, init: &[u8]) -> u32 {
+        self.globals.push((ty, mutable, init.to_vec()));
+        self.global_count() - 1
+    }
+
+    pub fn export(&mut self, name: &str, kind: ExternalKind, idx: u32) {
+        self.exports.push((name.to_string(), kind, idx));
+    }
+
+    /// Adds an exception tag carrying values of the `params` types.
+    pub fn tag(&mut self, params: &[ValType]) -> u32 {
+        let type_idx = self.add_type(params, &[]);
+        self.tags.push(type_idx);
+        (self.tags.len() - 1) as u32
+    }
+
+    pub fn start(&mut self, func_idx: u32) {
+        self.start = Some(func_idx);
+    }
+
+    /// Adds an element segment of function references, copied into table 0 at `offset`
+    /// or passive without one.
+    pub fn elements(&mut self, offset: Option<i32>, funcs: &[u32]) -> u32 {
+        self.elements.push((offset, funcs.to_vec()));
+        (self.elements.len() - 1) as u32
+    }
+
+    /// Adds a data segment, copied into memory 0 at `offset` or passive without one.
+    pub fn data(&mut self, offset: Option<i32>, bytes: &[u8]) -> u32 {
+        let offset = offset.map(|offset| {
+            let mut expr = Vec::new();
+            offset_expr(offset, &mut expr);
+            expr
+        });
+        self.data.push((offset, bytes.to_vec()));
+        (self.data.len() - 1) as u32
+    }
+
+    /// Adds an active data segment copied into memory 0 at the result of the constant
+    /// expression `offset`, including the final `end`.
+    pub fn data_at(&mut self, offset: &[u8], bytes: &[u8]) -> u32 {
+        self.data.push((Some(offset.to_vec()), bytes.to_vec()));
+        (self.data.len() - 1) as u32
+    }
+
+    /// Adds a custom section, emitted after the code section.
+    pub fn custom(&mut self, name: &str, contents: &[u8]) {
+        self.customs.push((name.to_string(), contents.to_vec()));
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+        let mut types = Vec::new();
+        uleb(self.types.len() as u64, &mut types);
+        for group in &self.types {
+            if group.len() != 1 {
+                types.push(0x4e);
+                uleb(group.len() as u64, &mut types);
+            }
+            group.iter().for_each(|ty| encode_sub_type(ty, &mut types));
+        }
+        section(0x01, &types, &mut out);
+
+        if !self.imports.is_empty() {
+            let mut imports = Vec::new();
+            uleb(self.imports.len() as u64, &mut imports);
+            for (module, name, kind, ty) in &self.imports {
+                for name in [module, name] {
+                    uleb(name.len() as u64, &mut imports);
+                    imports.extend_from_slice(name.as_bytes());
+                }
+                imports.push(*kind as u8);
+                imports.extend_from_slice(ty);
+            }
+            section(0x02, &imports, &mut out);
+        }
+
+        let mut funcs = Vec::new();
+        uleb(self.funcs.len() as u64, &mut funcs);
+        for func in &self.funcs {
+            uleb(func.type_idx as u64, &mut funcs);
+        }
+        section(0x03, &funcs, &mut out);
+
+        if !self.tables.is_empty() {
+            let mut tables = Vec::new();
+            uleb(self.tables.len() as u64, &mut tables);
+            for (elem, limits, init) in &self.tables {
+                if init.is_some() {
+                    tables.extend_from_slice(&[0x40, 0x00]);
+                }
+                encode_val_type(ValType::Ref(*elem), &mut tables);
+                encode_limits(limits, &mut tables);
+                tables.extend_from_slice(init.as_deref().unwrap_or_default());
+            }
+            section(0x04, &tables, &mut out);
+        }
+
+        if !self.memories.is_empty() {
+            let mut memories = Vec::new();
+            uleb(self.memories.len() as u64, &mut memories);
+            for (limits, flags) in &self.memories {
+                let at = memories.len();
+                encode_limits(limits, &mut memories);
+                memories[at] |= flags;
+            }
+            section(0x05, &memories, &mut out);
+        }
+
+        if !self.tags.is_empty() {
+            let mut tags = Vec::new();
+            uleb(self.tags.len() as u64, &mut tags);
+            for type_idx in &self.tags {
+                tags.push(0x00);
+                uleb(*type_idx as u64, &mut tags);
+            }
+            section(0x0d, &tags, &mut out);
+        }
+
+        if !self.globals.is_empty() {
+            let mut globals = Vec::new();
+            uleb(self.globals.len() as u64, &mut globals);
+            for (ty, mutable, init) in &self.globals {
+                encode_val_type(*ty, &mut globals);
+                globals.push(*mutable as u8);
+                globals.extend_from_slice(init);
+            }
+            section(0x06, &globals, &mut out);
+        }
+
+        if !self.exports.is_empty() {
+            let mut exports = Vec::new();
+            uleb(self.exports.len() as u64, &mut exports);
+            for (name, kind, idx) in &self.exports {
+                uleb(name.len() as u64, &mut exports);
+                exports.extend_from_slice(name.as_bytes());
+                exports.push(*kind as u8);
+                uleb(*idx as u64, &mut exports);
+            }
+            section(0x07, &exports, &mut out);
+        }
+
+        if let Some(start) = self.start {
+            let mut payload = Vec::new();
+            uleb(start as u64, &mut payload);
+            section(0x08, &payload, &mut out);
+        }
+
+        if !self.elements.is_empty() {
+            let mut elements = Vec::new();
+            uleb(self.elements.len() as u64, &mut elements);
+            for (offset, funcs) in &self.elements {
+                match offset {
+                    Some(offset) => {
+                        elements.push(0x00);
+                        offset_expr(*offset, &mut elements);
+                    }
+                    None => elements.extend_from_slice(&[0x01, 0x00]),
+                }
+                uleb(funcs.len() as u64, &mut elements);
+                for func in funcs {
+                    uleb(*func as u64, &mut elements);
+                }
+            }
+            section(0x09, &elements, &mut out);
+        }
+
+        if !self.data.is_empty() {
+            let mut count = Vec::new();
+            uleb(self.data.len() as u64, &mut count);
+            section(0x0c, &count, &mut out);
+        }
+
+        let mut code = Vec::new();
+        uleb(self.funcs.len() as u64, &mut code);
+        for func in &self.funcs {
+            let mut body = Vec::new();
+            uleb(func.locals.len() as u64, &mut body);
+            for local in &func.locals {
+                body.push(0x01);
+                encode_val_type(*local, &mut body);
+            }
+            body.extend_from_slice(&func.body);
+            uleb(body.len() as u64, &mut code);
+            code.extend(body);
+        }
+        section(0x0a, &code, &mut out);
+
+        if !self.data.is_empty() {
+            let mut data = Vec::new();
+            uleb(self.data.len() as u64, &mut data);
+            for (offset, bytes) in &self.data {
+                match offset {
+                    Some(offset) => {
+                        data.push(0x00);
+                        data.extend_from_slice(offset);
+                    }
+                    None => data.push(0x01),
+                }
+                uleb(bytes.len() as u64, &mut data);
+                data.extend_from_slice(bytes);
+            }
+            section(0x0b, &data, &mut out);
+        }
+
+        for (name, contents) in &self.customs {
+            let mut payload = Vec::new();
+            uleb(name.len() as u64, &mut payload);
+            payload.extend_from_slice(name.as_bytes());
+            payload.extend_from_slice(contents);
+            section(0x00, &payload, &mut out);
+        }
+
+        out
+    }
+}
+
+/// Assembles a component from sections whose items the test encodes itself.
+#[derive(Default)]
+pub struct ComponentBuilder {
+    sections: Vec<(u8, Vec<u8>)>,
+}
+
+impl ComponentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a core module section holding `module`.
+    pub fn module(&mut self, module: &[u8]) {
+        self.sections.push((0x01, module.to_vec()));
+    }
+
+    /// Adds a section of `items`, each already encoded.
+    pub fn items(&mut self, id: u8, items: &[&[u8]]) {
+        let mut payload = Vec::new();
+        uleb(items.len() as u64, &mut payload);
+        items.iter().for_each(|item| payload.extend_from_slice(item));
+        self.sections.push((id, payload));
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = vec![0x00, 0x61, 0x73, 0x6d, 0x0d, 0x00, 0x01, 0x00];
+        for (id, payload) in &self.sections {
+            section(*id, payload, &mut out);
+        }
+        out
+    }
+}
+
+/// Encodes a name as its length and UTF-8 bytes.
+pub fn name(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    uleb(name.len() as u64, &mut out);
+    out.extend_from_slice(name.as_bytes());
+    out
+}
+
+/// Appends the binary encoding of `ty`, using the single-byte shorthands where they
+/// exist.
+pub fn encode_val_type(ty: ValType, out: &mut Vec<u8>) {
+    let ty = match ty {
+        ValType::I32 => 0x7f,
+        ValType::I64 => 0x7e,
+        ValType::F32 => 0x7d,
+        ValType::F64 => 0x7c,
+        ValType::V128 => 0x7b,
+        ValType::Ref(RefType { nullable, heap }) => {
+            let abstract_byte = match heap {
+                HeapType::Func => 0x70,
+                HeapType::Extern => 0x6f,
+                HeapType::Exn => 0x69,
+                HeapType::NoFunc => 0x73,
+                HeapType::NoExtern => 0x72,
+                HeapType::NoExn => 0x74,
+                HeapType::Any => 0x6e,
+                HeapType::Eq => 0x6d,
+                HeapType::I31 => 0x6c,
+                HeapType::Struct => 0x6b,
+                HeapType::Array => 0x6a,
+                HeapType::None => 0x71,
+                HeapType::Concrete(idx) => {
+                    out.push(if nullable { 0x63 } else { 0x64 });
+                    sleb(idx as i64, out);
+                    return;
+                }
+            };
+            if !nullable {
+                out.push(0x64);
+            }
+            abstract_byte
+        }
+    };
+    out.push(ty);
+}
+
+fn encode_sub_type(ty: &SubType, out: &mut Vec<u8>) {
+    if !ty.is_final || ty.supertype.is_some() {
+        out.push(if ty.is_final { 0x4f } else { 0x50 });
+        uleb(ty.supertype.is_some() as u64, out);
+        if let Some(supertype) = ty.supertype {
+            uleb(supertype as u64, out);
+        }
+    }
+    let field = |field: &FieldType, out: &mut Vec<u8>| {
+        match field.storage {
+            StorageType::Val(ty) => encode_val_type(ty, out),
+            StorageType::I8 => out.push(0x78),
+            StorageType::I16 => out.push(0x77),
+        }
+        out.push(field.mutable as u8);
+    };
+    match &ty.composite {
+        CompositeType::Func(functype) => {
+            out.push(0x60);
+            uleb(functype.params.len() as u64, out);
+            functype.params.iter().for_each(|t| encode_val_type(*t, out));
+            uleb(functype.results.len() as u64, out);
+            functype.results.iter().for_each(|t| encode_val_type(*t, out));
+        }
+        CompositeType::Struct(fields) => {
+            out.push(0x5f);
+            uleb(fields.len() as u64, out);
+            fields.iter().for_each(|f| field(f, out));
+        }
+        CompositeType::Array(elem) => {
+            out.push(0x5e);
+            field(elem, out);
+        }
+    }
+}
+
+fn encode_limits(limits: &Limits, out: &mut Vec<u8>) {
+    match limits.max {
+        None => {
+            out.push(0x00);
+            uleb(limits.min, out);
+        }
+        Some(max) => {
+            out.push(0x01);
+            uleb(limits.min, out);
+            uleb(max, out);
+        }
+    }
+}
+
+fn offset_expr(offset: i32, out: &mut Vec<u8>) {
+    out.push(0x41);     // i32.const
+    sleb(offset as i64, out);
+    out.push(0x0b);     // end
+}
+
+fn section(id: u8, payload: &[u8], out: &mut Vec<u8>) {
+    out.push(id);
+    uleb(payload.len() as u64, out);
+    out.extend_from_slice(payload);
+}
+
+/// A module with a memory and a bump allocator for `realloc`.
+fn libc() -> Vec<u8> {
+    let mut builder = ModuleBuilder::new();
+    let memory = builder.memory(1, None);
+    builder.global(ValType::I32, true, &[0x41, 0x80, 0x08, 0x0b]);
+    let realloc = builder.func(&[ValType::I32; 4], &[ValType::I32], &[], &[
+        0x23, 0x00,                     // global.get 0
+        0x23, 0x00, 0x20, 0x03, 0x6a,   // global.get 0, local.get 3, i32.add
+        0x41, 0x07, 0x6a,               // i32.const 7, i32.add
+        0x41, 0x78, 0x71,               // i32.const -8, i32.and
+        0x24, 0x00, 0x0b,               // global.set 0, end
+    ]);
+    builder.export("memory", ExternalKind::Memory, memory);
+    builder.export("realloc", ExternalKind::Func, realloc);
+    builder.build()
+}
+
+/// A component of the world `import greet: func(name: string) -> string;
+/// export run: func(name: string) -> string;`, whose `run` passes its string to `greet`
+/// and returns what that returns.
+pub fn greeter() -> Vec<u8> {
+    use ValType::I32;
+
+    let mut builder = ModuleBuilder::new();
+    builder.import_memory("libc", "memory", 1, None);
+    let greet = builder.import_func("host", "greet", &[I32; 3], &[]);
+    let run = builder.func(&[I32, I32], &[I32], &[], &[
+        0x20, 0x00, 0x20, 0x01, 0x41, 0x08, 0x10, greet as u8,
+        0x41, 0x08, 0x0b,
+    ]);
+    builder.export("run", ExternalKind::Func, run);
+
+    let mut component = ComponentBuilder::new();
+    component.module(&libc());
+    component.module(&builder.build());
+    component.items(0x02, &[&[0x00, 0x00, 0x00]]);
+    component.items(0x06, &[
+        &[&[0x00, 0x02, 0x01, 0x00][..], &name("memory")].concat(),
+        &[&[0x00, 0x00, 0x01, 0x00][..], &name("realloc")].concat(),
+    ]);
+    component.items(0x07, &[&[&[0x40, 0x01][..], &name("name"), &[0x73, 0x00, 0x73]].concat()]);
+    component.items(0x0a, &[&[&[0x00][..], &name("greet"), &[0x01, 0x00]].concat()]);
+    let options = [0x03, 0x00, 0x03, 0x00, 0x04, 0x00];
+    component.items(0x08, &[&[&[0x01, 0x00, 0x00][..], &options].concat()]);
+    component.items(0x02, &[
+        &[&[0x01, 0x01][..], &name("greet"), &[0x00, 0x01]].concat(),
+        &[&[0x00, 0x01, 0x02][..], &name("libc"), &[0x12, 0x00], &name("host"), &[0x12, 0x01]]
+            .concat(),
+    ]);
+    component.items(0x06, &[&[&[0x00, 0x00, 0x01, 0x02][..], &name("run")].concat()]);
+    component.items(0x08, &[&[&[0x00, 0x00, 0x02][..], &options, &[0x00]].concat()]);
+    component.items(0x0b, &[&[&[0x00][..], &name("run"), &[0x01, 0x01, 0x00]].concat()]);
+    component.build()
+}
@@ -0,0 +1,1143 @@
+#![allow(dead_code)]
+
+use crate::bytecode::simd::*;
+use crate::value::ValType;
+
+/// The operands and immediates of a SIMD instruction, which the validator, interpreter
+/// and disassembler all need.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// `i32 -> v128`, with a memarg whose natural alignment is `2^align` bytes.
+    Load { align: usize },
+    /// `i32 v128 -> ()`, with a memarg.
+    Store,
+    /// `i32 v128 -> v128`, with a memarg and the index of the lane loaded into.
+    LoadLane { align: usize },
+    /// `i32 v128 -> ()`, with a memarg and the index of the lane stored.
+    StoreLane { align: usize },
+    /// `() -> v128`, with the 16 bytes of the vector.
+    Const,
+    /// `v128 v128 -> v128`, with 16 lane indices into both operands.
+    Shuffle,
+    /// A scalar of this type copied into every lane.
+    Splat(ValType),
+    /// `v128 -> ty`, with a lane index less than `lanes`.
+    ExtractLane { ty: ValType, lanes: usize },
+    /// `v128 ty -> v128`, with a lane index less than `lanes`.
+    ReplaceLane { ty: ValType, lanes: usize },
+    Unary,
+    Binary,
+    /// `v128 v128 v128 -> v128`
+    Ternary,
+    /// `v128 -> i32`
+    Test,
+    /// `v128 i32 -> v128`
+    Shift,
+}
+
+pub fn kind(op: u32) -> Option<Kind> {
+    use ValType::*;
+
+    let kind = match op {
+        V128_LOAD => Kind::Load { align: 4 },
+        V128_LOAD8X8_S..=V128_LOAD32X2_U | V128_LOAD64_SPLAT | V128_LOAD64_ZERO => {
+            Kind::Load { align: 3 }
+        }
+        V128_LOAD8_SPLAT => Kind::Load { align: 0 },
+        V128_LOAD16_SPLAT => Kind::Load { align: 1 },
+        V128_LOAD32_SPLAT | V128_LOAD32_ZERO => Kind::Load { align: 2 },
+        V128_STORE => Kind::Store,
+        V128_LOAD8_LANE..=V128_LOAD64_LANE => {
+            Kind::LoadLane { align: (op - V128_LOAD8_LANE) as usize }
+        }
+        V128_STORE8_LANE..=V128_STORE64_LANE => {
+            Kind::StoreLane { align: (op - V128_STORE8_LANE) as usize }
+        }
+        V128_CONST => Kind::Const,
+        I8X16_SHUFFLE => Kind::Shuffle,
+        I8X16_SPLAT | I16X8_SPLAT | I32X4_SPLAT => Kind::Splat(I32),
+        I64X2_SPLAT => Kind::Splat(I64),
+        F32X4_SPLAT => Kind::Splat(F32),
+        F64X2_SPLAT => Kind::Splat(F64),
+        I8X16_EXTRACT_LANE_S | I8X16_EXTRACT_LANE_U => Kind::ExtractLane { ty: I32, lanes: 16 },
+        I16X8_EXTRACT_LANE_S | I16X8_EXTRACT_LANE_U => Kind::ExtractLane { ty: I32, lanes: 8 },
+        I32X4_EXTRACT_LANE => Kind::ExtractLane { ty: I32, lanes: 4 },
+        I64X2_EXTRACT_LANE => Kind::ExtractLane { ty: I64, lanes: 2 },
+        F32X4_EXTRACT_LANE => Kind::ExtractLane { ty: F32, lanes: 4 },
+        F64X2_EXTRACT_LANE => Kind::ExtractLane { ty: F64, lanes: 2 },
+        I8X16_REPLACE_LANE => Kind::ReplaceLane { ty: I32, lanes: 16 },
+        I16X8_REPLACE_LANE => Kind::ReplaceLane { ty: I32, lanes: 8 },
+        I32X4_REPLACE_LANE => Kind::ReplaceLane { ty: I32, lanes: 4 },
+        I64X2_REPLACE_LANE => Kind::ReplaceLane { ty: I64, lanes: 2 },
+        F32X4_REPLACE_LANE => Kind::ReplaceLane { ty: F32, lanes: 4 },
+        F64X2_REPLACE_LANE => Kind::ReplaceLane { ty: F64, lanes: 2 },
+        V128_BITSELECT => Kind::Ternary,
+        V128_ANY_TRUE | I8X16_ALL_TRUE | I16X8_ALL_TRUE | I32X4_ALL_TRUE | I64X2_ALL_TRUE => {
+            Kind::Test
+        }
+        I8X16_BITMASK | I16X8_BITMASK | I32X4_BITMASK | I64X2_BITMASK => Kind::Test,
+        I8X16_SHL | I8X16_SHR_S | I8X16_SHR_U | I16X8_SHL | I16X8_SHR_S | I16X8_SHR_U => {
+            Kind::Shift
+        }
+        I32X4_SHL | I32X4_SHR_S | I32X4_SHR_U | I64X2_SHL | I64X2_SHR_S | I64X2_SHR_U => {
+            Kind::Shift
+        }
+        V128_NOT
+        | F32X4_DEMOTE_F64X2_ZERO
+        | F64X2_PROMOTE_LOW_F32X4
+        | I8X16_ABS
+        | I8X16_NEG
+        | I8X16_POPCNT
+        | F32X4_CEIL..=F32X4_NEAREST
+        | F64X2_CEIL
+        | F64X2_FLOOR
+        | F64X2_TRUNC
+        | F64X2_NEAREST
+        | I16X8_EXTADD_PAIRWISE_I8X16_S..=I32X4_EXTADD_PAIRWISE_I16X8_U
+        | I16X8_ABS
+        | I16X8_NEG
+        | I16X8_EXTEND_LOW_I8X16_S..=I16X8_EXTEND_HIGH_I8X16_U
+        | I32X4_ABS
+        | I32X4_NEG
+        | I32X4_EXTEND_LOW_I16X8_S..=I32X4_EXTEND_HIGH_I16X8_U
+        | I64X2_ABS
+        | I64X2_NEG
+        | I64X2_EXTEND_LOW_I32X4_S..=I64X2_EXTEND_HIGH_I32X4_U
+        | F32X4_ABS
+        | F32X4_NEG
+        | F32X4_SQRT
+        | F64X2_ABS
+        | F64X2_NEG
+        | F64X2_SQRT
+        | I32X4_TRUNC_SAT_F32X4_S..=F64X2_CONVERT_LOW_I32X4_U => Kind::Unary,
+        I8X16_SWIZZLE
+        | I8X16_EQ..=F64X2_GE
+        | V128_AND..=V128_XOR
+        | I8X16_NARROW_I16X8_S
+        | I8X16_NARROW_I16X8_U
+        | I8X16_ADD..=I8X16_SUB_SAT_U
+        | I8X16_MIN_S..=I8X16_MAX_U
+        | I8X16_AVGR_U
+        | I16X8_Q15MULR_SAT_S
+        | I16X8_NARROW_I32X4_S
+        | I16X8_NARROW_I32X4_U
+        | I16X8_ADD..=I16X8_SUB_SAT_U
+        | I16X8_MUL..=I16X8_MAX_U
+        | I16X8_AVGR_U..=I16X8_EXTMUL_HIGH_I8X16_U
+        | I32X4_ADD
+        | I32X4_SUB
+        | I32X4_MUL..=I32X4_DOT_I16X8_S
+        | I32X4_EXTMUL_LOW_I16X8_S..=I32X4_EXTMUL_HIGH_I16X8_U
+        | I64X2_ADD
+        | I64X2_SUB
+        | I64X2_MUL..=I64X2_EXTMUL_HIGH_I32X4_U
+        | F32X4_ADD..=F32X4_PMAX
+        | F64X2_ADD..=F64X2_PMAX => Kind::Binary,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// A lane type, stored little-endian in the vector.
+pub trait Lane: Copy {
+    const BYTES: usize;
+    fn read(bytes: &[u8]) -> Self;
+    fn write(self, bytes: &mut [u8]);
+}
+
+macro_rules! lane {
+    ($($t: ty),*) => {$(
+        impl Lane for $t {
+            const BYTES: usize = std::mem::size_of::<$t>();
+
+            fn read(bytes: &[u8]) -> Self {
+                <$t>::from_le_bytes(bytes.try_into().unwrap())
+            }
+
+            fn write(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_le_bytes());
+            }
+        }
+    )*};
+}
+
+lane!(i8, u8, i16, u16, i32, u32, i64, u64, f32, f64);
+
+/// Float operations whose wasm semantics differ from Rust's.
+pub trait Float: Lane + PartialOrd {
+    /// The minimum, which is NaN if either operand is and treats -0 as less than +0.
+    fn wasm_min(self, other: Self) -> Self;
+    fn wasm_max(self, other: Self) -> Self;
+    /// Rounds to the nearest integer, with ties to even.
+    fn nearest(self) -> Self;
+}
+
+macro_rules! float {
+    ($($t: ty),*) => {$(
+        impl Float for $t {
+            fn wasm_min(self, other: Self) -> Self {
+                if self.is_nan() || other.is_nan() {
+                    <$t>::NAN
+                } else if self == other {
+                    // Only differs from either when they are zeros of opposite sign
+                    <$t>::from_bits(self.to_bits() | other.to_bits())
+                } else {
+                    self.min(other)
+                }
+            }
+
+            fn wasm_max(self, other: Self) -> Self {
+                if self.is_nan() || other.is_nan() {
+                    <$t>::NAN
+                } else if self == other {
+                    <$t>::from_bits(self.to_bits() & other.to_bits())
+                } else {
+                    self.max(other)
+                }
+            }
+
+            fn nearest(self) -> Self {
+                self.round_ties_even()
+            }
+        }
+    )*};
+}
+
+float!(f32, f64);
+
+pub fn lane<T: Lane>(v: u128, idx: usize) -> T {
+    let bytes = v.to_le_bytes();
+    T::read(&bytes[idx * T::BYTES..(idx + 1) * T::BYTES])
+}
+
+pub fn replace_lane<T: Lane>(v: u128, idx: usize, value: T) -> u128 {
+    let mut bytes = v.to_le_bytes();
+    value.write(&mut bytes[idx * T::BYTES..(idx + 1) * T::BYTES]);
+    u128::from_le_bytes(bytes)
+}
+
+pub fn splat<T: Lane>(value: T) -> u128 {
+    let mut bytes = [0; 16];
+    for chunk in bytes.chunks_exact_mut(T::BYTES) {
+        value.write(chunk);
+    }
+    u128::from_le_bytes(bytes)
+}
+
+/// Applies `f` to each lane. When `U` is narrower than `T` the upper lanes of the result
+/// are zero, and when it is wider only the lower lanes of `v` are used.
+pub fn map<T: Lane, U: Lane>(v: u128, f: impl Fn(T) -> U) -> u128 {
+    let bytes = v.to_le_bytes();
+    let mut out = [0; 16];
+    for (src, dst) in bytes.chunks_exact(T::BYTES).zip(out.chunks_exact_mut(U::BYTES)) {
+        f(T::read(src)).write(dst);
+    }
+    u128::from_le_bytes(out)
+}
+
+/// Applies `f` to each pair of lanes, with the same widths as [`map`].
+pub fn zip<T: Lane, U: Lane>(a: u128, b: u128, f: impl Fn(T, T) -> U) -> u128 {
+    let (a, b) = (a.to_le_bytes(), b.to_le_bytes());
+    let lanes = a.chunks_exact(T::BYTES).zip(b.chunks_exact(T::BYTES));
+    let mut out = [0; 16];
+    for ((a, b), dst) in lanes.zip(out.chunks_exact_mut(U::BYTES)) {
+        f(T::read(a), T::read(b)).write(dst);
+    }
+    u128::from_le_bytes(out)
+}
+
+/// Sets each lane to all ones where `f` holds for the lanes of `a` and `b`, else zero.
+pub fn compare<T: Lane>(a: u128, b: u128, f: impl Fn(T, T) -> bool) -> u128 {
+    let (a, b) = (a.to_le_bytes(), b.to_le_bytes());
+    let mut out = [0; 16];
+    for (i, dst) in out.chunks_exact_mut(T::BYTES).enumerate() {
+        let range = i * T::BYTES..(i + 1) * T::BYTES;
+        if f(T::read(&a[range.clone()]), T::read(&b[range])) {
+            dst.fill(0xff);
+        }
+    }
+    u128::from_le_bytes(out)
+}
+
+/// Converts the lanes of `a` then `b` into lanes half as wide.
+fn narrow<T: Lane, U: Lane>(a: u128, b: u128, f: impl Fn(T) -> U) -> u128 {
+    map(a, &f) | map(b, &f) << 64
+}
+
+/// Widens each lane of the lower half of `v`, or the upper half if `high`.
+fn extend<T: Lane, U: Lane>(v: u128, high: bool, f: impl Fn(T) -> U) -> u128 {
+    map(if high { v >> 64 } else { v }, f)
+}
+
+/// Multiplies the lanes of the lower or upper halves into lanes twice as wide.
+fn extmul<T: Lane, U: Lane>(a: u128, b: u128, high: bool, f: impl Fn(T, T) -> U) -> u128 {
+    if high {
+        zip(a >> 64, b >> 64, f)
+    } else {
+        zip(a, b, f)
+    }
+}
+
+/// Combines each adjacent pair of lanes into one lane twice as wide.
+fn pairwise<T: Lane, U: Lane>(v: u128, f: impl Fn(T, T) -> U) -> u128 {
+    let bytes = v.to_le_bytes();
+    let mut out = [0; 16];
+    for (pair, dst) in bytes.chunks_exact(2 * T::BYTES).zip(out.chunks_exact_mut(U::BYTES)) {
+        let (lo, hi) = pair.split_at(T::BYTES);
+        f(T::read(lo), T::read(hi)).write(dst);
+    }
+    u128::from_le_bytes(out)
+}
+
+pub fn shuffle(a: u128, b: u128, lanes: &[u8; 16]) -> u128 {
+    let mut bytes = [0; 32];
+    bytes[..16].copy_from_slice(&a.to_le_bytes());
+    bytes[16..].copy_from_slice(&b.to_le_bytes());
+    u128::from_le_bytes(lanes.map(|lane| bytes[lane as usize]))
+}
+
+fn swizzle(a: u128, s: u128) -> u128 {
+    let bytes = a.to_le_bytes();
+    let selected = s.to_le_bytes().map(|idx| bytes.get(idx as usize).copied().unwrap_or(0));
+    u128::from_le_bytes(selected)
+}
+
+pub fn bitselect(a: u128, b: u128, mask: u128) -> u128 {
+    (a & mask) | (b & !mask)
+}
+
+/// Whether every lane `bytes` wide is non-zero.
+fn all_true(v: u128, bytes: usize) -> bool {
+    v.to_le_bytes().chunks_exact(bytes).all(|lane| lane.iter().any(|b| *b != 0))
+}
+
+/// The sign bits of the lanes `bytes` wide.
+fn bitmask(v: u128, bytes: usize) -> i32 {
+    let lanes = v.to_le_bytes();
+    let signs = lanes.chunks_exact(bytes).map(|lane| (lane[bytes - 1] >> 7) as i32);
+    signs.enumerate().fold(0, |mask, (i, sign)| mask | sign << i)
+}
+
+fn dot(a: u128, b: u128) -> u128 {
+    let products = |i| lane::<i16>(a, i) as i32 * lane::<i16>(b, i) as i32;
+    let lanes = [0, 1, 2, 3].map(|i| products(2 * i).wrapping_add(products(2 * i + 1)));
+    lanes.iter().enumerate().fold(0, |v, (i, lane)| replace_lane(v, i, *lane))
+}
+
+fn q15mulr(a: i16, b: i16) -> i16 {
+    let product = (a as i32 * b as i32 + 0x4000) >> 15;
+    product.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+/// Executes a [`Kind::Unary`] instruction.
+pub fn unary(op: u32, v: u128) -> u128 {
+    match op {
+        V128_NOT => !v,
+        F32X4_DEMOTE_F64X2_ZERO => map(v, |x: f64| x as f32),
+        F64X2_PROMOTE_LOW_F32X4 => map(v, |x: f32| x as f64),
+        I8X16_ABS => map(v, i8::wrapping_abs),
+        I8X16_NEG => map(v, i8::wrapping_neg),
+        I8X16_POPCNT => map(v, |x: u8| x.count_ones() as u8),
+        F32X4_CEIL => map(v, f32::ceil),
+        F32X4_FLOOR => map(v, f32::floor),
+        F32X4_TRUNC => map(v, f32::trunc),
+        F32X4_NEAREST => map(v, f32::nearest),
+        F64X2_CEIL => map(v, f64::ceil),
+        F64X2_FLOOR => map(v, f64::floor),
+        F64X2_TRUNC => map(v, f64::trunc),
+        F64X2_NEAREST => map(v, f64::nearest),
+        I16X8_EXTADD_PAIRWISE_I8X16_S => pairwise(v, |a: i8, b: i8| a as i16 + b as i16),
+        I16X8_EXTADD_PAIRWISE_I8X16_U => pairwise(v, |a: u8, b: u8| a as u16 + b as u16),
+        I32X4_EXTADD_PAIRWISE_I16X8_S => pairwise(v, |a: i16, b: i16| a as i32 + b as i32),
+        I32X4_EXTADD_PAIRWISE_I16X8_U => pairwise(v, |a: u16, b: u16| a as u32 + b as u32),
+        I16X8_ABS => map(v, i16::wrapping_abs),
+        I16X8_NEG => map(v, i16::wrapping_neg),
+        I16X8_EXTEND_LOW_I8X16_S => extend(v, false, |x: i8| x as i16),
+        I16X8_EXTEND_HIGH_I8X16_S => extend(v, true, |x: i8| x as i16),
+        I16X8_EXTEND_LOW_I8X16_U => extend(v, false, |x: u8| x as u16),
+        I16X8_EXTEND_HIGH_I8X16_U => extend(v, true, |x: u8| x as u16),
+        I32X4_ABS => map(v, i32::wrapping_abs),
+        I32X4_NEG => map(v, i32::wrapping_neg),
+        I32X4_EXTEND_LOW_I16X8_S => extend(v, false, |x: i16| x as i32),
+        I32X4_EXTEND_HIGH_I16X8_S => extend(v, true, |x: i16| x as i32),
+        I32X4_EXTEND_LOW_I16X8_U => extend(v, false, |x: u16| x as u32),
+        I32X4_EXTEND_HIGH_I16X8_U => extend(v, true, |x: u16| x as u32),
+        I64X2_ABS => map(v, i64::wrapping_abs),
+        I64X2_NEG => map(v, i64::wrapping_neg),
+        I64X2_EXTEND_LOW_I32X4_S => extend(v, false, |x: i32| x as i64),
+        I64X2_EXTEND_HIGH_I32X4_S => extend(v, true, |x: i32| x as i64),
+        I64X2_EXTEND_LOW_I32X4_U => extend(v, false, |x: u32| x as u64),
+        I64X2_EXTEND_HIGH_I32X4_U => extend(v, true, |x: u32| x as u64),
+        F32X4_ABS => map(v, f32::abs),
+        F32X4_NEG => map(v, |x: f32| -x),
+        F32X4_SQRT => map(v, f32::sqrt),
+        F64X2_ABS => map(v, f64::abs),
+        F64X2_NEG => map(v, |x: f64| -x),
+        F64X2_SQRT => map(v, f64::sqrt),
+        // Rust's float to integer casts saturate and turn NaN into 0, as these require
+        I32X4_TRUNC_SAT_F32X4_S => map(v, |x: f32| x as i32),
+        I32X4_TRUNC_SAT_F32X4_U => map(v, |x: f32| x as u32),
+        F32X4_CONVERT_I32X4_S => map(v, |x: i32| x as f32),
+        F32X4_CONVERT_I32X4_U => map(v, |x: u32| x as f32),
+        I32X4_TRUNC_SAT_F64X2_S_ZERO => map(v, |x: f64| x as i32),
+        I32X4_TRUNC_SAT_F64X2_U_ZERO => map(v, |x: f64| x as u32),
+        F64X2_CONVERT_LOW_I32X4_S => map(v, |x: i32| x as f64),
+        F64X2_CONVERT_LOW_I32X4_U => map(v, |x: u32| x as f64),
+        op => unreachable!("SIMD instruction {op:#04x} is not unary"),
+    }
+}
+
+/// Executes a [`Kind::Binary`] instruction.
+pub fn binary(op: u32, a: u128, b: u128) -> u128 {
+    match op {
+        I8X16_SWIZZLE => swizzle(a, b),
+        I8X16_EQ => compare(a, b, |a: i8, b| a == b),
+        I8X16_NE => compare(a, b, |a: i8, b| a != b),
+        I8X16_LT_S => compare(a, b, |a: i8, b| a < b),
+        I8X16_LT_U => compare(a, b, |a: u8, b| a < b),
+        I8X16_GT_S => compare(a, b, |a: i8, b| a > b),
+        I8X16_GT_U => compare(a, b, |a: u8, b| a > b),
+        I8X16_LE_S => compare(a, b, |a: i8, b| a <= b),
+        I8X16_LE_U => compare(a, b, |a: u8, b| a <= b),
+        I8X16_GE_S => compare(a, b, |a: i8, b| a >= b),
+        I8X16_GE_U => compare(a, b, |a: u8, b| a >= b),
+        I16X8_EQ => compare(a, b, |a: i16, b| a == b),
+        I16X8_NE => compare(a, b, |a: i16, b| a != b),
+        I16X8_LT_S => compare(a, b, |a: i16, b| a < b),
+        I16X8_LT_U => compare(a, b, |a: u16, b| a < b),
+        I16X8_GT_S => compare(a, b, |a: i16, b| a > b),
+        I16X8_GT_U => compare(a, b, |a: u16, b| a > b),
+        I16X8_LE_S => compare(a, b, |a: i16, b| a <= b),
+        I16X8_LE_U => compare(a, b, |a: u16, b| a <= b),
+        I16X8_GE_S => compare(a, b, |a: i16, b| a >= b),
+        I16X8_GE_U => compare(a, b, |a: u16, b| a >= b),
+        I32X4_EQ => compare(a, b, |a: i32, b| a == b),
+        I32X4_NE => compare(a, b, |a: i32, b| a != b),
+        I32X4_LT_S => compare(a, b, |a: i32, b| a < b),
+        I32X4_LT_U => compare(a, b, |a: u32, b| a < b),
+        I32X4_GT_S => compare(a, b, |a: i32, b| a > b),
+        I32X4_GT_U => compare(a, b, |a: u32, b| a > b),
+        I32X4_LE_S => compare(a, b, |a: i32, b| a <= b),
+        I32X4_LE_U => compare(a, b, |a: u32, b| a <= b),
+        I32X4_GE_S => compare(a, b, |a: i32, b| a >= b),
+        I32X4_GE_U => compare(a, b, |a: u32, b| a >= b),
+        I64X2_EQ => compare(a, b, |a: i64, b| a == b),
+        I64X2_NE => compare(a, b, |a: i64, b| a != b),
+        I64X2_LT_S => compare(a, b, |a: i64, b| a < b),
+        I64X2_GT_S => compare(a, b, |a: i64, b| a > b),
+        I64X2_LE_S => compare(a, b, |a: i64, b| a <= b),
+        I64X2_GE_S => compare(a, b, |a: i64, b| a >= b),
+        F32X4_EQ => compare(a, b, |a: f32, b| a == b),
+        F32X4_NE => compare(a, b, |a: f32, b| a != b),
+        F32X4_LT => compare(a, b, |a: f32, b| a < b),
+        F32X4_GT => compare(a, b, |a: f32, b| a > b),
+        F32X4_LE => compare(a, b, |a: f32, b| a <= b),
+        F32X4_GE => compare(a, b, |a: f32, b| a >= b),
+        F64X2_EQ => compare(a, b, |a: f64, b| a == b),
+        F64X2_NE => compare(a, b, |a: f64, b| a != b),
+        F64X2_LT => compare(a, b, |a: f64, b| a < b),
+        F64X2_GT => compare(a, b, |a: f64, b| a > b),
+        F64X2_LE => compare(a, b, |a: f64, b| a <= b),
+        F64X2_GE => compare(a, b, |a: f64, b| a >= b),
+        V128_AND => a & b,
+        V128_ANDNOT => a & !b,
+        V128_OR => a | b,
+        V128_XOR => a ^ b,
+        I8X16_NARROW_I16X8_S => {
+            narrow(a, b, |x: i16| x.clamp(i8::MIN as i16, i8::MAX as i16) as i8)
+        }
+        I8X16_NARROW_I16X8_U => narrow(a, b, |x: i16| x.clamp(0, u8::MAX as i16) as u8),
+        I8X16_ADD => zip(a, b, i8::wrapping_add),
+        I8X16_ADD_SAT_S => zip(a, b, i8::saturating_add),
+        I8X16_ADD_SAT_U => zip(a, b, u8::saturating_add),
+        I8X16_SUB => zip(a, b, i8::wrapping_sub),
+        I8X16_SUB_SAT_S => zip(a, b, i8::saturating_sub),
+        I8X16_SUB_SAT_U => zip(a, b, u8::saturating_sub),
+        I8X16_MIN_S => zip(a, b, i8::min),
+        I8X16_MIN_U => zip(a, b, u8::min),
+        I8X16_MAX_S => zip(a, b, i8::max),
+        I8X16_MAX_U => zip(a, b, u8::max),
+        I8X16_AVGR_U => zip(a, b, |a: u8, b: u8| (a as u16 + b as u16).div_ceil(2) as u8),
+        I16X8_Q15MULR_SAT_S => zip(a, b, q15mulr),
+        I16X8_NARROW_I32X4_S => {
+            narrow(a, b, |x: i32| x.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+        }
+        I16X8_NARROW_I32X4_U => narrow(a, b, |x: i32| x.clamp(0, u16::MAX as i32) as u16),
+        I16X8_ADD => zip(a, b, i16::wrapping_add),
+        I16X8_ADD_SAT_S => zip(a, b, i16::saturating_add),
+        I16X8_ADD_SAT_U => zip(a, b, u16::saturating_add),
+        I16X8_SUB => zip(a, b, i16::wrapping_sub),
+        I16X8_SUB_SAT_S => zip(a, b, i16::saturating_sub),
+        I16X8_SUB_SAT_U => zip(a, b, u16::saturating_sub),
+        I16X8_MUL => zip(a, b, i16::wrapping_mul),
+        I16X8_MIN_S => zip(a, b, i16::min),
+        I16X8_MIN_U => zip(a, b, u16::min),
+        I16X8_MAX_S => zip(a, b, i16::max),
+        I16X8_MAX_U => zip(a, b, u16::max),
+        I16X8_AVGR_U => zip(a, b, |a: u16, b: u16| (a as u32 + b as u32).div_ceil(2) as u16),
+        I16X8_EXTMUL_LOW_I8X16_S => extmul(a, b, false, |a: i8, b| a as i16 * b as i16),
+        I16X8_EXTMUL_HIGH_I8X16_S => extmul(a, b, true, |a: i8, b| a as i16 * b as i16),
+        I16X8_EXTMUL_LOW_I8X16_U => extmul(a, b, false, |a: u8, b| a as u16 * b as u16),
+        I16X8_EXTMUL_HIGH_I8X16_U => extmul(a, b, true, |a: u8, b| a as u16 * b as u16),
+        I32X4_ADD => zip(a, b, i32::wrapping_add),
+        I32X4_SUB => zip(a, b, i32::wrapping_sub),
+        I32X4_MUL => zip(a, b, i32::wrapping_mul),
+        I32X4_MIN_S => zip(a, b, i32::min),
+        I32X4_MIN_U => zip(a, b, u32::min),
+        I32X4_MAX_S => zip(a, b, i32::max),
+        I32X4_MAX_U => zip(a, b, u32::max),
+        I32X4_DOT_I16X8_S => dot(a, b),
+        I32X4_EXTMUL_LOW_I16X8_S => extmul(a, b, false, |a: i16, b| a as i32 * b as i32),
+        I32X4_EXTMUL_HIGH_I16X8_S => extmul(a, b, true, |a: i16, b| a as i32 * b as i32),
+        I32X4_EXTMUL_LOW_I16X8_U => extmul(a, b, false, |a: u16, b| a as u32 * b as u32),
+        I32X4_EXTMUL_HIGH_I16X8_U => extmul(a, b, true, |a: u16, b| a as u32 * b as u32),
+        I64X2_ADD => zip(a, b, i64::wrapping_add),
+        I64X2_SUB => zip(a, b, i64::wrapping_sub),
+        I64X2_MUL => zip(a, b, i64::wrapping_mul),
+        I64X2_EXTMUL_LOW_I32X4_S => extmul(a, b, false, |a: i32, b| a as i64 * b as i64),
+        I64X2_EXTMUL_HIGH_I32X4_S => extmul(a, b, true, |a: i32, b| a as i64 * b as i64),
+        I64X2_EXTMUL_LOW_I32X4_U => extmul(a, b, false, |a: u32, b| a as u64 * b as u64),
+        I64X2_EXTMUL_HIGH_I32X4_U => extmul(a, b, true, |a: u32, b| a as u64 * b as u64),
+        F32X4_ADD => zip(a, b, |a: f32, b| a + b),
+        F32X4_SUB => zip(a, b, |a: f32, b| a - b),
+        F32X4_MUL => zip(a, b, |a: f32, b| a * b),
+        F32X4_DIV => zip(a, b, |a: f32, b| a / b),
+        F32X4_MIN => zip(a, b, f32::wasm_min),
+        F32X4_MAX => zip(a, b, f32::wasm_max),
+        F32X4_PMIN => zip(a, b, |a: f32, b| if b < a { b } else { a }),
+        F32X4_PMAX => zip(a, b, |a: f32, b| if a < b { b } else { a }),
+        F64X2_ADD => zip(a, b, |a: f64, b| a + b),
+        F64X2_SUB => zip(a, b, |a: f64, b| a - b),
+        F64X2_MUL => zip(a, b, |a: f64, b| a * b),
+        F64X2_DIV => zip(a, b, |a: f64, b| a / b),
+        F64X2_MIN => zip(a, b, f64::wasm_min),
+        F64X2_MAX => zip(a, b, f64::wasm_max),
+        F64X2_PMIN => zip(a, b, |a: f64, b| if b < a { b } else { a }),
+        F64X2_PMAX => zip(a, b, |a: f64, b| if a < b { b } else { a }),
+        op => unreachable!("SIMD instruction {op:#04x} is not binary"),
+    }
+}
+
+/// Executes a [`Kind::Test`] instruction.
+pub fn test(op: u32, v: u128) -> i32 {
+    match op {
+        V128_ANY_TRUE => (v != 0) as i32,
+        I8X16_ALL_TRUE => all_true(v, 1) as i32,
+        I16X8_ALL_TRUE => all_true(v, 2) as i32,
+        I32X4_ALL_TRUE => all_true(v, 4) as i32,
+        I64X2_ALL_TRUE => all_true(v, 8) as i32,
+        I8X16_BITMASK => bitmask(v, 1),
+        I16X8_BITMASK => bitmask(v, 2),
+        I32X4_BITMASK => bitmask(v, 4),
+        I64X2_BITMASK => bitmask(v, 8),
+        op => unreachable!("SIMD instruction {op:#04x} is not a test"),
+    }
+}
+
+/// Executes a [`Kind::Shift`] instruction. The shift amount is taken modulo the lane
+/// width, which the wrapping shifts already do.
+pub fn shift(op: u32, v: u128, amount: u32) -> u128 {
+    match op {
+        I8X16_SHL => map(v, |x: i8| x.wrapping_shl(amount)),
+        I8X16_SHR_S => map(v, |x: i8| x.wrapping_shr(amount)),
+        I8X16_SHR_U => map(v, |x: u8| x.wrapping_shr(amount)),
+        I16X8_SHL => map(v, |x: i16| x.wrapping_shl(amount)),
+        I16X8_SHR_S => map(v, |x: i16| x.wrapping_shr(amount)),
+        I16X8_SHR_U => map(v, |x: u16| x.wrapping_shr(amount)),
+        I32X4_SHL => map(v, |x: i32| x.wrapping_shl(amount)),
+        I32X4_SHR_S => map(v, |x: i32| x.wrapping_shr(amount)),
+        I32X4_SHR_U => map(v, |x: u32| x.wrapping_shr(amount)),
+        I64X2_SHL => map(v, |x: i64| x.wrapping_shl(amount)),
+        I64X2_SHR_S => map(v, |x: i64| x.wrapping_shr(amount)),
+        I64X2_SHR_U => map(v, |x: u64| x.wrapping_shr(amount)),
+        op => unreachable!("SIMD instruction {op:#04x} is not a shift"),
+    }
+}
+
+// The assertions of the spec's simd_*.wast scripts, carried as tables per opcode family, as
+// the crate has no text format parser to run the scripts themselves with.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i8x16(lanes: [i8; 16]) -> u128 {
+        u128::from_le_bytes(lanes.map(|lane| lane as u8))
+    }
+
+    fn i16x8(lanes: [i16; 8]) -> u128 {
+        lanes.iter().enumerate().fold(0, |v, (i, lane)| replace_lane(v, i, *lane))
+    }
+
+    fn f32x4(lanes: [f32; 4]) -> u128 {
+        lanes.iter().enumerate().fold(0, |v, (i, lane)| replace_lane(v, i, *lane))
+    }
+
+    fn lanes_f32(v: u128) -> [f32; 4] {
+        [0, 1, 2, 3].map(|i| lane(v, i))
+    }
+
+    /// A lane compared as the spec's assertions do, bit for bit except that a NaN matches
+    /// any NaN.
+    trait Expected: Lane + std::fmt::Debug {
+        fn matches(self, expected: Self) -> bool;
+    }
+
+    macro_rules! expected {
+        ($($t: ty),*) => {$(
+            impl Expected for $t {
+                fn matches(self, expected: Self) -> bool {
+                    self == expected
+                }
+            }
+        )*};
+    }
+
+    expected!(i8, u8, i16, u16, i32, u32, i64, u64);
+
+    macro_rules! expected_float {
+        ($($t: ty),*) => {$(
+            impl Expected for $t {
+                fn matches(self, expected: Self) -> bool {
+                    self.to_bits() == expected.to_bits() || (self.is_nan() && expected.is_nan())
+                }
+            }
+        )*};
+    }
+
+    expected_float!(f32, f64);
+
+    /// The vector with `values` in its lanes, repeated until every lane is filled.
+    fn vector<T: Lane>(values: &[T]) -> u128 {
+        (0..16 / T::BYTES).fold(0, |v, i| replace_lane(v, i, values[i % values.len()]))
+    }
+
+    fn check<U: Expected>(op: u32, result: u128, expected: impl Fn(usize) -> U) {
+        for i in 0..16 / U::BYTES {
+            let (actual, expected) = (lane::<U>(result, i), expected(i));
+            assert!(actual.matches(expected), "{op:#04x} lane {i}: {actual:?}, not {expected:?}");
+        }
+    }
+
+    /// Checks an instruction that keeps the lane count on `(operand, result)` cases. Each
+    /// case gets a lane of its own, so one spilling into its neighbours fails as well.
+    fn unary_cases<T: Lane, U: Expected>(op: u32, cases: &[(T, U)]) {
+        for chunk in cases.chunks(16 / T::BYTES) {
+            let v = vector(&chunk.iter().map(|case| case.0).collect::<Vec<_>>());
+            check(op, unary(op, v), |i| chunk[i % chunk.len()].1);
+        }
+    }
+
+    fn binary_cases<T: Lane, U: Expected>(op: u32, cases: &[(T, T, U)]) {
+        for chunk in cases.chunks(16 / T::BYTES) {
+            let a = vector(&chunk.iter().map(|case| case.0).collect::<Vec<_>>());
+            let b = vector(&chunk.iter().map(|case| case.1).collect::<Vec<_>>());
+            check(op, binary(op, a, b), |i| chunk[i % chunk.len()].2);
+        }
+    }
+
+    /// Checks an instruction that changes the lane count, with each case splatted.
+    fn unary_splats<T: Lane, U: Expected>(op: u32, cases: &[(T, U)]) {
+        for &(v, expected) in cases {
+            check(op, unary(op, splat(v)), |_| expected);
+        }
+    }
+
+    fn binary_splats<T: Lane, U: Expected>(op: u32, cases: &[(T, T, U)]) {
+        for &(a, b, expected) in cases {
+            check(op, binary(op, splat(a), splat(b)), |_| expected);
+        }
+    }
+
+    /// Like [`unary_splats`], for the instructions that zero the upper two lanes.
+    fn unary_zero_splats<T: Lane, U: Expected>(op: u32, cases: &[(T, U)]) {
+        let zero = U::read(&[0; 8][..U::BYTES]);
+        for &(v, expected) in cases {
+            check(op, unary(op, splat(v)), |i| if i < 2 { expected } else { zero });
+        }
+    }
+
+    fn shift_cases<T: Expected>(op: u32, cases: &[(T, u32, T)]) {
+        for &(v, amount, expected) in cases {
+            check(op, shift(op, splat(v), amount), |_| expected);
+        }
+    }
+
+    #[test]
+    fn saturating_arithmetic() {
+        let (max, min) = (splat(i8::MAX), splat(i8::MIN));
+        assert_eq!(max, binary(I8X16_ADD_SAT_S, max, splat(1i8)));
+        assert_eq!(min, binary(I8X16_SUB_SAT_S, min, splat(1i8)));
+        assert_eq!(splat(0xffu8), binary(I8X16_ADD_SAT_U, splat(0xffu8), splat(1u8)));
+        assert_eq!(0, binary(I8X16_SUB_SAT_U, 0, splat(1u8)));
+        assert_eq!(splat(i8::MIN), binary(I8X16_ADD, max, splat(1i8)));
+        assert_eq!(splat(i16::MAX), binary(I16X8_Q15MULR_SAT_S, splat(i16::MIN), splat(i16::MIN)));
+        assert_eq!(splat(0xffu8), binary(I8X16_AVGR_U, splat(0xffu8), splat(0xffu8)));
+        assert_eq!(splat(1u8), binary(I8X16_AVGR_U, 0, splat(1u8)));
+    }
+
+    #[test]
+    fn conversions() {
+        let narrowed = binary(I8X16_NARROW_I16X8_U, splat(-1i16), splat(256i16));
+        assert_eq!(0xff, lane::<u8>(narrowed, 8));
+        assert_eq!(0, lane::<u8>(narrowed, 7));
+        let narrowed = binary(I16X8_NARROW_I32X4_S, splat(70000i32), splat(-70000i32));
+        assert_eq!(i16x8([i16::MAX, i16::MAX, i16::MAX, i16::MAX, i16::MIN, i16::MIN,
+            i16::MIN, i16::MIN]), narrowed);
+
+        let floats = f32x4([f32::NAN, 3e9, f32::NEG_INFINITY, -1.5]);
+        let truncated = unary(I32X4_TRUNC_SAT_F32X4_S, floats);
+        assert_eq!([0, i32::MAX, i32::MIN, -1], [0, 1, 2, 3].map(|i| lane::<i32>(truncated, i)));
+        let truncated = unary(I32X4_TRUNC_SAT_F32X4_U, floats);
+        assert_eq!([0, 3_000_000_000, 0, 0], [0, 1, 2, 3].map(|i| lane::<u32>(truncated, i)));
+
+        let doubles = replace_lane(replace_lane(0, 0, 1.5f64), 1, f64::MAX);
+        let demoted = unary(F32X4_DEMOTE_F64X2_ZERO, doubles);
+        assert_eq!([1.5, f32::INFINITY, 0.0, 0.0], lanes_f32(demoted));
+        let promoted = unary(F64X2_PROMOTE_LOW_F32X4, f32x4([0.5, -2.0, 9.0, 9.0]));
+        assert_eq!((0.5, -2.0), (lane::<f64>(promoted, 0), lane::<f64>(promoted, 1)));
+        let truncated = unary(I32X4_TRUNC_SAT_F64X2_U_ZERO, replace_lane(0, 0, -1.0f64));
+        assert_eq!(0, truncated);
+
+        let extended = unary(I16X8_EXTEND_HIGH_I8X16_S, i8x16([0, 0, 0, 0, 0, 0, 0, 0,
+            -1, 2, -3, 4, -5, 6, -7, 8]));
+        assert_eq!(i16x8([-1, 2, -3, 4, -5, 6, -7, 8]), extended);
+        let sums = unary(I16X8_EXTADD_PAIRWISE_I8X16_U, splat(0xffu8));
+        assert_eq!(splat(510u16), sums);
+        let products = binary(I32X4_EXTMUL_HIGH_I16X8_U, splat(0xffffu16), splat(2u16));
+        assert_eq!(splat(0x1fffeu32), products);
+    }
+
+    #[test]
+    fn float_lanes() {
+        let (a, b) = (f32x4([-0.0, 0.0, f32::NAN, 1.0]), f32x4([0.0, -0.0, 1.0, 2.0]));
+        let min = binary(F32X4_MIN, a, b);
+        let lanes = lanes_f32(min);
+        assert!(lanes[0] == 0.0 && lanes[0].is_sign_negative());
+        assert!(lanes[1] == 0.0 && lanes[1].is_sign_negative());
+        assert!(lanes[2].is_nan());
+        assert_eq!(1.0, lanes[3]);
+
+        let max = binary(F32X4_MAX, f32x4([-0.0; 4]), f32x4([0.0; 4]));
+        assert!(lanes_f32(max)[0].is_sign_positive());
+        // pmin and pmax return the first operand unless the second is strictly ordered
+        let (a, b) = (f32x4([-0.0, f32::NAN, 1.0, 2.0]), f32x4([0.0, 0.0, 0.5, 3.0]));
+        let pmin = binary(F32X4_PMIN, a, b);
+        let lanes = lanes_f32(pmin);
+        assert!(lanes[0].is_sign_negative() && lanes[1].is_nan());
+        assert_eq!((0.5, 2.0), (lanes[2], lanes[3]));
+
+        let nearest = unary(F32X4_NEAREST, f32x4([2.5, 3.5, -0.5, -4.5]));
+        let lanes = lanes_f32(nearest);
+        assert_eq!([2.0, 4.0, -0.0, -4.0], lanes);
+        assert!(lanes[2].is_sign_negative());
+        assert_eq!(f32x4([1.0, 0.0, 1.0, 0.0]).to_le_bytes()[..4],
+            unary(F32X4_ABS, f32x4([-1.0, -0.0, 1.0, 0.0])).to_le_bytes()[..4]);
+    }
+
+    #[test]
+    fn integer_lanes() {
+        assert_eq!(splat(8u8), unary(I8X16_POPCNT, u128::MAX));
+        assert_eq!(splat(i64::MIN), unary(I64X2_ABS, splat(i64::MIN)));
+        assert_eq!(splat(i32::MIN), binary(I32X4_DOT_I16X8_S, splat(i16::MIN), splat(i16::MIN)));
+        assert_eq!(splat(2u8), shift(I8X16_SHL, splat(1u8), 9));
+        assert_eq!(splat(-1i16), shift(I16X8_SHR_S, splat(i16::MIN), 15));
+        assert_eq!(splat(1u16), shift(I16X8_SHR_U, splat(i16::MIN), 15));
+
+        let lt = binary(I8X16_LT_U, splat(1u8), splat(0x80u8));
+        assert_eq!(u128::MAX, lt);
+        assert_eq!(0, binary(I8X16_LT_S, splat(1u8), splat(0x80u8)));
+        assert_eq!(0xffff, test(I8X16_BITMASK, lt));
+        assert_eq!(0b10, test(I32X4_BITMASK, replace_lane(0, 1, -1i32)));
+        assert_eq!(0, test(I16X8_ALL_TRUE, replace_lane(splat(1u16), 7, 0u16)));
+        assert_eq!(1, test(V128_ANY_TRUE, 1 << 127));
+    }
+
+    #[test]
+    fn shuffles() {
+        let a = u128::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+        let b = a + splat(16u8);
+        let lanes = [31, 0, 30, 1, 29, 2, 28, 3, 27, 4, 26, 5, 25, 6, 24, 7];
+        assert_eq!(u128::from_le_bytes(lanes), shuffle(a, b, &lanes));
+        let indices = i8x16([3, 16, -1, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x7f]);
+        let swizzled = binary(I8X16_SWIZZLE, a, indices).to_le_bytes();
+        assert_eq!([3, 0, 0, 15], swizzled[..4]);
+        assert_eq!(0, swizzled[15]);
+        assert_eq!(0b1100, bitselect(0b1010, 0b0101, 0b1001));
+    }
+
+    #[test]
+    fn simd_sat_arith() {
+        binary_cases(I8X16_ADD_SAT_S, &[(0i8, 0, 0i8), (1, 1, 2), (-1, -1, -2),
+            (i8::MAX, 1, i8::MAX), (i8::MIN, -1, i8::MIN), (i8::MAX, i8::MAX, i8::MAX),
+            (i8::MIN, i8::MIN, i8::MIN), (0x40, 0x40, i8::MAX), (-64, -65, i8::MIN), (-1, 1, 0),
+            (i8::MAX, i8::MIN, -1)]);
+        binary_cases(I8X16_ADD_SAT_U, &[(0u8, 0, 0u8), (1, 1, 2), (0xff, 1, 0xff),
+            (0xff, 0xff, 0xff), (0x80, 0x80, 0xff), (0x7f, 0x80, 0xff), (0xfe, 1, 0xff),
+            (0x7f, 0x7f, 0xfe)]);
+        binary_cases(I8X16_SUB_SAT_S, &[(0i8, 0, 0i8), (1, 1, 0), (-1, -1, 0),
+            (i8::MAX, -1, i8::MAX), (i8::MIN, 1, i8::MIN), (i8::MAX, i8::MIN, i8::MAX),
+            (i8::MIN, i8::MAX, i8::MIN), (-2, i8::MAX, i8::MIN), (-1, i8::MAX, i8::MIN),
+            (0, i8::MIN, i8::MAX)]);
+        binary_cases(I8X16_SUB_SAT_U, &[(0u8, 0, 0u8), (1, 1, 0), (0, 1, 0), (0x80, 0x81, 0),
+            (0xff, 0, 0xff), (0xff, 0xff, 0), (0x7f, 0xff, 0), (0xff, 1, 0xfe)]);
+        binary_cases(I16X8_ADD_SAT_S, &[(0i16, 0, 0i16), (1, 1, 2), (i16::MAX, 1, i16::MAX),
+            (i16::MIN, -1, i16::MIN), (0x4000, 0x4000, i16::MAX), (-0x4000, -0x4001, i16::MIN),
+            (i16::MAX, i16::MIN, -1)]);
+        binary_cases(I16X8_ADD_SAT_U, &[(0u16, 0, 0u16), (1, 1, 2), (0xffff, 1, 0xffff),
+            (0x8000, 0x8000, 0xffff), (0x7fff, 0x8000, 0xffff), (0x7fff, 0x7fff, 0xfffe)]);
+        binary_cases(I16X8_SUB_SAT_S, &[(1i16, 1, 0i16), (i16::MAX, -1, i16::MAX),
+            (i16::MIN, 1, i16::MIN), (0, i16::MIN, i16::MAX), (-1, i16::MAX, i16::MIN),
+            (-2, i16::MAX, i16::MIN)]);
+        binary_cases(I16X8_SUB_SAT_U, &[(1u16, 1, 0u16), (0, 1, 0), (0x8000, 0x8001, 0),
+            (0xffff, 0xffff, 0), (0xffff, 1, 0xfffe), (0xffff, 0, 0xffff)]);
+    }
+
+    #[test]
+    fn simd_arith() {
+        binary_cases(I8X16_ADD, &[(1i8, 1, 2i8), (i8::MAX, 1, i8::MIN), (i8::MIN, -1, i8::MAX),
+            (-1, -1, -2)]);
+        binary_cases(I8X16_SUB, &[(1i8, 1, 0i8), (i8::MIN, 1, i8::MAX), (0, i8::MIN, i8::MIN)]);
+        binary_cases(I16X8_MUL, &[(0x1000i16, 0x10, 0i16), (i16::MIN, -1, i16::MIN),
+            (-1, -1, 1), (i16::MAX, 2, -2)]);
+        binary_cases(I32X4_ADD, &[(i32::MAX, 1, i32::MIN), (i32::MIN, -1, i32::MAX)]);
+        binary_cases(I32X4_MUL, &[(i32::MIN, -1, i32::MIN), (0x10000, 0x10000, 0i32),
+            (i32::MAX, 2, -2)]);
+        binary_cases(I64X2_ADD, &[(i64::MAX, 1, i64::MIN), (-1, -1, -2i64)]);
+        binary_cases(I64X2_SUB, &[(i64::MIN, 1, i64::MAX), (0, i64::MIN, i64::MIN)]);
+        binary_cases(I64X2_MUL, &[(i64::MIN, -1, i64::MIN), (1 << 32, 1 << 32, 0i64)]);
+
+        unary_cases(I8X16_NEG, &[(0i8, 0i8), (1, -1), (i8::MIN, i8::MIN), (i8::MAX, -i8::MAX)]);
+        unary_cases(I8X16_ABS, &[(0i8, 0i8), (-1, 1), (i8::MIN, i8::MIN), (i8::MAX, i8::MAX)]);
+        unary_cases(I16X8_NEG, &[(1i16, -1i16), (i16::MIN, i16::MIN), (i16::MAX, -i16::MAX)]);
+        unary_cases(I16X8_ABS, &[(-1i16, 1i16), (i16::MIN, i16::MIN), (i16::MAX, i16::MAX)]);
+        unary_cases(I32X4_NEG, &[(1i32, -1i32), (i32::MIN, i32::MIN), (i32::MAX, -i32::MAX)]);
+        unary_cases(I32X4_ABS, &[(-1i32, 1i32), (i32::MIN, i32::MIN), (i32::MAX, i32::MAX)]);
+        unary_cases(I64X2_NEG, &[(1i64, -1i64), (i64::MIN, i64::MIN)]);
+        unary_cases(I64X2_ABS, &[(-1i64, 1i64), (i64::MIN, i64::MIN)]);
+        unary_cases(I8X16_POPCNT, &[(0u8, 0u8), (0xff, 8), (0x55, 4), (0x80, 1), (0x0f, 4)]);
+
+        unary_cases(V128_NOT, &[(0u64, u64::MAX), (0x5555_5555_5555_5555, 0xaaaa_aaaa_aaaa_aaaa)]);
+        binary_cases(V128_AND, &[(0b1100u64, 0b1010, 0b1000u64), (u64::MAX, 0, 0)]);
+        binary_cases(V128_OR, &[(0b1100u64, 0b1010, 0b1110u64), (u64::MAX, 0, u64::MAX)]);
+        binary_cases(V128_XOR, &[(0b1100u64, 0b1010, 0b0110u64), (u64::MAX, u64::MAX, 0)]);
+        binary_cases(V128_ANDNOT, &[(0b1100u64, 0b1010, 0b0100u64), (u64::MAX, u64::MAX, 0)]);
+    }
+
+    #[test]
+    fn simd_arith2() {
+        binary_cases(I8X16_MIN_S, &[(0i8, 0, 0i8), (i8::MIN, i8::MAX, i8::MIN), (-1, 0, -1),
+            (i8::MAX, i8::MIN, i8::MIN), (1, -1, -1)]);
+        binary_cases(I8X16_MIN_U, &[(0u8, 0, 0u8), (0xff, 0, 0), (0x80, 0x7f, 0x7f),
+            (1, 0xff, 1)]);
+        binary_cases(I8X16_MAX_S, &[(i8::MIN, i8::MAX, i8::MAX), (-1, 0, 0i8), (1, -1, 1)]);
+        binary_cases(I8X16_MAX_U, &[(0xffu8, 0, 0xffu8), (0x80, 0x7f, 0x80), (1, 0xff, 0xff)]);
+        binary_cases(I16X8_MIN_S, &[(i16::MIN, i16::MAX, i16::MIN), (-1, 0, -1i16)]);
+        binary_cases(I16X8_MIN_U, &[(0xffffu16, 0, 0u16), (0x8000, 0x7fff, 0x7fff)]);
+        binary_cases(I16X8_MAX_S, &[(i16::MIN, i16::MAX, i16::MAX), (-1, 0, 0i16)]);
+        binary_cases(I16X8_MAX_U, &[(0xffffu16, 0, 0xffffu16), (0x8000, 0x7fff, 0x8000)]);
+        binary_cases(I32X4_MIN_S, &[(i32::MIN, i32::MAX, i32::MIN), (-1, 0, -1i32)]);
+        binary_cases(I32X4_MIN_U, &[(u32::MAX, 0, 0u32), (0x8000_0000, 0x7fff_ffff, 0x7fff_ffff)]);
+        binary_cases(I32X4_MAX_S, &[(i32::MIN, i32::MAX, i32::MAX), (-1, 0, 0i32)]);
+        binary_cases(I32X4_MAX_U, &[(u32::MAX, 0, u32::MAX), (0x8000_0000, 0x7fff_ffff,
+            0x8000_0000)]);
+
+        binary_cases(I8X16_AVGR_U, &[(0u8, 0, 0u8), (0, 1, 1), (1, 2, 2), (3, 0, 2),
+            (0xff, 0xff, 0xff), (0xff, 0, 0x80), (0xfe, 0xff, 0xff), (0x80, 0x7f, 0x80)]);
+        binary_cases(I16X8_AVGR_U, &[(0u16, 1, 1u16), (1, 2, 2), (0xffff, 0, 0x8000),
+            (0xffff, 0xffff, 0xffff)]);
+        binary_cases(I16X8_Q15MULR_SAT_S, &[(0i16, 0, 0i16), (1, 1, 0), (-1, -1, 0),
+            (i16::MAX, i16::MAX, 0x7ffe), (i16::MIN, i16::MIN, i16::MAX),
+            (i16::MIN, i16::MAX, -i16::MAX), (0x4000, 0x4000, 0x2000),
+            (i16::MIN, 0x4000, -0x4000)]);
+        binary_splats(I32X4_DOT_I16X8_S, &[(1i16, 1, 2i32), (-1, 1, -2),
+            (i16::MIN, i16::MIN, i32::MIN), (i16::MAX, i16::MAX, 2_147_352_578),
+            (i16::MIN, i16::MAX, -2_147_418_112)]);
+    }
+
+    #[test]
+    fn simd_compare() {
+        binary_cases(I8X16_LT_S, &[(1i8, i8::MIN, 0i8), (i8::MIN, 1, -1), (-1, -1, 0)]);
+        binary_cases(I8X16_LT_U, &[(1u8, 0x80, -1i8), (0x80, 1, 0), (0xff, 0xff, 0)]);
+        binary_cases(I8X16_GE_S, &[(i8::MIN, i8::MIN, -1i8), (i8::MIN, 0, 0)]);
+        binary_cases(I16X8_LE_U, &[(0x8000u16, 0x7fff, 0i16), (0x7fff, 0x8000, -1),
+            (0xffff, 0xffff, -1)]);
+        binary_cases(I32X4_GT_U, &[(u32::MAX, 0, -1i32), (0, 1, 0)]);
+        binary_cases(I32X4_GT_S, &[(u32::MAX, 0, 0i32), (1, 0, -1)]);
+        binary_cases(I64X2_LT_S, &[(i64::MIN, i64::MAX, -1i64), (-1, 0, -1), (0, -1, 0)]);
+        binary_cases(I64X2_NE, &[(i64::MIN, i64::MAX, -1i64), (-1, -1, 0)]);
+
+        binary_cases(F32X4_EQ, &[(0.0f32, -0.0, -1i32), (f32::NAN, f32::NAN, 0),
+            (1.0, f32::NAN, 0), (f32::INFINITY, f32::INFINITY, -1)]);
+        binary_cases(F32X4_NE, &[(f32::NAN, f32::NAN, -1i32), (0.0, -0.0, 0)]);
+        binary_cases(F32X4_LT, &[(f32::NAN, 1.0, 0i32), (f32::NEG_INFINITY, f32::INFINITY, -1),
+            (-0.0, 0.0, 0)]);
+        binary_cases(F32X4_GE, &[(f32::NAN, f32::NAN, 0i32), (-0.0, 0.0, -1)]);
+        binary_cases(F64X2_LE, &[(f64::NAN, 0.0, 0i64), (-0.0, 0.0, -1),
+            (f64::MAX, f64::INFINITY, -1)]);
+        binary_cases(F64X2_GT, &[(f64::NAN, 0.0, 0i64), (f64::INFINITY, f64::MAX, -1)]);
+    }
+
+    #[test]
+    fn simd_float() {
+        let (nan, inf) = (f32::NAN, f32::INFINITY);
+        binary_cases(F32X4_MIN, &[(0.0f32, 0.0, 0.0f32), (-0.0, 0.0, -0.0), (0.0, -0.0, -0.0),
+            (nan, 1.0, nan), (1.0, nan, nan), (-nan, 0.0, nan), (-inf, inf, -inf), (1.0, 2.0, 1.0),
+            (-1.0, -2.0, -2.0)]);
+        binary_cases(F32X4_MAX, &[(-0.0f32, 0.0, 0.0f32), (0.0, -0.0, 0.0), (nan, 1.0, nan),
+            (1.0, nan, nan), (-inf, inf, inf), (1.0, 2.0, 2.0)]);
+        binary_cases(F32X4_PMIN, &[(-0.0f32, 0.0, -0.0f32), (0.0, -0.0, 0.0), (nan, 0.0, nan),
+            (0.0, nan, 0.0), (1.0, 2.0, 1.0), (2.0, 1.0, 1.0), (-inf, inf, -inf)]);
+        binary_cases(F32X4_PMAX, &[(-0.0f32, 0.0, -0.0f32), (0.0, -0.0, 0.0), (nan, 0.0, nan),
+            (0.0, nan, 0.0), (1.0, 2.0, 2.0), (2.0, 1.0, 2.0)]);
+
+        binary_cases(F32X4_ADD, &[(inf, -inf, nan), (nan, 1.0, nan), (-0.0f32, -0.0, -0.0f32),
+            (-0.0, 0.0, 0.0), (f32::MAX, f32::MAX, inf), (1.0, f32::EPSILON / 2.0, 1.0)]);
+        binary_cases(F32X4_SUB, &[(inf, inf, nan), (-0.0f32, 0.0, -0.0f32), (0.0, -0.0, 0.0)]);
+        binary_cases(F32X4_MUL, &[(0.0f32, inf, nan), (-0.0, 1.0, -0.0f32), (-1.0, 0.0, -0.0),
+            (f32::MAX, 2.0, inf)]);
+        binary_cases(F32X4_DIV, &[(1.0f32, 0.0, inf), (-1.0, 0.0, -inf), (0.0, 0.0, nan),
+            (1.0, -0.0, -inf), (inf, inf, nan), (1.0, inf, 0.0f32)]);
+        unary_cases(F32X4_SQRT, &[(-1.0f32, nan), (-0.0, -0.0f32), (4.0, 2.0), (inf, inf),
+            (2.0, std::f32::consts::SQRT_2)]);
+        unary_cases(F32X4_ABS, &[(-0.0f32, 0.0f32), (-inf, inf), (-1.5, 1.5)]);
+        unary_cases(F32X4_NEG, &[(0.0f32, -0.0f32), (inf, -inf), (-1.5, 1.5)]);
+        // The sign of a NaN is flipped or cleared too, where a NaN only matches another above
+        assert_eq!(splat(-nan), unary(F32X4_NEG, splat(nan)));
+        assert_eq!(splat(nan), unary(F32X4_ABS, splat(-nan)));
+
+        let (nan, inf) = (f64::NAN, f64::INFINITY);
+        binary_cases(F64X2_MIN, &[(-0.0f64, 0.0, -0.0f64), (0.0, -0.0, -0.0), (nan, 1.0, nan),
+            (1.0, nan, nan), (-inf, inf, -inf)]);
+        binary_cases(F64X2_MAX, &[(-0.0f64, 0.0, 0.0f64), (0.0, -0.0, 0.0), (nan, 1.0, nan),
+            (1.0, nan, nan), (-inf, inf, inf)]);
+        binary_cases(F64X2_PMIN, &[(-0.0f64, 0.0, -0.0f64), (nan, 0.0, nan), (0.0, nan, 0.0),
+            (2.0, 1.0, 1.0)]);
+        binary_cases(F64X2_PMAX, &[(-0.0f64, 0.0, -0.0f64), (nan, 0.0, nan), (0.0, nan, 0.0),
+            (1.0, 2.0, 2.0)]);
+        binary_cases(F64X2_DIV, &[(1.0f64, 0.0, inf), (0.0, 0.0, nan), (1.0, -0.0, -inf)]);
+        unary_cases(F64X2_SQRT, &[(-1.0f64, nan), (-0.0, -0.0f64), (4.0, 2.0)]);
+        assert_eq!(splat(-nan), unary(F64X2_NEG, splat(nan)));
+        assert_eq!(splat(nan), unary(F64X2_ABS, splat(-nan)));
+    }
+
+    #[test]
+    fn simd_float_rounding() {
+        let (nan, inf) = (f32::NAN, f32::INFINITY);
+        unary_cases(F32X4_CEIL, &[(-0.5f32, -0.0f32), (0.5, 1.0), (-1.5, -1.0), (1.5, 2.0),
+            (inf, inf), (nan, nan), (-0.0, -0.0), (8_388_607.5, 8_388_608.0)]);
+        unary_cases(F32X4_FLOOR, &[(-0.5f32, -1.0f32), (0.5, 0.0), (-0.0, -0.0), (1.5, 1.0),
+            (-1.5, -2.0), (-8_388_607.5, -8_388_608.0)]);
+        unary_cases(F32X4_TRUNC, &[(-0.5f32, -0.0f32), (1.5, 1.0), (-1.5, -1.0), (0.9, 0.0),
+            (-inf, -inf)]);
+        unary_cases(F32X4_NEAREST, &[(0.5f32, 0.0f32), (1.5, 2.0), (2.5, 2.0), (-0.5, -0.0),
+            (-1.5, -2.0), (4_194_303.5, 4_194_304.0), (8_388_609.0, 8_388_609.0), (nan, nan)]);
+
+        unary_cases(F64X2_CEIL, &[(-0.5f64, -0.0f64), (0.5, 1.0),
+            (4_503_599_627_370_495.5, 4_503_599_627_370_496.0)]);
+        unary_cases(F64X2_FLOOR, &[(-0.5f64, -1.0f64), (0.5, 0.0),
+            (4_503_599_627_370_495.5, 4_503_599_627_370_495.0)]);
+        unary_cases(F64X2_TRUNC, &[(-1.5f64, -1.0f64), (-0.5, -0.0)]);
+        unary_cases(F64X2_NEAREST, &[(2.5f64, 2.0f64), (3.5, 4.0), (-0.5, -0.0),
+            (4_503_599_627_370_495.5, 4_503_599_627_370_496.0)]);
+    }
+
+    #[test]
+    fn simd_conversions() {
+        let (nan, inf) = (f32::NAN, f32::INFINITY);
+        unary_cases(I32X4_TRUNC_SAT_F32X4_S, &[(0.0f32, 0i32), (-0.0, 0), (1.5, 1), (-1.5, -1),
+            (1.9, 1), (nan, 0), (-nan, 0), (inf, i32::MAX), (-inf, i32::MIN),
+            (2_147_483_648.0, i32::MAX), (-2_147_483_904.0, i32::MIN),
+            (2_147_483_520.0, 2_147_483_520), (-2_147_483_648.0, i32::MIN)]);
+        unary_cases(I32X4_TRUNC_SAT_F32X4_U, &[(0.0f32, 0u32), (-1.5, 0), (-0.9, 0), (1.9, 1),
+            (4_294_967_296.0, u32::MAX), (4_294_967_040.0, 4_294_967_040), (nan, 0),
+            (inf, u32::MAX), (-inf, 0), (2_147_483_648.0, 2_147_483_648)]);
+        unary_cases(F32X4_CONVERT_I32X4_S, &[(0i32, 0.0f32), (-1, -1.0),
+            (i32::MAX, 2_147_483_648.0), (i32::MIN, -2_147_483_648.0), (16_777_217, 16_777_216.0),
+            (16_777_219, 16_777_220.0), (-16_777_217, -16_777_216.0)]);
+        unary_cases(F32X4_CONVERT_I32X4_U, &[(0u32, 0.0f32), (u32::MAX, 4_294_967_296.0),
+            (0x8000_0000, 2_147_483_648.0), (16_777_217, 16_777_216.0),
+            (16_777_219, 16_777_220.0)]);
+
+        let (nan64, inf64) = (f64::NAN, f64::INFINITY);
+        unary_zero_splats(I32X4_TRUNC_SAT_F64X2_S_ZERO, &[(0.0f64, 0i32), (-0.0, 0), (1.5, 1),
+            (-1.5, -1), (nan64, 0), (inf64, i32::MAX), (-inf64, i32::MIN),
+            (2_147_483_647.9, i32::MAX), (2_147_483_648.0, i32::MAX),
+            (-2_147_483_648.9, i32::MIN), (-2_147_483_649.0, i32::MIN)]);
+        unary_zero_splats(I32X4_TRUNC_SAT_F64X2_U_ZERO, &[(0.0f64, 0u32), (-1.0, 0), (-0.9, 0),
+            (1.9, 1), (4_294_967_295.9, u32::MAX), (4_294_967_296.0, u32::MAX), (nan64, 0),
+            (-inf64, 0)]);
+        unary_splats(F64X2_CONVERT_LOW_I32X4_S, &[(0i32, 0.0f64), (-1, -1.0),
+            (i32::MAX, 2_147_483_647.0), (i32::MIN, -2_147_483_648.0)]);
+        unary_splats(F64X2_CONVERT_LOW_I32X4_U, &[(u32::MAX, 4_294_967_295.0f64),
+            (0x8000_0000, 2_147_483_648.0)]);
+
+        // Demotion rounds to nearest, ties to even, and overflows to infinity past the midpoint
+        // between the largest f32 and 2^128
+        let midpoint = 2f64.powi(128) - 2f64.powi(103);
+        unary_zero_splats(F32X4_DEMOTE_F64X2_ZERO, &[(0.0f64, 0.0f32), (-0.0, -0.0),
+            (f64::MAX, inf), (-f64::MAX, -inf), (1e-50, 0.0), (-1e-50, -0.0), (0.1, 0.1),
+            (nan64, nan), (midpoint, inf), (midpoint - 2f64.powi(75), f32::MAX),
+            (1.0 + 2f64.powi(-24), 1.0), (1.0 + 2f64.powi(-24) + 2f64.powi(-52),
+            1.0 + f32::EPSILON)]);
+        unary_splats(F64X2_PROMOTE_LOW_F32X4, &[(0.0f32, 0.0f64), (-0.0, -0.0),
+            (f32::MAX, 3.402_823_466_385_288_6e38), (f32::from_bits(1), 2f64.powi(-149)),
+            (nan, nan64), (inf, inf64)]);
+
+        binary_splats(I8X16_NARROW_I16X8_S, &[(0i16, 0, 0i8), (127, 127, 127),
+            (128, 128, i8::MAX), (-128, -128, i8::MIN), (-129, -129, i8::MIN),
+            (i16::MAX, i16::MAX, i8::MAX), (i16::MIN, i16::MIN, i8::MIN)]);
+        binary_splats(I8X16_NARROW_I16X8_U, &[(0i16, 0, 0u8), (-1, -1, 0), (255, 255, 255),
+            (256, 256, 255), (i16::MIN, i16::MIN, 0), (i16::MAX, i16::MAX, 255)]);
+        binary_splats(I16X8_NARROW_I32X4_S, &[(0i32, 0, 0i16), (32_768, 32_768, i16::MAX),
+            (-32_769, -32_769, i16::MIN), (i32::MAX, i32::MAX, i16::MAX),
+            (i32::MIN, i32::MIN, i16::MIN)]);
+        binary_splats(I16X8_NARROW_I32X4_U, &[(0i32, 0, 0u16), (-1, -1, 0),
+            (65_535, 65_535, 65_535), (65_536, 65_536, 65_535), (i32::MIN, i32::MIN, 0)]);
+    }
+
+    #[test]
+    fn simd_extend() {
+        for op in [I16X8_EXTEND_LOW_I8X16_S, I16X8_EXTEND_HIGH_I8X16_S] {
+            unary_splats(op, &[(0i8, 0i16), (-1, -1), (i8::MIN, -128), (i8::MAX, 127)]);
+        }
+        for op in [I16X8_EXTEND_LOW_I8X16_U, I16X8_EXTEND_HIGH_I8X16_U] {
+            unary_splats(op, &[(0u8, 0u16), (0xff, 0xff), (0x80, 0x80)]);
+        }
+        for op in [I32X4_EXTEND_LOW_I16X8_S, I32X4_EXTEND_HIGH_I16X8_S] {
+            unary_splats(op, &[(i16::MIN, -32_768i32), (-1, -1), (i16::MAX, 32_767)]);
+        }
+        for op in [I32X4_EXTEND_LOW_I16X8_U, I32X4_EXTEND_HIGH_I16X8_U] {
+            unary_splats(op, &[(0xffffu16, 0xffffu32), (0x8000, 0x8000)]);
+        }
+        for op in [I64X2_EXTEND_LOW_I32X4_S, I64X2_EXTEND_HIGH_I32X4_S] {
+            unary_splats(op, &[(i32::MIN, -2_147_483_648i64), (-1, -1)]);
+        }
+        for op in [I64X2_EXTEND_LOW_I32X4_U, I64X2_EXTEND_HIGH_I32X4_U] {
+            unary_splats(op, &[(u32::MAX, 0xffff_ffffu64), (0x8000_0000, 0x8000_0000)]);
+        }
+
+        unary_splats(I16X8_EXTADD_PAIRWISE_I8X16_S, &[(0i8, 0i16), (-1, -2), (i8::MIN, -256),
+            (i8::MAX, 254)]);
+        unary_splats(I16X8_EXTADD_PAIRWISE_I8X16_U, &[(0xffu8, 510u16), (0x80, 256)]);
+        unary_splats(I32X4_EXTADD_PAIRWISE_I16X8_S, &[(i16::MIN, -65_536i32), (i16::MAX, 65_534),
+            (-1, -2)]);
+        unary_splats(I32X4_EXTADD_PAIRWISE_I16X8_U, &[(0xffffu16, 131_070u32)]);
+
+        for op in [I16X8_EXTMUL_LOW_I8X16_S, I16X8_EXTMUL_HIGH_I8X16_S] {
+            binary_splats(op, &[(i8::MIN, i8::MIN, 16_384i16), (i8::MAX, i8::MIN, -16_256),
+                (-1, -1, 1), (i8::MAX, i8::MAX, 16_129)]);
+        }
+        for op in [I16X8_EXTMUL_LOW_I8X16_U, I16X8_EXTMUL_HIGH_I8X16_U] {
+            binary_splats(op, &[(0xffu8, 0xff, 65_025u16), (0x80, 2, 256)]);
+        }
+        for op in [I32X4_EXTMUL_LOW_I16X8_S, I32X4_EXTMUL_HIGH_I16X8_S] {
+            binary_splats(op, &[(i16::MIN, i16::MIN, 0x4000_0000i32),
+                (i16::MAX, i16::MIN, -1_073_709_056), (-1, -1, 1)]);
+        }
+        for op in [I32X4_EXTMUL_LOW_I16X8_U, I32X4_EXTMUL_HIGH_I16X8_U] {
+            binary_splats(op, &[(0xffffu16, 0xffff, 0xfffe_0001u32)]);
+        }
+        for op in [I64X2_EXTMUL_LOW_I32X4_S, I64X2_EXTMUL_HIGH_I32X4_S] {
+            binary_splats(op, &[(i32::MIN, i32::MIN, 0x4000_0000_0000_0000i64),
+                (i32::MAX, i32::MIN, -4_611_686_016_279_904_256)]);
+        }
+        for op in [I64X2_EXTMUL_LOW_I32X4_U, I64X2_EXTMUL_HIGH_I32X4_U] {
+            binary_splats(op, &[(u32::MAX, u32::MAX, 0xffff_fffe_0000_0001u64)]);
+        }
+    }
+
+    #[test]
+    fn simd_bit_shift() {
+        shift_cases(I8X16_SHL, &[(1i8, 1, 2i8), (1, 8, 1), (1, 9, 2), (i8::MIN, 1, 0),
+            (-1, 7, i8::MIN), (1, 32, 1)]);
+        shift_cases(I8X16_SHR_S, &[(i8::MIN, 7, -1i8), (i8::MIN, 8, i8::MIN), (i8::MIN, 15, -1),
+            (i8::MAX, 7, 0), (-2, 1, -1)]);
+        shift_cases(I8X16_SHR_U, &[(0x80u8, 7, 1u8), (0x80, 8, 0x80), (0x80, 15, 1),
+            (0xff, 1, 0x7f)]);
+        shift_cases(I16X8_SHL, &[(1i16, 16, 1i16), (1, 17, 2), (1, 15, i16::MIN)]);
+        shift_cases(I16X8_SHR_S, &[(i16::MIN, 15, -1i16), (i16::MIN, 16, i16::MIN)]);
+        shift_cases(I16X8_SHR_U, &[(0x8000u16, 15, 1u16), (0x8000, 31, 1)]);
+        shift_cases(I32X4_SHL, &[(1i32, 32, 1i32), (1, 33, 2), (1, 31, i32::MIN)]);
+        shift_cases(I32X4_SHR_S, &[(i32::MIN, 31, -1i32), (i32::MIN, 32, i32::MIN),
+            (i32::MIN, u32::MAX, -1)]);
+        shift_cases(I32X4_SHR_U, &[(0x8000_0000u32, 31, 1u32), (0x8000_0000, 64, 0x8000_0000)]);
+        shift_cases(I64X2_SHL, &[(1i64, 64, 1i64), (1, 65, 2), (1, 63, i64::MIN)]);
+        shift_cases(I64X2_SHR_S, &[(i64::MIN, 63, -1i64), (i64::MIN, 64, i64::MIN), (-2, 1, -1)]);
+        shift_cases(I64X2_SHR_U, &[(1u64 << 63, 63, 1u64), (u64::MAX, 127, 1)]);
+    }
+
+    #[test]
+    fn simd_swizzle_and_shuffle() {
+        let a = u128::from_le_bytes(std::array::from_fn(|i| 0xf0 + i as u8));
+        let cases: [([u8; 16], [u8; 16]); 3] = [
+            ([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+                [0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc,
+                    0xfd, 0xfe, 0xff]),
+            ([15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0],
+                [0xff, 0xfe, 0xfd, 0xfc, 0xfb, 0xfa, 0xf9, 0xf8, 0xf7, 0xf6, 0xf5, 0xf4, 0xf3,
+                    0xf2, 0xf1, 0xf0]),
+            ([16, 17, 31, 32, 0x7f, 0x80, 0xff, 0, 1, 0xf0, 0x10, 2, 0xfe, 15, 0x40, 3],
+                [0, 0, 0, 0, 0, 0, 0, 0xf0, 0xf1, 0, 0, 0xf2, 0, 0xff, 0, 0xf3]),
+        ];
+        for (indices, expected) in cases {
+            let swizzled = binary(I8X16_SWIZZLE, a, u128::from_le_bytes(indices));
+            assert_eq!(expected, swizzled.to_le_bytes(), "{indices:?}");
+        }
+
+        let b = u128::from_le_bytes(std::array::from_fn(|i| 0xe0 + i as u8));
+        let cases: [([u8; 16], [u8; 16]); 3] = [
+            ([0; 16], [0xf0; 16]),
+            ([31; 16], [0xef; 16]),
+            ([0, 16, 1, 17, 2, 18, 3, 19, 12, 28, 13, 29, 14, 30, 15, 31],
+                [0xf0, 0xe0, 0xf1, 0xe1, 0xf2, 0xe2, 0xf3, 0xe3, 0xfc, 0xec, 0xfd, 0xed, 0xfe,
+                    0xee, 0xff, 0xef]),
+        ];
+        for (lanes, expected) in cases {
+            assert_eq!(expected, shuffle(a, b, &lanes).to_le_bytes(), "{lanes:?}");
+        }
+    }
+
+    #[test]
+    fn simd_boolean() {
+        let cases = [
+            (I8X16_BITMASK, splat(0xffu8), 0xffff),
+            (I8X16_BITMASK, splat(0x7fu8), 0),
+            (I8X16_BITMASK, splat(0x80u16), 0x5555),
+            (I8X16_BITMASK, 1 << 127, 0x8000),
+            (I16X8_BITMASK, splat(-1i16), 0xff),
+            (I16X8_BITMASK, splat(0x80u16), 0),
+            (I16X8_BITMASK, splat(0x8000u32), 0x55),
+            (I32X4_BITMASK, splat(i32::MIN), 0xf),
+            (I32X4_BITMASK, splat(i32::MAX), 0),
+            (I64X2_BITMASK, splat(-1i64), 0b11),
+            (I64X2_BITMASK, 1 << 63, 0b01),
+            (I8X16_ALL_TRUE, splat(1u8), 1),
+            (I8X16_ALL_TRUE, splat(0x100u16), 0),
+            (I8X16_ALL_TRUE, 0, 0),
+            (I16X8_ALL_TRUE, splat(1u16), 1),
+            (I16X8_ALL_TRUE, splat(0x1_0000u32), 0),
+            (I32X4_ALL_TRUE, splat(1u32), 1),
+            (I32X4_ALL_TRUE, splat(1u64), 0),
+            (I64X2_ALL_TRUE, splat(1u64), 1),
+            (I64X2_ALL_TRUE, 1 << 64, 0),
+            (I64X2_ALL_TRUE, splat(1u8), 1),
+            (V128_ANY_TRUE, 0, 0),
+            (V128_ANY_TRUE, 1, 1),
+            (V128_ANY_TRUE, 1 << 127, 1),
+            (V128_ANY_TRUE, u128::MAX, 1),
+        ];
+        for (op, v, expected) in cases {
+            assert_eq!(expected, test(op, v), "{op:#04x} of {v:#034x}");
+        }
+    }
+
+    #[test]
+    fn every_opcode_has_a_kind() {
+        let reserved = [0x9a, 0xa2, 0xa5, 0xa6, 0xaf, 0xb0, 0xb2, 0xb3, 0xb4, 0xbb, 0xc2, 0xc5,
+            0xc6, 0xcf, 0xd0, 0xd2, 0xd3, 0xd4, 0xe2, 0xee];
+        for op in 0..=0xff {
+            assert_eq!(!reserved.contains(&op), kind(op).is_some(), "{op:#04x}");
+        }
+        assert_eq!(None, kind(0x100));
+    }
+}
@@ -0,0 +1,1567 @@
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet};
+
+use crate::bytecode::{self, atomic, catch, gc, misc, op::*};
+use crate::bytecode::read::{Reader, UNEXPECTED_END};
+use crate::const_expr::{ConstExpr, ConstOp};
+use crate::memory::{MAX_PAGES, MAX_PAGES_64};
+use crate::v128::{self, Kind};
+use crate::value::{
+    slot_count, Block, CompositeType, ExternalKind, FieldType, GlobalType, HeapType, Limits,
+    RefType, SegmentMode, StorageType, SubType, ValType, Value,
+};
+use crate::wasm_module::WasmModule;
+
+/// A validation failure, located by its offset within the code section.
+pub struct ValidationError {
+    pub offset: usize,
+    pub msg: String,
+}
+
+/// Validates every function in the module, recording the branch targets of each of
+/// their blocks.
+pub fn validate(module: &mut WasmModule) -> Result<(), ValidationError> {
+    validate_declarations(module)?;
+    validate_data(module)?;
+    for idx in 0..module.functions.len() {
+        if !module.functions[idx].imported {
+            validate_function(module, idx)?;
+        }
+    }
+    Ok(())
+}
+
+/// Validates everything the sections before the code section declare, which is all a
+/// function body needs to be validated against.
+pub fn validate_declarations(module: &WasmModule) -> Result<(), ValidationError> {
+    validate_types(module)?;
+    for (idx, function) in module.functions.iter().enumerate() {
+        if module.get_func_type(function.functype).is_none() {
+            return Err(ValidationError {
+                offset: function.code_start,
+                msg: format!("Function {idx} has invalid type index {}", function.functype),
+            });
+        }
+    }
+    for memory in &module.memories {
+        let range = if memory.memory64 { MAX_PAGES_64 } else { MAX_PAGES };
+        validate_limits(&memory.limits, range)?;
+    }
+    for (idx, type_idx) in module.tags.iter().enumerate() {
+        match module.get_func_type(*type_idx) {
+            Some(functype) if functype.results.is_empty() => {}
+            Some(_) => return Err(module_error(&format!("Tag {idx} must not have results"))),
+            None => return Err(module_error(&format!("Tag {idx} has invalid type index"))),
+        }
+    }
+    validate_value_types(module)?;
+    for (idx, table) in module.tables.iter().enumerate() {
+        validate_limits(&table.ty.limits, u32::MAX as u64)?;
+        let Some(init) = &table.init else {
+            if !table.ty.elem.nullable {
+                let msg = format!("Table {idx} of non-nullable references needs an initializer");
+                return Err(module_error(&msg));
+            }
+            continue;
+        };
+        match const_expr_type(module, init, module.globals.len()) {
+            Ok(ty) if module.matches(ty, ValType::from(table.ty.elem)) => {}
+            Ok(_) => {
+                let msg = format!("Table {idx} has an initializer of the wrong type");
+                return Err(module_error(&msg));
+            }
+            Err(msg) => return Err(module_error(&format!("Table {idx} {msg}"))),
+        }
+    }
+    for (idx, global) in module.globals.iter().enumerate() {
+        let Some(init) = &global.init else {
+            continue;
+        };
+        // Initializers may only read the globals before them
+        match const_expr_type(module, init, idx) {
+            Ok(ty) if module.matches(ty, global.ty.ty) => {}
+            Ok(_) => {
+                let msg = format!("Global {idx} has an initializer of the wrong type");
+                return Err(module_error(&msg));
+            }
+            Err(msg) => return Err(module_error(&format!("Global {idx} {msg}"))),
+        }
+    }
+    validate_elements(module)?;
+    validate_exports(module)
+}
+
+/// Validates the body of a defined function, recording the branch targets of its blocks.
+pub fn validate_function(module: &mut WasmModule, idx: usize) -> Result<(), ValidationError> {
+    let validated = FuncValidator::new(module, idx).validate()?;
+    let function = &mut module.functions[idx];
+    function.blocks = validated.blocks;
+    function.max_stack = validated.max_stack;
+    function.local_slots = validated.local_slots;
+    function.wide_operands = validated.wide_operands;
+    Ok(())
+}
+
+/// Validates the data segments, whose section follows the code section.
+pub fn validate_data(module: &WasmModule) -> Result<(), ValidationError> {
+    if module.data_count.is_some_and(|count| count as usize != module.data.len()) {
+        return Err(module_error("Data count and data section have inconsistent lengths"));
+    }
+    for (idx, segment) in module.data.iter().enumerate() {
+        if let SegmentMode::Active { index, offset } = &segment.mode {
+            let Some(memory) = module.memories.get(*index as usize) else {
+                return Err(module_error(&format!("Data segment {idx} refers to unknown memory")));
+            };
+            match const_expr_type(module, offset, module.globals.len()) {
+                Ok(ty) if ty == memory.address_type() => {}
+                Ok(_) => {
+                    return Err(module_error(&format!("Data segment {idx} has an invalid offset")));
+                }
+                Err(msg) => return Err(module_error(&format!("Data segment {idx} {msg}"))),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_elements(module: &WasmModule) -> Result<(), ValidationError> {
+    for (idx, segment) in module.elements.iter().enumerate() {
+        if let SegmentMode::Active { index, offset } = &segment.mode {
+            match module.tables.get(*index as usize) {
+                Some(table) if module.ref_matches(segment.ty, table.ty.elem) => {}
+                Some(_) => {
+                    let msg = format!("Element segment {idx} does not match its table's type");
+                    return Err(module_error(&msg));
+                }
+                None => {
+                    let msg = format!("Element segment {idx} refers to unknown table");
+                    return Err(module_error(&msg));
+                }
+            }
+            match const_expr_type(module, offset, module.globals.len()) {
+                Ok(ValType::I32) => {}
+                Ok(_) => {
+                    let msg = format!("Element segment {idx} has an invalid offset");
+                    return Err(module_error(&msg));
+                }
+                Err(msg) => return Err(module_error(&format!("Element segment {idx} {msg}"))),
+            }
+        }
+        for init in &segment.init {
+            match const_expr_type(module, init, module.globals.len()) {
+                Ok(ty) if module.matches(ty, ValType::from(segment.ty)) => {}
+                Ok(_) => {
+                    let msg = format!("Element segment {idx} has an invalid element");
+                    return Err(module_error(&msg));
+                }
+                Err(msg) => return Err(module_error(&format!("Element segment {idx} {msg}"))),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Type checks a constant expression, which may only read immutable globals below
+/// `globals`, returning the type of the single value it produces.
+fn const_expr_type(
+    module: &WasmModule,
+    expr: &ConstExpr,
+    globals: usize,
+) -> Result<ValType, String> {
+    let mut stack = Vec::new();
+    for op in &expr.0 {
+        let ty = match *op {
+            ConstOp::Value(Value::FuncRef(idx)) => match module.functions.get(idx as usize) {
+                Some(function) => func_ref_type(function.functype),
+                None => return Err(String::from("refers to unknown function")),
+            },
+            ConstOp::Value(Value::RefNull(heap)) if !known_heap_type(module, heap) => {
+                return Err(String::from("refers to unknown type"));
+            }
+            ConstOp::Value(value) => value.ty().unwrap(),
+            ConstOp::RefI31 => {
+                if stack.pop() != Some(ValType::I32) {
+                    return Err(String::from("has a type mismatch in a constant expression"));
+                }
+                ValType::Ref(RefType::non_null(HeapType::I31))
+            }
+            ConstOp::GlobalGet(global_idx) => match module.globals.get(global_idx as usize) {
+                Some(global) if (global_idx as usize) < globals && !global.ty.mutable => {
+                    global.ty.ty
+                }
+                _ => return Err(format!("cannot read global {global_idx} in a constant")),
+            },
+            op => {
+                let ty = match op {
+                    ConstOp::I32Add | ConstOp::I32Sub | ConstOp::I32Mul => ValType::I32,
+                    _ => ValType::I64,
+                };
+                if stack.pop() != Some(ty) || stack.pop() != Some(ty) {
+                    return Err(String::from("has a type mismatch in a constant expression"));
+                }
+                ty
+            }
+        };
+        stack.push(ty);
+    }
+    match stack[..] {
+        [ty] => Ok(ty),
+        _ => Err(String::from("has a constant expression that doesn't produce one value")),
+    }
+}
+
+/// A reference to a struct or array of the given type.
+fn object_type(type_idx: usize, nullable: bool) -> ValType {
+    ValType::Ref(RefType { nullable, heap: HeapType::Concrete(type_idx as u32) })
+}
+
+/// The type of `ref.func` for a function of the given type.
+fn func_ref_type(type_idx: usize) -> ValType {
+    ValType::Ref(RefType::non_null(HeapType::Concrete(type_idx as u32)))
+}
+
+fn known_heap_type(module: &WasmModule, heap: HeapType) -> bool {
+    match heap {
+        HeapType::Concrete(idx) => (idx as usize) < module.types.len(),
+        _ => true,
+    }
+}
+
+fn known_type(module: &WasmModule, ty: ValType) -> bool {
+    match ty {
+        ValType::Ref(ty) => known_heap_type(module, ty.heap),
+        _ => true,
+    }
+}
+
+/// The value types a type definition refers to, with packed fields left out.
+fn defined_val_types(ty: &SubType) -> Vec<ValType> {
+    let field = |field: &FieldType| match field.storage {
+        StorageType::Val(ty) => Some(ty),
+        _ => None,
+    };
+    match &ty.composite {
+        CompositeType::Func(functype) => {
+            functype.params.iter().chain(&functype.results).copied().collect()
+        }
+        CompositeType::Struct(fields) => fields.iter().filter_map(field).collect(),
+        CompositeType::Array(elem) => field(elem).into_iter().collect(),
+    }
+}
+
+/// Checks that types only refer to types in their own or earlier recursion groups, and
+/// that each is a valid subtype of its declared supertype.
+fn validate_types(module: &WasmModule) -> Result<(), ValidationError> {
+    for (idx, ty) in module.types.iter().enumerate() {
+        let group_end = module.rec_group(idx as u32).end;
+        for val_type in defined_val_types(ty) {
+            if let ValType::Ref(RefType { heap: HeapType::Concrete(target), .. }) = val_type {
+                if target >= group_end {
+                    return Err(module_error(&format!("Type {idx} refers to unknown type")));
+                }
+            }
+        }
+        let Some(supertype) = ty.supertype else {
+            continue;
+        };
+        let Some(parent) = module.types.get(supertype as usize).filter(|_| supertype < idx as u32)
+        else {
+            return Err(module_error(&format!("Type {idx} has invalid supertype {supertype}")));
+        };
+        if parent.is_final {
+            return Err(module_error(&format!("Type {idx} extends final type {supertype}")));
+        }
+        if !composite_matches(module, &ty.composite, &parent.composite) {
+            let msg = format!("Type {idx} does not match its supertype {supertype}");
+            return Err(module_error(&msg));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a type may declare another as its supertype: function parameters are
+/// contravariant and results covariant, and structs may add fields after their
+/// supertype's. Mutable fields must have the same type.
+fn composite_matches(module: &WasmModule, sub: &CompositeType, sup: &CompositeType) -> bool {
+    let field = |sub: &FieldType, sup: &FieldType| {
+        let storage = storage_matches(module, sub.storage, sup.storage);
+        let invariant = !sup.mutable || storage_matches(module, sup.storage, sub.storage);
+        sub.mutable == sup.mutable && storage && invariant
+    };
+    match (sub, sup) {
+        (CompositeType::Func(sub), CompositeType::Func(sup)) => {
+            module.all_match(&sup.params, &sub.params)
+                && module.all_match(&sub.results, &sup.results)
+        }
+        (CompositeType::Struct(sub), CompositeType::Struct(sup)) => {
+            sub.len() >= sup.len() && sub.iter().zip(sup).all(|(a, b)| field(a, b))
+        }
+        (CompositeType::Array(sub), CompositeType::Array(sup)) => field(sub, sup),
+        _ => false,
+    }
+}
+
+fn storage_matches(module: &WasmModule, sub: StorageType, sup: StorageType) -> bool {
+    match (sub, sup) {
+        (StorageType::Val(sub), StorageType::Val(sup)) => module.matches(sub, sup),
+        (sub, sup) => sub == sup,
+    }
+}
+
+/// Checks that the references outside type definitions are to types that exist.
+fn validate_value_types(module: &WasmModule) -> Result<(), ValidationError> {
+    let locals = module.functions.iter().flat_map(|function| function.locals.iter().copied());
+    let globals = module.globals.iter().map(|global| global.ty.ty);
+    let tables = module.tables.iter().map(|table| ValType::from(table.ty.elem));
+    let elements = module.elements.iter().map(|segment| ValType::from(segment.ty));
+    let mut all = locals.chain(globals).chain(tables).chain(elements);
+    match all.find(|ty| !known_type(module, *ty)) {
+        Some(ty) => Err(module_error(&format!("Unknown type in {ty:?}"))),
+        None => Ok(()),
+    }
+}
+
+fn validate_exports(module: &WasmModule) -> Result<(), ValidationError> {
+    let mut names = HashSet::new();
+    for export in &module.exports {
+        if !names.insert(export.name.as_str()) {
+            return Err(module_error(&format!("Duplicate export name {}", export.name)));
+        }
+        let count = match export.kind {
+            ExternalKind::Func => module.functions.len(),
+            ExternalKind::Table => module.tables.len(),
+            ExternalKind::Memory => module.memories.len(),
+            ExternalKind::Global => module.globals.len(),
+            ExternalKind::Tag => module.tags.len(),
+        };
+        if export.index >= count {
+            let (name, kind, index) = (&export.name, export.kind, export.index);
+            return Err(module_error(&format!("Export {name} refers to unknown {kind:?} {index}")));
+        }
+    }
+    Ok(())
+}
+
+fn validate_limits(limits: &Limits, range: u64) -> Result<(), ValidationError> {
+    if limits.min > range || limits.max.is_some_and(|max| max > range) {
+        return Err(module_error(&format!("Limits must be at most {range}")));
+    }
+    if limits.max.is_some_and(|max| max < limits.min) {
+        return Err(module_error("Limits maximum must not be smaller than the minimum"));
+    }
+    Ok(())
+}
+
+fn module_error(msg: &str) -> ValidationError {
+    ValidationError {
+        offset: 0,
+        msg: msg.to_string(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ControlKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+struct Control {
+    kind: ControlKind,
+    start: usize,
+    body: usize,
+    else_: Option<usize>,
+    catches: Option<usize>,
+    start_types: Vec<ValType>,
+    end_types: Vec<ValType>,
+    height: usize,
+    /// The number of locals initialized before the block, which are still initialized
+    /// after it. Those set inside it are not.
+    inits: usize,
+    unreachable: bool,
+}
+
+impl Control {
+    fn label_types(&self) -> &[ValType] {
+        match self.kind {
+            ControlKind::Loop => &self.start_types,
+            _ => &self.end_types,
+        }
+    }
+}
+
+/// Type checks a single function body following the algorithm in the appendix of the
+/// specification. `None` on the operand stack is a value of unknown type, which only
+/// appears after an unconditional branch.
+struct FuncValidator<'a> {
+    module: &'a WasmModule<'a>,
+    code: &'a [u8],
+    code_start: usize,
+    ip: usize,
+    locals: Vec<ValType>,
+    /// Whether each local has a value. Only locals of non-defaultable types start
+    /// without one, and must be set before they are read.
+    initialized: Vec<bool>,
+    /// The locals set so far that started uninitialized, in order.
+    inits: Vec<usize>,
+    results: Vec<ValType>,
+    operands: Vec<Option<ValType>>,
+    max_height: usize,
+    /// Whether a `v128` has been pushed, after which an operand may take two slots.
+    pushed_v128: bool,
+    controls: Vec<Control>,
+    blocks: HashMap<usize, Block>,
+    wide_operands: HashSet<usize>,
+}
+
+/// What the interpreter needs to know about a validated function's body.
+struct Validated {
+    blocks: HashMap<usize, Block>,
+    max_stack: usize,
+    local_slots: Vec<usize>,
+    wide_operands: HashSet<usize>,
+}
+
+impl<'a> FuncValidator<'a> {
+    fn new(module: &'a WasmModule<'a>, idx: usize) -> Self {
+        let function = &module.functions[idx];
+        let functype = module.func_type(function.functype);
+        let mut locals = functype.params.clone();
+        locals.extend_from_slice(&function.locals);
+        let params = functype.params.len();
+        let initialized = locals.iter().enumerate()
+            .map(|(idx, ty)| idx < params || ty.is_defaultable())
+            .collect();
+
+        Self {
+            module,
+            code: module.function_code(idx),
+            code_start: function.code_start,
+            ip: 0,
+            locals,
+            initialized,
+            inits: Vec::new(),
+            results: functype.results.clone(),
+            operands: Vec::new(),
+            max_height: 0,
+            pushed_v128: false,
+            controls: Vec::new(),
+            blocks: HashMap::new(),
+            wide_operands: HashSet::new(),
+        }
+    }
+
+    /// Returns the function's blocks and how its values are laid out in stack slots.
+    fn validate(mut self) -> Result<Validated, ValidationError> {
+        let results = self.results.clone();
+        self.push_control(ControlKind::Function, 0, Vec::new(), results);
+
+        while !self.controls.is_empty() {
+            if self.ip >= self.code.len() {
+                return Err(self.error("Function body ended without an end instruction"));
+            }
+            self.instruction()?;
+        }
+
+        if self.ip != self.code.len() {
+            return Err(self.error("Unexpected bytes after the end of the function body"));
+        }
+        let mut local_slots = vec![0];
+        for ty in &self.locals {
+            local_slots.push(local_slots.last().unwrap() + ty.slots());
+        }
+        let max_stack = if self.pushed_v128 { 2 * self.max_height } else { self.max_height };
+        Ok(Validated {
+            blocks: self.blocks,
+            max_stack,
+            local_slots,
+            wide_operands: self.wide_operands,
+        })
+    }
+
+    fn instruction(&mut self) -> Result<(), ValidationError> {
+        use ValType::*;
+
+        let op = self.read_byte()?;
+        match op {
+            UNREACHABLE => self.set_unreachable(),
+            NOP => {}
+            BLOCK | LOOP => {
+                let kind = if op == BLOCK {
+                    ControlKind::Block
+                } else {
+                    ControlKind::Loop
+                };
+                let start = self.ip - 1;
+                let (params, results) = self.block_type()?;
+                self.pop_all(&params)?;
+                self.push_control(kind, start, params, results);
+            }
+            IF => {
+                let start = self.ip - 1;
+                let (params, results) = self.block_type()?;
+                self.pop_expect(I32)?;
+                self.pop_all(&params)?;
+                self.push_control(ControlKind::If, start, params, results);
+            }
+            ELSE => {
+                if self.controls.last().unwrap().kind != ControlKind::If {
+                    return Err(self.error("Else without a matching if"));
+                }
+                let control = self.pop_control()?;
+                let (start, body) = (control.start, control.body);
+                self.push_control(ControlKind::Else, start, control.start_types, control.end_types);
+                let control = self.controls.last_mut().unwrap();
+                control.body = body;
+                control.else_ = Some(self.ip - 1);
+            }
+            END => {
+                let control = self.pop_control()?;
+                if control.kind == ControlKind::If && control.start_types != control.end_types {
+                    let msg = "Type mismatch: if without else must not change the stack";
+                    return Err(self.error(msg));
+                }
+                if control.kind != ControlKind::Function {
+                    self.blocks.insert(control.start, Block {
+                        body: control.body,
+                        else_: control.else_,
+                        end: self.ip - 1,
+                        params: slot_count(&control.start_types),
+                        results: slot_count(&control.end_types),
+                        catches: control.catches,
+                    });
+                }
+                self.push_all(&control.end_types);
+            }
+            TRY_TABLE => {
+                let start = self.ip - 1;
+                let (params, results) = self.block_type()?;
+                // Catch labels are relative to the enclosing block, not the try_table
+                let catches = self.ip;
+                for _ in 0..self.read_size()? {
+                    self.catch_clause()?;
+                }
+                self.pop_all(&params)?;
+                self.push_control(ControlKind::Block, start, params, results);
+                self.controls.last_mut().unwrap().catches = Some(catches);
+            }
+            THROW => {
+                let params = self.tag()?;
+                self.pop_all(&params)?;
+                self.set_unreachable();
+            }
+            THROW_REF => {
+                self.pop_expect(ValType::EXNREF)?;
+                self.set_unreachable();
+            }
+            BR => {
+                let types = self.label_types()?;
+                self.pop_all(&types)?;
+                self.set_unreachable();
+            }
+            BR_IF => {
+                self.pop_expect(I32)?;
+                let types = self.label_types()?;
+                self.pop_all(&types)?;
+                self.push_all(&types);
+            }
+            BR_TABLE => {
+                self.pop_expect(I32)?;
+                let num_labels = self.read_size()?;
+                let mut targets = Vec::new();
+                for _ in 0..num_labels {
+                    targets.push(self.label_types()?);
+                }
+                let default = self.label_types()?;
+                for types in &targets {
+                    if types.len() != default.len() {
+                        let msg = "Type mismatch: br_table targets have different arities";
+                        return Err(self.error(msg));
+                    }
+                    self.peek_all(types)?;
+                }
+                self.pop_all(&default)?;
+                self.set_unreachable();
+            }
+            RETURN => {
+                let results = self.results.clone();
+                self.pop_all(&results)?;
+                self.set_unreachable();
+            }
+            CALL | RETURN_CALL => {
+                let func_idx = self.read_size()?;
+                let functype = match self.module.functions.get(func_idx) {
+                    Some(function) => self.module.func_type(function.functype),
+                    None => return Err(self.error(&format!("Unknown function {func_idx}"))),
+                };
+                let (params, results) = (functype.params.clone(), functype.results.clone());
+                self.pop_all(&params)?;
+                self.call_results(op == RETURN_CALL, &results)?;
+            }
+            CALL_INDIRECT | RETURN_CALL_INDIRECT => {
+                let type_idx = self.read_size()?;
+                let table = self.table()?;
+                if !self.module.matches(table, ValType::FUNCREF) {
+                    return Err(self.error("call_indirect requires a funcref table"));
+                }
+                let Some(functype) = self.module.get_func_type(type_idx) else {
+                    return Err(self.error(&format!("Unknown function type {type_idx}")));
+                };
+                let (params, results) = (functype.params.clone(), functype.results.clone());
+                self.pop_expect(I32)?;
+                self.pop_all(&params)?;
+                self.call_results(op == RETURN_CALL_INDIRECT, &results)?;
+            }
+            CALL_REF | RETURN_CALL_REF => {
+                let type_idx = self.read_size()?;
+                let Some(functype) = self.module.get_func_type(type_idx) else {
+                    return Err(self.error(&format!("Unknown function type {type_idx}")));
+                };
+                let (params, results) = (functype.params.clone(), functype.results.clone());
+                self.pop_expect(ValType::Ref(RefType::null(HeapType::Concrete(type_idx as u32))))?;
+                self.pop_all(&params)?;
+                self.call_results(op == RETURN_CALL_REF, &results)?;
+            }
+            BR_ON_NULL => {
+                let ty = self.pop_ref()?;
+                let types = self.label_types()?;
+                self.pop_all(&types)?;
+                self.push_all(&types);
+                self.operands.push(ty.map(|ty| ValType::Ref(RefType::non_null(ty.heap))));
+            }
+            BR_ON_NON_NULL => {
+                let ty = self.pop_ref()?;
+                let mut types = self.label_types()?;
+                let Some(Ref(label)) = types.pop() else {
+                    return Err(self.error("br_on_non_null requires a label taking a reference"));
+                };
+                if ty.is_some_and(|ty| {
+                    !self.module.ref_matches(RefType::non_null(ty.heap), label)
+                }) {
+                    let msg = format!("Type mismatch: expected {label:?}, found {ty:?}");
+                    return Err(self.error(&msg));
+                }
+                self.pop_all(&types)?;
+                self.push_all(&types);
+            }
+            DROP => {
+                if self.pop_operand()? == Some(V128) {
+                    self.wide_operands.insert(self.ip - 1);
+                }
+            }
+            SELECT => {
+                self.pop_expect(I32)?;
+                let rhs = self.pop_operand()?;
+                let lhs = self.pop_operand()?;
+                if lhs.or(rhs) == Some(V128) {
+                    self.wide_operands.insert(self.ip - 1);
+                }
+                if lhs.or(rhs).is_some_and(|ty| ty.is_ref()) {
+                    return Err(self.error("Type mismatch: select without a type needs numbers"));
+                }
+                match (lhs, rhs) {
+                    (Some(lhs), Some(rhs)) if lhs != rhs => {
+                        let msg = format!("Type mismatch: select between {lhs:?} and {rhs:?}");
+                        return Err(self.error(&msg));
+                    }
+                    _ => self.operands.push(lhs.or(rhs)),
+                }
+            }
+            SELECT_T => {
+                if self.read_size()? != 1 {
+                    return Err(self.error("Typed select must have exactly one type"));
+                }
+                let ty = self.val_type()?;
+                self.pop_expect(I32)?;
+                self.pop_expect(ty)?;
+                self.unary(ty, ty)?;
+            }
+            LOCAL_GET => {
+                let (idx, ty) = self.local()?;
+                if !self.initialized[idx] {
+                    return Err(self.error(&format!("Uninitialized local {idx}")));
+                }
+                self.push(ty);
+            }
+            LOCAL_SET => {
+                let (idx, ty) = self.local()?;
+                self.pop_expect(ty)?;
+                self.initialize(idx);
+            }
+            LOCAL_TEE => {
+                let (idx, ty) = self.local()?;
+                self.pop_expect(ty)?;
+                self.initialize(idx);
+                self.push(ty);
+            }
+            GLOBAL_GET => {
+                let ty = self.global()?.ty;
+                self.push(ty);
+            }
+            GLOBAL_SET => {
+                let global = self.global()?;
+                if !global.mutable {
+                    return Err(self.error("Global is immutable"));
+                }
+                self.pop_expect(global.ty)?;
+            }
+            TABLE_GET => {
+                let elem = self.table()?;
+                self.unary(I32, elem)?;
+            }
+            TABLE_SET => {
+                let elem = self.table()?;
+                self.pop_expect(elem)?;
+                self.pop_expect(I32)?;
+            }
+            I32_LOAD..=I64_LOAD32_U => {
+                let (ty, align) = match op {
+                    I32_LOAD => (I32, 2),
+                    I64_LOAD => (I64, 3),
+                    F32_LOAD => (F32, 2),
+                    F64_LOAD => (F64, 3),
+                    I32_LOAD8_S | I32_LOAD8_U => (I32, 0),
+                    I32_LOAD16_S | I32_LOAD16_U => (I32, 1),
+                    I64_LOAD8_S | I64_LOAD8_U => (I64, 0),
+                    I64_LOAD16_S | I64_LOAD16_U => (I64, 1),
+                    _ => (I64, 2),
+                };
+                let addr = self.memarg(align)?;
+                self.unary(addr, ty)?;
+            }
+            I32_STORE..=I64_STORE32 => {
+                let (ty, align) = match op {
+                    I32_STORE => (I32, 2),
+                    I64_STORE => (I64, 3),
+                    F32_STORE => (F32, 2),
+                    F64_STORE => (F64, 3),
+                    I32_STORE8 => (I32, 0),
+                    I32_STORE16 => (I32, 1),
+                    I64_STORE8 => (I64, 0),
+                    I64_STORE16 => (I64, 1),
+                    _ => (I64, 2),
+                };
+                let addr = self.memarg(align)?;
+                self.pop_expect(ty)?;
+                self.pop_expect(addr)?;
+            }
+            MEMORY_SIZE => {
+                let addr = self.memory_index()?;
+                self.push(addr);
+            }
+            MEMORY_GROW => {
+                let addr = self.memory_index()?;
+                self.unary(addr, addr)?;
+            }
+            I32_CONST => {
+                self.read(Reader::s32)?;
+                self.push(I32);
+            }
+            I64_CONST => {
+                self.read(Reader::s64)?;
+                self.push(I64);
+            }
+            F32_CONST => {
+                self.ip += 4;
+                self.push(F32);
+            }
+            F64_CONST => {
+                self.ip += 8;
+                self.push(F64);
+            }
+            I32_EQZ => self.unary(I32, I32)?,
+            I32_EQ..=I32_GE_U => self.binary(I32, I32)?,
+            I64_EQZ => self.unary(I64, I32)?,
+            I64_EQ..=I64_GE_U => self.binary(I64, I32)?,
+            F32_EQ..=F32_GE => self.binary(F32, I32)?,
+            F64_EQ..=F64_GE => self.binary(F64, I32)?,
+            I32_CLZ..=I32_POPCNT => self.unary(I32, I32)?,
+            I32_ADD..=I32_ROTR => self.binary(I32, I32)?,
+            REF_NULL => {
+                let heap = self.heap_type()?;
+                self.push(ValType::Ref(RefType::null(heap)));
+            }
+            REF_IS_NULL => {
+                self.pop_ref()?;
+                self.push(I32);
+            }
+            REF_EQ => {
+                let eqref = ValType::Ref(RefType::null(HeapType::Eq));
+                self.pop_expect(eqref)?;
+                self.pop_expect(eqref)?;
+                self.push(I32);
+            }
+            REF_AS_NON_NULL => {
+                let ty = self.pop_ref()?;
+                self.operands.push(ty.map(|ty| ValType::Ref(RefType::non_null(ty.heap))));
+            }
+            REF_FUNC => {
+                let func_idx = self.read_size()?;
+                let Some(function) = self.module.functions.get(func_idx) else {
+                    return Err(self.error(&format!("Unknown function {func_idx}")));
+                };
+                self.push(func_ref_type(function.functype));
+            }
+            MISC_PREFIX => self.misc_instruction()?,
+            SIMD_PREFIX => self.simd_instruction()?,
+            ATOMIC_PREFIX => self.atomic_instruction()?,
+            GC_PREFIX => self.gc_instruction()?,
+            op => return Err(self.error(&format!("Instruction {op:#04x} not yet implemented"))),
+        }
+
+        if self.ip > self.code.len() {
+            return Err(self.error("Unexpected end of function body"));
+        }
+        Ok(())
+    }
+
+    /// Validates an instruction with the [`MISC_PREFIX`] prefix.
+    fn misc_instruction(&mut self) -> Result<(), ValidationError> {
+        use ValType::*;
+
+        match self.read_size()? as u32 {
+            misc::MEMORY_INIT => {
+                self.data_index()?;
+                let addr = self.memory_index()?;
+                self.pop_all(&[addr, I32, I32])?;
+            }
+            misc::DATA_DROP => self.data_index()?,
+            misc::MEMORY_COPY => {
+                let dst = self.memory_index()?;
+                let src = self.memory_index()?;
+                // The length is only i64 if both memories are 64-bit
+                let len = if dst == I64 && src == I64 { I64 } else { I32 };
+                self.pop_all(&[dst, src, len])?;
+            }
+            misc::MEMORY_FILL => {
+                let addr = self.memory_index()?;
+                self.pop_all(&[addr, I32, addr])?;
+            }
+            misc::TABLE_INIT => {
+                let elem = self.element_index()?;
+                let table = self.table()?;
+                if !self.module.matches(elem, table) {
+                    return Err(self.error("Type mismatch: element segment and table differ"));
+                }
+                self.pop_all(&[I32, I32, I32])?;
+            }
+            misc::ELEM_DROP => {
+                self.element_index()?;
+            }
+            misc::TABLE_COPY => {
+                let dst = self.table()?;
+                let src = self.table()?;
+                if !self.module.matches(src, dst) {
+                    return Err(self.error("Type mismatch: tables have different element types"));
+                }
+                self.pop_all(&[I32, I32, I32])?;
+            }
+            misc::TABLE_GROW => {
+                let elem = self.table()?;
+                self.pop_expect(I32)?;
+                self.unary(elem, I32)?;
+            }
+            misc::TABLE_SIZE => {
+                self.table()?;
+                self.push(I32);
+            }
+            misc::TABLE_FILL => {
+                let elem = self.table()?;
+                self.pop_expect(I32)?;
+                self.pop_expect(elem)?;
+                self.pop_expect(I32)?;
+            }
+            op => {
+                let msg = format!("Instruction 0xfc {op} not yet implemented");
+                return Err(self.error(&msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates an instruction with the [`SIMD_PREFIX`] prefix.
+    fn simd_instruction(&mut self) -> Result<(), ValidationError> {
+        use ValType::*;
+
+        let op = self.read_size()? as u32;
+        let Some(kind) = v128::kind(op) else {
+            return Err(self.error(&format!("Unknown instruction 0xfd {op}")));
+        };
+        match kind {
+            Kind::Load { align } => {
+                let addr = self.memarg(align)?;
+                self.unary(addr, V128)?;
+            }
+            Kind::Store => {
+                let addr = self.memarg(4)?;
+                self.pop_expect(V128)?;
+                self.pop_expect(addr)?;
+            }
+            Kind::LoadLane { align } => {
+                let addr = self.memarg(align)?;
+                self.lane_index(16 >> align)?;
+                self.pop_expect(V128)?;
+                self.unary(addr, V128)?;
+            }
+            Kind::StoreLane { align } => {
+                let addr = self.memarg(align)?;
+                self.lane_index(16 >> align)?;
+                self.pop_expect(V128)?;
+                self.pop_expect(addr)?;
+            }
+            Kind::Const => {
+                self.ip += 16;
+                self.push(V128);
+            }
+            Kind::Shuffle => {
+                for _ in 0..16 {
+                    self.lane_index(32)?;
+                }
+                self.binary(V128, V128)?;
+            }
+            Kind::Splat(ty) => self.unary(ty, V128)?,
+            Kind::ExtractLane { ty, lanes } => {
+                self.lane_index(lanes)?;
+                self.unary(V128, ty)?;
+            }
+            Kind::ReplaceLane { ty, lanes } => {
+                self.lane_index(lanes)?;
+                self.pop_expect(ty)?;
+                self.unary(V128, V128)?;
+            }
+            Kind::Unary => self.unary(V128, V128)?,
+            Kind::Binary => self.binary(V128, V128)?,
+            Kind::Ternary => {
+                self.pop_all(&[V128, V128, V128])?;
+                self.push(V128);
+            }
+            Kind::Test => self.unary(V128, I32)?,
+            Kind::Shift => {
+                self.pop_expect(I32)?;
+                self.unary(V128, V128)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates an instruction with the [`ATOMIC_PREFIX`] prefix. Atomics are allowed
+    /// on unshared memories too.
+    fn atomic_instruction(&mut self) -> Result<(), ValidationError> {
+        use ValType::*;
+
+        let op = self.read_size()? as u32;
+        match op {
+            atomic::MEMORY_ATOMIC_NOTIFY => {
+                let addr = self.atomic_memarg(4)?;
+                self.pop_all(&[addr, I32])?;
+                self.push(I32);
+            }
+            atomic::MEMORY_ATOMIC_WAIT32 | atomic::MEMORY_ATOMIC_WAIT64 => {
+                let (ty, width) = match op {
+                    atomic::MEMORY_ATOMIC_WAIT32 => (I32, 4),
+                    _ => (I64, 8),
+                };
+                let addr = self.atomic_memarg(width)?;
+                self.pop_all(&[addr, ty, I64])?;
+                self.push(I32);
+            }
+            atomic::ATOMIC_FENCE => {
+                if self.read_byte()? != 0x00 {
+                    return Err(self.error("Expected zero flags byte"));
+                }
+            }
+            _ => {
+                let Some((ty, width)) = atomic::access(op) else {
+                    return Err(self.error(&format!("Unknown instruction 0xfe {op}")));
+                };
+                let addr = self.atomic_memarg(width)?;
+                match op {
+                    atomic::I32_ATOMIC_LOAD..atomic::I32_ATOMIC_STORE => self.unary(addr, ty)?,
+                    atomic::I32_ATOMIC_STORE..atomic::I32_ATOMIC_RMW_ADD => {
+                        self.pop_all(&[addr, ty])?;
+                    }
+                    atomic::I32_ATOMIC_RMW_ADD..atomic::I32_ATOMIC_RMW_CMPXCHG => {
+                        self.pop_all(&[addr, ty])?;
+                        self.push(ty);
+                    }
+                    _ => {
+                        self.pop_all(&[addr, ty, ty])?;
+                        self.push(ty);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates an instruction with the [`GC_PREFIX`] prefix.
+    fn gc_instruction(&mut self) -> Result<(), ValidationError> {
+        use ValType::*;
+
+        let op = self.read_size()? as u32;
+        match op {
+            gc::STRUCT_NEW | gc::STRUCT_NEW_DEFAULT => {
+                let (type_idx, fields) = self.struct_type()?;
+                if op == gc::STRUCT_NEW {
+                    let types: Vec<_> = fields.iter().map(|f| f.storage.unpacked()).collect();
+                    self.pop_all(&types)?;
+                } else if !fields.iter().all(|field| field.storage.unpacked().is_defaultable()) {
+                    return Err(self.error("struct.new_default needs defaultable fields"));
+                }
+                self.push(object_type(type_idx, false));
+            }
+            gc::STRUCT_GET | gc::STRUCT_GET_S | gc::STRUCT_GET_U | gc::STRUCT_SET => {
+                let (type_idx, fields) = self.struct_type()?;
+                let field_idx = self.read_size()?;
+                let Some(field) = fields.get(field_idx) else {
+                    return Err(self.error(&format!("Unknown field {field_idx}")));
+                };
+                if op == gc::STRUCT_SET {
+                    self.check_mutable(field)?;
+                    self.pop_expect(field.storage.unpacked())?;
+                    self.pop_expect(object_type(type_idx, true))?;
+                } else {
+                    self.check_packing(op != gc::STRUCT_GET, field)?;
+                    self.pop_expect(object_type(type_idx, true))?;
+                    self.push(field.storage.unpacked());
+                }
+            }
+            gc::ARRAY_NEW | gc::ARRAY_NEW_DEFAULT => {
+                let (type_idx, elem) = self.array_type()?;
+                self.pop_expect(I32)?;
+                if op == gc::ARRAY_NEW {
+                    self.pop_expect(elem.storage.unpacked())?;
+                } else if !elem.storage.unpacked().is_defaultable() {
+                    return Err(self.error("array.new_default needs defaultable elements"));
+                }
+                self.push(object_type(type_idx, false));
+            }
+            gc::ARRAY_NEW_FIXED => {
+                let (type_idx, elem) = self.array_type()?;
+                for _ in 0..self.read_size()? {
+                    self.pop_expect(elem.storage.unpacked())?;
+                }
+                self.push(object_type(type_idx, false));
+            }
+            gc::ARRAY_NEW_DATA | gc::ARRAY_NEW_ELEM => {
+                let (type_idx, elem) = self.array_type()?;
+                self.array_segment(op == gc::ARRAY_NEW_DATA, &elem)?;
+                self.pop_expect(I32)?;
+                self.pop_expect(I32)?;
+                self.push(object_type(type_idx, false));
+            }
+            gc::ARRAY_GET | gc::ARRAY_GET_S | gc::ARRAY_GET_U => {
+                let (type_idx, elem) = self.array_type()?;
+                self.check_packing(op != gc::ARRAY_GET, &elem)?;
+                self.pop_expect(I32)?;
+                self.pop_expect(object_type(type_idx, true))?;
+                self.push(elem.storage.unpacked());
+            }
+            gc::ARRAY_SET | gc::ARRAY_FILL => {
+                let (type_idx, elem) = self.array_type()?;
+                self.check_mutable(&elem)?;
+                if op == gc::ARRAY_FILL {
+                    self.pop_expect(I32)?;
+                }
+                self.pop_expect(elem.storage.unpacked())?;
+                self.pop_expect(I32)?;
+                self.pop_expect(object_type(type_idx, true))?;
+            }
+            gc::ARRAY_LEN => {
+                self.pop_expect(Ref(RefType::null(HeapType::Array)))?;
+                self.push(I32);
+            }
+            gc::ARRAY_COPY => {
+                let (dst_idx, dst) = self.array_type()?;
+                let (src_idx, src) = self.array_type()?;
+                self.check_mutable(&dst)?;
+                if !storage_matches(self.module, src.storage, dst.storage) {
+                    return Err(self.error("Type mismatch: array.copy between incompatible arrays"));
+                }
+                self.pop_expect(I32)?;
+                self.pop_expect(I32)?;
+                self.pop_expect(object_type(src_idx, true))?;
+                self.pop_expect(I32)?;
+                self.pop_expect(object_type(dst_idx, true))?;
+            }
+            gc::ARRAY_INIT_DATA | gc::ARRAY_INIT_ELEM => {
+                let (type_idx, elem) = self.array_type()?;
+                self.check_mutable(&elem)?;
+                self.array_segment(op == gc::ARRAY_INIT_DATA, &elem)?;
+                self.pop_all(&[I32, I32, I32])?;
+                self.pop_expect(object_type(type_idx, true))?;
+            }
+            gc::REF_TEST | gc::REF_TEST_NULL | gc::REF_CAST | gc::REF_CAST_NULL => {
+                let nullable = op == gc::REF_TEST_NULL || op == gc::REF_CAST_NULL;
+                let ty = RefType { nullable, heap: self.heap_type()? };
+                if let Some(operand) = self.pop_ref()? {
+                    self.check_same_hierarchy(operand, ty)?;
+                }
+                match op {
+                    gc::REF_TEST | gc::REF_TEST_NULL => self.push(I32),
+                    _ => self.push(Ref(ty)),
+                }
+            }
+            gc::BR_ON_CAST | gc::BR_ON_CAST_FAIL => {
+                let flags = self.read_byte()?;
+                if flags & !(gc::CAST_SOURCE_NULL | gc::CAST_TARGET_NULL) != 0 {
+                    return Err(self.error(&format!("Invalid cast flags {flags:#04x}")));
+                }
+                let mut types = self.label_types()?;
+                let source = RefType {
+                    nullable: flags & gc::CAST_SOURCE_NULL != 0,
+                    heap: self.heap_type()?,
+                };
+                let target = RefType {
+                    nullable: flags & gc::CAST_TARGET_NULL != 0,
+                    heap: self.heap_type()?,
+                };
+                if !self.module.ref_matches(target, source) {
+                    let msg = format!("Type mismatch: cannot cast from {source:?} to {target:?}");
+                    return Err(self.error(&msg));
+                }
+                // What's left of the source type once the target type is taken out
+                let rest = RefType { nullable: source.nullable && !target.nullable, ..source };
+                let (branch, fallthrough) = match op {
+                    gc::BR_ON_CAST => (target, rest),
+                    _ => (rest, target),
+                };
+                let Some(Ref(label)) = types.pop() else {
+                    return Err(self.error("br_on_cast requires a label taking a reference"));
+                };
+                if !self.module.ref_matches(branch, label) {
+                    let msg = format!("Type mismatch: expected {label:?}, found {branch:?}");
+                    return Err(self.error(&msg));
+                }
+                self.pop_expect(Ref(source))?;
+                self.pop_all(&types)?;
+                self.push_all(&types);
+                self.push(Ref(fallthrough));
+            }
+            gc::ANY_CONVERT_EXTERN | gc::EXTERN_CONVERT_ANY => {
+                let (from, to) = match op {
+                    gc::ANY_CONVERT_EXTERN => (HeapType::Extern, HeapType::Any),
+                    _ => (HeapType::Any, HeapType::Extern),
+                };
+                let operand = self.pop_expect(Ref(RefType::null(from)))?;
+                let nullable = matches!(operand, Some(Ref(ty)) if ty.nullable);
+                self.push(Ref(RefType { nullable, heap: to }));
+            }
+            gc::REF_I31 => self.unary(I32, Ref(RefType::non_null(HeapType::I31)))?,
+            gc::I31_GET_S | gc::I31_GET_U => {
+                self.unary(Ref(RefType::null(HeapType::I31)), I32)?;
+            }
+            op => return Err(self.error(&format!("Instruction 0xfb {op} not yet implemented"))),
+        }
+        Ok(())
+    }
+
+    /// Reads a type index that must be of a struct type, returning its fields.
+    fn struct_type(&mut self) -> Result<(usize, Vec<FieldType>), ValidationError> {
+        let idx = self.read_size()?;
+        match self.module.struct_type(idx) {
+            Some(fields) => Ok((idx, fields.to_vec())),
+            None => Err(self.error(&format!("Type {idx} is not a struct type"))),
+        }
+    }
+
+    /// Reads a type index that must be of an array type, returning its element type.
+    fn array_type(&mut self) -> Result<(usize, FieldType), ValidationError> {
+        let idx = self.read_size()?;
+        match self.module.array_type(idx) {
+            Some(elem) => Ok((idx, *elem)),
+            None => Err(self.error(&format!("Type {idx} is not an array type"))),
+        }
+    }
+
+    fn check_mutable(&self, field: &FieldType) -> Result<(), ValidationError> {
+        match field.mutable {
+            true => Ok(()),
+            false => Err(self.error("Cannot set an immutable field")),
+        }
+    }
+
+    /// Checks that a field is read with `_s` or `_u` exactly when it is packed.
+    fn check_packing(&self, extending: bool, field: &FieldType) -> Result<(), ValidationError> {
+        match (extending, field.storage.is_packed()) {
+            (true, false) => Err(self.error("Only packed fields can be read with sign extension")),
+            (false, true) => Err(self.error("Packed fields must be read with _s or _u")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the data or element segment index of an instruction creating or
+    /// initializing an array of `elem` from it: numbers and vectors come from data
+    /// segments, references from element segments.
+    fn array_segment(&mut self, data: bool, elem: &FieldType) -> Result<(), ValidationError> {
+        if data {
+            if elem.storage.byte_width().is_none() {
+                return Err(self.error("Arrays of references can't be created from data"));
+            }
+            return self.data_index();
+        }
+        let ty = self.element_index()?;
+        if !storage_matches(self.module, StorageType::Val(ty), elem.storage) {
+            return Err(self.error("Type mismatch: element segment does not match the array"));
+        }
+        Ok(())
+    }
+
+    /// Checks that a cast's operand is in the same hierarchy as the type it's cast to.
+    fn check_same_hierarchy(&self, operand: RefType, ty: RefType) -> Result<(), ValidationError> {
+        if self.module.top(operand.heap) != self.module.top(ty.heap) {
+            let msg = format!("Type mismatch: cannot cast from {operand:?} to {ty:?}");
+            return Err(self.error(&msg));
+        }
+        Ok(())
+    }
+
+    /// Pushes a call's results, or for a tail call checks they are the function's own,
+    /// which the callee returns in its place.
+    fn call_results(&mut self, tail: bool, results: &[ValType]) -> Result<(), ValidationError> {
+        if !tail {
+            self.push_all(results);
+        } else if self.module.all_match(results, &self.results) {
+            self.set_unreachable();
+        } else {
+            return Err(self.error("Type mismatch: tail call results differ from the function's"));
+        }
+        Ok(())
+    }
+
+    fn unary(&mut self, operand: ValType, result: ValType) -> Result<(), ValidationError> {
+        self.pop_expect(operand)?;
+        self.push(result);
+        Ok(())
+    }
+
+    fn binary(&mut self, operand: ValType, result: ValType) -> Result<(), ValidationError> {
+        self.pop_expect(operand)?;
+        self.pop_expect(operand)?;
+        self.push(result);
+        Ok(())
+    }
+
+    /// Reads a block type: empty, a single result type, or the index of a function type
+    /// giving its params and results.
+    fn block_type(&mut self) -> Result<(Vec<ValType>, Vec<ValType>), ValidationError> {
+        let byte = self.read(|reader| reader.peek().ok_or_else(|| UNEXPECTED_END.to_string()))?;
+        if byte == 0x40 {
+            self.ip += 1;
+            return Ok((Vec::new(), Vec::new()));
+        }
+        if ValType::from_byte(byte).is_some() || matches!(byte, 0x63 | 0x64) {
+            return Ok((Vec::new(), vec![self.val_type()?]));
+        }
+
+        // Type indices are encoded as positive 33-bit signed integers
+        let idx = self.read(Reader::s33)?;
+        match usize::try_from(idx).ok().and_then(|idx| self.module.get_func_type(idx)) {
+            Some(functype) => Ok((functype.params.clone(), functype.results.clone())),
+            None => Err(self.error(&format!("Unknown block type {idx}"))),
+        }
+    }
+
+    fn label_types(&mut self) -> Result<Vec<ValType>, ValidationError> {
+        let depth = self.read_size()?;
+        if depth >= self.controls.len() {
+            return Err(self.error(&format!("Unknown label {depth}")));
+        }
+        let control = &self.controls[self.controls.len() - 1 - depth];
+        Ok(control.label_types().to_vec())
+    }
+
+    /// Checks the alignment and memory of a load or store's immediate, whose natural
+    /// alignment is `2^max_align` bytes, and returns the memory's address type.
+    fn memarg(&mut self, max_align: usize) -> Result<ValType, ValidationError> {
+        let (align, addr) = self.memarg_immediate()?;
+        if align > max_align {
+            return Err(self.error("Alignment must not be larger than natural"));
+        }
+        Ok(addr)
+    }
+
+    /// Checks an atomic access's immediate, whose alignment must be exactly the natural
+    /// alignment of its `width`, and returns the memory's address type.
+    fn atomic_memarg(&mut self, width: usize) -> Result<ValType, ValidationError> {
+        let (align, addr) = self.memarg_immediate()?;
+        if align != width.trailing_zeros() as usize {
+            return Err(self.error("Alignment of atomic instructions must be natural"));
+        }
+        Ok(addr)
+    }
+
+    /// Reads a memory immediate's alignment exponent, memory and offset, which must fit
+    /// the address type of the memory. The memory index is only present, and not 0, when
+    /// bit 6 of the alignment is set.
+    fn memarg_immediate(&mut self) -> Result<(usize, ValType), ValidationError> {
+        let mut align = self.read_size()?;
+        let mut mem = 0;
+        if align & bytecode::MEMARG_INDEX_BIT != 0 {
+            align &= !bytecode::MEMARG_INDEX_BIT;
+            mem = self.read_size()?;
+        }
+        let offset = self.read(Reader::u64)?;
+        let Some(memory) = self.module.memories.get(mem) else {
+            return Err(self.error(&format!("Unknown memory {mem}")));
+        };
+        if !memory.memory64 && offset > u32::MAX as u64 {
+            return Err(self.error("Offset out of range for a 32-bit memory"));
+        }
+        Ok((align, memory.address_type()))
+    }
+
+    fn lane_index(&mut self, lanes: usize) -> Result<(), ValidationError> {
+        let lane = self.code.get(self.ip).copied();
+        self.ip += 1;
+        match lane {
+            Some(lane) if (lane as usize) < lanes => Ok(()),
+            Some(lane) => Err(self.error(&format!("Invalid lane index {lane}"))),
+            None => Err(self.error("Unexpected end of function body")),
+        }
+    }
+
+    /// Reads a memory index, returning the memory's address type.
+    fn memory_index(&mut self) -> Result<ValType, ValidationError> {
+        let mem = self.read_size()?;
+        match self.module.memories.get(mem) {
+            Some(memory) => Ok(memory.address_type()),
+            None => Err(self.error(&format!("Unknown memory {mem}"))),
+        }
+    }
+
+    /// Reads a data segment index. Instructions using them need the DataCount section,
+    /// so that functions can be validated before the data section is read.
+    fn data_index(&mut self) -> Result<(), ValidationError> {
+        let idx = self.read_size()?;
+        match self.module.data_count {
+            Some(count) if idx < count as usize => Ok(()),
+            Some(_) => Err(self.error(&format!("Unknown data segment {idx}"))),
+            None => Err(self.error("Data segment instructions need a DataCount section")),
+        }
+    }
+
+    /// Reads an element segment index, returning the segment's element type.
+    fn element_index(&mut self) -> Result<ValType, ValidationError> {
+        let idx = self.read_size()?;
+        match self.module.elements.get(idx) {
+            Some(segment) => Ok(ValType::from(segment.ty)),
+            None => Err(self.error(&format!("Unknown element segment {idx}"))),
+        }
+    }
+
+    /// Reads a table index, returning the table's element type.
+    fn table(&mut self) -> Result<ValType, ValidationError> {
+        let idx = self.read_size()?;
+        match self.module.tables.get(idx) {
+            Some(table) => Ok(ValType::from(table.ty.elem)),
+            None => Err(self.error(&format!("Unknown table {idx}"))),
+        }
+    }
+
+    /// Reads a tag index and returns the types of the values it carries.
+    fn tag(&mut self) -> Result<Vec<ValType>, ValidationError> {
+        let idx = self.read_size()?;
+        match self.module.tags.get(idx) {
+            Some(type_idx) => Ok(self.module.func_type(*type_idx).params.clone()),
+            None => Err(self.error(&format!("Unknown tag {idx}"))),
+        }
+    }
+
+    /// Checks a `try_table` catch clause, whose label must take the values it branches
+    /// with: the tag's payload and/or the caught exception.
+    fn catch_clause(&mut self) -> Result<(), ValidationError> {
+        let kind = self.read_byte()?;
+        let mut types = match kind {
+            catch::CATCH | catch::CATCH_REF => self.tag()?,
+            catch::CATCH_ALL | catch::CATCH_ALL_REF => Vec::new(),
+            _ => return Err(self.error(&format!("Invalid catch clause {kind:#04x}"))),
+        };
+        if kind == catch::CATCH_REF || kind == catch::CATCH_ALL_REF {
+            types.push(ValType::EXNREF);
+        }
+        if !self.module.all_match(&types, &self.label_types()?) {
+            return Err(self.error("Type mismatch: catch clause does not match its label"));
+        }
+        Ok(())
+    }
+
+    fn global(&mut self) -> Result<GlobalType, ValidationError> {
+        let idx = self.read_size()?;
+        match self.module.globals.get(idx) {
+            Some(global) => Ok(global.ty),
+            None => Err(self.error(&format!("Unknown global {idx}"))),
+        }
+    }
+
+    /// Reads a local index, returning it with the local's type.
+    fn local(&mut self) -> Result<(usize, ValType), ValidationError> {
+        let idx = self.read_size()?;
+        match self.locals.get(idx) {
+            Some(ty) => Ok((idx, *ty)),
+            None => Err(self.error(&format!("Unknown local {idx}"))),
+        }
+    }
+
+    fn initialize(&mut self, idx: usize) {
+        if !self.initialized[idx] {
+            self.initialized[idx] = true;
+            self.inits.push(idx);
+        }
+    }
+
+    fn heap_type(&mut self) -> Result<HeapType, ValidationError> {
+        let Some((heap, len)) = bytecode::read::read_heap_type(self.code, self.ip) else {
+            return Err(self.error("Invalid heap type"));
+        };
+        self.ip += len;
+        if !known_heap_type(self.module, heap) {
+            return Err(self.error(&format!("Unknown heap type {heap:?}")));
+        }
+        Ok(heap)
+    }
+
+    fn val_type(&mut self) -> Result<ValType, ValidationError> {
+        let Some((ty, len)) = bytecode::read::read_val_type(self.code, self.ip) else {
+            return Err(self.error("Invalid value type"));
+        };
+        self.ip += len;
+        if !known_type(self.module, ty) {
+            return Err(self.error(&format!("Unknown type in {ty:?}")));
+        }
+        Ok(ty)
+    }
+
+    fn push(&mut self, ty: ValType) {
+        self.pushed_v128 |= ty == ValType::V128;
+        self.operands.push(Some(ty));
+        self.max_height = self.max_height.max(self.operands.len());
+    }
+
+    fn push_all(&mut self, types: &[ValType]) {
+        for ty in types {
+            self.push(*ty);
+        }
+    }
+
+    fn pop_operand(&mut self) -> Result<Option<ValType>, ValidationError> {
+        let control = self.controls.last().unwrap();
+        if self.operands.len() == control.height {
+            if control.unreachable {
+                return Ok(None);
+            }
+            return Err(self.error("Type mismatch: value stack is empty"));
+        }
+        Ok(self.operands.pop().unwrap())
+    }
+
+    /// Pops an operand that must match `expected`, returning its actual type.
+    fn pop_expect(&mut self, expected: ValType) -> Result<Option<ValType>, ValidationError> {
+        match self.pop_operand()? {
+            Some(actual) if !self.module.matches(actual, expected) => Err(self.error(&format!(
+                "Type mismatch: expected {expected:?}, found {actual:?}"
+            ))),
+            actual => Ok(actual),
+        }
+    }
+
+    /// Pops an operand that must be a reference of any type.
+    fn pop_ref(&mut self) -> Result<Option<RefType>, ValidationError> {
+        match self.pop_operand()? {
+            Some(ValType::Ref(ty)) => Ok(Some(ty)),
+            Some(ty) => {
+                let msg = format!("Type mismatch: expected a reference, found {ty:?}");
+                Err(self.error(&msg))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn pop_all(&mut self, types: &[ValType]) -> Result<(), ValidationError> {
+        for ty in types.iter().rev() {
+            self.pop_expect(*ty)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the top operands match `types`, leaving them on the stack.
+    fn peek_all(&mut self, types: &[ValType]) -> Result<(), ValidationError> {
+        let mut popped = Vec::with_capacity(types.len());
+        for ty in types.iter().rev() {
+            popped.push(self.pop_expect(*ty)?);
+        }
+        self.operands.extend(popped.into_iter().rev());
+        Ok(())
+    }
+
+    fn push_control(
+        &mut self,
+        kind: ControlKind,
+        start: usize,
+        start_types: Vec<ValType>,
+        end_types: Vec<ValType>,
+    ) {
+        let height = self.operands.len();
+        self.push_all(&start_types);
+        self.controls.push(Control {
+            kind,
+            start,
+            body: self.ip,
+            else_: None,
+            catches: None,
+            start_types,
+            end_types,
+            height,
+            inits: self.inits.len(),
+            unreachable: false,
+        });
+    }
+
+    fn pop_control(&mut self) -> Result<Control, ValidationError> {
+        let end_types = self.controls.last().unwrap().end_types.clone();
+        self.pop_all(&end_types)?;
+        let control = self.controls.pop().unwrap();
+        if self.operands.len() != control.height {
+            return Err(self.error("Type mismatch: values remaining on the stack at end of block"));
+        }
+        for idx in self.inits.drain(control.inits..) {
+            self.initialized[idx] = false;
+        }
+        Ok(control)
+    }
+
+    fn set_unreachable(&mut self) {
+        let control = self.controls.last_mut().unwrap();
+        self.operands.truncate(control.height);
+        control.unreachable = true;
+    }
+
+    /// Reads an immediate with a `Reader`, which fails on malformed or truncated ones.
+    fn read<T>(
+        &mut self,
+        read: impl FnOnce(&mut Reader<'a>) -> Result<T, String>,
+    ) -> Result<T, ValidationError> {
+        let mut reader = Reader::new(self.code, self.ip);
+        let result = read(&mut reader);
+        self.ip = reader.pos;
+        result.map_err(|msg| self.error(&msg))
+    }
+
+    fn read_byte(&mut self) -> Result<u8, ValidationError> {
+        self.read(Reader::u8)
+    }
+
+    fn read_size(&mut self) -> Result<usize, ValidationError> {
+        self.read(Reader::u32).map(|val| val as usize)
+    }
+
+    fn error(&self, msg: &str) -> ValidationError {
+        ValidationError {
+            offset: self.code_start + self.ip.saturating_sub(1),
+            msg: msg.to_string(),
+        }
+    }
+}
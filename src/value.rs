@@ -1,27 +1,318 @@
 #![allow(dead_code)]
 
-#[derive(Debug)]
+use std::collections::{HashMap, HashSet};
+
+use crate::const_expr::ConstExpr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     I32(i32),
     I64(i64),
     F32(f32),
     F64(f64),
     V128(i128),
-    RefNull(RefType),
+    /// A null reference of this heap type.
+    RefNull(HeapType),
+    /// A reference to the function with this index.
+    FuncRef(u32),
+    ExternRef(ExternRef),
+    ExnRef(ExnRef),
+    /// A struct, array or `i31ref`, or a host reference converted with
+    /// `any.convert_extern`.
+    AnyRef(AnyRef),
 }
 
-#[derive(Debug)]
-pub enum RefType {
-    FuncRef,
-    ExternRef,
+impl Value {
+    /// The value's type. References to functions are typed as `funcref` rather than
+    /// their exact function type, which a value doesn't know on its own.
+    pub fn ty(&self) -> Option<ValType> {
+        match self {
+            Value::I32(_) => Some(ValType::I32),
+            Value::I64(_) => Some(ValType::I64),
+            Value::F32(_) => Some(ValType::F32),
+            Value::F64(_) => Some(ValType::F64),
+            Value::V128(_) => Some(ValType::V128),
+            Value::RefNull(heap) => Some(ValType::Ref(RefType::null(*heap))),
+            Value::FuncRef(_) => Some(ValType::FUNCREF),
+            Value::ExternRef(_) => Some(ValType::EXTERNREF),
+            Value::ExnRef(_) => Some(ValType::EXNREF),
+            Value::AnyRef(_) => Some(ValType::ANYREF),
+        }
+    }
 }
 
-#[derive(Debug)]
+/// An opaque reference to a host object, created with [`Vm::extern_ref`]. It is only
+/// meaningful to the VM that created it.
+///
+/// [`Vm::extern_ref`]: crate::vm::Vm::extern_ref
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExternRef(pub(crate) u64);
+
+/// A caught exception, which wasm can rethrow with `throw_ref`. Its tag and payload are
+/// available from [`Vm::exception`] of the VM that caught it.
+///
+/// [`Vm::exception`]: crate::vm::Vm::exception
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExnRef(pub(crate) u32);
+
+/// A reference into the `any` hierarchy returned from wasm. A struct or array it refers
+/// to is only kept alive while wasm can still reach it, from a global, a table or the
+/// stack of a suspended invocation, so the host shouldn't hold on to one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnyRef(pub(crate) u64);
+
+/// What a reference may point to. Each family of heap types has a top, which every
+/// type in it is a subtype of, and a bottom, which only contains null: `nofunc` is
+/// below every concrete function type, which are all below `func`, and `none` is below
+/// every struct, array and `i31`, which are below `eq` and then `any`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapType {
+    Func,
+    Extern,
+    Exn,
+    Any,
+    Eq,
+    I31,
+    Struct,
+    Array,
+    NoFunc,
+    NoExtern,
+    NoExn,
+    None,
+    /// The type with this index in the type section.
+    Concrete(u32),
+}
+
+impl HeapType {
+    /// The abstract heap type with this encoding.
+    pub fn from_byte(byte: u8) -> Option<HeapType> {
+        match byte {
+            0x70 => Some(HeapType::Func),
+            0x6f => Some(HeapType::Extern),
+            0x69 => Some(HeapType::Exn),
+            0x73 => Some(HeapType::NoFunc),
+            0x72 => Some(HeapType::NoExtern),
+            0x74 => Some(HeapType::NoExn),
+            0x6e => Some(HeapType::Any),
+            0x6d => Some(HeapType::Eq),
+            0x6c => Some(HeapType::I31),
+            0x6b => Some(HeapType::Struct),
+            0x6a => Some(HeapType::Array),
+            0x71 => Some(HeapType::None),
+            _ => None,
+        }
+    }
+
+    /// The top of an abstract type's family, which determines how its references are
+    /// stored. A concrete type's family depends on its definition, so is found with
+    /// [`WasmModule::top`] instead.
+    ///
+    /// [`WasmModule::top`]: crate::wasm_module::WasmModule::top
+    pub fn top(&self) -> Option<HeapType> {
+        use HeapType::*;
+        match self {
+            Func | NoFunc => Some(Func),
+            Extern | NoExtern => Some(Extern),
+            Exn | NoExn => Some(Exn),
+            Any | Eq | I31 | Struct | Array | None => Some(Any),
+            Concrete(_) => Option::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefType {
+    /// Whether the reference may be null.
+    pub nullable: bool,
+    pub heap: HeapType,
+}
+
+impl RefType {
+    pub const FUNCREF: RefType = RefType::null(HeapType::Func);
+    pub const EXTERNREF: RefType = RefType::null(HeapType::Extern);
+    pub const EXNREF: RefType = RefType::null(HeapType::Exn);
+    pub const ANYREF: RefType = RefType::null(HeapType::Any);
+
+    /// `(ref null heap)`
+    pub const fn null(heap: HeapType) -> Self {
+        Self { nullable: true, heap }
+    }
+
+    /// `(ref heap)`
+    pub const fn non_null(heap: HeapType) -> Self {
+        Self { nullable: false, heap }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValType {
-    I32 = 0x7f,
-    I64 = 0x7e,
-    F32 = 0x7d,
-    F64 = 0x7c,
+    I32,
+    I64,
+    F32,
+    F64,
+    V128,
+    Ref(RefType),
+}
+
+impl ValType {
+    pub const FUNCREF: ValType = ValType::Ref(RefType::FUNCREF);
+    pub const EXTERNREF: ValType = ValType::Ref(RefType::EXTERNREF);
+    pub const EXNREF: ValType = ValType::Ref(RefType::EXNREF);
+    pub const ANYREF: ValType = ValType::Ref(RefType::ANYREF);
+
+    /// The value type encoded as the single byte `byte`: a number type, `v128`, or the
+    /// shorthand for a nullable abstract reference such as `funcref`.
+    pub fn from_byte(byte: u8) -> Option<ValType> {
+        match byte {
+            0x7f => Some(ValType::I32),
+            0x7e => Some(ValType::I64),
+            0x7d => Some(ValType::F32),
+            0x7c => Some(ValType::F64),
+            0x7b => Some(ValType::V128),
+            _ => HeapType::from_byte(byte).map(|heap| ValType::Ref(RefType::null(heap))),
+        }
+    }
+
+    pub fn is_ref(&self) -> bool {
+        matches!(self, ValType::Ref(_))
+    }
+
+    /// Whether locals of this type start with a default value; non-nullable references
+    /// have none, so must be set before they are read.
+    pub fn is_defaultable(&self) -> bool {
+        !matches!(self, ValType::Ref(RefType { nullable: false, .. }))
+    }
+
+    /// The number of 64-bit stack slots a value of this type takes: two for a `v128`,
+    /// one for anything else.
+    pub fn slots(&self) -> usize {
+        if *self == ValType::V128 { 2 } else { 1 }
+    }
+}
+
+/// The number of stack slots the values of `types` take together.
+pub fn slot_count(types: &[ValType]) -> usize {
+    types.iter().map(ValType::slots).sum()
+}
+
+impl From<RefType> for ValType {
+    fn from(ty: RefType) -> Self {
+        ValType::Ref(ty)
+    }
+}
+
+/// The size range of a memory in pages, or of a table in elements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub min: u64,
+    pub max: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryType {
+    pub limits: Limits,
+    /// Whether the memory may be shared between instances on different threads.
+    pub shared: bool,
+    /// Whether the memory is addressed with i64 rather than i32.
+    pub memory64: bool,
+}
+
+impl MemoryType {
+    /// The type of the memory's addresses, sizes and lengths.
+    pub fn address_type(&self) -> ValType {
+        if self.memory64 { ValType::I64 } else { ValType::I32 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TableType {
+    pub elem: RefType,
+    pub limits: Limits,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub ty: TableType,
+    /// The value of the table's elements when it is created, which tables of
+    /// non-nullable references must have. Otherwise they start null.
+    pub init: Option<ConstExpr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalType {
+    pub ty: ValType,
+    pub mutable: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub ty: GlobalType,
+    /// `None` for an imported global, whose value the host provides.
+    pub init: Option<ConstExpr>,
+}
+
+/// How a data or element segment is used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentMode {
+    /// Copied in with `memory.init` or `table.init`.
+    Passive,
+    /// Copied into the memory or table `index` at `offset` during instantiation.
+    Active { index: u32, offset: ConstExpr },
+    /// Only declares the functions it references; it can't be copied anywhere.
+    Declarative,
+}
+
+#[derive(Debug, Clone)]
+pub struct DataSegment {
+    pub mode: SegmentMode,
+    pub init: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ElementSegment {
+    pub ty: RefType,
+    pub mode: SegmentMode,
+    pub init: Vec<ConstExpr>,
+}
+
+/// What an import or export refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalKind {
+    Func = 0x00,
+    Table = 0x01,
+    Memory = 0x02,
+    Global = 0x03,
+    Tag = 0x04,
+}
+
+impl ExternalKind {
+    pub fn from_byte(byte: u8) -> Option<ExternalKind> {
+        match byte {
+            0x00 => Some(ExternalKind::Func),
+            0x01 => Some(ExternalKind::Table),
+            0x02 => Some(ExternalKind::Memory),
+            0x03 => Some(ExternalKind::Global),
+            0x04 => Some(ExternalKind::Tag),
+            _ => None,
+        }
+    }
+}
+
+/// An import, which takes the next `index` in the index space of its kind. Imports
+/// come before the module's own definitions.
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub kind: ExternalKind,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExternalKind,
+    pub index: usize,
 }
 
 #[derive(Debug)]
@@ -30,25 +321,126 @@ pub enum TypeIndex {
     Index(u32),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct FuncType {
     pub params: Vec<ValType>,
     pub results: Vec<ValType>,
 }
 
+/// How a struct field or array element is stored: as a value, or packed into the low
+/// 8 or 16 bits of an i32.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageType {
+    Val(ValType),
+    I8,
+    I16,
+}
+
+impl StorageType {
+    /// The type of the values read and written, which is i32 for packed storage.
+    pub fn unpacked(&self) -> ValType {
+        match self {
+            StorageType::Val(ty) => *ty,
+            StorageType::I8 | StorageType::I16 => ValType::I32,
+        }
+    }
+
+    pub fn is_packed(&self) -> bool {
+        !matches!(self, StorageType::Val(_))
+    }
+
+    /// The size in bytes of a value in a data segment, for `array.new_data`.
+    pub fn byte_width(&self) -> Option<usize> {
+        match self {
+            StorageType::I8 => Some(1),
+            StorageType::I16 => Some(2),
+            StorageType::Val(ValType::I32 | ValType::F32) => Some(4),
+            StorageType::Val(ValType::I64 | ValType::F64) => Some(8),
+            StorageType::Val(ValType::V128) => Some(16),
+            StorageType::Val(ValType::Ref(_)) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldType {
+    pub storage: StorageType,
+    pub mutable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompositeType {
+    Func(FuncType),
+    Struct(Vec<FieldType>),
+    /// An array of elements of this type.
+    Array(FieldType),
+}
+
+/// An entry of the type section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubType {
+    /// Whether no other type may declare this one as its supertype.
+    pub is_final: bool,
+    pub supertype: Option<u32>,
+    pub composite: CompositeType,
+}
+
+impl SubType {
+    /// A final type without a supertype, as every type was before GC.
+    pub fn plain(composite: CompositeType) -> Self {
+        Self { is_final: true, supertype: None, composite }
+    }
+}
+
+/// Branch information for a `block`, `loop` or `if`, computed during validation so the
+/// interpreter can jump without scanning for the matching `end`. Offsets are relative
+/// to the function's code, and params and results are counted in stack slots.
+#[derive(Debug, Clone, Copy)]
+pub struct Block {
+    pub body: usize,
+    pub else_: Option<usize>,
+    pub end: usize,
+    pub params: usize,
+    pub results: usize,
+    /// The offset of a `try_table`'s catch clauses.
+    pub catches: Option<usize>,
+}
+
 #[derive(Debug)]
 pub struct Function {
     pub functype: usize,
+    pub locals: Vec<ValType>,
     pub code_start: usize,
     pub code_len: usize,
+    pub blocks: HashMap<usize, Block>,
+    /// An upper bound on the slots the function's operands take.
+    pub max_stack: usize,
+    /// The slot of each parameter and local relative to the frame, followed by the
+    /// number of slots they take together.
+    pub local_slots: Vec<usize>,
+    /// The offsets of the untyped `drop` and `select` instructions whose operands are
+    /// `v128`s, which take two slots each.
+    pub wide_operands: HashSet<usize>,
+    /// Whether the host provides the function, in which case it has no code.
+    pub imported: bool,
 }
 
 impl Function {
     pub fn new(functype_idx: usize) -> Self {
         Self {
             functype: functype_idx,
+            locals: Vec::new(),
             code_start: 0,
             code_len: 0,
+            blocks: HashMap::new(),
+            max_stack: 0,
+            local_slots: Vec::new(),
+            wide_operands: HashSet::new(),
+            imported: false,
         }
     }
-}
\ No newline at end of file
+
+    pub fn imported(functype_idx: usize) -> Self {
+        Self { imported: true, ..Self::new(functype_idx) }
+    }
+}
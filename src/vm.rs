@@ -1,100 +1,999 @@
 #![allow(dead_code)]
 
-use crate::bytecode::{self, op::*};
-use crate::value::Value;
+use std::any::Any;
+use std::collections::HashMap;
+use std::ops::{BitAnd, BitOr, BitXor};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::bytecode::{self, atomic, catch, gc, misc, op::*, simd};
+use crate::fuel::CostTable;
+use crate::gc::{self as objects, Heap, Object};
+use crate::hook::{Hook, HookAction, Location};
+use crate::interrupt::InterruptHandle;
+use crate::limits::{ResourceLimiter, DEFAULT_CALL_DEPTH, DEFAULT_STACK_SLOTS};
+use crate::memory::{Memory, Table, PAGE_SIZE};
+use crate::shared_memory::{SharedMemory, WaitResult};
+use crate::stack::{self, Stack};
+use crate::v128::{self, Kind};
+use crate::value::{
+    slot_count, AnyRef, Block, Export, ExternRef, ExternalKind, FieldType, HeapType, RefType,
+    SegmentMode, StorageType, ValType, Value,
+};
 use crate::wasm_module::WasmModule;
 
+/// How many interrupt polls pass between reads of the clock when a deadline is set.
+const DEADLINE_POLL_INTERVAL: u32 = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum Trap {
+    Unreachable,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    CallStackExhausted,
+    ValueStackExhausted,
+    MemoryOutOfBounds,
+    TableOutOfBounds,
+    /// `call_indirect` with an index past the end of the table.
+    UndefinedElement,
+    /// `call_indirect` through a null reference.
+    UninitializedElement,
+    IndirectCallTypeMismatch,
+    /// An atomic access at an address that isn't a multiple of its width.
+    UnalignedAtomic,
+    /// `memory.atomic.wait` on a memory that isn't shared.
+    ExpectedSharedMemory,
+    /// A null reference where a non-null one is required.
+    NullReference,
+    /// An array access past the end of the array.
+    ArrayOutOfBounds,
+    /// `ref.cast` of a reference that isn't of the target type.
+    CastFailure,
+    /// A struct or array could not be allocated.
+    HeapExhausted,
+    /// A reference to an object or exception that doesn't exist, which only the host can
+    /// make up.
+    DanglingReference,
+    /// An exception was thrown that no `try_table` caught.
+    UncaughtException(Exception),
+    /// Instantiation was denied by the [`ResourceLimiter`].
+    ResourceLimitExceeded(String),
+    /// The fuel budget ran out. Execution can continue with [`Vm::resume`] after
+    /// adding more fuel.
+    OutOfFuel,
+    /// Execution was stopped through an [`InterruptHandle`] or by reaching its deadline.
+    Interrupted,
+    /// A host function failed.
+    Host(String),
+    /// A value could not be lifted or lowered by the canonical ABI.
+    InvalidValue(String),
+    /// A [`Hook`] paused execution. Continue it with [`Vm::resume`].
+    Paused,
+    InvalidArguments(String),
+}
+
+/// A thrown exception: its tag and the values it carries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exception {
+    pub tag: usize,
+    pub payload: Vec<Value>,
+}
+
+/// A function the host provides for a function import. It is called with the instance
+/// that imports it, which it may call back into with [`Vm::invoke`].
+pub type HostFunc<'a> =
+    Arc<dyn Fn(&mut Vm, &[Value]) -> Result<Vec<Value>, Trap> + Send + Sync + 'a>;
+
+/// What the host provides for a module's imports. Each list is in the order of
+/// [`WasmModule::imports_of`] for its kind.
+#[derive(Default)]
+pub struct Imports<'a> {
+    pub functions: Vec<HostFunc<'a>>,
+    /// The host keeps access to imported memories through its own handles.
+    pub memories: Vec<SharedMemory>,
+    /// The values of the imported globals, which are immutable.
+    pub globals: Vec<Value>,
+}
+
+struct Frame {
+    func: usize,
+    ip: usize,
+    locals: usize,
+    labels: usize,
+}
+
+struct Label {
+    height: usize,
+    arity: usize,
+    target: usize,
+    is_loop: bool,
+    /// The offset of the catch clauses if this is a `try_table`.
+    catches: Option<usize>,
+}
+
 pub struct Vm<'a> {
-    module: &'a WasmModule,
+    module: &'a WasmModule<'a>,
     code: &'a [u8],
     ip: usize,
-    stack: Vec<Value>,
+    stack: Stack,
+    frames: Vec<Frame>,
+    labels: Vec<Label>,
+    memories: Vec<Memory>,
+    tables: Vec<Table>,
+    globals: Vec<u128>,
+    /// The contents of each data segment, emptied when it is dropped.
+    data: Vec<&'a [u8]>,
+    /// The elements of each element segment as slots, emptied when it is dropped.
+    elements: Vec<Vec<u64>>,
+    externs: Vec<Box<dyn Any + Send>>,
+    /// The host function of each imported function, by function index.
+    host_functions: Vec<HostFunc<'a>>,
+    /// How many host functions are running, which makes [`Vm::invoke`] a nested call.
+    host_calls: usize,
+    /// The call depth at which the current execution returns to its invoker.
+    base_depth: usize,
+    /// The exceptions caught with a reference, indexed by their `exnref`. An entry is
+    /// reclaimed once no `exnref` can refer to it, and its index reused.
+    exceptions: Vec<Option<Exception>>,
+    free_exceptions: Vec<u32>,
+    heap: Heap,
+    /// The object and exception references handed to the host, each with the number of
+    /// times it was, which keep what they refer to alive until [`Vm::release`]d.
+    host_refs: HashMap<u64, usize>,
+    limiter: Option<Arc<dyn ResourceLimiter>>,
+    max_call_depth: usize,
+    max_stack_slots: usize,
+    fuel: Option<u64>,
+    costs: CostTable,
+    suspended: Option<usize>,
+    interrupt: InterruptHandle,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    polls: u32,
+    hook: Option<Box<dyn Hook + 'a>>,
+    skip_hook: bool,
 }
 
 pub fn interpret(module: &WasmModule) {
-    if module.start_function.is_none() {
+    let Some(start) = module.start_function else {
         return;
-    }
-    let code = {
-        let function = &module.functions[module.start_function.unwrap()];
-        let code_start = function.code_start;
-        let code_end = code_start + function.code_len;
-        &module.code_section[code_start..code_end]
     };
 
-    let mut vm = Vm {
-        module,
-        code,
-        ip: 0,
-        stack: Vec::new(),
+    let mut vm = match Vm::new(module) {
+        Ok(vm) => vm,
+        Err(trap) => return eprintln!("Trap: {trap:?}"),
     };
-    vm.interpret();
+    vm.set_hook(Some(Box::new(Tracer)));
+    match vm.invoke(start, &[]) {
+        Ok(results) => {
+            for value in results {
+                println!("{value:?}");
+            }
+        }
+        Err(trap) => eprintln!("Trap: {trap:?}"),
+    }
 }
 
-//TODO Call Frames
 impl<'a> Vm<'a> {
-    pub fn interpret(&mut self) {
-        let _local_count = self.read_byte();
+    pub fn new(module: &'a WasmModule<'a>) -> Result<Self, Trap> {
+        Self::instantiate(module, None, Imports::default())
+    }
+
+    /// Instantiates the module with its allocations checked by `limiter`.
+    pub fn with_limiter(
+        module: &'a WasmModule<'a>,
+        limiter: Arc<dyn ResourceLimiter>,
+    ) -> Result<Self, Trap> {
+        Self::instantiate(module, Some(limiter), Imports::default())
+    }
+
+    /// Instantiates the module with `memory` as its memory 0, which must be declared
+    /// shared with limits the memory satisfies. Instances sharing a memory may run on
+    /// different threads; each still applies its own active data segments.
+    pub fn with_shared_memory(
+        module: &'a WasmModule<'a>,
+        memory: SharedMemory,
+    ) -> Result<Self, Trap> {
+        if !module.memories.first().is_some_and(|memory| memory.shared) {
+            return Err(Trap::InvalidArguments(String::from("Memory 0 is not shared")));
+        }
+        Self::instantiate(module, None, Imports { memories: vec![memory], ..Imports::default() })
+    }
+
+    /// Instantiates the module with `imports`, which must match the module's imports
+    /// in number and type. Memories past the imported ones back the memories the module
+    /// defines, so the host can share those too.
+    pub fn with_imports(module: &'a WasmModule<'a>, imports: Imports<'a>) -> Result<Self, Trap> {
+        for (kind, count) in [
+            (ExternalKind::Func, imports.functions.len()),
+            (ExternalKind::Memory, imports.memories.len()),
+            (ExternalKind::Global, imports.globals.len()),
+        ] {
+            let expected = module.imports_of(kind).count();
+            let allowed = match kind {
+                ExternalKind::Memory => expected..=module.memories.len(),
+                _ => expected..=expected,
+            };
+            if !allowed.contains(&count) {
+                let msg = format!("Expected {expected} imports of {kind:?}, got {count}");
+                return Err(Trap::InvalidArguments(msg));
+            }
+        }
+        Self::instantiate(module, None, imports)
+    }
+
+    /// Creates an instance from `imports`, which must at least cover the module's imports.
+    /// Extra memories replace the module's own, in order.
+    fn instantiate(
+        module: &'a WasmModule<'a>,
+        limiter: Option<Arc<dyn ResourceLimiter>>,
+        imports: Imports<'a>,
+    ) -> Result<Self, Trap> {
+        for (kind, count) in [
+            (ExternalKind::Func, imports.functions.len()),
+            (ExternalKind::Memory, imports.memories.len()),
+            (ExternalKind::Global, imports.globals.len()),
+        ] {
+            if let Some(import) = module.imports_of(kind).nth(count) {
+                let msg = format!("{kind:?} import {}.{} not provided", import.module, import.name);
+                return Err(Trap::InvalidArguments(msg));
+            }
+        }
+        let globals = Self::evaluate_globals(module, imports.globals)?;
+        let mut stack = Stack::new(cfg!(debug_assertions));
+        stack.set_type_tops(type_tops(module));
+        if limiter.as_ref().is_some_and(|limiter| !limiter.instance_creating()) {
+            return Err(Trap::ResourceLimitExceeded(String::from("Too many instances")));
+        }
+        let (max_call_depth, max_stack_slots) = match &limiter {
+            Some(limiter) => (limiter.max_call_depth(), limiter.max_stack_slots()),
+            None => (DEFAULT_CALL_DEPTH, DEFAULT_STACK_SLOTS),
+        };
+
+        // From here on dropping the Vm tells the limiter the instance is gone
+        let mut vm = Self {
+            module,
+            code: &[],
+            ip: 0,
+            stack,
+            frames: Vec::new(),
+            labels: Vec::new(),
+            memories: Vec::new(),
+            tables: Vec::new(),
+            globals: globals.iter().map(|value| stack::value_to_raw(*value)).collect(),
+            data: module.data.iter().map(|segment| segment.init.as_slice()).collect(),
+            elements: module.elements.iter()
+                .map(|segment| {
+                    segment.init.iter()
+                        .map(|init| stack::value_to_raw(init.evaluate(&globals)) as u64)
+                        .collect()
+                })
+                .collect(),
+            externs: Vec::new(),
+            host_functions: imports.functions,
+            host_calls: 0,
+            base_depth: 0,
+            exceptions: Vec::new(),
+            free_exceptions: Vec::new(),
+            heap: Heap::new(),
+            host_refs: HashMap::new(),
+            limiter,
+            max_call_depth,
+            max_stack_slots,
+            fuel: None,
+            costs: CostTable::default(),
+            suspended: None,
+            interrupt: InterruptHandle::default(),
+            timeout: None,
+            deadline: None,
+            polls: 0,
+            hook: None,
+            skip_hook: false,
+        };
+
+        let memories = imports.memories.into_iter().zip(&module.memories);
+        for (idx, (shared, memory)) in memories.enumerate() {
+            let limits = memory.limits;
+            if shared.size() < limits.min || limits.max.is_some_and(|max| shared.max() > max) {
+                let msg = format!("Shared memory does not match the limits of memory {idx}");
+                return Err(Trap::InvalidArguments(msg));
+            }
+            vm.memories.push(Memory::from_shared(shared, *memory));
+        }
+        for memory in &module.memories[vm.memories.len()..] {
+            let limits = memory.limits;
+            let bytes = pages_to_bytes(limits.min);
+            let max = limits.max.map(pages_to_bytes);
+            if vm.limiter.as_ref().is_some_and(|l| !l.memory_growing(0, bytes, max)) {
+                let msg = format!("Memory of {} pages denied", limits.min);
+                return Err(Trap::ResourceLimitExceeded(msg));
+            }
+            let Some(memory) = Memory::new(*memory) else {
+                let msg = format!("Memory of {} pages could not be allocated", limits.min);
+                return Err(Trap::ResourceLimitExceeded(msg));
+            };
+            vm.memories.push(memory);
+        }
+        for table in &module.tables {
+            let limits = table.ty.limits;
+            let (min, max) = (limits.min as u32, limits.max.map(|max| max as u32));
+            if vm.limiter.as_ref().is_some_and(|l| !l.table_growing(0, min, max)) {
+                let msg = format!("Table of {} elements denied", limits.min);
+                return Err(Trap::ResourceLimitExceeded(msg));
+            }
+            let Some(mut instance) = Table::new(table.ty.elem, limits) else {
+                let msg = format!("Table of {} elements could not be allocated", limits.min);
+                return Err(Trap::ResourceLimitExceeded(msg));
+            };
+            if let Some(init) = &table.init {
+                let raw = stack::value_to_raw(init.evaluate(&globals)) as u64;
+                instance.fill(0, raw, instance.size());
+            }
+            vm.tables.push(instance);
+        }
+        vm.initialize_segments(&globals)?;
+
+        Ok(vm)
+    }
+
+    /// The initial value of every global, taking imported ones from `imported`.
+    fn evaluate_globals(module: &WasmModule, imported: Vec<Value>) -> Result<Vec<Value>, Trap> {
+        let mut imported = imported.into_iter();
+        let mut globals = Vec::with_capacity(module.globals.len());
+        for (idx, global) in module.globals.iter().enumerate() {
+            let value = match &global.init {
+                Some(init) => init.evaluate(&globals),
+                None => {
+                    let value = imported.next().unwrap();
+                    // Objects from elsewhere can't be in this instance's heap
+                    if !value_matches(module, &Heap::new(), &[], value, global.ty.ty) {
+                        let msg = format!("Imported global {idx} must be {:?}", global.ty.ty);
+                        return Err(Trap::InvalidArguments(msg));
+                    }
+                    value
+                }
+            };
+            globals.push(value);
+        }
+        Ok(globals)
+    }
+
+    /// Copies the active segments into their tables and memories, then drops them along
+    /// with the declarative segments, as though by `table.init`, `memory.init` and the
+    /// drop instructions.
+    fn initialize_segments(&mut self, globals: &[Value]) -> Result<(), Trap> {
+        for (idx, segment) in self.module.elements.iter().enumerate() {
+            if let SegmentMode::Active { index, offset } = &segment.mode {
+                let Value::I32(offset) = offset.evaluate(globals) else { unreachable!() };
+                let elements = &self.elements[idx];
+                if !self.tables[*index as usize].write(offset as u32, elements) {
+                    return Err(Trap::TableOutOfBounds);
+                }
+            }
+            if segment.mode != SegmentMode::Passive {
+                self.elements[idx].clear();
+            }
+        }
+        for (idx, segment) in self.module.data.iter().enumerate() {
+            if let SegmentMode::Active { index, offset } = &segment.mode {
+                let offset = match offset.evaluate(globals) {
+                    Value::I32(offset) => offset as u32 as u64,
+                    Value::I64(offset) => offset as u64,
+                    _ => unreachable!(),
+                };
+                if !self.memories[*index as usize].write(offset, &segment.init) {
+                    return Err(Trap::MemoryOutOfBounds);
+                }
+                self.data[idx] = &[];
+            }
+        }
+        Ok(())
+    }
+
+    /// Enables or disables type checking of every stack access. Checked mode is on by
+    /// default in debug builds.
+    pub fn set_checked(&mut self, checked: bool) {
+        assert!(self.frames.is_empty(), "Cannot change checked mode while running");
+        self.stack = Stack::new(checked);
+        self.stack.set_type_tops(type_tops(self.module));
+    }
+
+    /// Installs a hook that is called before every instruction, or removes it.
+    pub fn set_hook(&mut self, hook: Option<Box<dyn Hook + 'a>>) {
+        self.hook = hook;
+    }
+
+    /// Sets the fuel budget for execution, or disables metering with `None`. Each
+    /// instruction consumes fuel according to the cost table, and execution traps with
+    /// [`Trap::OutOfFuel`] when it runs out.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Adds to the fuel budget, enabling metering if it was disabled.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(fuel));
+    }
+
+    /// The remaining fuel, or `None` if metering is disabled.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn set_cost_table(&mut self, costs: CostTable) {
+        self.costs = costs;
+    }
+
+    /// A handle that can stop this VM from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Limits the wall-clock time of each subsequent invocation, measured from the call
+    /// to `invoke`. Time spent suspended out of fuel still counts.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn invoke(&mut self, func_idx: usize, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let Some(function) = self.module.functions.get(func_idx) else {
+            return Err(Trap::InvalidArguments(format!("Unknown function {func_idx}")));
+        };
+        let functype = self.module.func_type(function.functype);
+        let params = &functype.params;
+        let matching = args.len() == params.len()
+            && args.iter().zip(params)
+                .all(|(arg, ty)| value_matches(self.module, &self.heap, &self.exceptions, *arg, *ty));
+        if !matching {
+            return Err(Trap::InvalidArguments(format!(
+                "Expected arguments {:?}, found {args:?}",
+                functype.params
+            )));
+        }
+
+        if self.host_calls > 0 {
+            return self.invoke_nested(func_idx, args);
+        }
+
+        self.reset();
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        for arg in args {
+            self.stack.push_value(*arg);
+        }
+
+        let result = self.call(func_idx).and_then(|_| self.execute());
+        self.finish(func_idx, result)
+    }
+
+    /// Calls a function from within a host function, above the frames waiting for the
+    /// host to return. Execution can't be suspended there, so running out of fuel or a
+    /// hook pausing is a trap. On a trap the nested frames are unwound, leaving the
+    /// waiting ones as they were.
+    fn invoke_nested(&mut self, func_idx: usize, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let (depth, labels, height) = (self.frames.len(), self.labels.len(), self.stack.len());
+        let base_depth = std::mem::replace(&mut self.base_depth, depth);
+        for arg in args {
+            self.stack.push_value(*arg);
+        }
+
+        let result = self.call(func_idx).and_then(|_| self.execute());
+        self.base_depth = base_depth;
+        match result {
+            Ok(()) => Ok(self.pop_results(func_idx)),
+            Err(trap) => {
+                while self.frames.len() > depth {
+                    let func = self.frames.last().unwrap().func;
+                    self.with_hook(|hook, vm| hook.exit(vm, func));
+                    self.frames.pop();
+                }
+                self.labels.truncate(labels);
+                self.stack.unwind(height, 0);
+                self.skip_hook = false;
+                Err(match trap {
+                    Trap::OutOfFuel | Trap::Paused => {
+                        Trap::Host(String::from("Cannot suspend inside a host function"))
+                    }
+                    trap => trap,
+                })
+            }
+        }
+    }
+
+    /// Continues an invocation that ran out of fuel or was paused by a hook.
+    pub fn resume(&mut self) -> Result<Vec<Value>, Trap> {
+        let Some(func_idx) = self.suspended.take() else {
+            return Err(Trap::InvalidArguments(String::from("No suspended invocation")));
+        };
+        let result = self.execute();
+        self.finish(func_idx, result)
+    }
+
+    /// Whether an invocation is suspended and can be resumed.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    pub fn module(&self) -> &'a WasmModule<'a> {
+        self.module
+    }
+
+    /// The number of active calls.
+    pub fn call_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The location of each active call, outermost first. The last is the instruction
+    /// about to execute.
+    pub fn call_stack(&self) -> Vec<Location> {
+        let mut locations: Vec<_> = self.frames.iter()
+            .map(|frame| Location { func: frame.func, offset: frame.ip })
+            .collect();
+        if let Some(top) = locations.last_mut() {
+            top.offset = self.ip;
+        }
+        locations
+    }
+
+    /// The parameters and locals of the frame `depth` calls below the innermost.
+    pub fn locals(&self, depth: usize) -> Option<Vec<Value>> {
+        let frame = self.frames.iter().rev().nth(depth)?;
+        let function = &self.module.functions[frame.func];
+        let params = &self.module.func_type(function.functype).params;
+        let values = params.iter().chain(&function.locals).zip(&function.local_slots)
+            .map(|(ty, slot)| self.slot_value(self.stack.read(frame.locals + slot, *ty), *ty))
+            .collect();
+        Some(values)
+    }
+
+    /// The operands of the innermost frame, bottom first. Their types are only known in
+    /// checked mode, so otherwise this is `None`.
+    pub fn operands(&self) -> Option<Vec<Value>> {
+        let start = match self.frames.last() {
+            Some(frame) => {
+                let function = &self.module.functions[frame.func];
+                frame.locals + function.local_slots.last().unwrap()
+            }
+            None => 0,
+        };
+        self.stack.typed_values(start)
+    }
+
+    pub fn globals(&self) -> Vec<Value> {
+        let types = self.module.globals.iter().map(|g| g.ty.ty);
+        self.globals.iter().zip(types).map(|(raw, ty)| self.slot_value(*raw, ty)).collect()
+    }
+
+    /// The value of a slot holding a `ty`.
+    fn slot_value(&self, raw: u128, ty: ValType) -> Value {
+        let ty = if raw == stack::NULL_REF as u128 { ty } else { self.module.erased(ty) };
+        stack::raw_to_value(raw, ty)
+    }
+
+    pub fn memory(&self, idx: usize) -> Option<&Memory> {
+        self.memories.get(idx)
+    }
+
+    pub fn memory_mut(&mut self, idx: usize) -> Option<&mut Memory> {
+        self.memories.get_mut(idx)
+    }
+
+    /// The memory exported as `name`, if there is one.
+    pub fn exported_memory(&self, name: &str) -> Option<&Memory> {
+        match self.module.export(name)? {
+            Export { kind: ExternalKind::Memory, index, .. } => self.memories.get(*index),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self, idx: usize) -> Option<&Table> {
+        self.tables.get(idx)
+    }
+
+    /// The exception behind an `exnref` caught by this VM.
+    pub fn exception(&self, value: Value) -> Option<&Exception> {
+        let Value::ExnRef(handle) = value else {
+            return None;
+        };
+        self.exceptions.get(handle.0 as usize)?.as_ref()
+    }
+
+    /// Wraps a host object in an `externref` that can be passed into wasm. The object
+    /// lives as long as the VM.
+    pub fn extern_ref(&mut self, object: impl Any + Send) -> Value {
+        self.externs.push(Box::new(object));
+        Value::ExternRef(ExternRef(self.externs.len() as u64 - 1))
+    }
+
+    /// The host object behind an `externref` returned from wasm, if it is one of this
+    /// VM's and has type `T`.
+    pub fn extern_data<T: Any>(&self, value: Value) -> Option<&T> {
+        let Value::ExternRef(handle) = value else {
+            return None;
+        };
+        self.externs.get(handle.0 as usize)?.downcast_ref()
+    }
+
+    fn finish(&mut self, func_idx: usize, result: Result<(), Trap>) -> Result<Vec<Value>, Trap> {
+        let result = match result {
+            Ok(()) => Ok(self.pop_results(func_idx)),
+            Err(trap @ (Trap::OutOfFuel | Trap::Paused)) => {
+                // The hook has already seen the instruction execution stopped at
+                self.suspended = Some(func_idx);
+                self.skip_hook = true;
+                Err(trap)
+            }
+            Err(trap) => {
+                self.reset();
+                Err(trap)
+            }
+        };
+        // Drop what the invocation caught without keeping
+        if self.exceptions.len() > self.free_exceptions.len() {
+            self.collect_exceptions(&[]);
+        }
+        result
+    }
+
+    /// Pops the results of a call to `func_idx` off the stack, for the host.
+    fn pop_results(&mut self, func_idx: usize) -> Vec<Value> {
+        let functype = self.module.func_type(self.module.functions[func_idx].functype);
+        let mut results: Vec<_> =
+            functype.results.iter().rev().map(|ty| self.stack.pop_value(*ty)).collect();
+        results.reverse();
+        self.hand_out(&results);
+        results
+    }
+
+    /// Keeps the objects and exceptions `values` refer to alive while the host holds them.
+    fn hand_out(&mut self, values: &[Value]) {
+        for value in values {
+            if let Some(raw) = held_ref(*value) {
+                *self.host_refs.entry(raw).or_default() += 1;
+            }
+        }
+    }
+
+    /// Tells the VM the host no longer holds `value`, which it was given as a result, an
+    /// argument of a host function or in an uncaught exception. Every object or exception
+    /// reference the host is given keeps what it refers to alive until it is released as
+    /// many times.
+    pub fn release(&mut self, value: Value) {
+        let Some(raw) = held_ref(value) else {
+            return;
+        };
+        if let Some(count) = self.host_refs.get_mut(&raw) {
+            *count -= 1;
+            if *count == 0 {
+                self.host_refs.remove(&raw);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        while let Some(frame) = self.frames.last() {
+            let func = frame.func;
+            self.with_hook(|hook, vm| hook.exit(vm, func));
+            self.frames.pop();
+        }
+        self.stack.clear();
+        self.frames.clear();
+        self.labels.clear();
+        self.suspended = None;
+        self.skip_hook = false;
+    }
 
+    fn execute(&mut self) -> Result<(), Trap> {
         macro_rules! op_cmp {
             ($pop_func: ident, $op: tt) => {{
-                let rhs = self.$pop_func();
-                let lhs = self.$pop_func();
+                let rhs = self.stack.$pop_func();
+                let lhs = self.stack.$pop_func();
                 let result = if lhs $op rhs { 1i32 } else { 0i32 };
-                self.push_i32(result);
+                self.stack.push_i32(result);
             }};
             ($pop_func: ident $op: tt $rhs: expr) => {{
-                let lhs = self.$pop_func();
+                let lhs = self.stack.$pop_func();
                 let result = if lhs $op $rhs { 1i32 } else { 0i32 };
-                self.push_i32(result);
+                self.stack.push_i32(result);
+            }};
+        }
+        macro_rules! op_unary {
+            ($pop_func: ident, $push_func: ident, $func: ident) => {{
+                let v = self.stack.$pop_func();
+                self.stack.$push_func(v.$func() as _);
             }};
         }
         macro_rules! op_binary_simple {
             ($pop_func: ident, $push_func: ident, $op: tt) => {{
-                let rhs = self.$pop_func();
-                let lhs = self.$pop_func();
-                self.$push_func(lhs $op rhs);
+                let rhs = self.stack.$pop_func();
+                let lhs = self.stack.$pop_func();
+                self.stack.$push_func(lhs $op rhs);
+            }};
+        }
+        macro_rules! op_binary {
+            ($pop_func: ident, $push_func: ident, $func: ident) => {{
+                let rhs = self.stack.$pop_func();
+                let lhs = self.stack.$pop_func();
+                self.stack.$push_func(lhs.$func(rhs as _));
+            }};
+        }
+        macro_rules! op_load {
+            ($n: expr, $push_func: ident, $t: ty) => {{
+                let bytes = self.load::<$n>()?;
+                self.stack.$push_func(<$t>::from_le_bytes(bytes) as _);
+            }};
+        }
+        macro_rules! op_store {
+            ($pop_func: ident, $t: ty) => {{
+                let value = self.stack.$pop_func() as $t;
+                self.store(value.to_le_bytes())?;
+            }};
+        }
+        macro_rules! op_div {
+            ($pop_func: ident, $push_func: ident, $func: ident) => {{
+                let rhs = self.stack.$pop_func();
+                let lhs = self.stack.$pop_func();
+                if rhs == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                match lhs.$func(rhs) {
+                    Some(v) => self.stack.$push_func(v),
+                    None => return Err(Trap::IntegerOverflow),
+                }
             }};
         }
 
-        self.stack.clear();
-
-        loop {
-            {
-                // Stack Debugging
-                for v in &self.stack {
-                    print!("[{v:?}]");
+        while self.frames.len() > self.base_depth {
+            if self.hook.is_some() && !std::mem::take(&mut self.skip_hook) {
+                let location = Location {
+                    func: self.frames.last().unwrap().func,
+                    offset: self.ip,
+                };
+                let action = self.with_hook(|hook, vm| hook.instruction(vm, location));
+                if let Some(HookAction::Pause) = action {
+                    return Err(Trap::Paused);
                 }
-                print!("\n");
             }
 
-            if self.ip >= self.code.len() {
-                break;
+            let op = self.read_byte();
+            if let Some(fuel) = self.fuel {
+                let cost = self.cost(op);
+                if fuel < cost {
+                    self.ip -= 1;
+                    return Err(Trap::OutOfFuel);
+                }
+                self.fuel = Some(fuel - cost);
             }
 
-            match self.read_byte() {
-                //Control Flow
+            match op {
+                // Control Flow
+                UNREACHABLE => return Err(Trap::Unreachable),
                 NOP => {}
-                END => {}
+                BLOCK | LOOP => {
+                    let block = self.block();
+                    self.enter_block(op == LOOP, block);
+                }
+                IF => {
+                    let block = self.block();
+                    let condition = self.stack.pop_i32();
+                    self.enter_block(false, block);
+                    if condition == 0 {
+                        // Without an else branch, skip straight to the end to pop the label
+                        self.ip = block.else_.map_or(block.end, |e| e + 1);
+                    }
+                }
+                ELSE => {
+                    // Reached the end of the then branch; skip over the else branch
+                    let label = self.labels.pop().unwrap();
+                    self.ip = label.target;
+                }
+                END => {
+                    if self.labels.len() > self.frames.last().unwrap().labels {
+                        self.labels.pop();
+                    } else {
+                        self.return_from_function();
+                    }
+                }
+                BR => {
+                    let depth = self.read_size();
+                    self.branch(depth)?;
+                }
+                BR_IF => {
+                    let depth = self.read_size();
+                    if self.stack.pop_i32() != 0 {
+                        self.branch(depth)?;
+                    }
+                }
+                BR_TABLE => {
+                    let num_labels = self.read_size();
+                    let idx = self.stack.pop_u32() as usize;
+                    let mut depth = 0;
+                    for i in 0..=num_labels {
+                        let label = self.read_size();
+                        if i == idx.min(num_labels) {
+                            depth = label;
+                            break;
+                        }
+                    }
+                    self.branch(depth)?;
+                }
+                TRY_TABLE => {
+                    let block = self.block();
+                    self.enter_block(false, block);
+                }
+                THROW => {
+                    let tag = self.read_size();
+                    let module = self.module;
+                    let params = &module.func_type(module.tags[tag]).params;
+                    let mut payload: Vec<_> =
+                        params.iter().rev().map(|ty| self.stack.pop_value(*ty)).collect();
+                    payload.reverse();
+                    self.throw(Exception { tag, payload }, None)?;
+                }
+                THROW_REF => {
+                    let raw = self.stack.pop_raw(ValType::EXNREF);
+                    if raw == stack::NULL_REF {
+                        return Err(Trap::NullReference);
+                    }
+                    let exception = self.exceptions.get(raw as usize).cloned().flatten();
+                    let Some(exception) = exception else {
+                        return Err(Trap::DanglingReference);
+                    };
+                    self.throw(exception, Some(raw as u32))?;
+                }
+                RETURN => self.return_from_function(),
+                CALL => {
+                    let func_idx = self.read_size();
+                    self.call(func_idx)?;
+                }
+                CALL_INDIRECT => {
+                    let func_idx = self.indirect_callee()?;
+                    self.call(func_idx)?;
+                }
+                RETURN_CALL => {
+                    let func_idx = self.read_size();
+                    self.return_call(func_idx)?;
+                }
+                RETURN_CALL_INDIRECT => {
+                    let func_idx = self.indirect_callee()?;
+                    self.return_call(func_idx)?;
+                }
+                CALL_REF => {
+                    let func_idx = self.ref_callee()?;
+                    self.call(func_idx)?;
+                }
+                RETURN_CALL_REF => {
+                    let func_idx = self.ref_callee()?;
+                    self.return_call(func_idx)?;
+                }
+                BR_ON_NULL => {
+                    let depth = self.read_size();
+                    if self.stack.get(self.stack.len() - 1) == stack::NULL_REF {
+                        self.stack.pop_any();
+                        self.branch(depth)?;
+                    }
+                }
+                BR_ON_NON_NULL => {
+                    let depth = self.read_size();
+                    if self.stack.get(self.stack.len() - 1) == stack::NULL_REF {
+                        self.stack.pop_any();
+                    } else {
+                        self.branch(depth)?;
+                    }
+                }
                 DROP => {
-                    self.stack.pop().expect("Empty stack");
+                    let width = self.operand_width();
+                    self.stack.unwind(self.stack.len() - width, 0);
+                }
+                SELECT | SELECT_T => {
+                    let width = if op == SELECT_T {
+                        self.read_size();       // number of types, always 1
+                        self.read_val_type().slots()
+                    } else {
+                        self.operand_width()
+                    };
+                    if self.stack.pop_i32() != 0 {
+                        self.stack.unwind(self.stack.len() - width, 0);
+                    } else {
+                        self.stack.unwind(self.stack.len() - 2 * width, width);
+                    }
+                }
+                // Variables
+                GLOBAL_GET => {
+                    let idx = self.read_size();
+                    let ty = self.module.globals[idx].ty.ty;
+                    self.stack.push_typed(self.globals[idx], ty);
+                }
+                GLOBAL_SET => {
+                    let idx = self.read_size();
+                    let ty = self.module.globals[idx].ty.ty;
+                    self.globals[idx] = self.stack.pop_typed(ty);
+                }
+                TABLE_GET => {
+                    let table_idx = self.read_size();
+                    let idx = self.stack.pop_u32();
+                    let table = &self.tables[table_idx];
+                    let raw = table.get(idx).ok_or(Trap::TableOutOfBounds)?;
+                    self.stack.push_raw(raw, ValType::from(table.elem));
+                }
+                TABLE_SET => {
+                    let table_idx = self.read_size();
+                    let table = &mut self.tables[table_idx];
+                    let raw = self.stack.pop_raw(ValType::from(table.elem));
+                    let idx = self.stack.pop_u32();
+                    if !table.set(idx, raw) {
+                        return Err(Trap::TableOutOfBounds);
+                    }
+                }
+                LOCAL_GET => {
+                    let (slot, width) = self.local_slot();
+                    for i in 0..width {
+                        self.stack.push_copy(slot + i);
+                    }
+                }
+                LOCAL_SET => {
+                    let (slot, width) = self.local_slot();
+                    for i in (0..width).rev() {
+                        self.stack.pop_into(slot + i);
+                    }
+                }
+                LOCAL_TEE => {
+                    let (slot, width) = self.local_slot();
+                    let top = self.stack.len() - width;
+                    for i in 0..width {
+                        self.stack.copy_into(top + i, slot + i);
+                    }
+                }
+                // Memory
+                I32_LOAD => op_load!(4, push_i32, i32),
+                I64_LOAD => op_load!(8, push_i64, i64),
+                F32_LOAD => op_load!(4, push_f32, f32),
+                F64_LOAD => op_load!(8, push_f64, f64),
+                I32_LOAD8_S => op_load!(1, push_i32, i8),
+                I32_LOAD8_U => op_load!(1, push_i32, u8),
+                I32_LOAD16_S => op_load!(2, push_i32, i16),
+                I32_LOAD16_U => op_load!(2, push_i32, u16),
+                I64_LOAD8_S => op_load!(1, push_i64, i8),
+                I64_LOAD8_U => op_load!(1, push_i64, u8),
+                I64_LOAD16_S => op_load!(2, push_i64, i16),
+                I64_LOAD16_U => op_load!(2, push_i64, u16),
+                I64_LOAD32_S => op_load!(4, push_i64, i32),
+                I64_LOAD32_U => op_load!(4, push_i64, u32),
+                I32_STORE => op_store!(pop_i32, i32),
+                I64_STORE => op_store!(pop_i64, i64),
+                F32_STORE => op_store!(pop_f32, f32),
+                F64_STORE => op_store!(pop_f64, f64),
+                I32_STORE8 => op_store!(pop_i32, u8),
+                I32_STORE16 => op_store!(pop_i32, u16),
+                I64_STORE8 => op_store!(pop_i64, u8),
+                I64_STORE16 => op_store!(pop_i64, u16),
+                I64_STORE32 => op_store!(pop_i64, u32),
+                MEMORY_SIZE => {
+                    let mem = self.read_size();
+                    let size = self.memories[mem].size();
+                    self.push_address(mem, size);
+                }
+                MEMORY_GROW => {
+                    let mem = self.read_size();
+                    let delta = self.pop_address(mem);
+                    let result = self.grow_memory(mem, delta);
+                    self.push_address(mem, result.unwrap_or(u64::MAX));
                 }
                 // Constants
                 I32_CONST => {
                     let value = self.read_i32();
-                    self.push_i32(value);
+                    self.stack.push_i32(value);
                 }
                 I64_CONST => {
                     let value = self.read_i64();
-                    self.push_i64(value);
+                    self.stack.push_i64(value);
                 }
                 F32_CONST => {
                     let value = self.read_f32();
-                    self.push_f32(value);
+                    self.stack.push_f32(value);
                 }
                 F64_CONST => {
                     let value = self.read_f64();
-                    self.push_f64(value);
+                    self.stack.push_f64(value);
                 }
                 // Comparisons
                 I32_EQZ => op_cmp!(pop_i32 == 0),
@@ -104,7 +1003,7 @@ impl<'a> Vm<'a> {
                 I32_LT_U => op_cmp!(pop_u32, <),
                 I32_GT_S => op_cmp!(pop_i32, >),
                 I32_GT_U => op_cmp!(pop_u32, >),
-                // I32_LE_S => op_cmp!(pop_i32, <=),
+                I32_LE_S => op_cmp!(pop_i32, <=),
                 I32_LE_U => op_cmp!(pop_u32, <=),
                 I32_GE_S => op_cmp!(pop_i32, >=),
                 I32_GE_U => op_cmp!(pop_u32, >=),
@@ -115,7 +1014,7 @@ impl<'a> Vm<'a> {
                 I64_LT_U => op_cmp!(pop_u64, <),
                 I64_GT_S => op_cmp!(pop_i64, >),
                 I64_GT_U => op_cmp!(pop_u64, >),
-                // I64_LE_S => op_cmp!(pop_i64, <=),
+                I64_LE_S => op_cmp!(pop_i64, <=),
                 I64_LE_U => op_cmp!(pop_u64, <=),
                 I64_GE_S => op_cmp!(pop_i64, >=),
                 I64_GE_U => op_cmp!(pop_u64, >=),
@@ -132,117 +1031,2667 @@ impl<'a> Vm<'a> {
                 F64_LE => op_cmp!(pop_f64, <=),
                 F64_GE => op_cmp!(pop_f64, >=),
                 // i32 Arithmetic
-                I32_ADD => op_binary_simple!(pop_i32, push_i32, +),
-                I32_SUB => op_binary_simple!(pop_i32, push_i32, -),
-                I32_MUL => op_binary_simple!(pop_i32, push_i32, *),
-                I32_DIV_S => op_binary_simple!(pop_i32, push_i32, /),
-                I32_DIV_U => op_binary_simple!(pop_u32, push_u32, +),
-                I32_REM_S => op_binary_simple!(pop_i32, push_i32, %),
-                I32_REM_U => op_binary_simple!(pop_u32, push_u32, %),
+                I32_CLZ => op_unary!(pop_i32, push_i32, leading_zeros),
+                I32_CTZ => op_unary!(pop_i32, push_i32, trailing_zeros),
+                I32_POPCNT => op_unary!(pop_i32, push_i32, count_ones),
+                I32_ADD => op_binary!(pop_i32, push_i32, wrapping_add),
+                I32_SUB => op_binary!(pop_i32, push_i32, wrapping_sub),
+                I32_MUL => op_binary!(pop_i32, push_i32, wrapping_mul),
+                I32_DIV_S => op_div!(pop_i32, push_i32, checked_div),
+                I32_DIV_U => op_div!(pop_u32, push_u32, checked_div),
+                I32_REM_S => {
+                    // Unlike division, i32::MIN % -1 is defined to be 0
+                    let rhs = self.stack.pop_i32();
+                    let lhs = self.stack.pop_i32();
+                    if rhs == 0 {
+                        return Err(Trap::IntegerDivideByZero);
+                    }
+                    self.stack.push_i32(lhs.wrapping_rem(rhs));
+                }
+                I32_REM_U => op_div!(pop_u32, push_u32, checked_rem),
                 I32_AND => op_binary_simple!(pop_i32, push_i32, &),
                 I32_OR => op_binary_simple!(pop_i32, push_i32, |),
                 I32_XOR => op_binary_simple!(pop_i32, push_i32, ^),
-                I32_SHL => op_binary_simple!(pop_i32, push_i32, <<),
-                I32_SHR_S => op_binary_simple!(pop_i32, push_i32, >>),
-                I32_SHR_U => op_binary_simple!(pop_u32, push_u32, >>),
+                I32_SHL => op_binary!(pop_i32, push_i32, wrapping_shl),
+                I32_SHR_S => op_binary!(pop_i32, push_i32, wrapping_shr),
+                I32_SHR_U => op_binary!(pop_u32, push_u32, wrapping_shr),
+                I32_ROTL => op_binary!(pop_i32, push_i32, rotate_left),
+                I32_ROTR => op_binary!(pop_i32, push_i32, rotate_right),
+                // Reference
+                REF_NULL => {
+                    let (heap, len) = bytecode::read::read_heap_type(self.code, self.ip).unwrap();
+                    self.ip += len;
+                    self.stack.push_raw(stack::NULL_REF, ValType::Ref(RefType::null(heap)));
+                }
+                REF_IS_NULL => {
+                    let is_null = self.stack.pop_any() == stack::NULL_REF;
+                    self.stack.push_i32(is_null as i32);
+                }
+                REF_FUNC => {
+                    let func_idx = self.read_size();
+                    self.stack.push_raw(func_idx as u64, ValType::FUNCREF);
+                }
+                REF_EQ => {
+                    let rhs = self.stack.pop_raw(ValType::ANYREF);
+                    let lhs = self.stack.pop_raw(ValType::ANYREF);
+                    self.stack.push_i32((lhs == rhs) as i32);
+                }
+                REF_AS_NON_NULL => {
+                    if self.stack.get(self.stack.len() - 1) == stack::NULL_REF {
+                        return Err(Trap::NullReference);
+                    }
+                }
+                MISC_PREFIX => self.execute_misc()?,
+                SIMD_PREFIX => self.execute_simd()?,
+                ATOMIC_PREFIX => self.execute_atomic()?,
+                GC_PREFIX => self.execute_gc()?,
                 op => unimplemented!("Instruction {op:#04x} not yet implemented"),
             }
         }
-
-        // If there is a return value it is left on the stack. Print it
-        if let Some(value) = self.stack.pop() {
-            println!("{value:?}");
-        }
+        Ok(())
     }
 
-    fn read_byte(&mut self) -> u8 {
-        self.ip += 1;
-        self.code[self.ip - 1]
+    /// The fuel the instruction `op` starts costs, including what a bulk instruction costs
+    /// for its length, which is on top of the stack.
+    fn cost(&mut self, op: u8) -> u64 {
+        if !matches!(op, MISC_PREFIX | SIMD_PREFIX | ATOMIC_PREFIX | GC_PREFIX) {
+            return self.costs.cost(op);
+        }
+        let ip = self.ip;
+        let sub_op = self.read_size() as u32;
+        self.ip = ip;
+        let len = self.stack.len().checked_sub(1).map_or(0, |top| self.stack.get(top));
+        let bulk = self.costs.bulk_cost(op, sub_op, len);
+        self.costs.prefixed_cost(op, sub_op).saturating_add(bulk)
     }
 
-    fn read_i32(&mut self) -> i32 {
-        let (num, offset) = bytecode::read::read_i32(self.code, self.ip);
-        self.ip += offset;
-        num as i32
+    /// Executes an instruction with the [`MISC_PREFIX`] prefix.
+    fn execute_misc(&mut self) -> Result<(), Trap> {
+        match self.read_size() as u32 {
+            misc::MEMORY_INIT => {
+                let data_idx = self.read_size();
+                let mem = self.read_size();
+                let len = self.stack.pop_u32() as usize;
+                let src = self.stack.pop_u32() as usize;
+                let dst = self.pop_address(mem);
+                let bytes = self.data[data_idx].get(src..src + len);
+                if !bytes.is_some_and(|bytes| self.memories[mem].write(dst, bytes)) {
+                    return Err(Trap::MemoryOutOfBounds);
+                }
+            }
+            misc::DATA_DROP => {
+                let data_idx = self.read_size();
+                self.data[data_idx] = &[];
+            }
+            misc::MEMORY_COPY => {
+                let dst_mem = self.read_size();
+                let src_mem = self.read_size();
+                // The length is i64 only if both memories are 64-bit
+                let len = if self.memories[dst_mem].is_64() && self.memories[src_mem].is_64() {
+                    self.stack.pop_u64()
+                } else {
+                    self.stack.pop_u32() as u64
+                };
+                let src = self.pop_address(src_mem);
+                let dst = self.pop_address(dst_mem);
+                if !self.copy_memory(dst_mem, dst, src_mem, src, len) {
+                    return Err(Trap::MemoryOutOfBounds);
+                }
+            }
+            misc::MEMORY_FILL => {
+                let mem = self.read_size();
+                let len = self.pop_address(mem);
+                let value = self.stack.pop_u32() as u8;
+                let dst = self.pop_address(mem);
+                if !self.memories[mem].fill(dst, value, len) {
+                    return Err(Trap::MemoryOutOfBounds);
+                }
+            }
+            misc::TABLE_INIT => {
+                let elem_idx = self.read_size();
+                let table_idx = self.read_size();
+                let len = self.stack.pop_u32() as usize;
+                let src = self.stack.pop_u32() as usize;
+                let dst = self.stack.pop_u32();
+                let elements = self.elements[elem_idx].get(src..src + len);
+                if !elements.is_some_and(|elements| self.tables[table_idx].write(dst, elements)) {
+                    return Err(Trap::TableOutOfBounds);
+                }
+            }
+            misc::ELEM_DROP => {
+                let elem_idx = self.read_size();
+                self.elements[elem_idx].clear();
+            }
+            misc::TABLE_COPY => {
+                let dst_table = self.read_size();
+                let src_table = self.read_size();
+                let len = self.stack.pop_u32();
+                let src = self.stack.pop_u32();
+                let dst = self.stack.pop_u32();
+                // Copied out first, since the tables may be the same and overlap
+                let elements = self.tables[src_table].slice(src, len).map(<[u64]>::to_vec);
+                if !elements.is_some_and(|elements| self.tables[dst_table].write(dst, &elements)) {
+                    return Err(Trap::TableOutOfBounds);
+                }
+            }
+            misc::TABLE_GROW => {
+                let table_idx = self.read_size();
+                let delta = self.stack.pop_u32();
+                let init = self.stack.pop_raw(ValType::from(self.tables[table_idx].elem));
+                let result = self.grow_table(table_idx, delta, init);
+                self.stack.push_i32(result.map_or(-1, |old| old as i32));
+            }
+            misc::TABLE_SIZE => {
+                let table_idx = self.read_size();
+                self.stack.push_u32(self.tables[table_idx].size());
+            }
+            misc::TABLE_FILL => {
+                let table_idx = self.read_size();
+                let table = &mut self.tables[table_idx];
+                let len = self.stack.pop_u32();
+                let raw = self.stack.pop_raw(ValType::from(table.elem));
+                let start = self.stack.pop_u32();
+                if !table.fill(start, raw, len) {
+                    return Err(Trap::TableOutOfBounds);
+                }
+            }
+            op => unimplemented!("Instruction 0xfc {op} not yet implemented"),
+        }
+        Ok(())
     }
 
-    fn read_i64(&mut self) -> i64 {
-        let (num, offset) = bytecode::read::read_i64(self.code, self.ip);
-        self.ip += offset;
-        num
-    }
+    /// Executes an instruction with the [`SIMD_PREFIX`] prefix.
+    fn execute_simd(&mut self) -> Result<(), Trap> {
+        macro_rules! op_load {
+            ($n: expr, $f: expr) => {{
+                let bytes = self.load::<$n>()?;
+                let mut v = [0; 16];
+                v[..$n].copy_from_slice(&bytes);
+                self.stack.push_v128($f(u128::from_le_bytes(v)));
+            }};
+        }
+        macro_rules! op_extract {
+            ($t: ty, $push_func: ident) => {{
+                let lane = self.read_byte() as usize;
+                let v = self.stack.pop_v128();
+                self.stack.$push_func(v128::lane::<$t>(v, lane) as _);
+            }};
+        }
+        macro_rules! op_replace {
+            ($pop_func: ident, $t: ty) => {{
+                let lane = self.read_byte() as usize;
+                let value = self.stack.$pop_func() as $t;
+                let v = self.stack.pop_v128();
+                self.stack.push_v128(v128::replace_lane(v, lane, value));
+            }};
+        }
+        macro_rules! op_splat {
+            ($pop_func: ident, $t: ty) => {{
+                let value = self.stack.$pop_func() as $t;
+                self.stack.push_v128(v128::splat(value));
+            }};
+        }
 
-    fn read_f32(&mut self) -> f32 {
-        self.ip += 4;
-        bytecode::read::read_f32(self.code, self.ip - 4)
+        let op = self.read_size() as u32;
+        match op {
+            simd::V128_LOAD => op_load!(16, |v| v),
+            simd::V128_LOAD8X8_S => op_load!(8, |v| v128::map(v, |x: i8| x as i16)),
+            simd::V128_LOAD8X8_U => op_load!(8, |v| v128::map(v, |x: u8| x as u16)),
+            simd::V128_LOAD16X4_S => op_load!(8, |v| v128::map(v, |x: i16| x as i32)),
+            simd::V128_LOAD16X4_U => op_load!(8, |v| v128::map(v, |x: u16| x as u32)),
+            simd::V128_LOAD32X2_S => op_load!(8, |v| v128::map(v, |x: i32| x as i64)),
+            simd::V128_LOAD32X2_U => op_load!(8, |v| v128::map(v, |x: u32| x as u64)),
+            simd::V128_LOAD8_SPLAT => op_load!(1, |v| v128::splat(v as u8)),
+            simd::V128_LOAD16_SPLAT => op_load!(2, |v| v128::splat(v as u16)),
+            simd::V128_LOAD32_SPLAT => op_load!(4, |v| v128::splat(v as u32)),
+            simd::V128_LOAD64_SPLAT => op_load!(8, |v| v128::splat(v as u64)),
+            simd::V128_LOAD32_ZERO => op_load!(4, |v| v),
+            simd::V128_LOAD64_ZERO => op_load!(8, |v| v),
+            simd::V128_STORE => {
+                let v = self.stack.pop_v128();
+                self.store(v.to_le_bytes())?;
+            }
+            simd::V128_LOAD8_LANE => self.load_lane::<1>()?,
+            simd::V128_LOAD16_LANE => self.load_lane::<2>()?,
+            simd::V128_LOAD32_LANE => self.load_lane::<4>()?,
+            simd::V128_LOAD64_LANE => self.load_lane::<8>()?,
+            simd::V128_STORE8_LANE => self.store_lane::<1>()?,
+            simd::V128_STORE16_LANE => self.store_lane::<2>()?,
+            simd::V128_STORE32_LANE => self.store_lane::<4>()?,
+            simd::V128_STORE64_LANE => self.store_lane::<8>()?,
+            simd::V128_CONST => {
+                let bytes = self.code[self.ip..self.ip + 16].try_into().unwrap();
+                self.ip += 16;
+                self.stack.push_v128(u128::from_le_bytes(bytes));
+            }
+            simd::I8X16_SHUFFLE => {
+                let lanes = self.code[self.ip..self.ip + 16].try_into().unwrap();
+                self.ip += 16;
+                let b = self.stack.pop_v128();
+                let a = self.stack.pop_v128();
+                self.stack.push_v128(v128::shuffle(a, b, lanes));
+            }
+            simd::I8X16_SPLAT => op_splat!(pop_i32, u8),
+            simd::I16X8_SPLAT => op_splat!(pop_i32, u16),
+            simd::I32X4_SPLAT => op_splat!(pop_i32, u32),
+            simd::I64X2_SPLAT => op_splat!(pop_i64, u64),
+            simd::F32X4_SPLAT => op_splat!(pop_f32, f32),
+            simd::F64X2_SPLAT => op_splat!(pop_f64, f64),
+            simd::I8X16_EXTRACT_LANE_S => op_extract!(i8, push_i32),
+            simd::I8X16_EXTRACT_LANE_U => op_extract!(u8, push_i32),
+            simd::I16X8_EXTRACT_LANE_S => op_extract!(i16, push_i32),
+            simd::I16X8_EXTRACT_LANE_U => op_extract!(u16, push_i32),
+            simd::I32X4_EXTRACT_LANE => op_extract!(i32, push_i32),
+            simd::I64X2_EXTRACT_LANE => op_extract!(i64, push_i64),
+            simd::F32X4_EXTRACT_LANE => op_extract!(f32, push_f32),
+            simd::F64X2_EXTRACT_LANE => op_extract!(f64, push_f64),
+            simd::I8X16_REPLACE_LANE => op_replace!(pop_i32, u8),
+            simd::I16X8_REPLACE_LANE => op_replace!(pop_i32, u16),
+            simd::I32X4_REPLACE_LANE => op_replace!(pop_i32, u32),
+            simd::I64X2_REPLACE_LANE => op_replace!(pop_i64, u64),
+            simd::F32X4_REPLACE_LANE => op_replace!(pop_f32, f32),
+            simd::F64X2_REPLACE_LANE => op_replace!(pop_f64, f64),
+            simd::V128_BITSELECT => {
+                let mask = self.stack.pop_v128();
+                let b = self.stack.pop_v128();
+                let a = self.stack.pop_v128();
+                self.stack.push_v128(v128::bitselect(a, b, mask));
+            }
+            op => match v128::kind(op) {
+                Some(Kind::Unary) => {
+                    let v = self.stack.pop_v128();
+                    self.stack.push_v128(v128::unary(op, v));
+                }
+                Some(Kind::Binary) => {
+                    let b = self.stack.pop_v128();
+                    let a = self.stack.pop_v128();
+                    self.stack.push_v128(v128::binary(op, a, b));
+                }
+                Some(Kind::Test) => {
+                    let v = self.stack.pop_v128();
+                    self.stack.push_i32(v128::test(op, v));
+                }
+                Some(Kind::Shift) => {
+                    let amount = self.stack.pop_u32();
+                    let v = self.stack.pop_v128();
+                    self.stack.push_v128(v128::shift(op, v, amount));
+                }
+                _ => unimplemented!("Instruction 0xfd {op} not yet implemented"),
+            },
+        }
+        Ok(())
     }
 
-    fn read_f64(&mut self) -> f64 {
-        self.ip += 8;
-        bytecode::read::read_f64(self.code, self.ip - 4)
-    }
+    fn call(&mut self, func_idx: usize) -> Result<(), Trap> {
+        if self.frames.len() >= self.max_call_depth {
+            return Err(Trap::CallStackExhausted);
+        }
+        let function = &self.module.functions[func_idx];
+        if function.imported {
+            return self.call_host(func_idx);
+        }
+        let num_params = self.module.func_type(function.functype).params.len();
+        let param_slots = function.local_slots[num_params];
+        let local_slots = function.local_slots.last().unwrap() - param_slots;
+        if self.stack.len() + local_slots + function.max_stack > self.max_stack_slots {
+            return Err(Trap::ValueStackExhausted);
+        }
+        self.poll_interrupt()?;
+        if let Some(caller) = self.frames.last_mut() {
+            caller.ip = self.ip;
+        }
 
-    fn pop_u32(&mut self) -> u32 {
-        unsafe { std::mem::transmute(self.pop_i32()) }
-    }
+        let locals = self.stack.len() - param_slots;
+        for ty in &function.locals {
+            self.stack.push_typed(stack::default_raw(*ty), *ty);
+        }
 
-    fn pop_u64(&mut self) -> u64 {
-        unsafe { std::mem::transmute(self.pop_i64()) }
+        self.frames.push(Frame {
+            func: func_idx,
+            ip: 0,
+            locals,
+            labels: self.labels.len(),
+        });
+        self.code = self.module.function_code(func_idx);
+        self.ip = 0;
+        self.with_hook(|hook, vm| hook.enter(vm, func_idx));
+        Ok(())
     }
 
-    fn pop_i32(&mut self) -> i32 {
-        match self.stack.pop() {
-            Some(Value::I32(v)) => v,
-            _ => panic!("Runtime Error: Expected i32"),
+    /// Replaces the arguments of an imported function on the stack with the results of
+    /// its host function.
+    fn call_host(&mut self, func_idx: usize) -> Result<(), Trap> {
+        self.poll_interrupt()?;
+        let functype = self.module.func_type(self.module.functions[func_idx].functype);
+        let mut args: Vec<_> =
+            functype.params.iter().rev().map(|ty| self.stack.pop_value(*ty)).collect();
+        args.reverse();
+        if let Some(caller) = self.frames.last_mut() {
+            caller.ip = self.ip;
         }
-    }
 
-    fn pop_i64(&mut self) -> i64 {
-        match self.stack.pop() {
-            Some(Value::I64(v)) => v,
-            _ => panic!("Runtime Error: Expected i64"),
+        self.hand_out(&args);
+        let host = self.host_functions[func_idx].clone();
+        self.host_calls += 1;
+        let results = host(self, &args);
+        self.host_calls -= 1;
+        let results = results?;
+
+        let matching = results.len() == functype.results.len()
+            && results.iter().zip(&functype.results)
+                .all(|(result, ty)| value_matches(self.module, &self.heap, &self.exceptions, *result, *ty));
+        if !matching {
+            return Err(Trap::Host(format!(
+                "Function {func_idx} returned {results:?}, expected {:?}",
+                functype.results
+            )));
         }
+        for result in results {
+            self.stack.push_value(result);
+        }
+        // The host may have called back in, which left the code of another function
+        if let Some(caller) = self.frames.last() {
+            self.code = self.module.function_code(caller.func);
+            self.ip = caller.ip;
+        }
+        Ok(())
     }
 
-    fn pop_f32(&mut self) -> f32 {
-        match self.stack.pop() {
-            Some(Value::F32(v)) => v,
-            _ => panic!("Runtime Error: Expected f32"),
+    /// Replaces the current frame with a call to `func_idx`, so the call stack doesn't
+    /// grow. The arguments take the place of the current frame's locals.
+    fn return_call(&mut self, func_idx: usize) -> Result<(), Trap> {
+        if self.module.functions[func_idx].imported {
+            // The host's results become the current function's
+            self.call_host(func_idx)?;
+            self.return_from_function();
+            return Ok(());
         }
-    }
+        let func = self.frames.last().unwrap().func;
+        self.with_hook(|hook, vm| hook.exit(vm, func));
+        let frame = self.frames.pop().unwrap();
+        let functype = self.module.functions[func_idx].functype;
+        let param_slots = slot_count(&self.module.func_type(functype).params);
+        self.stack.unwind(frame.locals, param_slots);
+        self.labels.truncate(frame.labels);
 
-    fn pop_f64(&mut self) -> f64 {
-        match self.stack.pop() {
-            Some(Value::F64(v)) => v,
-            _ => panic!("Runtime Error: Expected f64"),
+        // The caller's saved ip is written back when the callee's frame is pushed
+        if let Some(caller) = self.frames.last() {
+            self.ip = caller.ip;
         }
+        self.call(func_idx)
     }
 
-    fn push(&mut self, value: Value) {
-        self.stack.push(value);
-    }
+    /// Unwinds to the innermost `try_table` that catches the exception, leaving call
+    /// frames as needed, and branches to the label of the clause that caught it.
+    /// `exnref` is the exception's existing reference if `throw_ref` rethrew it.
+    fn throw(&mut self, exception: Exception, exnref: Option<u32>) -> Result<(), Trap> {
+        while self.frames.len() > self.base_depth {
+            let frame = self.frames.last().unwrap();
+            let (func, frame_labels) = (frame.func, frame.labels);
+            while self.labels.len() > frame_labels {
+                let label = self.labels.pop().unwrap();
+                let tag = exception.tag;
+                let clause = label.catches.and_then(|offset| self.find_catch(offset, tag));
+                let Some((kind, depth)) = clause else {
+                    continue;
+                };
+                self.stack.unwind(label.height, 0);
+                if kind == catch::CATCH || kind == catch::CATCH_REF {
+                    for value in &exception.payload {
+                        self.stack.push_value(*value);
+                    }
+                }
+                if kind == catch::CATCH_REF || kind == catch::CATCH_ALL_REF {
+                    let idx = exnref.unwrap_or_else(|| self.add_exception(exception.clone()));
+                    self.stack.push_raw(idx as u64, ValType::EXNREF);
+                }
+                return self.branch(depth);
+            }
+
+            self.with_hook(|hook, vm| hook.exit(vm, func));
+            let frame = self.frames.pop().unwrap();
+            self.stack.unwind(frame.locals, 0);
+            if let Some(caller) = self.frames.last() {
+                self.code = self.module.function_code(caller.func);
+            }
+        }
+        // The host gets the payload with the trap
+        self.hand_out(&exception.payload);
+        Err(Trap::UncaughtException(exception))
+    }
+
+    /// Stores a caught exception, returning its `exnref`. The table is swept whenever it
+    /// would grow to a power of two, so catching in a loop doesn't grow it without bound.
+    fn add_exception(&mut self, exception: Exception) -> u32 {
+        let len = self.exceptions.len();
+        if self.free_exceptions.is_empty() && len >= 64 && len.is_power_of_two() {
+            self.collect_exceptions(&exception.payload);
+        }
+        match self.free_exceptions.pop() {
+            Some(idx) => {
+                self.exceptions[idx as usize] = Some(exception);
+                idx
+            }
+            None => {
+                self.exceptions.push(Some(exception));
+                (self.exceptions.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Frees the exceptions no `exnref` refers to, other than those in `held`. Like the
+    /// garbage collector it can't tell an `exnref` from other values, so anything that
+    /// could be one keeps its exception, and so does the payload of every exception kept.
+    fn collect_exceptions(&mut self, held: &[Value]) {
+        let mut marked = vec![false; self.exceptions.len()];
+        let fields = self.heap.fields().copied();
+        let mut pending: Vec<usize> = self.roots().chain(fields)
+            .filter_map(|raw| usize::try_from(raw).ok().filter(|idx| *idx < marked.len()))
+            .chain(held.iter().filter_map(exnref_index))
+            .collect();
+        while let Some(idx) = pending.pop() {
+            let Some(Some(exception)) = self.exceptions.get(idx) else {
+                continue;
+            };
+            if !std::mem::replace(&mut marked[idx], true) {
+                pending.extend(exception.payload.iter().filter_map(exnref_index));
+            }
+        }
+
+        for (idx, (exception, marked)) in self.exceptions.iter_mut().zip(marked).enumerate() {
+            if exception.is_some() && !marked {
+                *exception = None;
+                self.free_exceptions.push(idx as u32);
+            }
+        }
+        if self.free_exceptions.len() == self.exceptions.len() {
+            self.exceptions.clear();
+            self.free_exceptions.clear();
+        }
+    }
+
+    /// The first of the catch clauses at `offset` that catches exceptions with `tag`, as
+    /// its kind and label.
+    fn find_catch(&self, offset: usize, tag: usize) -> Option<(u8, usize)> {
+        let read_size = |ip: &mut usize| {
+            let (num, len) = bytecode::read::read_size(self.code, *ip);
+            *ip += len;
+            num as usize
+        };
+        let mut ip = offset;
+        for _ in 0..read_size(&mut ip) {
+            let kind = self.code[ip];
+            ip += 1;
+            let clause_tag = match kind {
+                catch::CATCH | catch::CATCH_REF => Some(read_size(&mut ip)),
+                _ => None,
+            };
+            let depth = read_size(&mut ip);
+            if clause_tag.is_none_or(|clause_tag| clause_tag == tag) {
+                return Some((kind, depth));
+            }
+        }
+        None
+    }
+
+    /// Reads a `call_indirect`'s immediates and operand and returns the function to
+    /// call, checking it has the expected type.
+    fn indirect_callee(&mut self) -> Result<usize, Trap> {
+        let type_idx = self.read_size();
+        let table_idx = self.read_size();
+        let idx = self.stack.pop_u32();
+        let raw = self.tables[table_idx].get(idx).ok_or(Trap::UndefinedElement)?;
+        if raw == stack::NULL_REF {
+            return Err(Trap::UninitializedElement);
+        }
+        let func_idx = raw as usize;
+        let functype = self.module.functions[func_idx].functype;
+        let actual = HeapType::Concrete(functype as u32);
+        if !self.module.heap_matches(actual, HeapType::Concrete(type_idx as u32)) {
+            return Err(Trap::IndirectCallTypeMismatch);
+        }
+        Ok(func_idx)
+    }
+
+    /// Reads a `call_ref`'s type immediate and pops the function to call, which the
+    /// validator has checked is of that type.
+    fn ref_callee(&mut self) -> Result<usize, Trap> {
+        self.read_size();
+        let raw = self.stack.pop_raw(ValType::FUNCREF);
+        if raw == stack::NULL_REF {
+            return Err(Trap::NullReference);
+        }
+        Ok(raw as usize)
+    }
+
+    fn return_from_function(&mut self) {
+        let func = self.frames.last().unwrap().func;
+        self.with_hook(|hook, vm| hook.exit(vm, func));
+        let frame = self.frames.pop().unwrap();
+        let functype = self.module.functions[frame.func].functype;
+        let arity = slot_count(&self.module.func_type(functype).results);
+        self.stack.unwind(frame.locals, arity);
+        self.labels.truncate(frame.labels);
+
+        if let Some(caller) = self.frames.last() {
+            self.code = self.module.function_code(caller.func);
+            self.ip = caller.ip;
+        }
+    }
+
+    /// Looks up the block whose opcode was just read, leaving `ip` at its body.
+    fn block(&mut self) -> Block {
+        let frame = self.frames.last().unwrap();
+        let block = self.module.functions[frame.func].blocks[&(self.ip - 1)];
+        self.ip = block.body;
+        block
+    }
+
+    fn enter_block(&mut self, is_loop: bool, block: Block) {
+        let (arity, target) = if is_loop {
+            (block.params, block.body)
+        } else {
+            (block.results, block.end + 1)
+        };
+        self.labels.push(Label {
+            height: self.stack.len() - block.params,
+            arity,
+            target,
+            is_loop,
+            catches: block.catches,
+        });
+    }
+
+    fn branch(&mut self, depth: usize) -> Result<(), Trap> {
+        let frame_labels = self.frames.last().unwrap().labels;
+        if depth >= self.labels.len() - frame_labels {
+            // Branching to the function's own label is a return
+            self.return_from_function();
+            return Ok(());
+        }
+
+        let idx = self.labels.len() - 1 - depth;
+        let label = &self.labels[idx];
+        self.stack.unwind(label.height, label.arity);
+        self.ip = label.target;
+        if label.is_loop {
+            self.labels.truncate(idx + 1);
+            self.poll_interrupt()
+        } else {
+            self.labels.truncate(idx);
+            Ok(())
+        }
+    }
+
+    #[inline]
+    fn poll_interrupt(&mut self) -> Result<(), Trap> {
+        if self.interrupt.take() {
+            return Err(Trap::Interrupted);
+        }
+        if let Some(deadline) = self.deadline {
+            self.polls = self.polls.wrapping_add(1);
+            if self.polls.is_multiple_of(DEADLINE_POLL_INTERVAL) && Instant::now() >= deadline {
+                return Err(Trap::Interrupted);
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the hook, if there is one, with the hook temporarily taken out of the VM.
+    #[inline]
+    fn with_hook<R>(&mut self, f: impl FnOnce(&mut dyn Hook, &Self) -> R) -> Option<R> {
+        let mut hook = self.hook.take()?;
+        let result = f(hook.as_mut(), self);
+        self.hook = Some(hook);
+        Some(result)
+    }
+
+    /// Grows a memory, returning its previous size in pages, or `None` if the growth
+    /// exceeds its maximum or is denied by the limiter.
+    fn grow_memory(&mut self, idx: usize, delta: u64) -> Option<u64> {
+        let memory = &mut self.memories[idx];
+        let desired = memory.size().checked_add(delta)?;
+        if desired > memory.max_pages() {
+            return None;
+        }
+        if let Some(limiter) = &self.limiter {
+            let max = memory.max().map(pages_to_bytes);
+            if !limiter.memory_growing(memory.len(), pages_to_bytes(desired), max) {
+                return None;
+            }
+        }
+        memory.grow(delta)
+    }
+
+    /// Copies `len` bytes from `src` in memory `src_mem` to `dst` in memory `dst_mem`,
+    /// returning `false`, writing nothing, if either range is out of bounds.
+    fn copy_memory(
+        &mut self,
+        dst_mem: usize,
+        dst: u64,
+        src_mem: usize,
+        src: u64,
+        len: u64,
+    ) -> bool {
+        if dst_mem == src_mem {
+            return self.memories[dst_mem].copy(dst, src, len);
+        }
+        let bytes = self.memories[src_mem].read(src, len);
+        bytes.is_some_and(|bytes| self.memories[dst_mem].write(dst, &bytes))
+    }
+
+    /// Grows a table, returning its previous size, or `None` if the growth exceeds its
+    /// maximum or is denied by the limiter.
+    fn grow_table(&mut self, idx: usize, delta: u32, init: u64) -> Option<u32> {
+        let table = &mut self.tables[idx];
+        let desired = table.size().checked_add(delta)?;
+        if let Some(limiter) = &self.limiter {
+            if !limiter.table_growing(table.size(), desired, table.max()) {
+                return None;
+            }
+        }
+        table.grow(delta, init)
+    }
+
+    /// Executes an instruction with the [`GC_PREFIX`] prefix.
+    fn execute_gc(&mut self) -> Result<(), Trap> {
+        let module = self.module;
+        let op = self.read_size() as u32;
+        match op {
+            gc::STRUCT_NEW | gc::STRUCT_NEW_DEFAULT => {
+                let type_idx = self.read_size();
+                self.collect_if_needed();
+                let fields = module.struct_type(type_idx).unwrap();
+                let mut slots = objects::slots(fields.len()).ok_or(Trap::HeapExhausted)?;
+                if op == gc::STRUCT_NEW {
+                    for field in fields.iter().rev() {
+                        slots.push(self.pop_field(field));
+                    }
+                    slots.reverse();
+                } else {
+                    slots.extend(fields.iter().map(default_field));
+                }
+                self.push_object(type_idx, slots);
+            }
+            gc::STRUCT_GET | gc::STRUCT_GET_S | gc::STRUCT_GET_U => {
+                let type_idx = self.read_size();
+                let field_idx = self.read_size();
+                let field = &module.struct_type(type_idx).unwrap()[field_idx];
+                let object = self.pop_object()?;
+                let raw = self.heap.get(object).fields[field_idx];
+                self.push_field(field, raw, op == gc::STRUCT_GET_S);
+            }
+            gc::STRUCT_SET => {
+                let type_idx = self.read_size();
+                let field_idx = self.read_size();
+                let field = &module.struct_type(type_idx).unwrap()[field_idx];
+                let raw = self.pop_field(field);
+                let object = self.pop_object()?;
+                self.heap.get_mut(object).fields[field_idx] = raw;
+            }
+            gc::ARRAY_NEW | gc::ARRAY_NEW_DEFAULT => {
+                let type_idx = self.read_size();
+                self.collect_if_needed();
+                let elem = module.array_type(type_idx).unwrap();
+                let len = self.stack.pop_u32() as usize;
+                let raw = match op {
+                    gc::ARRAY_NEW => self.pop_field(elem),
+                    _ => default_field(elem),
+                };
+                let mut slots = objects::slots(len).ok_or(Trap::HeapExhausted)?;
+                slots.resize(len, raw);
+                self.push_object(type_idx, slots);
+            }
+            gc::ARRAY_NEW_FIXED => {
+                let type_idx = self.read_size();
+                let len = self.read_size();
+                self.collect_if_needed();
+                let elem = module.array_type(type_idx).unwrap();
+                let mut slots = objects::slots(len).ok_or(Trap::HeapExhausted)?;
+                for _ in 0..len {
+                    slots.push(self.pop_field(elem));
+                }
+                slots.reverse();
+                self.push_object(type_idx, slots);
+            }
+            gc::ARRAY_NEW_DATA | gc::ARRAY_NEW_ELEM => {
+                let type_idx = self.read_size();
+                let segment = self.read_size();
+                self.collect_if_needed();
+                let len = self.stack.pop_u32() as usize;
+                let offset = self.stack.pop_u32() as usize;
+                let elements = if op == gc::ARRAY_NEW_DATA {
+                    let elem = module.array_type(type_idx).unwrap();
+                    self.data_elements(segment, elem, offset, len)?
+                } else {
+                    self.segment_elements(segment, offset, len)?
+                };
+                let mut slots = objects::slots(len).ok_or(Trap::HeapExhausted)?;
+                slots.extend(elements);
+                self.push_object(type_idx, slots);
+            }
+            gc::ARRAY_GET | gc::ARRAY_GET_S | gc::ARRAY_GET_U => {
+                let elem = module.array_type(self.read_size()).unwrap();
+                let idx = self.stack.pop_u32() as usize;
+                let object = self.pop_object()?;
+                let raw = *self.heap.get(object).fields.get(idx).ok_or(Trap::ArrayOutOfBounds)?;
+                self.push_field(elem, raw, op == gc::ARRAY_GET_S);
+            }
+            gc::ARRAY_SET => {
+                let elem = module.array_type(self.read_size()).unwrap();
+                let raw = self.pop_field(elem);
+                let idx = self.stack.pop_u32() as usize;
+                let object = self.pop_object()?;
+                let slot = self.heap.get_mut(object).fields.get_mut(idx);
+                *slot.ok_or(Trap::ArrayOutOfBounds)? = raw;
+            }
+            gc::ARRAY_LEN => {
+                let object = self.pop_object()?;
+                self.stack.push_u32(self.heap.get(object).fields.len() as u32);
+            }
+            gc::ARRAY_FILL => {
+                let elem = module.array_type(self.read_size()).unwrap();
+                let len = self.stack.pop_u32() as usize;
+                let raw = self.pop_field(elem);
+                let offset = self.stack.pop_u32() as usize;
+                let object = self.pop_object()?;
+                let fields = &mut self.heap.get_mut(object).fields;
+                fields.get_mut(offset..offset + len).ok_or(Trap::ArrayOutOfBounds)?.fill(raw);
+            }
+            gc::ARRAY_COPY => {
+                self.read_size();
+                self.read_size();
+                let len = self.stack.pop_u32() as usize;
+                let src_offset = self.stack.pop_u32() as usize;
+                let src = self.pop_object()?;
+                let dst_offset = self.stack.pop_u32() as usize;
+                let dst = self.pop_object()?;
+                // Copied out first, since the arrays may be the same and overlap
+                let src_fields = &self.heap.get(src).fields;
+                let elements = src_fields.get(src_offset..src_offset + len).map(<[u128]>::to_vec);
+                let dst_fields = &mut self.heap.get_mut(dst).fields;
+                let dst_range = dst_fields.get_mut(dst_offset..dst_offset + len);
+                let (Some(elements), Some(dst_range)) = (elements, dst_range) else {
+                    return Err(Trap::ArrayOutOfBounds);
+                };
+                dst_range.copy_from_slice(&elements);
+            }
+            gc::ARRAY_INIT_DATA | gc::ARRAY_INIT_ELEM => {
+                let elem = module.array_type(self.read_size()).unwrap();
+                let segment = self.read_size();
+                let len = self.stack.pop_u32() as usize;
+                let src = self.stack.pop_u32() as usize;
+                let dst = self.stack.pop_u32() as usize;
+                let object = self.pop_object()?;
+                if self.heap.get(object).fields.len() < dst + len {
+                    return Err(Trap::ArrayOutOfBounds);
+                }
+                let elements = if op == gc::ARRAY_INIT_DATA {
+                    self.data_elements(segment, elem, src, len)?
+                } else {
+                    self.segment_elements(segment, src, len)?
+                };
+                let fields = &mut self.heap.get_mut(object).fields;
+                for (slot, raw) in fields[dst..dst + len].iter_mut().zip(elements) {
+                    *slot = raw;
+                }
+            }
+            gc::REF_TEST | gc::REF_TEST_NULL | gc::REF_CAST | gc::REF_CAST_NULL => {
+                let (heap, len) = bytecode::read::read_heap_type(self.code, self.ip).unwrap();
+                self.ip += len;
+                let nullable = op == gc::REF_TEST_NULL || op == gc::REF_CAST_NULL;
+                let ty = RefType { nullable, heap };
+                let matches = self.ref_matches(self.stack.get(self.stack.len() - 1), ty)?;
+                if op == gc::REF_TEST || op == gc::REF_TEST_NULL {
+                    self.stack.pop_raw(ValType::Ref(ty));
+                    self.stack.push_i32(matches as i32);
+                } else if !matches {
+                    return Err(Trap::CastFailure);
+                }
+            }
+            gc::BR_ON_CAST | gc::BR_ON_CAST_FAIL => {
+                let flags = self.read_byte();
+                let depth = self.read_size();
+                // Only the target type matters at runtime
+                let (_, len) = bytecode::read::read_heap_type(self.code, self.ip).unwrap();
+                self.ip += len;
+                let (heap, len) = bytecode::read::read_heap_type(self.code, self.ip).unwrap();
+                self.ip += len;
+                let ty = RefType { nullable: flags & gc::CAST_TARGET_NULL != 0, heap };
+                let matches = self.ref_matches(self.stack.get(self.stack.len() - 1), ty)?;
+                if matches == (op == gc::BR_ON_CAST) {
+                    self.branch(depth)?;
+                }
+            }
+            gc::ANY_CONVERT_EXTERN => {
+                let raw = self.stack.pop_raw(ValType::EXTERNREF);
+                self.stack.push_raw(objects::convert(raw), ValType::ANYREF);
+            }
+            gc::EXTERN_CONVERT_ANY => {
+                let raw = self.stack.pop_raw(ValType::ANYREF);
+                self.stack.push_raw(objects::convert(raw), ValType::EXTERNREF);
+            }
+            gc::REF_I31 => {
+                let value = self.stack.pop_i32();
+                self.stack.push_raw(objects::i31(value), ValType::ANYREF);
+            }
+            gc::I31_GET_S | gc::I31_GET_U => {
+                let raw = self.stack.pop_raw(ValType::ANYREF);
+                if raw == stack::NULL_REF {
+                    return Err(Trap::NullReference);
+                }
+                self.stack.push_i32(objects::i31_value(raw, op == gc::I31_GET_S));
+            }
+            op => unimplemented!("Instruction 0xfb {op} not yet implemented"),
+        }
+        Ok(())
+    }
+
+    /// Collects garbage if enough objects have been allocated since the last collection.
+    /// Allocating instructions call this before popping their operands, so everything
+    /// wasm can still reach is in a root.
+    fn collect_if_needed(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+        // Payloads of exceptions nothing refers to any more are no roots
+        self.collect_exceptions(&[]);
+        let payloads = self.exceptions.iter().flatten().flat_map(|exception| &exception.payload);
+        let payloads = payloads.map(|value| stack::value_to_raw(*value));
+        let roots: Vec<_> = self.roots().chain(payloads).collect();
+        self.heap.collect(roots);
+    }
+
+    /// The slots wasm or the host can read a reference from, other than objects' fields
+    /// and exception payloads: the stack, globals, tables, element segments and
+    /// references held by the host.
+    fn roots(&self) -> impl Iterator<Item = u128> + '_ {
+        let tables = self.tables.iter().flat_map(|table| table.slice(0, table.size()).unwrap());
+        let elements = self.elements.iter().flatten().chain(tables).map(|raw| *raw as u128);
+        let slots = self.stack.slots().iter().map(|raw| *raw as u128);
+        let host_refs = self.host_refs.keys().map(|raw| *raw as u128);
+        slots.chain(self.globals.iter().copied()).chain(host_refs).chain(elements)
+    }
+
+    fn push_object(&mut self, type_idx: usize, fields: Vec<u128>) {
+        let raw = self.heap.allocate(Object { type_idx: type_idx as u32, fields });
+        self.stack.push_raw(raw, ValType::ANYREF);
+    }
+
+    /// Pops a reference to a struct or array, trapping if it is null.
+    fn pop_object(&mut self) -> Result<u64, Trap> {
+        let raw = self.stack.pop_raw(ValType::ANYREF);
+        if raw == stack::NULL_REF {
+            return Err(Trap::NullReference);
+        }
+        if self.heap.object(raw).is_none() {
+            return Err(Trap::DanglingReference);
+        }
+        Ok(raw)
+    }
+
+    /// Pops the value to store in a field, truncating it if the field is packed.
+    fn pop_field(&mut self, field: &FieldType) -> u128 {
+        let raw = self.stack.pop_typed(field.storage.unpacked());
+        match field.storage {
+            StorageType::I8 => raw & 0xff,
+            StorageType::I16 => raw & 0xffff,
+            StorageType::Val(_) => raw,
+        }
+    }
+
+    /// Pushes the value of a field, sign or zero extending it if the field is packed.
+    fn push_field(&mut self, field: &FieldType, raw: u128, signed: bool) {
+        let value = match (field.storage, signed) {
+            (StorageType::Val(ty), _) => return self.stack.push_typed(raw, ty),
+            (StorageType::I8, true) => raw as i8 as i32,
+            (StorageType::I16, true) => raw as i16 as i32,
+            (_, false) => raw as i32,
+        };
+        self.stack.push_i32(value);
+    }
+
+    /// The elements of an array of `elem` read from a data segment, trapping if they
+    /// are out of its bounds.
+    fn data_elements(
+        &self,
+        segment: usize,
+        elem: &FieldType,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u128>, Trap> {
+        let width = elem.storage.byte_width().unwrap();
+        let end = len.checked_mul(width).and_then(|size| size.checked_add(offset));
+        let bytes = end.and_then(|end| self.data[segment].get(offset..end));
+        let bytes = bytes.ok_or(Trap::MemoryOutOfBounds)?;
+        let element = |chunk: &[u8]| {
+            let mut slot = [0; 16];
+            slot[..width].copy_from_slice(chunk);
+            u128::from_le_bytes(slot)
+        };
+        Ok(bytes.chunks(width).map(element).collect())
+    }
+
+    /// Elements of an element segment, trapping if they are out of its bounds.
+    fn segment_elements(
+        &self,
+        segment: usize,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u128>, Trap> {
+        let elements = self.elements[segment].get(offset..offset.saturating_add(len));
+        let elements = elements.ok_or(Trap::TableOutOfBounds)?;
+        Ok(elements.iter().map(|raw| *raw as u128).collect())
+    }
+
+    /// Whether a reference in the same hierarchy as `ty` is of that type, checking
+    /// functions by their type and objects by the type they were allocated with.
+    fn ref_matches(&self, raw: u64, ty: RefType) -> Result<bool, Trap> {
+        if raw == stack::NULL_REF {
+            return Ok(ty.nullable);
+        }
+        let actual = match self.module.top(ty.heap) {
+            HeapType::Func => {
+                let function = &self.module.functions[raw as usize];
+                HeapType::Concrete(function.functype as u32)
+            }
+            HeapType::Any => self.heap.type_of(raw).ok_or(Trap::DanglingReference)?,
+            top => top,
+        };
+        Ok(self.module.heap_matches(actual, ty.heap))
+    }
+
+    /// Executes an instruction with the [`ATOMIC_PREFIX`] prefix.
+    fn execute_atomic(&mut self) -> Result<(), Trap> {
+        macro_rules! op_load {
+            ($push_func: ident, $t: ty) => {{
+                let (mem, ea) = self.atomic_address::<{ size_of::<$t>() }>()?;
+                let bytes = self.memories[mem].atomic_load(ea).ok_or(Trap::MemoryOutOfBounds)?;
+                self.stack.$push_func(<$t>::from_le_bytes(bytes) as _);
+            }};
+        }
+        macro_rules! op_store {
+            ($pop_func: ident, $t: ty) => {{
+                let value = self.stack.$pop_func() as $t;
+                let (mem, ea) = self.atomic_address::<{ size_of::<$t>() }>()?;
+                if !self.memories[mem].atomic_store(ea, value.to_le_bytes()) {
+                    return Err(Trap::MemoryOutOfBounds);
+                }
+            }};
+        }
+        macro_rules! op_rmw {
+            ($pop_func: ident, $push_func: ident, $t: ty, $f: expr) => {{
+                let operand = self.stack.$pop_func() as $t;
+                let (mem, ea) = self.atomic_address::<{ size_of::<$t>() }>()?;
+                let f: fn($t, $t) -> $t = $f;
+                let old = self.memories[mem]
+                    .atomic_rmw(ea, |old| f(<$t>::from_le_bytes(old), operand).to_le_bytes())
+                    .ok_or(Trap::MemoryOutOfBounds)?;
+                self.stack.$push_func(<$t>::from_le_bytes(old) as _);
+            }};
+        }
+        macro_rules! op_cmpxchg {
+            ($pop_func: ident, $push_func: ident, $t: ty) => {{
+                let replacement = (self.stack.$pop_func() as $t).to_le_bytes();
+                let expected = (self.stack.$pop_func() as $t).to_le_bytes();
+                let (mem, ea) = self.atomic_address::<{ size_of::<$t>() }>()?;
+                let old = self.memories[mem]
+                    .atomic_rmw(ea, |old| if old == expected { replacement } else { old })
+                    .ok_or(Trap::MemoryOutOfBounds)?;
+                self.stack.$push_func(<$t>::from_le_bytes(old) as _);
+            }};
+        }
+        macro_rules! op_wait {
+            ($pop_func: ident, $t: ty) => {{
+                let timeout = self.stack.pop_i64();
+                let expected = (self.stack.$pop_func() as $t).to_le_bytes();
+                let (mem, ea) = self.atomic_address::<{ size_of::<$t>() }>()?;
+                let memory = &self.memories[mem];
+                let Some(memory) = memory.shared().filter(|_| memory.is_shared()) else {
+                    return Err(Trap::ExpectedSharedMemory);
+                };
+                // A negative timeout waits forever, or until interrupted
+                let timeout = u64::try_from(timeout).ok().map(Duration::from_nanos);
+                let (interrupt, deadline) = (&self.interrupt, self.deadline);
+                let stop = || interrupt.take() || deadline.is_some_and(|d| Instant::now() >= d);
+                let result = memory.wait(ea, expected, timeout, stop)
+                    .ok_or(Trap::MemoryOutOfBounds)?;
+                if result == WaitResult::Stopped {
+                    return Err(Trap::Interrupted);
+                }
+                self.stack.push_i32(result as i32);
+            }};
+        }
+
+        match self.read_size() as u32 {
+            atomic::MEMORY_ATOMIC_NOTIFY => {
+                let count = self.stack.pop_u32();
+                let (mem, ea) = self.atomic_address::<4>()?;
+                // Nothing can be waiting on an unshared memory
+                let shared = self.memories[mem].shared();
+                let woken = shared.map_or(0, |memory| memory.notify(ea, count));
+                self.stack.push_u32(woken);
+            }
+            atomic::MEMORY_ATOMIC_WAIT32 => op_wait!(pop_i32, u32),
+            atomic::MEMORY_ATOMIC_WAIT64 => op_wait!(pop_i64, u64),
+            atomic::ATOMIC_FENCE => {
+                self.ip += 1;       // flags
+                std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
+            }
+
+            atomic::I32_ATOMIC_LOAD => op_load!(push_i32, u32),
+            atomic::I64_ATOMIC_LOAD => op_load!(push_i64, u64),
+            atomic::I32_ATOMIC_LOAD8_U => op_load!(push_i32, u8),
+            atomic::I32_ATOMIC_LOAD16_U => op_load!(push_i32, u16),
+            atomic::I64_ATOMIC_LOAD8_U => op_load!(push_i64, u8),
+            atomic::I64_ATOMIC_LOAD16_U => op_load!(push_i64, u16),
+            atomic::I64_ATOMIC_LOAD32_U => op_load!(push_i64, u32),
+            atomic::I32_ATOMIC_STORE => op_store!(pop_i32, u32),
+            atomic::I64_ATOMIC_STORE => op_store!(pop_i64, u64),
+            atomic::I32_ATOMIC_STORE8 => op_store!(pop_i32, u8),
+            atomic::I32_ATOMIC_STORE16 => op_store!(pop_i32, u16),
+            atomic::I64_ATOMIC_STORE8 => op_store!(pop_i64, u8),
+            atomic::I64_ATOMIC_STORE16 => op_store!(pop_i64, u16),
+            atomic::I64_ATOMIC_STORE32 => op_store!(pop_i64, u32),
+
+            atomic::I32_ATOMIC_RMW_ADD => op_rmw!(pop_i32, push_i32, u32, u32::wrapping_add),
+            atomic::I64_ATOMIC_RMW_ADD => op_rmw!(pop_i64, push_i64, u64, u64::wrapping_add),
+            atomic::I32_ATOMIC_RMW8_ADD_U => op_rmw!(pop_i32, push_i32, u8, u8::wrapping_add),
+            atomic::I32_ATOMIC_RMW16_ADD_U => op_rmw!(pop_i32, push_i32, u16, u16::wrapping_add),
+            atomic::I64_ATOMIC_RMW8_ADD_U => op_rmw!(pop_i64, push_i64, u8, u8::wrapping_add),
+            atomic::I64_ATOMIC_RMW16_ADD_U => op_rmw!(pop_i64, push_i64, u16, u16::wrapping_add),
+            atomic::I64_ATOMIC_RMW32_ADD_U => op_rmw!(pop_i64, push_i64, u32, u32::wrapping_add),
+            atomic::I32_ATOMIC_RMW_SUB => op_rmw!(pop_i32, push_i32, u32, u32::wrapping_sub),
+            atomic::I64_ATOMIC_RMW_SUB => op_rmw!(pop_i64, push_i64, u64, u64::wrapping_sub),
+            atomic::I32_ATOMIC_RMW8_SUB_U => op_rmw!(pop_i32, push_i32, u8, u8::wrapping_sub),
+            atomic::I32_ATOMIC_RMW16_SUB_U => op_rmw!(pop_i32, push_i32, u16, u16::wrapping_sub),
+            atomic::I64_ATOMIC_RMW8_SUB_U => op_rmw!(pop_i64, push_i64, u8, u8::wrapping_sub),
+            atomic::I64_ATOMIC_RMW16_SUB_U => op_rmw!(pop_i64, push_i64, u16, u16::wrapping_sub),
+            atomic::I64_ATOMIC_RMW32_SUB_U => op_rmw!(pop_i64, push_i64, u32, u32::wrapping_sub),
+            atomic::I32_ATOMIC_RMW_AND => op_rmw!(pop_i32, push_i32, u32, u32::bitand),
+            atomic::I64_ATOMIC_RMW_AND => op_rmw!(pop_i64, push_i64, u64, u64::bitand),
+            atomic::I32_ATOMIC_RMW8_AND_U => op_rmw!(pop_i32, push_i32, u8, u8::bitand),
+            atomic::I32_ATOMIC_RMW16_AND_U => op_rmw!(pop_i32, push_i32, u16, u16::bitand),
+            atomic::I64_ATOMIC_RMW8_AND_U => op_rmw!(pop_i64, push_i64, u8, u8::bitand),
+            atomic::I64_ATOMIC_RMW16_AND_U => op_rmw!(pop_i64, push_i64, u16, u16::bitand),
+            atomic::I64_ATOMIC_RMW32_AND_U => op_rmw!(pop_i64, push_i64, u32, u32::bitand),
+            atomic::I32_ATOMIC_RMW_OR => op_rmw!(pop_i32, push_i32, u32, u32::bitor),
+            atomic::I64_ATOMIC_RMW_OR => op_rmw!(pop_i64, push_i64, u64, u64::bitor),
+            atomic::I32_ATOMIC_RMW8_OR_U => op_rmw!(pop_i32, push_i32, u8, u8::bitor),
+            atomic::I32_ATOMIC_RMW16_OR_U => op_rmw!(pop_i32, push_i32, u16, u16::bitor),
+            atomic::I64_ATOMIC_RMW8_OR_U => op_rmw!(pop_i64, push_i64, u8, u8::bitor),
+            atomic::I64_ATOMIC_RMW16_OR_U => op_rmw!(pop_i64, push_i64, u16, u16::bitor),
+            atomic::I64_ATOMIC_RMW32_OR_U => op_rmw!(pop_i64, push_i64, u32, u32::bitor),
+            atomic::I32_ATOMIC_RMW_XOR => op_rmw!(pop_i32, push_i32, u32, u32::bitxor),
+            atomic::I64_ATOMIC_RMW_XOR => op_rmw!(pop_i64, push_i64, u64, u64::bitxor),
+            atomic::I32_ATOMIC_RMW8_XOR_U => op_rmw!(pop_i32, push_i32, u8, u8::bitxor),
+            atomic::I32_ATOMIC_RMW16_XOR_U => op_rmw!(pop_i32, push_i32, u16, u16::bitxor),
+            atomic::I64_ATOMIC_RMW8_XOR_U => op_rmw!(pop_i64, push_i64, u8, u8::bitxor),
+            atomic::I64_ATOMIC_RMW16_XOR_U => op_rmw!(pop_i64, push_i64, u16, u16::bitxor),
+            atomic::I64_ATOMIC_RMW32_XOR_U => op_rmw!(pop_i64, push_i64, u32, u32::bitxor),
+            atomic::I32_ATOMIC_RMW_XCHG => op_rmw!(pop_i32, push_i32, u32, |_, x| x),
+            atomic::I64_ATOMIC_RMW_XCHG => op_rmw!(pop_i64, push_i64, u64, |_, x| x),
+            atomic::I32_ATOMIC_RMW8_XCHG_U => op_rmw!(pop_i32, push_i32, u8, |_, x| x),
+            atomic::I32_ATOMIC_RMW16_XCHG_U => op_rmw!(pop_i32, push_i32, u16, |_, x| x),
+            atomic::I64_ATOMIC_RMW8_XCHG_U => op_rmw!(pop_i64, push_i64, u8, |_, x| x),
+            atomic::I64_ATOMIC_RMW16_XCHG_U => op_rmw!(pop_i64, push_i64, u16, |_, x| x),
+            atomic::I64_ATOMIC_RMW32_XCHG_U => op_rmw!(pop_i64, push_i64, u32, |_, x| x),
+            atomic::I32_ATOMIC_RMW_CMPXCHG => op_cmpxchg!(pop_i32, push_i32, u32),
+            atomic::I64_ATOMIC_RMW_CMPXCHG => op_cmpxchg!(pop_i64, push_i64, u64),
+            atomic::I32_ATOMIC_RMW8_CMPXCHG_U => op_cmpxchg!(pop_i32, push_i32, u8),
+            atomic::I32_ATOMIC_RMW16_CMPXCHG_U => op_cmpxchg!(pop_i32, push_i32, u16),
+            atomic::I64_ATOMIC_RMW8_CMPXCHG_U => op_cmpxchg!(pop_i64, push_i64, u8),
+            atomic::I64_ATOMIC_RMW16_CMPXCHG_U => op_cmpxchg!(pop_i64, push_i64, u16),
+            atomic::I64_ATOMIC_RMW32_CMPXCHG_U => op_cmpxchg!(pop_i64, push_i64, u32),
+            op => unimplemented!("Instruction 0xfe {op} not yet implemented"),
+        }
+        Ok(())
+    }
+
+    /// Reads an atomic access's immediate and address and returns the effective address
+    /// of its `N` bytes, trapping if they are out of bounds or misaligned.
+    fn atomic_address<const N: usize>(&mut self) -> Result<(usize, usize), Trap> {
+        let (mem, offset) = self.memarg();
+        let ea = self.pop_address(mem).checked_add(offset).ok_or(Trap::MemoryOutOfBounds)?;
+        let ea = usize::try_from(ea).map_err(|_| Trap::MemoryOutOfBounds)?;
+        if ea.checked_add(N).is_none_or(|end| end > self.memories[mem].len()) {
+            Err(Trap::MemoryOutOfBounds)
+        } else if !ea.is_multiple_of(N) {
+            Err(Trap::UnalignedAtomic)
+        } else {
+            Ok((mem, ea))
+        }
+    }
+
+    /// Reads a load's immediate and address and returns the bytes at that address.
+    fn load<const N: usize>(&mut self) -> Result<[u8; N], Trap> {
+        let (mem, offset) = self.memarg();
+        let addr = self.pop_address(mem);
+        self.memories[mem].load(addr, offset).ok_or(Trap::MemoryOutOfBounds)
+    }
+
+    /// Reads a store's immediate and address and writes `bytes` at that address.
+    fn store<const N: usize>(&mut self, bytes: [u8; N]) -> Result<(), Trap> {
+        let (mem, offset) = self.memarg();
+        let addr = self.pop_address(mem);
+        if self.memories[mem].store(addr, offset, bytes) {
+            Ok(())
+        } else {
+            Err(Trap::MemoryOutOfBounds)
+        }
+    }
 
-    fn push_u32(&mut self, v: u32) {
-        self.push_i32(unsafe { std::mem::transmute(v) });
+    /// Reads a `v128.loadN_lane`'s immediates and operands and replaces the lane with
+    /// the `N` bytes at the address.
+    fn load_lane<const N: usize>(&mut self) -> Result<(), Trap> {
+        let v = self.stack.pop_v128();
+        let (mem, offset) = self.memarg();
+        let lane = self.read_byte() as usize;
+        let addr = self.pop_address(mem);
+        let bytes = self.memories[mem].load::<N>(addr, offset).ok_or(Trap::MemoryOutOfBounds)?;
+        let mut v = v.to_le_bytes();
+        v[lane * N..(lane + 1) * N].copy_from_slice(&bytes);
+        self.stack.push_v128(u128::from_le_bytes(v));
+        Ok(())
     }
 
-    fn push_u64(&mut self, v: u64) {
-        self.push_i64(unsafe { std::mem::transmute(v) });
+    /// Reads a `v128.storeN_lane`'s immediates and operands and writes the lane's `N`
+    /// bytes at the address.
+    fn store_lane<const N: usize>(&mut self) -> Result<(), Trap> {
+        let v = self.stack.pop_v128().to_le_bytes();
+        let (mem, offset) = self.memarg();
+        let lane = self.read_byte() as usize;
+        let addr = self.pop_address(mem);
+        let bytes = v[lane * N..(lane + 1) * N].try_into().unwrap();
+        if self.memories[mem].store::<N>(addr, offset, bytes) {
+            Ok(())
+        } else {
+            Err(Trap::MemoryOutOfBounds)
+        }
+    }
+
+    /// Reads a memory immediate, returning its memory and offset.
+    fn memarg(&mut self) -> (usize, u64) {
+        let align = self.read_size();
+        let mem = if align & bytecode::MEMARG_INDEX_BIT != 0 { self.read_size() } else { 0 };
+        let (offset, len) = bytecode::read::read_u64(self.code, self.ip);
+        self.ip += len;
+        (mem, offset)
+    }
+
+    /// Pops an address, size or length for memory `mem`, as an i64 or i32 depending on
+    /// its address type.
+    fn pop_address(&mut self, mem: usize) -> u64 {
+        if self.memories[mem].is_64() {
+            self.stack.pop_u64()
+        } else {
+            self.stack.pop_u32() as u64
+        }
+    }
+
+    /// Pushes an address or size for memory `mem`, truncated to an i32 unless it is a
+    /// 64-bit memory.
+    fn push_address(&mut self, mem: usize, value: u64) {
+        if self.memories[mem].is_64() {
+            self.stack.push_u64(value);
+        } else {
+            self.stack.push_u32(value as u32);
+        }
+    }
+
+    /// Reads a local index and returns the absolute slot where the local starts and how
+    /// many slots it takes.
+    fn local_slot(&mut self) -> (usize, usize) {
+        let idx = self.read_size();
+        let frame = self.frames.last().unwrap();
+        let slots = &self.module.functions[frame.func].local_slots;
+        (frame.locals + slots[idx], slots[idx + 1] - slots[idx])
+    }
+
+    /// The number of slots taken by each operand of the `drop` or `select` just read.
+    fn operand_width(&self) -> usize {
+        let frame = self.frames.last().unwrap();
+        let wide = &self.module.functions[frame.func].wide_operands;
+        if wide.contains(&(self.ip - 1)) { 2 } else { 1 }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        self.ip += 1;
+        self.code[self.ip - 1]
+    }
+
+    fn read_size(&mut self) -> usize {
+        let (num, offset) = bytecode::read::read_size(self.code, self.ip);
+        self.ip += offset;
+        num as usize
+    }
+
+    fn read_val_type(&mut self) -> ValType {
+        let (ty, len) = bytecode::read::read_val_type(self.code, self.ip).unwrap();
+        self.ip += len;
+        ty
+    }
+
+    fn read_i32(&mut self) -> i32 {
+        let (num, offset) = bytecode::read::read_i32(self.code, self.ip);
+        self.ip += offset;
+        num
     }
 
-    fn push_i32(&mut self, v: i32) {
-        self.stack.push(Value::I32(v));
+    fn read_i64(&mut self) -> i64 {
+        let (num, offset) = bytecode::read::read_i64(self.code, self.ip);
+        self.ip += offset;
+        num
     }
 
-    fn push_i64(&mut self, v: i64) {
-        self.stack.push(Value::I64(v));
+    fn read_f32(&mut self) -> f32 {
+        self.ip += 4;
+        bytecode::read::read_f32(self.code, self.ip - 4)
     }
 
-    fn push_f32(&mut self, v: f32) {
-        self.stack.push(Value::F32(v));
+    fn read_f64(&mut self) -> f64 {
+        self.ip += 8;
+        bytecode::read::read_f64(self.code, self.ip - 8)
     }
+}
+
+/// The top of each type's family, for the stack to tag references to concrete types.
+fn type_tops(module: &WasmModule) -> Vec<HeapType> {
+    (0..module.types.len()).map(|idx| module.top(HeapType::Concrete(idx as u32))).collect()
+}
+
+/// The initial value of a field of `struct.new_default` and `array.new_default`.
+fn default_field(field: &FieldType) -> u128 {
+    stack::default_raw(field.storage.unpacked())
+}
 
-    fn push_f64(&mut self, v: f64) {
-        self.stack.push(Value::F64(v));
+/// The index in the exception table of the exception `value` refers to.
+fn exnref_index(value: &Value) -> Option<usize> {
+    match value {
+        Value::ExnRef(handle) => Some(handle.0 as usize),
+        _ => None,
     }
-}
\ No newline at end of file
+}
+
+/// The slot of an object or exception reference in `value`, which the host holds it by.
+/// An object reference may have been converted to an `externref`.
+fn held_ref(value: Value) -> Option<u64> {
+    let raw = match value {
+        Value::AnyRef(AnyRef(raw)) | Value::ExternRef(ExternRef(raw)) => raw,
+        Value::ExnRef(handle) => return Some(handle.0 as u64),
+        _ => return None,
+    };
+    objects::object_index(raw as u128).map(|_| raw)
+}
+
+/// Whether `value` is of type `ty`. A function reference is checked against the type
+/// of the function it refers to, a struct or array against its type in `heap`, and an
+/// exception reference against the `exceptions` still held.
+fn value_matches(
+    module: &WasmModule,
+    heap: &Heap,
+    exceptions: &[Option<Exception>],
+    value: Value,
+    ty: ValType,
+) -> bool {
+    match (value, ty) {
+        (Value::RefNull(HeapType::Concrete(idx)), _) if idx as usize >= module.types.len() => {
+            false
+        }
+        (Value::RefNull(null), ValType::Ref(ty)) => {
+            ty.nullable && module.top(null) == module.top(ty.heap)
+        }
+        (Value::FuncRef(idx), ValType::Ref(ty)) => module.functions.get(idx as usize)
+            .is_some_and(|function| {
+                module.heap_matches(HeapType::Concrete(function.functype as u32), ty.heap)
+            }),
+        (Value::AnyRef(reference), ValType::Ref(ty)) => heap.type_of(reference.0)
+            .is_some_and(|actual| module.heap_matches(actual, ty.heap)),
+        // Converting a reference that looks like an object's must give an existing one
+        (Value::ExternRef(ExternRef(raw)), ValType::Ref(ty)) => ty.heap == HeapType::Extern
+            && (objects::object_index(raw as u128).is_none()
+                || heap.object(objects::convert(raw)).is_some()),
+        (Value::ExnRef(handle), ValType::Ref(ty)) => ty.heap == HeapType::Exn
+            && exceptions.get(handle.0 as usize).is_some_and(Option::is_some),
+        (value, ty) => value.ty() == Some(ty),
+    }
+}
+
+/// The size in bytes of `pages` pages, saturating at `usize::MAX`, for the limiter.
+fn pages_to_bytes(pages: u64) -> usize {
+    usize::try_from(pages).map_or(usize::MAX, |pages| pages.saturating_mul(PAGE_SIZE))
+}
+
+/// Prints the value stack before every instruction.
+pub struct Tracer;
+
+impl Hook for Tracer {
+    fn instruction(&mut self, vm: &Vm, _location: Location) -> HookAction {
+        vm.stack.dump();
+        HookAction::Continue
+    }
+}
+
+impl Drop for Vm<'_> {
+    fn drop(&mut self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.instance_dropped();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::BasicLimiter;
+    use crate::test_util::ModuleBuilder;
+    use crate::value::{ExnRef, HeapType, Limits, RefType, ValType::*};
+    use crate::wasm_module;
+
+    fn run(
+        builder: &ModuleBuilder,
+        func_idx: usize,
+        args: &[Value],
+        checked: bool,
+    ) -> Result<Vec<Value>, Trap> {
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        let mut vm = Vm::new(&module).unwrap();
+        vm.set_checked(checked);
+        vm.invoke(func_idx, args)
+    }
+
+    #[test]
+    fn locals_and_calls() {
+        let mut builder = ModuleBuilder::new();
+        // (a, b) -> a * 2 + b, via a scratch local
+        builder.func(&[I32, I32], &[I32], &[I32], &[
+            LOCAL_GET, 0, I32_CONST, 2, I32_MUL, LOCAL_SET, 2,
+            LOCAL_GET, 2, LOCAL_GET, 1, I32_ADD, END,
+        ]);
+        builder.func(&[], &[I32], &[], &[I32_CONST, 20, I32_CONST, 3, CALL, 0, END]);
+
+        for checked in [false, true] {
+            assert_eq!(Ok(vec![Value::I32(43)]), run(&builder, 1, &[], checked));
+        }
+    }
+
+    #[test]
+    fn integer_traps() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[I32, I32], &[I32], &[], &[LOCAL_GET, 0, LOCAL_GET, 1, I32_DIV_S, END]);
+
+        let args = [Value::I32(1), Value::I32(0)];
+        assert_eq!(Err(Trap::IntegerDivideByZero), run(&builder, 0, &args, true));
+        let args = [Value::I32(i32::MIN), Value::I32(-1)];
+        assert_eq!(Err(Trap::IntegerOverflow), run(&builder, 0, &args, true));
+    }
+
+    #[test]
+    fn unbounded_recursion_exhausts_call_stack() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[CALL, 0, END]);
+        assert_eq!(Err(Trap::CallStackExhausted), run(&builder, 0, &[], false));
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_frame_space() {
+        let mut builder = ModuleBuilder::new();
+        builder.table(RefType::FUNCREF, 1, None);
+        builder.elements(Some(0), &[0]);
+        // Mutually recursive is_even and is_odd, calling each other in tail position
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, I32_EQZ, IF, 0x40, I32_CONST, 1, RETURN, END,
+            LOCAL_GET, 0, I32_CONST, 1, I32_SUB, RETURN_CALL, 1, END,
+        ]);
+        builder.func(&[I32], &[I32], &[I64], &[
+            LOCAL_GET, 0, I32_EQZ, IF, 0x40, I32_CONST, 0, RETURN, END,
+            LOCAL_GET, 0, I32_CONST, 1, I32_SUB, I32_CONST, 0, RETURN_CALL_INDIRECT, 0, 0, END,
+        ]);
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, CALL, 1, I32_CONST, 10, I32_ADD, END,
+        ]);
+
+        for checked in [false, true] {
+            let n = Value::I32(10_001);
+            assert_eq!(Ok(vec![Value::I32(0)]), run(&builder, 0, &[n], checked));
+            assert_eq!(Ok(vec![Value::I32(1)]), run(&builder, 1, &[n], checked));
+            assert_eq!(Ok(vec![Value::I32(11)]), run(&builder, 2, &[Value::I32(7)], checked));
+        }
+
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, END]);
+        builder.func(&[I32], &[], &[], &[LOCAL_GET, 0, RETURN_CALL, 0, END]);
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("tail call results differ"));
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut builder = ModuleBuilder::new();
+        builder.tag(&[I32]);
+        builder.tag(&[]);
+        builder.func(&[I32], &[], &[], &[LOCAL_GET, 0, THROW, 0, END]);
+        // Catches the payload thrown from another frame
+        builder.func(&[I32], &[I32], &[], &[
+            BLOCK, 0x7f, TRY_TABLE, 0x40, 1, catch::CATCH, 0, 0,
+                LOCAL_GET, 0, CALL, 0,
+            END, I32_CONST, 0x7f, END, END,
+        ]);
+        builder.func(&[I32], &[], &[], &[LOCAL_GET, 0, CALL, 0, END]);
+        builder.func(&[], &[ValType::EXNREF], &[], &[
+            BLOCK, 0x69, TRY_TABLE, 0x40, 1, catch::CATCH_ALL_REF, 0,
+                I32_CONST, 5, CALL, 0,
+            END, REF_NULL, 0x69, END, END,
+        ]);
+        // Rethrows the exception caught by the previous function
+        builder.func(&[], &[I32], &[], &[
+            BLOCK, 0x7f, TRY_TABLE, 0x40, 1, catch::CATCH, 0, 0,
+                CALL, 3, THROW_REF,
+            END, UNREACHABLE, END, END,
+        ]);
+        builder.func(&[], &[], &[], &[REF_NULL, 0x69, THROW_REF, END]);
+        builder.func(&[I32], &[], &[], &[
+            TRY_TABLE, 0x40, 1, catch::CATCH, 1, 0, LOCAL_GET, 0, CALL, 0, END, END,
+        ]);
+        builder.func(&[ValType::EXNREF], &[], &[], &[LOCAL_GET, 0, THROW_REF, END]);
+        // Catches and drops an exception on each iteration
+        builder.func(&[I32], &[], &[], &[
+            LOOP, 0x40, CALL, 3, DROP,
+            LOCAL_GET, 0, I32_CONST, 1, I32_SUB, LOCAL_TEE, 0, BR_IF, 0, END, END,
+        ]);
+
+        let uncaught = |value| {
+            Err(Trap::UncaughtException(Exception { tag: 0, payload: vec![Value::I32(value)] }))
+        };
+        for checked in [false, true] {
+            let args = [Value::I32(42)];
+            assert_eq!(Ok(vec![Value::I32(42)]), run(&builder, 1, &args, checked));
+            assert_eq!(uncaught(42), run(&builder, 2, &args, checked));
+            assert_eq!(Ok(vec![Value::I32(5)]), run(&builder, 4, &[], checked));
+            assert_eq!(Err(Trap::NullReference), run(&builder, 5, &[], checked));
+            assert_eq!(uncaught(7), run(&builder, 6, &[Value::I32(7)], checked));
+        }
+
+        let bytes = builder.build();
+
+        let module = wasm_module::load(&bytes).ok().unwrap();
+        let mut vm = Vm::new(&module).unwrap();
+        let exnref = vm.invoke(3, &[]).unwrap()[0];
+        let expected = Exception { tag: 0, payload: vec![Value::I32(5)] };
+        assert_eq!(Some(&expected), vm.exception(exnref));
+        assert_eq!(Ok(vec![Value::I32(42)]), vm.invoke(1, &[Value::I32(42)]));
+
+        // Only exceptions something still refers to are kept
+        for _ in 0..1000 {
+            vm.invoke(4, &[]).unwrap();
+        }
+        vm.invoke(8, &[Value::I32(1000)]).unwrap();
+        assert_eq!(1, vm.exceptions.iter().flatten().count());
+        assert_eq!(Err(Trap::UncaughtException(expected)), vm.invoke(7, &[exnref]));
+        let forged = Value::ExnRef(ExnRef(7));
+        assert!(matches!(vm.invoke(7, &[forged]), Err(Trap::InvalidArguments(_))));
+        vm.release(exnref);
+        vm.invoke(4, &[]).unwrap();
+        assert!(vm.exceptions.is_empty());
+        assert!(matches!(vm.invoke(7, &[exnref]), Err(Trap::InvalidArguments(_))));
+
+        let mut builder = ModuleBuilder::new();
+        builder.tag(&[I32]);
+        builder.func(&[], &[], &[], &[TRY_TABLE, 0x40, 1, catch::CATCH, 0, 0, END, END]);
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("catch clause does not match its label"));
+    }
+
+    #[test]
+    fn control_flow() {
+        let mut builder = ModuleBuilder::new();
+        // Sum of 1..=n using a loop, with an early exit for n <= 0
+        builder.func(&[I32], &[I32], &[I32], &[
+            LOCAL_GET, 0, I32_CONST, 0, I32_LE_S,
+            IF, 0x40, I32_CONST, 0, RETURN, END,
+            BLOCK, 0x40,
+                LOOP, 0x40,
+                    LOCAL_GET, 1, LOCAL_GET, 0, I32_ADD, LOCAL_SET, 1,
+                    LOCAL_GET, 0, I32_CONST, 1, I32_SUB, LOCAL_TEE, 0,
+                    I32_EQZ, BR_IF, 1,
+                    BR, 0,
+                END,
+            END,
+            LOCAL_GET, 1, END,
+        ]);
+        // br_table selecting a constant, with if/else producing a value
+        builder.func(&[I32], &[I32], &[], &[
+            BLOCK, 0x40, BLOCK, 0x40, BLOCK, 0x40,
+            LOCAL_GET, 0, BR_TABLE, 2, 0, 1, 2,
+            END, I32_CONST, 10, RETURN,
+            END, I32_CONST, 1, I32_CONST, 0, IF, 0x7f, I32_CONST, 20, ELSE, I32_CONST, 21, END,
+            I32_ADD, RETURN,
+            END, I32_CONST, 30, END,
+        ]);
+
+        assert_eq!(Ok(vec![Value::I32(55)]), run(&builder, 0, &[Value::I32(10)], true));
+        assert_eq!(Ok(vec![Value::I32(0)]), run(&builder, 0, &[Value::I32(-3)], true));
+        assert_eq!(Ok(vec![Value::I32(10)]), run(&builder, 1, &[Value::I32(0)], true));
+        assert_eq!(Ok(vec![Value::I32(22)]), run(&builder, 1, &[Value::I32(1)], true));
+        assert_eq!(Ok(vec![Value::I32(30)]), run(&builder, 1, &[Value::I32(7)], true));
+    }
+
+    #[test]
+    fn multi_value() {
+        let mut builder = ModuleBuilder::new();
+        let swap = builder.func(&[I32, I32], &[I32, I32], &[], &[LOCAL_GET, 1, LOCAL_GET, 0, END]);
+        let pair = builder.add_type(&[I32, I32], &[I32, I32]) as u8;
+        let combine = builder.add_type(&[I32, I32], &[I32]) as u8;
+        let count = builder.add_type(&[I32], &[I32]) as u8;
+        builder.func(&[I32], &[I32, I32, I32], &[], &[
+            I32_CONST, 3, I32_CONST, 4, CALL, swap as u8,
+            BLOCK, pair, BR, 0, END,
+            LOCAL_GET, 0, IF, combine, I32_SUB, ELSE, I32_ADD, END,
+            // Count down to zero, passing the counter as the loop's param
+            I32_CONST, 5,
+            LOOP, count, I32_CONST, 1, I32_SUB, LOCAL_TEE, 0, LOCAL_GET, 0, BR_IF, 0, END,
+            I32_CONST, 9,
+            END,
+        ]);
+
+        let expected = vec![Value::I32(1), Value::I32(0), Value::I32(9)];
+        assert_eq!(Ok(expected), run(&builder, 1, &[Value::I32(1)], true));
+        let expected = vec![Value::I32(7), Value::I32(0), Value::I32(9)];
+        assert_eq!(Ok(expected), run(&builder, 1, &[Value::I32(0)], false));
+
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[BLOCK, 5, END, END]);
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("Unknown block type 5"));
+    }
+
+    #[test]
+    fn reference_types() {
+        let mut builder = ModuleBuilder::new();
+        builder.table(RefType::FUNCREF, 2, None);
+        builder.table(RefType::EXTERNREF, 1, Some(4));
+        let answer = builder.func(&[], &[I32], &[], &[I32_CONST, 42, END]) as u8;
+        let answer_type = builder.add_type(&[], &[I32]) as u8;
+        // Stores the externref and reads it back, then grows its table twice
+        builder.func(&[ValType::EXTERNREF], &[ValType::EXTERNREF, I32, I32, I32], &[], &[
+            I32_CONST, 0, LOCAL_GET, 0, TABLE_SET, 1,
+            I32_CONST, 0, TABLE_GET, 1,
+            REF_NULL, 0x6f, I32_CONST, 2, MISC_PREFIX, 15, 1,
+            REF_NULL, 0x6f, I32_CONST, 5, MISC_PREFIX, 15, 1,
+            MISC_PREFIX, 16, 1,
+            END,
+        ]);
+        // Fills the first slot of the funcref table and calls through it
+        builder.func(&[I32], &[I32], &[], &[
+            I32_CONST, 0, REF_FUNC, answer, I32_CONST, 1, MISC_PREFIX, 17, 0,
+            LOCAL_GET, 0, CALL_INDIRECT, answer_type, 0,
+            END,
+        ]);
+        builder.func(&[I32], &[I32], &[], &[
+            REF_NULL, 0x70, REF_FUNC, answer, LOCAL_GET, 0, SELECT_T, 1, 0x70, REF_IS_NULL, END,
+        ]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+
+        for checked in [false, true] {
+            let mut vm = Vm::new(&module).unwrap();
+            vm.set_checked(checked);
+            let handle = vm.extern_ref(String::from("host object"));
+            let results = vm.invoke(1, &[handle]).unwrap();
+            // Growing to 3 returns the old size, growing past the maximum of 4 fails
+            let expected = vec![handle, Value::I32(1), Value::I32(-1), Value::I32(3)];
+            assert_eq!(expected, results);
+            assert_eq!(Some(&String::from("host object")), vm.extern_data::<String>(results[0]));
+            assert_eq!(None, vm.extern_data::<u32>(results[0]));
+            assert_eq!(3, vm.table(1).unwrap().size());
+
+            assert_eq!(Ok(vec![Value::I32(42)]), vm.invoke(2, &[Value::I32(0)]));
+            assert_eq!(Err(Trap::UninitializedElement), vm.invoke(2, &[Value::I32(1)]));
+            assert_eq!(Err(Trap::UndefinedElement), vm.invoke(2, &[Value::I32(2)]));
+            assert_eq!(Ok(vec![Value::I32(0)]), vm.invoke(3, &[Value::I32(0)]));
+            assert_eq!(Ok(vec![Value::I32(1)]), vm.invoke(3, &[Value::I32(1)]));
+        }
+    }
+
+    #[test]
+    fn bulk_memory() {
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, None);
+        let active = builder.data(Some(16), b"hello") as u8;
+        let passive = builder.data(None, b"world!") as u8;
+        let args = [I32, I32, I32];
+        let op = |bytes: &[u8]| {
+            [&[LOCAL_GET, 0, LOCAL_GET, 1, LOCAL_GET, 2], bytes, &[END]].concat()
+        };
+        builder.func(&args, &[], &[], &op(&[MISC_PREFIX, 8, passive, 0]));
+        builder.func(&args, &[], &[], &op(&[MISC_PREFIX, 8, active, 0]));
+        builder.func(&args, &[], &[], &op(&[MISC_PREFIX, 10, 0, 0]));
+        builder.func(&args, &[], &[], &op(&[MISC_PREFIX, 11, 0]));
+        builder.func(&[], &[], &[], &[MISC_PREFIX, 9, passive, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        let mut vm = Vm::new(&module).unwrap();
+        let args = |a, b, c| [Value::I32(a), Value::I32(b), Value::I32(c)];
+        let data = |vm: &Vm, start: usize, len: usize| {
+            vm.memory(0).unwrap().data()[start..start + len].to_vec()
+        };
+
+        assert_eq!(b"hello", &data(&vm, 16, 5)[..]);
+        assert_eq!(Ok(vec![]), vm.invoke(0, &args(32, 1, 5)));
+        assert_eq!(b"orld!", &data(&vm, 32, 5)[..]);
+        // Overlapping copies move the bytes as if through a temporary buffer
+        assert_eq!(Ok(vec![]), vm.invoke(2, &args(18, 16, 5)));
+        assert_eq!(b"hehello", &data(&vm, 16, 7)[..]);
+        assert_eq!(Ok(vec![]), vm.invoke(3, &args(60, 0x2a, 4)));
+        assert_eq!(vec![0x2a; 4], data(&vm, 60, 4));
+
+        // Out of bounds accesses trap without writing anything
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(3, &args(65534, 1, 4)));
+        assert_eq!(vec![0, 0], data(&vm, 65534, 2));
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(2, &args(65534, 16, 4)));
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(0, &args(0, 4, 3)));
+        assert_eq!(vec![0, 0, 0], data(&vm, 0, 3));
+        // Empty ranges only need to start in bounds
+        assert_eq!(Ok(vec![]), vm.invoke(0, &args(65536, 6, 0)));
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(0, &args(65537, 0, 0)));
+
+        // Dropped segments, including active ones after instantiation, are empty
+        assert_eq!(Ok(vec![]), vm.invoke(4, &[]));
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(0, &args(0, 0, 1)));
+        assert_eq!(Ok(vec![]), vm.invoke(0, &args(0, 0, 0)));
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(1, &args(0, 0, 1)));
+    }
+
+    #[test]
+    fn bulk_tables() {
+        let mut builder = ModuleBuilder::new();
+        builder.table(RefType::FUNCREF, 4, None);
+        builder.func(&[], &[I32], &[], &[I32_CONST, 1, END]);
+        builder.func(&[], &[I32], &[], &[I32_CONST, 2, END]);
+        builder.elements(Some(0), &[0, 1]);
+        let passive = builder.elements(None, &[1, 0]) as u8;
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, CALL_INDIRECT, 0, 0, END]);
+        let args = [I32, I32, I32];
+        let op = |bytes: &[u8]| {
+            [&[LOCAL_GET, 0, LOCAL_GET, 1, LOCAL_GET, 2], bytes, &[END]].concat()
+        };
+        builder.func(&args, &[], &[], &op(&[MISC_PREFIX, 12, passive, 0]));
+        builder.func(&args, &[], &[], &op(&[MISC_PREFIX, 14, 0, 0]));
+        builder.func(&[], &[], &[], &[MISC_PREFIX, 13, passive, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        let mut vm = Vm::new(&module).unwrap();
+        let args = |a, b, c| [Value::I32(a), Value::I32(b), Value::I32(c)];
+        let call = |vm: &mut Vm, idx| vm.invoke(2, &[Value::I32(idx)]);
+
+        assert_eq!(Ok(vec![Value::I32(2)]), call(&mut vm, 1));
+        assert_eq!(Err(Trap::UninitializedElement), call(&mut vm, 2));
+        assert_eq!(Ok(vec![]), vm.invoke(3, &args(2, 0, 2)));
+        assert_eq!(Ok(vec![Value::I32(2)]), call(&mut vm, 2));
+        assert_eq!(Ok(vec![Value::I32(1)]), call(&mut vm, 3));
+        assert_eq!(Ok(vec![]), vm.invoke(4, &args(0, 1, 3)));
+        assert_eq!(Ok(vec![Value::I32(2)]), call(&mut vm, 0));
+        assert_eq!(Ok(vec![Value::I32(1)]), call(&mut vm, 2));
+
+        assert_eq!(Err(Trap::TableOutOfBounds), vm.invoke(3, &args(3, 0, 2)));
+        assert_eq!(Ok(vec![Value::I32(1)]), call(&mut vm, 3));
+        assert_eq!(Ok(vec![]), vm.invoke(5, &[]));
+        assert_eq!(Err(Trap::TableOutOfBounds), vm.invoke(3, &args(0, 0, 1)));
+
+        // Active segments that don't fit fail instantiation
+        let mut builder = ModuleBuilder::new();
+        builder.table(RefType::FUNCREF, 2, None);
+        builder.func(&[], &[], &[], &[END]);
+        builder.elements(Some(1), &[0, 0]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+        assert_eq!(Some(Trap::TableOutOfBounds), Vm::new(&module).err());
+    }
+
+    #[test]
+    fn simd() {
+        use crate::bytecode::simd::*;
+        use crate::test_util::simd as op;
+
+        let v128 = |v: u128| [op(V128_CONST), v.to_le_bytes().to_vec()].concat();
+        let i32x4 = |lanes: [u32; 4]| v128(lanes.iter().rev().fold(0, |v, l| v << 32 | *l as u128));
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, None);
+        builder.data(Some(0), &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+        builder.func(&[], &[I32], &[], &[
+            i32x4([1, 2, 3, 4]), i32x4([10, 20, 30, 40]), op(I32X4_ADD),
+            op(I32X4_EXTRACT_LANE), vec![3, END],
+        ].concat());
+        builder.func(&[V128], &[V128], &[], &[
+            vec![LOCAL_GET, 0], op(I8X16_POPCNT), vec![END],
+        ].concat());
+        let reverse: Vec<u8> = (0..16).rev().collect();
+        builder.func(&[], &[], &[], &[
+            vec![I32_CONST, 16, I32_CONST, 0], op(V128_LOAD), vec![4, 0],
+            v128(0), op(I8X16_SHUFFLE), reverse, op(V128_STORE), vec![4, 0, END],
+        ].concat());
+        builder.func(&[], &[I32], &[], &[
+            vec![I32_CONST, 4], v128(0), op(V128_LOAD32_LANE), vec![2, 0, 2],
+            op(I32X4_EXTRACT_LANE), vec![2, END],
+        ].concat());
+        builder.func(&[I32], &[], &[], &[
+            vec![LOCAL_GET, 0], v128(u128::MAX), op(V128_STORE16_LANE), vec![1, 0, 7, END],
+        ].concat());
+        // Reinterprets an i64 splat as i32 lanes and converts the low two to f64
+        builder.func(&[I64], &[F64], &[], &[
+            vec![LOCAL_GET, 0], op(I64X2_SPLAT), op(F64X2_CONVERT_LOW_I32X4_S),
+            op(F64X2_EXTRACT_LANE), vec![1, END],
+        ].concat());
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+
+        for checked in [true, false] {
+            let mut vm = Vm::new(&module).unwrap();
+            vm.set_checked(checked);
+            assert_eq!(Ok(vec![Value::I32(44)]), vm.invoke(0, &[]));
+            let popcnt = vm.invoke(1, &[Value::V128(-1)]);
+            assert_eq!(Ok(vec![Value::V128(i128::from_le_bytes([8; 16]))]), popcnt);
+            assert_eq!(Ok(vec![]), vm.invoke(2, &[]));
+            let reversed: Vec<u8> = (0..16).rev().collect();
+            assert_eq!(&reversed[..], &vm.memory(0).unwrap().data()[16..32]);
+            assert_eq!(Ok(vec![Value::I32(0x07060504)]), vm.invoke(3, &[]));
+
+            assert_eq!(Ok(vec![]), vm.invoke(4, &[Value::I32(65534)]));
+            assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(4, &[Value::I32(65535)]));
+            assert_eq!(&[0xff, 0xff], &vm.memory(0).unwrap().data()[65534..]);
+            let lanes = Value::I64(0x0000_0003_ffff_ffff);
+            assert_eq!(Ok(vec![Value::F64(3.0)]), vm.invoke(5, &[lanes]));
+        }
+
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[I32], &[], &[
+            v128(0), op(I32X4_EXTRACT_LANE), vec![4, END],
+        ].concat());
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("Invalid lane index 4"));
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[V128], &[], &[
+            v128(0), vec![I32_CONST, 1], op(I32X4_ADD), vec![END],
+        ].concat());
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("Type mismatch"));
+    }
+
+    #[test]
+    fn v128_takes_two_slots() {
+        use crate::bytecode::simd::*;
+        use crate::test_util::simd as op;
+
+        let v128 = |v: i128| [op(V128_CONST), v.to_le_bytes().to_vec()].concat();
+        let (arg, init, set) = (-2, 0x1234_5678_9abc_def0_0fed_cba9_8765_4321, i128::MIN + 9);
+        let mut builder = ModuleBuilder::new();
+        builder.global(V128, true, &[v128(init), vec![END]].concat());
+        builder.func(&[V128], &[V128, I32], &[], &[LOCAL_GET, 0, I32_CONST, 7, END]);
+        // Moves a v128 through a local, drop, select, a branch and a call, between
+        // narrower values
+        builder.func(&[I32, V128, I64], &[I64, V128, I32], &[V128, I32], &[
+            vec![LOCAL_GET, 1, LOCAL_TEE, 3, DROP, LOCAL_GET, 2],
+            vec![BLOCK, 0x7b, I32_CONST, 99, LOCAL_GET, 3, GLOBAL_GET, 0, LOCAL_GET, 0, SELECT],
+            vec![BR, 0, END, CALL, 0, LOCAL_SET, 4, LOCAL_GET, 0, LOCAL_GET, 4, I32_ADD, END],
+        ].concat());
+        builder.func(&[V128], &[], &[], &[LOCAL_GET, 0, GLOBAL_SET, 0, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+
+        for checked in [true, false] {
+            let mut vm = Vm::new(&module).unwrap();
+            vm.set_checked(checked);
+            let args = |select| [Value::I32(select), Value::V128(arg), Value::I64(-5)];
+            let results = |v, sum| Ok(vec![Value::I64(-5), Value::V128(v), Value::I32(sum)]);
+            assert_eq!(results(arg, 8), vm.invoke(1, &args(1)));
+            assert_eq!(results(init, 7), vm.invoke(1, &args(0)));
+            assert_eq!(Ok(vec![]), vm.invoke(2, &[Value::V128(set)]));
+            assert_eq!(vec![Value::V128(set)], vm.globals());
+            assert_eq!(results(set, 7), vm.invoke(1, &args(0)));
+        }
+        assert_eq!(vec![0, 1, 3, 4, 6, 7], module.functions[1].local_slots);
+    }
+
+    #[test]
+    fn atomics() {
+        use crate::bytecode::atomic::*;
+        use crate::test_util::atomic as op;
+
+        let mut builder = ModuleBuilder::new();
+        builder.shared_memory(1, 2);
+        builder.func(&[I32, I32], &[I32], &[], &[
+            vec![LOCAL_GET, 0, LOCAL_GET, 1], op(I32_ATOMIC_RMW_ADD), vec![2, 0, END],
+        ].concat());
+        builder.func(&[I32, I32, I32], &[I32], &[], &[
+            vec![LOCAL_GET, 0, LOCAL_GET, 1, LOCAL_GET, 2], op(I32_ATOMIC_RMW8_CMPXCHG_U),
+            vec![0, 0, END],
+        ].concat());
+        builder.func(&[I32], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(I32_ATOMIC_LOAD16_U), vec![1, 0, END],
+        ].concat());
+        builder.func(&[I32, I64], &[I64], &[], &[
+            vec![LOCAL_GET, 0, LOCAL_GET, 1], op(I64_ATOMIC_RMW32_XCHG_U), vec![2, 0, END],
+        ].concat());
+        builder.func(&[I32, I32, I64], &[I32], &[], &[
+            vec![LOCAL_GET, 0, LOCAL_GET, 1, LOCAL_GET, 2], op(MEMORY_ATOMIC_WAIT32),
+            vec![2, 0, END],
+        ].concat());
+
+        for checked in [false, true] {
+            let args = |values: &[i32]| values.iter().map(|v| Value::I32(*v)).collect::<Vec<_>>();
+            assert_eq!(Ok(args(&[0])), run(&builder, 0, &args(&[8, 0x1ff]), checked));
+            assert_eq!(Ok(args(&[0])), run(&builder, 1, &args(&[8, 0, 0x1ab]), checked));
+            assert_eq!(Ok(args(&[0])), run(&builder, 2, &args(&[8]), checked));
+            assert_eq!(Err(Trap::UnalignedAtomic), run(&builder, 0, &args(&[6, 1]), checked));
+            assert_eq!(Err(Trap::UnalignedAtomic), run(&builder, 2, &args(&[1]), checked));
+            let out_of_bounds = run(&builder, 2, &args(&[65535]), checked);
+            assert_eq!(Err(Trap::MemoryOutOfBounds), out_of_bounds);
+        }
+
+        let bytes = builder.build();
+
+        let module = wasm_module::load(&bytes).ok().unwrap();
+        let mut vm = Vm::new(&module).unwrap();
+        assert!(vm.memory(0).unwrap().shared().is_some());
+        vm.invoke(0, &[Value::I32(8), Value::I32(0x1ff)]).unwrap();
+        let cmpxchg = |vm: &mut Vm, expected, replacement| {
+            vm.invoke(1, &[Value::I32(8), Value::I32(expected), Value::I32(replacement)])
+        };
+        assert_eq!(Ok(vec![Value::I32(0xff)]), cmpxchg(&mut vm, 0x1ff, 0x1ab));
+        assert_eq!(Ok(vec![Value::I32(0xab)]), cmpxchg(&mut vm, 0xff, 0));
+        assert_eq!(Ok(vec![Value::I32(0xab)]), cmpxchg(&mut vm, 0xab, 0));
+        assert_eq!(Ok(vec![Value::I32(0x0100)]), vm.invoke(2, &[Value::I32(8)]));
+        let old = vm.invoke(3, &[Value::I32(8), Value::I64(-1)]);
+        assert_eq!(Ok(vec![Value::I64(0x0100)]), old);
+        assert_eq!(&[0xff; 4], &vm.memory(0).unwrap().data()[8..12]);
+
+        let wait = |vm: &mut Vm, expected, timeout| {
+            vm.invoke(4, &[Value::I32(8), Value::I32(expected), Value::I64(timeout)])
+        };
+        assert_eq!(Ok(vec![Value::I32(1)]), wait(&mut vm, 0, -1));
+        assert_eq!(Ok(vec![Value::I32(2)]), wait(&mut vm, -1, 1_000_000));
+
+        let mut unshared = ModuleBuilder::new();
+        unshared.memory(1, None);
+        unshared.func(&[], &[I32], &[], &[
+            vec![I32_CONST, 0, I32_CONST, 0, I64_CONST, 0], op(MEMORY_ATOMIC_WAIT32),
+            vec![2, 0, END],
+        ].concat());
+        assert_eq!(Err(Trap::ExpectedSharedMemory), run(&unshared, 0, &[], false));
+        let bytes = unshared.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+        assert_eq!(
+            Err(Trap::InvalidArguments(String::from("Memory 0 is not shared"))),
+            Vm::with_shared_memory(&module, SharedMemory::new(Limits { min: 1, max: Some(1) }))
+                .map(|_| ()),
+        );
+
+        let mut builder = ModuleBuilder::new();
+        builder.shared_memory(1, 1);
+        builder.func(&[], &[I32], &[], &[
+            vec![I32_CONST, 0], op(I32_ATOMIC_LOAD), vec![1, 0, END],
+        ].concat());
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("Alignment of atomic instructions must be natural"));
+    }
+
+    #[test]
+    fn shared_memory_across_threads() {
+        use crate::bytecode::atomic::*;
+        use crate::test_util::atomic as op;
+
+        fn assert_send<T: Send>() {}
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send::<Vm>();
+        assert_send_sync::<SharedMemory>();
+
+        let mut builder = ModuleBuilder::new();
+        builder.shared_memory(1, 1);
+        // Atomically increments address 0 the given number of times
+        builder.func(&[I32], &[], &[], &[
+            vec![LOOP, 0x40, I32_CONST, 0, I32_CONST, 1], op(I32_ATOMIC_RMW_ADD),
+            vec![2, 0, DROP, LOCAL_GET, 0, I32_CONST, 1, I32_SUB, LOCAL_TEE, 0, BR_IF, 0],
+            vec![END, END],
+        ].concat());
+        builder.func(&[], &[I32], &[], &[
+            vec![I32_CONST, 4, I32_CONST, 0, I64_CONST, 0x7f], op(MEMORY_ATOMIC_WAIT32),
+            vec![2, 0, END],
+        ].concat());
+        builder.func(&[], &[I32], &[], &[
+            vec![I32_CONST, 4, I32_CONST, 1], op(MEMORY_ATOMIC_NOTIFY), vec![2, 0, END],
+        ].concat());
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let memory = SharedMemory::new(Limits { min: 1, max: Some(1) });
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let mut vm = Vm::with_shared_memory(&module, memory.clone()).unwrap();
+                scope.spawn(move || vm.invoke(0, &[Value::I32(1000)]).unwrap());
+            }
+            let mut waiter = Vm::with_shared_memory(&module, memory.clone()).unwrap();
+            let waiter = scope.spawn(move || waiter.invoke(1, &[]));
+
+            // The waiter may not be queued yet, so notify until it is woken
+            let mut vm = Vm::with_shared_memory(&module, memory.clone()).unwrap();
+            while vm.invoke(2, &[]) != Ok(vec![Value::I32(1)]) {
+                std::thread::yield_now();
+            }
+            assert_eq!(Ok(vec![Value::I32(0)]), waiter.join().unwrap());
+        });
+        assert_eq!(Some(4000), memory.read(0).map(u32::from_le_bytes));
+    }
+
+    #[test]
+    fn fuel_bounds_infinite_loop() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[LOOP, 0x40, BR, 0, END, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let mut vm = Vm::new(&module).unwrap();
+        vm.set_fuel(Some(1000));
+        assert_eq!(Err(Trap::OutOfFuel), vm.invoke(0, &[]));
+        assert_eq!(Some(0), vm.fuel());
+    }
+
+    #[test]
+    fn resume_after_adding_fuel() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[I32], &[], &[
+            I32_CONST, 1, I32_CONST, 2, I32_ADD, I32_CONST, 3, I32_MUL, END,
+        ]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let mut vm = Vm::new(&module).unwrap();
+        let mut costs = CostTable::uniform(1);
+        costs.set(I32_MUL, 10);
+        vm.set_cost_table(costs);
+        vm.set_fuel(Some(4));
+        assert_eq!(Err(Trap::OutOfFuel), vm.invoke(0, &[]));
+        assert_eq!(Some(0), vm.fuel());
+
+        vm.add_fuel(5);
+        assert_eq!(Err(Trap::OutOfFuel), vm.resume());
+        vm.add_fuel(100);
+        assert_eq!(Ok(vec![Value::I32(9)]), vm.resume());
+        assert_eq!(Some(94), vm.fuel());
+    }
+
+    #[test]
+    fn bulk_instructions_cost_their_length() {
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, None);
+        builder.func(&[I32], &[], &[], &[
+            I32_CONST, 0, I32_CONST, 7, LOCAL_GET, 0, MISC_PREFIX, 11, 0, END,
+        ]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let mut vm = Vm::new(&module).unwrap();
+        vm.set_fuel(Some(1000));
+        assert_eq!(Ok(vec![]), vm.invoke(0, &[Value::I32(10)]));
+        assert_eq!(Some(1000 - 3 - 11), vm.fuel());
+
+        // Running out before filling leaves memory as it was
+        assert_eq!(Err(Trap::OutOfFuel), vm.invoke(0, &[Value::I32(2000)]));
+        assert_eq!(Some(vec![0]), vm.memories[0].read(1999, 1));
+        vm.add_fuel(2000);
+        assert_eq!(Ok(vec![]), vm.resume());
+        assert_eq!(Some(vec![7]), vm.memories[0].read(1999, 1));
+        assert_eq!(Some(986 - 3 + 2000 - 2001), vm.fuel());
+
+        // Other prefixed instructions keep their prefix's cost
+        let mut costs = CostTable::uniform(1);
+        costs.set(MISC_PREFIX, 3);
+        costs.set_prefixed(MISC_PREFIX, misc::MEMORY_FILL, 20);
+        costs.set_per_element(0);
+        assert_eq!(20, costs.prefixed_cost(MISC_PREFIX, misc::MEMORY_FILL));
+        assert_eq!(3, costs.prefixed_cost(MISC_PREFIX, misc::MEMORY_COPY));
+        vm.set_cost_table(costs);
+        vm.set_fuel(Some(100));
+        assert_eq!(Ok(vec![]), vm.invoke(0, &[Value::I32(2000)]));
+        assert_eq!(Some(100 - 4 - 20), vm.fuel());
+    }
+
+    #[test]
+    fn interrupt_from_another_thread() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[LOOP, 0x40, BR, 0, END, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let mut vm = Vm::new(&module).unwrap();
+        let handle = vm.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            handle.interrupt();
+        });
+        assert_eq!(Err(Trap::Interrupted), vm.invoke(0, &[]));
+        interrupter.join().unwrap();
+    }
+
+    #[test]
+    fn interrupt_wait() {
+        use crate::bytecode::atomic::*;
+        use crate::test_util::atomic as op;
+
+        let mut builder = ModuleBuilder::new();
+        builder.shared_memory(1, 1);
+        builder.func(&[], &[I32], &[], &[
+            vec![I32_CONST, 0, I32_CONST, 0, I64_CONST, 0x7f], op(MEMORY_ATOMIC_WAIT32),
+            vec![2, 0, END],
+        ].concat());
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let mut vm = Vm::new(&module).unwrap();
+        let handle = vm.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            handle.interrupt();
+        });
+        assert_eq!(Err(Trap::Interrupted), vm.invoke(0, &[]));
+        interrupter.join().unwrap();
+
+        vm.set_timeout(Some(Duration::from_millis(20)));
+        assert_eq!(Err(Trap::Interrupted), vm.invoke(0, &[]));
+        // Nothing is left queued on the address
+        assert_eq!(0, vm.memory(0).unwrap().shared().unwrap().notify(0, 1));
+    }
+
+    #[test]
+    fn deadline_stops_infinite_loop() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[LOOP, 0x40, BR, 0, END, END]);
+        builder.func(&[], &[I32], &[], &[I32_CONST, 5, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let mut vm = Vm::new(&module).unwrap();
+        vm.set_timeout(Some(Duration::from_millis(20)));
+        let start = Instant::now();
+        assert_eq!(Err(Trap::Interrupted), vm.invoke(0, &[]));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // Each invocation gets a fresh deadline
+        assert_eq!(Ok(vec![Value::I32(5)]), vm.invoke(1, &[]));
+    }
+
+    #[test]
+    fn memory_loads_and_stores() {
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, None);
+        // Store a at address p, then load it back sign- and zero-extended from one byte
+        builder.func(&[I32, I32], &[I32, I64], &[], &[
+            LOCAL_GET, 1, LOCAL_GET, 0, I32_STORE, 2, 0,
+            LOCAL_GET, 1, I32_LOAD8_S, 0, 0,
+            LOCAL_GET, 1, I64_LOAD8_U, 0, 0,
+            END,
+        ]);
+
+        let args = [Value::I32(0x1ff), Value::I32(100)];
+        assert_eq!(Ok(vec![Value::I32(-1), Value::I64(0xff)]), run(&builder, 0, &args, true));
+        let args = [Value::I32(1), Value::I32(65533)];
+        assert_eq!(Err(Trap::MemoryOutOfBounds), run(&builder, 0, &args, true));
+    }
+
+    #[test]
+    fn memory64() {
+        let mut builder = ModuleBuilder::new();
+        builder.memory64(1, Some(3));
+        builder.func(&[I64, I32], &[I32], &[], &[
+            LOCAL_GET, 0, LOCAL_GET, 1, I32_STORE, 2, 0,
+            LOCAL_GET, 0, I32_LOAD, 2, 0, END,
+        ]);
+        // An offset of 4 GiB, which wraps to 0 if truncated to 32 bits
+        builder.func(&[I64], &[I32], &[], &[
+            LOCAL_GET, 0, I32_LOAD, 2, 0x80, 0x80, 0x80, 0x80, 0x10, END,
+        ]);
+        builder.func(&[I64], &[I64], &[], &[LOCAL_GET, 0, MEMORY_GROW, 0, END]);
+        builder.func(&[], &[I64], &[], &[MEMORY_SIZE, 0, END]);
+        builder.func(&[I64, I32, I64], &[], &[], &[
+            LOCAL_GET, 0, LOCAL_GET, 1, LOCAL_GET, 2, MISC_PREFIX, 11, 0, END,
+        ]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+        let mut vm = Vm::new(&module).unwrap();
+
+        let args = [Value::I64(65532), Value::I32(7)];
+        assert_eq!(Ok(vec![Value::I32(7)]), vm.invoke(0, &args));
+        for addr in [1 << 32, 65533, -1] {
+            let args = [Value::I64(addr), Value::I32(7)];
+            assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(0, &args));
+        }
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(1, &[Value::I64(0)]));
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(1, &[Value::I64(-1)]));
+
+        assert_eq!(Ok(vec![Value::I64(1)]), vm.invoke(2, &[Value::I64(1)]));
+        assert_eq!(Ok(vec![Value::I64(-1)]), vm.invoke(2, &[Value::I64(2)]));
+        assert_eq!(Ok(vec![Value::I64(-1)]), vm.invoke(2, &[Value::I64(1 << 32)]));
+        assert_eq!(Ok(vec![Value::I64(2)]), vm.invoke(3, &[]));
+
+        let args = [Value::I64(131070), Value::I32(0x2a), Value::I64(2)];
+        assert_eq!(Ok(vec![]), vm.invoke(4, &args));
+        assert_eq!(vec![0x2a, 0x2a], vm.memory(0).unwrap().data()[131070..].to_vec());
+        let args = [Value::I64(1 << 32), Value::I32(0), Value::I64(0)];
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(4, &args));
+
+        // Addresses must be i64, and 32-bit memories can't take 64-bit offsets
+        let mut builder = ModuleBuilder::new();
+        builder.memory64(1, None);
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, I32_LOAD, 2, 0, END]);
+        assert!(wasm_module::load(&builder.build()).is_err());
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, None);
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, I32_LOAD, 2, 0x80, 0x80, 0x80, 0x80, 0x10, END,
+        ]);
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("Offset out of range"));
+    }
+
+    #[test]
+    fn multiple_memories() {
+        let mut builder = ModuleBuilder::new();
+        builder.import_memory("env", "heap", 1, Some(1));
+        builder.memory(1, Some(2));
+        builder.export("heap", ExternalKind::Memory, 0);
+        builder.export("guest", ExternalKind::Memory, 1);
+        // Loads from memory 1, with the memory index flag set in the alignment
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, I32_LOAD, 0x42, 1, 0, END]);
+        // Copies from the imported memory into the module's own
+        builder.func(&[I32, I32, I32], &[], &[], &[
+            LOCAL_GET, 0, LOCAL_GET, 1, LOCAL_GET, 2, MISC_PREFIX, 10, 1, 0, END,
+        ]);
+        builder.func(&[I32, I32], &[], &[], &[LOCAL_GET, 0, LOCAL_GET, 1, I32_STORE, 2, 0, END]);
+        builder.func(&[], &[I32, I32, I32], &[], &[
+            I32_CONST, 1, MEMORY_GROW, 1, MEMORY_SIZE, 1, MEMORY_SIZE, 0, END,
+        ]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+
+        let heap = SharedMemory::new(Limits { min: 1, max: Some(1) });
+        heap.write(8, &0x1234_5678u32.to_le_bytes());
+        let imports = Imports { memories: vec![heap.clone()], ..Imports::default() };
+        let mut vm = Vm::with_imports(&module, imports).unwrap();
+        assert_eq!(Ok(vec![]), vm.invoke(1, &[Value::I32(100), Value::I32(8), Value::I32(4)]));
+        assert_eq!(Ok(vec![Value::I32(0x1234_5678)]), vm.invoke(0, &[Value::I32(100)]));
+        assert_eq!(Err(Trap::MemoryOutOfBounds), vm.invoke(0, &[Value::I32(65534)]));
+        // Stores to the imported memory are visible to the host, but not the guest heap
+        assert_eq!(Ok(vec![]), vm.invoke(2, &[Value::I32(16), Value::I32(-1)]));
+        assert_eq!(vec![0xff; 4], heap.to_vec()[16..20].to_vec());
+        assert_eq!(vec![0; 4], vm.exported_memory("guest").unwrap().data()[16..20].to_vec());
+        assert_eq!(vec![0xff; 4], vm.exported_memory("heap").unwrap().data()[16..20].to_vec());
+        let sizes = vec![Value::I32(1), Value::I32(2), Value::I32(1)];
+        assert_eq!(Ok(sizes), vm.invoke(3, &[]));
+
+        let missing = Vm::new(&module).err();
+        let msg = String::from("Memory import env.heap not provided");
+        assert_eq!(Some(Trap::InvalidArguments(msg)), missing);
+        let small = SharedMemory::new(Limits { min: 0, max: Some(1) });
+        let imports = Imports { memories: vec![small], ..Imports::default() };
+        assert!(Vm::with_imports(&module, imports).is_err());
+
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, None);
+        builder.func(&[], &[I32], &[], &[MEMORY_SIZE, 1, END]);
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("Unknown memory 1"));
+    }
+
+    #[test]
+    fn constant_expressions() {
+        let mut builder = ModuleBuilder::new();
+        let base = builder.import_global("env", "base", I32) as u8;
+        // base * 16 + 4, and 5 - 7
+        let offset = builder.global(I32, false, &[
+            GLOBAL_GET, base, I32_CONST, 16, I32_MUL, I32_CONST, 4, I32_ADD, END,
+        ]) as u8;
+        builder.global(I64, true, &[I64_CONST, 5, I64_CONST, 7, I64_SUB, END]);
+        builder.memory(1, None);
+        builder.data_at(&[GLOBAL_GET, offset, END], b"hi");
+        builder.func(&[], &[I32, I64], &[], &[GLOBAL_GET, 1, GLOBAL_GET, 2, END]);
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, I32_LOAD8_U, 0, 0, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+
+        let imports = Imports { globals: vec![Value::I32(2)], ..Imports::default() };
+        let mut vm = Vm::with_imports(&module, imports).unwrap();
+        assert_eq!(Ok(vec![Value::I32(36), Value::I64(-2)]), vm.invoke(0, &[]));
+        assert_eq!(Ok(vec![Value::I32(b'h' as i32)]), vm.invoke(1, &[Value::I32(36)]));
+
+        let msg = String::from("Global import env.base not provided");
+        assert_eq!(Some(Trap::InvalidArguments(msg)), Vm::new(&module).err());
+        let imports = Imports { globals: vec![Value::I64(2)], ..Imports::default() };
+        assert!(Vm::with_imports(&module, imports).is_err());
+
+        // Initializers can't read mutable globals or those after them
+        for init in [[GLOBAL_GET, 0, END], [GLOBAL_GET, 1, END]] {
+            let mut builder = ModuleBuilder::new();
+            builder.global(I32, true, &[I32_CONST, 0, END]);
+            builder.global(I32, false, &init);
+            let err = wasm_module::load(&builder.build()).err().unwrap();
+            assert!(err.formatted().contains("cannot read global"));
+        }
+        let mut builder = ModuleBuilder::new();
+        builder.global(I64, false, &[I32_CONST, 1, I64_CONST, 1, I64_ADD, END]);
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("type mismatch"));
+    }
+
+    #[test]
+    fn host_functions() {
+        let mut builder = ModuleBuilder::new();
+        let add = builder.import_func("env", "add", &[I32, I32], &[I32]) as u8;
+        let twice = builder.import_func("env", "twice", &[I32], &[I32]) as u8;
+        let double = builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, LOCAL_GET, 0, CALL, add, END,
+        ]);
+        // (x + 1) doubled by the host through `double`, then a tail call to the host
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, I32_CONST, 1, CALL, add, CALL, twice, I32_CONST, 3, RETURN_CALL, add,
+            END,
+        ]);
+        builder.export("double", ExternalKind::Func, double);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).unwrap_or_else(|e| panic!("{}", e.formatted()));
+
+        let add: HostFunc = Arc::new(|_, args| match args {
+            [Value::I32(a), Value::I32(b)] => Ok(vec![Value::I32(a + b)]),
+            _ => unreachable!(),
+        });
+        let twice: HostFunc = Arc::new(|vm, args| {
+            let double = vm.module().export("double").unwrap().index;
+            vm.invoke(double, args)
+        });
+        let imports = Imports { functions: vec![add, twice], ..Imports::default() };
+        let mut vm = Vm::with_imports(&module, imports).unwrap();
+        assert_eq!(Ok(vec![Value::I32(15)]), vm.invoke(3, &[Value::I32(5)]));
+        assert_eq!(Ok(vec![Value::I32(4)]), vm.invoke(0, &[Value::I32(1), Value::I32(3)]));
+
+        let msg = String::from("Func import env.add not provided");
+        assert_eq!(Some(Trap::InvalidArguments(msg)), Vm::new(&module).err());
+
+        // Traps in the host end the call, and so do results of the wrong type
+        let failing: HostFunc = Arc::new(|_, _| Err(Trap::Host(String::from("no"))));
+        let wrong: HostFunc = Arc::new(|_, _| Ok(vec![Value::I64(0)]));
+        let imports = Imports { functions: vec![wrong, failing], ..Imports::default() };
+        let mut vm = Vm::with_imports(&module, imports).unwrap();
+        assert!(matches!(vm.invoke(2, &[Value::I32(1)]), Err(Trap::Host(_))));
+        assert_eq!(Err(Trap::Host(String::from("no"))), vm.invoke(1, &[Value::I32(1)]));
+    }
+
+    #[test]
+    fn limiter_denies_memory_growth() {
+        let mut builder = ModuleBuilder::new();
+        builder.memory(1, Some(10));
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, MEMORY_GROW, 0, END]);
+        builder.func(&[], &[I32], &[], &[MEMORY_SIZE, 0, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let limiter = Arc::new(BasicLimiter::new().memory_size(4 * PAGE_SIZE));
+        let mut vm = Vm::with_limiter(&module, limiter).unwrap();
+        assert_eq!(Ok(vec![Value::I32(1)]), vm.invoke(0, &[Value::I32(2)]));
+        assert_eq!(Ok(vec![Value::I32(-1)]), vm.invoke(0, &[Value::I32(2)]));
+        assert_eq!(Ok(vec![Value::I32(3)]), vm.invoke(0, &[Value::I32(1)]));
+        assert_eq!(Ok(vec![Value::I32(4)]), vm.invoke(1, &[]));
+
+        // The declared maximum applies even without a limiter
+        let mut vm = Vm::new(&module).unwrap();
+        assert_eq!(Ok(vec![Value::I32(-1)]), vm.invoke(0, &[Value::I32(10)]));
+    }
+
+    #[test]
+    fn limiter_caps_instantiation() {
+        let mut builder = ModuleBuilder::new();
+        builder.memory(2, None);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let limiter = Arc::new(BasicLimiter::new().memory_size(PAGE_SIZE));
+        let result = Vm::with_limiter(&module, limiter);
+        assert!(matches!(result, Err(Trap::ResourceLimitExceeded(_))));
+
+        let limiter = Arc::new(BasicLimiter::new().instances(1));
+        let first = Vm::with_limiter(&module, limiter.clone()).unwrap();
+        let second = Vm::with_limiter(&module, limiter.clone());
+        assert!(matches!(second, Err(Trap::ResourceLimitExceeded(_))));
+        drop(first);
+        assert!(Vm::with_limiter(&module, limiter).is_ok());
+    }
+
+    #[test]
+    fn limiter_bounds_stack_depth() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[I32], &[], &[], &[LOCAL_GET, 0, CALL, 0, END]);
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+
+        let limiter = Arc::new(BasicLimiter::new().call_depth(10));
+        let mut vm = Vm::with_limiter(&module, limiter).unwrap();
+        assert_eq!(Err(Trap::CallStackExhausted), vm.invoke(0, &[Value::I32(0)]));
+
+        let limiter = Arc::new(BasicLimiter::new().stack_slots(100));
+        let mut vm = Vm::with_limiter(&module, limiter).unwrap();
+        assert_eq!(Err(Trap::ValueStackExhausted), vm.invoke(0, &[Value::I32(0)]));
+    }
+
+    #[test]
+    fn validation_rejects_type_mismatch() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[I32], &[], &[I64_CONST, 1, END]);
+        let err = wasm_module::load(&builder.build()).err().unwrap();
+        assert!(err.formatted().contains("expected I32, found I64"));
+    }
+
+    #[test]
+    fn typed_function_references() {
+        let double = RefType::non_null(HeapType::Concrete(0));
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[I32], &[I32], &[], &[LOCAL_GET, 0, I32_CONST, 2, I32_MUL, END]);
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, REF_FUNC, 0, RETURN_CALL_REF, 0, END,
+        ]);
+        // Falls back to the argument when the reference is null
+        builder.func(&[I32], &[I32], &[], &[
+            BLOCK, 0x7f, I32_CONST, 21, LOCAL_GET, 0,
+                IF, 0x63, 0, REF_FUNC, 0, ELSE, REF_NULL, 0, END,
+                BR_ON_NULL, 0, CALL_REF, 0,
+            END, END,
+        ]);
+        // Only reaches the non-nullable local through the branch
+        builder.func(&[I32], &[I32], &[Ref(double)], &[
+            BLOCK, 0x64, 0, LOCAL_GET, 0,
+                IF, 0x63, 0, REF_FUNC, 0, ELSE, REF_NULL, 0, END,
+                BR_ON_NON_NULL, 0, I32_CONST, 7, RETURN,
+            END, LOCAL_SET, 1, I32_CONST, 5, LOCAL_GET, 1, CALL_REF, 0, END,
+        ]);
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, REF_NULL, 0, REF_AS_NON_NULL, CALL_REF, 0, END,
+        ]);
+        let table = builder.table_with_init(double, 1, &[REF_FUNC, 0, END]);
+        builder.func(&[I32], &[I32], &[], &[
+            LOCAL_GET, 0, I32_CONST, 0, TABLE_GET, table as u8, CALL_REF, 0, END,
+        ]);
+        let nullable = Ref(RefType::null(HeapType::Concrete(0)));
+        builder.func(&[I32, nullable], &[I32], &[], &[
+            LOCAL_GET, 0, LOCAL_GET, 1, CALL_REF, 0, END,
+        ]);
+
+        for checked in [false, true] {
+            let call = |func, arg| run(&builder, func, &[Value::I32(arg)], checked);
+            assert_eq!(Ok(vec![Value::I32(8)]), call(1, 4));
+            assert_eq!(Ok(vec![Value::I32(42)]), call(2, 1));
+            assert_eq!(Ok(vec![Value::I32(21)]), call(2, 0));
+            assert_eq!(Ok(vec![Value::I32(10)]), call(3, 1));
+            assert_eq!(Ok(vec![Value::I32(7)]), call(3, 0));
+            assert_eq!(Err(Trap::NullReference), call(4, 1));
+            assert_eq!(Ok(vec![Value::I32(6)]), call(5, 3));
+        }
+
+        // Function reference arguments must be of the parameter's type
+        let args = |func| [Value::I32(3), Value::FuncRef(func)];
+        assert_eq!(Ok(vec![Value::I32(6)]), run(&builder, 6, &args(0), true));
+        assert!(matches!(run(&builder, 6, &args(6), true), Err(Trap::InvalidArguments(_))));
+    }
+
+    #[test]
+    fn validation_of_typed_references() {
+        let nullable = Ref(RefType::null(HeapType::Concrete(0)));
+        let non_null = Ref(RefType::non_null(HeapType::Concrete(0)));
+        let load = |builder: &ModuleBuilder| wasm_module::load(&builder.build()).err();
+
+        // Non-null references are also nullable ones and funcrefs, but not the reverse
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[END]);
+        builder.func(&[], &[ValType::FUNCREF], &[], &[REF_FUNC, 0, END]);
+        builder.func(&[non_null], &[nullable], &[], &[LOCAL_GET, 0, END]);
+        assert!(load(&builder).is_none());
+        builder.func(&[nullable], &[non_null], &[], &[LOCAL_GET, 0, END]);
+        assert!(load(&builder).unwrap().formatted().contains("Type mismatch"));
+
+        // A non-nullable local must be set before it is read, in the same block or an
+        // enclosing one
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[END]);
+        builder.func(&[], &[], &[non_null], &[
+            REF_FUNC, 0, LOCAL_SET, 0, BLOCK, 0x40, LOCAL_GET, 0, DROP, END, END,
+        ]);
+        assert!(load(&builder).is_none());
+        builder.func(&[], &[], &[non_null], &[
+            BLOCK, 0x40, REF_FUNC, 0, LOCAL_SET, 0, END, LOCAL_GET, 0, DROP, END,
+        ]);
+        assert!(load(&builder).unwrap().formatted().contains("Uninitialized local 0"));
+
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[REF_NULL, 0x70, CALL_REF, 0, END]);
+        assert!(load(&builder).unwrap().formatted().contains("Type mismatch"));
+
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[END]);
+        builder.table(RefType::non_null(HeapType::Concrete(0)), 1, None);
+        assert!(load(&builder).unwrap().formatted().contains("needs an initializer"));
+    }
+
+    #[test]
+    fn garbage_collection() {
+        use crate::bytecode::gc::*;
+        use crate::test_util::gc as op;
+        use crate::value::{CompositeType, FieldType, StorageType, SubType};
+
+        let field = |storage, mutable| FieldType { storage, mutable };
+        let mut builder = ModuleBuilder::new();
+        let pair = builder.struct_type(&[
+            field(StorageType::Val(I32), true),
+            field(StorageType::I8, false),
+        ]);
+        let point = builder.rec_group(vec![
+            SubType {
+                is_final: false,
+                supertype: None,
+                composite: CompositeType::Struct(vec![field(StorageType::Val(I32), false)]),
+            },
+            SubType {
+                is_final: true,
+                supertype: Some(1),
+                composite: CompositeType::Struct(vec![field(StorageType::Val(I32), false); 2]),
+            },
+        ]);
+        let point3 = point + 1;
+        let shorts = builder.array_type(field(StorageType::I16, true));
+        let node_ref = Ref(RefType::null(HeapType::Concrete(4)));
+        let node = builder.struct_type(&[
+            field(StorageType::Val(I32), false),
+            field(StorageType::Val(node_ref), false),
+        ]);
+        assert_eq!((0, 1, 2, 3, 4), (pair, point, point3, shorts, node));
+
+        // Packed fields are truncated when stored and extended when read
+        builder.func(&[I32], &[I32], &[Ref(RefType::null(HeapType::Concrete(0)))], &[
+            vec![LOCAL_GET, 0, I32_CONST, 0xc8, 0x01], op(STRUCT_NEW), vec![0, LOCAL_SET, 1],
+            vec![LOCAL_GET, 1, LOCAL_GET, 1], op(STRUCT_GET), vec![0, 0, I32_CONST, 1, I32_ADD],
+            op(STRUCT_SET), vec![0, 0],
+            vec![LOCAL_GET, 1], op(STRUCT_GET), vec![0, 0],
+            vec![LOCAL_GET, 1], op(STRUCT_GET_S), vec![0, 1, I32_ADD, END],
+        ].concat());
+        builder.func(&[I32], &[I32], &[Ref(RefType::null(HeapType::Concrete(3)))], &[
+            vec![I32_CONST, 0x85, 0x80, 0x04, LOCAL_GET, 0], op(ARRAY_NEW), vec![3, LOCAL_SET, 1],
+            vec![LOCAL_GET, 1, I32_CONST, 0, I32_CONST, 0x7f], op(ARRAY_SET), vec![3],
+            vec![LOCAL_GET, 1, I32_CONST, 0], op(ARRAY_GET_S), vec![3],
+            vec![LOCAL_GET, 1, I32_CONST, 0], op(ARRAY_GET_U), vec![3, I32_ADD],
+            vec![LOCAL_GET, 1], op(ARRAY_LEN), vec![I32_ADD],
+            vec![LOCAL_GET, 1, I32_CONST, 1], op(ARRAY_GET_U), vec![3, I32_ADD, END],
+        ].concat());
+        // Casts see the type an object was allocated with, including subtypes
+        builder.func(&[I32], &[I32], &[ValType::ANYREF], &[
+            vec![LOCAL_GET, 0, IF, 0x6e, I32_CONST, 1, I32_CONST, 2], op(STRUCT_NEW), vec![2],
+            vec![ELSE, I32_CONST, 7], op(STRUCT_NEW), vec![1, END, LOCAL_SET, 1],
+            vec![BLOCK, 0x64, 1, LOCAL_GET, 1], op(BR_ON_CAST), vec![1, 0, 0x6e, 1],
+            vec![DROP, I32_CONST, 0x7f, RETURN, END], op(STRUCT_GET), vec![1, 0],
+            vec![LOCAL_GET, 1], op(REF_TEST), vec![2, I32_CONST, 0xe4, 0x00, I32_MUL, I32_ADD, END],
+        ].concat());
+        builder.func(&[I32], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(REF_I31), op(I31_GET_S), vec![END],
+        ].concat());
+        builder.func(&[I32], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(REF_I31), op(REF_CAST), vec![1], op(STRUCT_GET), vec![1, 0],
+            vec![END],
+        ].concat());
+        // Converting to an externref and back gives the same reference
+        builder.func(&[], &[I32], &[Ref(RefType::null(HeapType::Eq))], &[
+            op(STRUCT_NEW_DEFAULT), vec![0, LOCAL_TEE, 0, LOCAL_GET, 0, REF_EQ, I32_CONST, 2],
+            vec![I32_MUL, LOCAL_GET, 0], op(STRUCT_NEW_DEFAULT), vec![0, REF_EQ, I32_ADD],
+            vec![LOCAL_GET, 0], op(EXTERN_CONVERT_ANY), op(ANY_CONVERT_EXTERN),
+            op(REF_CAST_NULL), vec![0, LOCAL_GET, 0, REF_EQ, I32_ADD, END],
+        ].concat());
+
+        for checked in [false, true] {
+            let call = |func, arg| run(&builder, func, &[Value::I32(arg)], checked);
+            assert_eq!(Ok(vec![Value::I32(-50)]), call(0, 5));
+            assert_eq!(Ok(vec![Value::I32(65541)]), call(1, 2));
+            assert_eq!(Err(Trap::ArrayOutOfBounds), call(1, 1));
+            assert_eq!(Ok(vec![Value::I32(101)]), call(2, 1));
+            assert_eq!(Ok(vec![Value::I32(7)]), call(2, 0));
+            assert_eq!(Ok(vec![Value::I32(-5)]), call(3, -5));
+            assert_eq!(Ok(vec![Value::I32(-1)]), call(3, i32::MAX));
+            assert_eq!(Err(Trap::CastFailure), call(4, 3));
+            assert_eq!(Ok(vec![Value::I32(3)]), run(&builder, 5, &[], checked));
+        }
+
+        // Objects reachable from globals and locals survive collections
+        let global = builder.global(node_ref, true, &[REF_NULL, 4, END]) as u8;
+        let garbage = builder.func(&[I32], &[I32], &[I32, node_ref], &[
+            vec![I32_CONST, 11, REF_NULL, 4], op(STRUCT_NEW), vec![4, GLOBAL_SET, global],
+            vec![I32_CONST, 22, GLOBAL_GET, global], op(STRUCT_NEW), vec![4, LOCAL_SET, 2],
+            vec![LOOP, 0x40, I32_CONST, 0, REF_NULL, 4], op(STRUCT_NEW), vec![4, DROP],
+            vec![LOCAL_GET, 1, I32_CONST, 1, I32_ADD, LOCAL_TEE, 1, LOCAL_GET, 0, I32_LT_U],
+            vec![BR_IF, 0, END],
+            vec![LOCAL_GET, 2], op(STRUCT_GET), vec![4, 1], op(STRUCT_GET), vec![4, 0],
+            vec![LOCAL_GET, 2], op(STRUCT_GET), vec![4, 0, I32_ADD],
+            vec![GLOBAL_GET, global], op(STRUCT_GET), vec![4, 0, I32_ADD, END],
+        ].concat());
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+        let mut vm = Vm::new(&module).unwrap();
+        let result = vm.invoke(garbage as usize, &[Value::I32(5000)]);
+        assert_eq!(Ok(vec![Value::I32(44)]), result);
+        assert!(vm.heap.len() <= 1024);
+    }
+
+    #[test]
+    fn host_references_are_roots() {
+        use crate::bytecode::gc::*;
+        use crate::test_util::gc as op;
+        use crate::value::{FieldType, StorageType};
+
+        let mut builder = ModuleBuilder::new();
+        builder.struct_type(&[FieldType { storage: StorageType::Val(I32), mutable: false }]);
+        let object = Ref(RefType::null(HeapType::Concrete(0)));
+        builder.func(&[], &[object], &[], &[
+            vec![I32_CONST, 42], op(STRUCT_NEW), vec![0, END],
+        ].concat());
+        builder.func(&[object], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(STRUCT_GET), vec![0, 0, END],
+        ].concat());
+        builder.func(&[I32], &[], &[], &[
+            vec![LOOP, 0x40, I32_CONST, 0], op(STRUCT_NEW), vec![0, DROP],
+            vec![LOCAL_GET, 0, I32_CONST, 1, I32_SUB, LOCAL_TEE, 0, BR_IF, 0, END, END],
+        ].concat());
+        builder.func(&[ValType::EXTERNREF], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(ANY_CONVERT_EXTERN), op(REF_TEST), vec![0, END],
+        ].concat());
+        builder.func(&[object], &[ValType::EXTERNREF], &[], &[
+            vec![LOCAL_GET, 0], op(EXTERN_CONVERT_ANY), vec![END],
+        ].concat());
+        let bytes = builder.build();
+        let module = wasm_module::load(&bytes).ok().unwrap();
+        let mut vm = Vm::new(&module).unwrap();
+
+        // A result survives the collections of later invocations
+        let held = vm.invoke(0, &[]).unwrap()[0];
+        for _ in 0..3000 {
+            vm.invoke(2, &[Value::I32(1)]).unwrap();
+        }
+        assert_eq!(Ok(vec![Value::I32(42)]), vm.invoke(1, &[held]));
+        let converted = vm.invoke(4, &[held]).unwrap()[0];
+        assert_eq!(Ok(vec![Value::I32(1)]), vm.invoke(3, &[converted]));
+        vm.release(held);
+        vm.release(converted);
+        assert!(vm.host_refs.is_empty());
+        vm.invoke(2, &[Value::I32(2000)]).unwrap();
+        assert!(vm.heap.len() < 2000);
+
+        // Host handles can't pass for objects once converted
+        assert_eq!(Ok(vec![Value::I32(0)]), vm.invoke(3, &[Value::ExternRef(ExternRef(3))]));
+        let forged = Value::ExternRef(ExternRef(objects::OBJECT_TAG | objects::CONVERTED_TAG));
+        assert!(matches!(vm.invoke(3, &[forged]), Err(Trap::InvalidArguments(_))));
+    }
+
+    #[test]
+    fn validation_of_gc_types() {
+        use crate::bytecode::gc::*;
+        use crate::test_util::gc as op;
+        use crate::value::{CompositeType, FieldType, StorageType, SubType};
+
+        let load = |builder: &ModuleBuilder| wasm_module::load(&builder.build()).err();
+        let field = |storage, mutable| FieldType { storage, mutable };
+        let int = |mutable| CompositeType::Struct(vec![field(StorageType::Val(I32), mutable)]);
+        let sub = |is_final, supertype, composite| SubType { is_final, supertype, composite };
+
+        // Identical recursion groups define the same type
+        let mut builder = ModuleBuilder::new();
+        builder.struct_type(&[field(StorageType::Val(I32), false)]);
+        builder.struct_type(&[field(StorageType::Val(I32), false)]);
+        let (first, second) = (HeapType::Concrete(0), HeapType::Concrete(1));
+        builder.func(&[Ref(RefType::null(second))], &[Ref(RefType::null(first))], &[], &[
+            LOCAL_GET, 0, END,
+        ]);
+        assert!(load(&builder).is_none());
+
+        let mut builder = ModuleBuilder::new();
+        builder.rec_group(vec![sub(true, None, int(false)), sub(true, Some(0), int(false))]);
+        assert!(load(&builder).unwrap().formatted().contains("extends final type 0"));
+
+        // Mutable fields can't change type in a subtype
+        let mut builder = ModuleBuilder::new();
+        builder.rec_group(vec![sub(false, None, int(true)), sub(true, Some(0), int(false))]);
+        assert!(load(&builder).unwrap().formatted().contains("does not match its supertype"));
+
+        // Types may only refer forward within their own recursion group
+        let mut builder = ModuleBuilder::new();
+        let next = Ref(RefType::null(HeapType::Concrete(1)));
+        builder.struct_type(&[field(StorageType::Val(next), false)]);
+        builder.struct_type(&[]);
+        assert!(load(&builder).unwrap().formatted().contains("Type 0 refers to unknown type"));
+
+        let mut builder = ModuleBuilder::new();
+        builder.struct_type(&[field(StorageType::I8, false)]);
+        let object = Ref(RefType::non_null(HeapType::Concrete(0)));
+        builder.func(&[object], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(STRUCT_GET_U), vec![0, 0, END],
+        ].concat());
+        assert!(load(&builder).is_none());
+        builder.func(&[object], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(STRUCT_GET), vec![0, 0, END],
+        ].concat());
+        assert!(load(&builder).unwrap().formatted().contains("Packed fields"));
+
+        let mut builder = ModuleBuilder::new();
+        builder.struct_type(&[field(StorageType::Val(I32), false)]);
+        builder.func(&[object], &[], &[], &[
+            vec![LOCAL_GET, 0, I32_CONST, 1], op(STRUCT_SET), vec![0, 0, END],
+        ].concat());
+        assert!(load(&builder).unwrap().formatted().contains("immutable field"));
+
+        // Casts stay within a hierarchy
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[ValType::EXTERNREF], &[I32], &[], &[
+            vec![LOCAL_GET, 0], op(REF_TEST), vec![0x6c, END],
+        ].concat());
+        assert!(load(&builder).unwrap().formatted().contains("cannot cast"));
+    }
+}
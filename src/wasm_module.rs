@@ -1,19 +1,424 @@
 #![allow(dead_code)]
 
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, Range};
+use std::sync::{Arc, OnceLock};
+
+use memmap2::Mmap;
+
 use crate::value::*;
-use crate::bytecode;
+use crate::bytecode::{self, gc, op::*, simd};
+use crate::bytecode::read::Reader;
+use crate::const_expr::{ConstExpr, ConstOp};
+use crate::validate;
+
+/// Loads a module that borrows `bytecode` for its code and custom sections.
+pub fn load(bytecode: &[u8]) -> Result<WasmModule<'_>, WasmLoadError> {
+    load_bytes(Bytes::Borrowed(bytecode))
+}
 
-pub fn load(bytecode: &[u8]) -> Result<WasmModule, WasmLoadError> {
-    WasmModuleLoader::new(bytecode).load()
+/// Loads a module that keeps `bytes` for its code and custom sections, rather than
+/// copying them.
+pub fn load_bytes(bytes: Bytes<'_>) -> Result<WasmModule<'_>, WasmLoadError> {
+    let mut module = WasmModuleLoader::new(&bytes, 0, WasmModule::default()).load()?;
+    module.bytes = bytes;
+    let code_offset = module.code_range.start;
+    validate::validate(&mut module).map_err(|err| validation_error(err, code_offset))?;
+    Ok(module)
+}
+
+/// The binary of a module, which may be borrowed, shared or mapped from a file.
+pub enum Bytes<'a> {
+    Borrowed(&'a [u8]),
+    Shared(Arc<[u8]>),
+    Mapped(Mmap),
+    /// Bytes read from a stream, which grow as more arrive.
+    Owned(Vec<u8>),
+}
+
+impl Bytes<'static> {
+    /// Maps `file` into memory, which must not change while a module refers to it.
+    pub fn map(file: &File) -> io::Result<Self> {
+        // SAFETY: Modules never write to the mapping, and a file changing underneath it
+        // is the caller's to rule out
+        unsafe { Mmap::map(file) }.map(Bytes::Mapped)
+    }
+}
+
+impl Deref for Bytes<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Bytes::Borrowed(bytes) => bytes,
+            Bytes::Shared(bytes) => bytes,
+            Bytes::Mapped(map) => map,
+            Bytes::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl Default for Bytes<'_> {
+    fn default() -> Self {
+        Bytes::Borrowed(&[])
+    }
+}
+
+impl fmt::Debug for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bytes({} bytes)", self.len())
+    }
+}
+
+impl From<Arc<[u8]>> for Bytes<'static> {
+    fn from(bytes: Arc<[u8]>) -> Self {
+        Bytes::Shared(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Bytes<'static> {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytes::Owned(bytes)
+    }
+}
+
+/// The version and layer from the preamble of a module or component, which follow the
+/// magic number as little-endian u16s.
+pub fn preamble(bytecode: &[u8]) -> Result<(u16, u16), String> {
+    if bytecode.len() < 8 {
+        return Err(String::from("Magic Number not found"));
+    }
+    if bytecode::read::read_32(bytecode, 0) != WASM_BINARY_MAGIC {
+        return Err(String::from("Magic Number not found"));
+    }
+    let field = |at: usize| u16::from_le_bytes([bytecode[at], bytecode[at + 1]]);
+    Ok((field(4), field(6)))
+}
+
+/// A custom section, whose contents stay in the module's bytes until asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomSection {
+    pub name: String,
+    /// Where the section, starting with its id, is in the binary.
+    pub offset: usize,
+    /// Where the contents after the name are in the binary.
+    pub contents: Range<usize>,
 }
 
 #[derive(Debug, Default)]
-pub struct WasmModule {
+pub struct WasmModule<'a> {
     pub version: u32,
-    pub types: Vec<FuncType>,
+    pub types: Vec<SubType>,
+    /// The range of type indices in each recursion group, in order. Types outside a
+    /// `rec` are in a group of their own.
+    pub rec_groups: Vec<Range<u32>>,
+    /// The imported functions, then those the module defines.
     pub functions: Vec<Function>,
+    pub tables: Vec<Table>,
+    /// The imported memories, then those the module defines.
+    pub memories: Vec<MemoryType>,
+    /// The type index of each exception tag, whose params are the values it carries.
+    pub tags: Vec<usize>,
+    pub globals: Vec<Global>,
+    pub imports: Vec<Import>,
+    pub exports: Vec<Export>,
     pub start_function: Option<usize>,
-    pub code_section: Vec<u8>
+    pub elements: Vec<ElementSegment>,
+    pub data: Vec<DataSegment>,
+    /// The number of data segments declared by the DataCount section, if present.
+    pub data_count: Option<u32>,
+    /// The binary the module was loaded from, which its code and custom sections refer
+    /// to rather than copy.
+    pub bytes: Bytes<'a>,
+    /// Where the contents of the code section are in `bytes`.
+    pub code_range: Range<usize>,
+    /// Every custom section, in the order they appear.
+    pub custom_sections: Vec<CustomSection>,
+    /// The names from the name section, decoded when first asked for.
+    function_names: OnceLock<HashMap<usize, String>>,
+}
+
+impl WasmModule<'_> {
+    /// The contents of the code section, which function bodies are relative to.
+    pub fn code_section(&self) -> &[u8] {
+        &self.bytes[self.code_range.clone()]
+    }
+
+    /// The instructions of a function's body, after its local declarations. Imported
+    /// functions have none.
+    pub fn function_code(&self, idx: usize) -> &[u8] {
+        let function = &self.functions[idx];
+        &self.code_section()[function.code_start..function.code_start + function.code_len]
+    }
+
+    /// The contents of the first custom section called `name`.
+    pub fn custom_section(&self, name: &str) -> Option<&[u8]> {
+        let section = self.custom_sections.iter().find(|section| section.name == name)?;
+        Some(self.custom_contents(section))
+    }
+
+    /// The contents of every custom section called `name`, in order.
+    pub fn custom_sections_named<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'s [u8]> {
+        self.custom_sections.iter()
+            .filter(move |section| section.name == name)
+            .map(|section| self.custom_contents(section))
+    }
+
+    pub fn custom_contents(&self, section: &CustomSection) -> &[u8] {
+        &self.bytes[section.contents.clone()]
+    }
+
+    /// The function names from the name section, which is decoded on first use.
+    pub fn function_names(&self) -> &HashMap<usize, String> {
+        self.function_names.get_or_init(|| {
+            self.custom_section("name").map(function_names).unwrap_or_default()
+        })
+    }
+
+    /// The function's name from the name section, if it has one.
+    pub fn function_name(&self, idx: usize) -> Option<&str> {
+        self.function_names().get(&idx).map(String::as_str)
+    }
+
+    /// The function's name, or `func[idx]` if it has none, for reports and traces.
+    pub fn function_label(&self, idx: usize) -> String {
+        match self.function_name(idx) {
+            Some(name) => name.to_string(),
+            None => format!("func[{idx}]"),
+        }
+    }
+
+    /// The export called `name`, if there is one.
+    pub fn export(&self, name: &str) -> Option<&Export> {
+        self.exports.iter().find(|export| export.name == name)
+    }
+
+    /// The imports of a kind, in the order the host must provide them.
+    pub fn imports_of(&self, kind: ExternalKind) -> impl Iterator<Item = &Import> {
+        self.imports.iter().filter(move |import| import.kind == kind)
+    }
+
+    /// The function type at `idx`, which validation has checked is one.
+    pub fn func_type(&self, idx: usize) -> &FuncType {
+        self.get_func_type(idx).unwrap_or_else(|| panic!("Type {idx} is not a function type"))
+    }
+
+    pub fn get_func_type(&self, idx: usize) -> Option<&FuncType> {
+        match &self.types.get(idx)?.composite {
+            CompositeType::Func(functype) => Some(functype),
+            _ => None,
+        }
+    }
+
+    pub fn struct_type(&self, idx: usize) -> Option<&[FieldType]> {
+        match &self.types.get(idx)?.composite {
+            CompositeType::Struct(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    pub fn array_type(&self, idx: usize) -> Option<&FieldType> {
+        match &self.types.get(idx)?.composite {
+            CompositeType::Array(elem) => Some(elem),
+            _ => None,
+        }
+    }
+
+    /// The top of a heap type's family: `func` for function types, and `any` for
+    /// structs and arrays.
+    pub fn top(&self, heap: HeapType) -> HeapType {
+        match heap {
+            HeapType::Concrete(idx) => match &self.types[idx as usize].composite {
+                CompositeType::Func(_) => HeapType::Func,
+                _ => HeapType::Any,
+            },
+            heap => heap.top().unwrap(),
+        }
+    }
+
+    /// The type with references widened to the nullable top of their family, which is
+    /// all the interpreter distinguishes at runtime.
+    pub fn erased(&self, ty: ValType) -> ValType {
+        match ty {
+            ValType::Ref(ty) => ValType::Ref(RefType::null(self.top(ty.heap))),
+            ty => ty,
+        }
+    }
+
+    /// The recursion group containing type `idx`.
+    pub fn rec_group(&self, idx: u32) -> Range<u32> {
+        let group = self.rec_groups.partition_point(|group| group.end <= idx);
+        self.rec_groups[group].clone()
+    }
+
+    /// Whether two types are the same once their recursion groups are compared by
+    /// structure: in the same position of groups whose definitions match, with
+    /// references within a group matching by their relative position.
+    pub fn types_equivalent(&self, a: u32, b: u32) -> bool {
+        if a == b {
+            return true;
+        }
+        let (group_a, group_b) = (self.rec_group(a), self.rec_group(b));
+        if group_a.len() != group_b.len() || a - group_a.start != b - group_b.start {
+            return false;
+        }
+        // Types outside the group are in earlier groups, so this recursion ends
+        let same = |x: HeapType, y: HeapType| match (x, y) {
+            (HeapType::Concrete(x), HeapType::Concrete(y)) => {
+                match (group_a.contains(&x), group_b.contains(&y)) {
+                    (true, true) => x - group_a.start == y - group_b.start,
+                    (false, false) => self.types_equivalent(x, y),
+                    _ => false,
+                }
+            }
+            _ => x == y,
+        };
+        group_a.clone().zip(group_b.clone()).all(|(x, y)| {
+            sub_types_equal(&self.types[x as usize], &self.types[y as usize], &same)
+        })
+    }
+
+    /// Whether a value of type `sub` may be used where one of type `sup` is expected.
+    pub fn matches(&self, sub: ValType, sup: ValType) -> bool {
+        match (sub, sup) {
+            (ValType::Ref(sub), ValType::Ref(sup)) => self.ref_matches(sub, sup),
+            _ => sub == sup,
+        }
+    }
+
+    /// Whether each of `sub` matches the type at the same position in `sup`.
+    pub fn all_match(&self, sub: &[ValType], sup: &[ValType]) -> bool {
+        sub.len() == sup.len() && sub.iter().zip(sup).all(|(sub, sup)| self.matches(*sub, *sup))
+    }
+
+    pub fn ref_matches(&self, sub: RefType, sup: RefType) -> bool {
+        (sup.nullable || !sub.nullable) && self.heap_matches(sub.heap, sup.heap)
+    }
+
+    /// Heap subtyping. A concrete type matches another if it is equivalent to it or to
+    /// one of its declared supertypes.
+    pub fn heap_matches(&self, sub: HeapType, sup: HeapType) -> bool {
+        use HeapType::*;
+        if sub == sup {
+            return true;
+        }
+        match (sub, sup) {
+            (Concrete(mut sub), Concrete(sup)) => loop {
+                if self.types_equivalent(sub, sup) {
+                    return true;
+                }
+                match self.types[sub as usize].supertype {
+                    Some(supertype) => sub = supertype,
+                    Option::None => return false,
+                }
+            },
+            (Concrete(idx), sup) => match self.types[idx as usize].composite {
+                CompositeType::Func(_) => sup == Func,
+                CompositeType::Struct(_) => matches!(sup, Struct | Eq | Any),
+                CompositeType::Array(_) => matches!(sup, Array | Eq | Any),
+            },
+            (None, sup) => self.top(sup) == Any,
+            (NoFunc, sup) => self.top(sup) == Func,
+            (NoExtern, Extern) | (NoExn, Exn) => true,
+            (I31 | Struct | Array, Eq) | (I31 | Struct | Array | Eq, Any) => true,
+            _ => false,
+        }
+    }
+
+    /// Finds a function by its name from the name section.
+    pub fn function_by_name(&self, name: &str) -> Option<usize> {
+        self.function_names().iter().find(|(_, n)| *n == name).map(|(idx, _)| *idx)
+    }
+}
+
+/// Reads the function names from the contents of a name section. Malformed names are
+/// ignored rather than invalidating the module.
+fn function_names(section: &[u8]) -> HashMap<usize, String> {
+    let mut names = HashMap::new();
+    let mut reader = Reader::new(section, 0);
+    while let (Ok(id), Ok(size)) = (reader.u8(), reader.u32()) {
+        let Ok(contents) = reader.take(size as usize) else {
+            break;
+        };
+        if id == 0x01 {
+            let mut reader = Reader::new(contents, 0);
+            for _ in 0..reader.u32().unwrap_or_default() {
+                match (reader.u32(), reader.name()) {
+                    (Ok(idx), Ok(name)) => names.insert(idx as usize, name),
+                    _ => break,
+                };
+            }
+        }
+    }
+    names
+}
+
+/// Whether two type definitions are the same, comparing the heap types they refer to
+/// with `same`.
+fn sub_types_equal(a: &SubType, b: &SubType, same: &dyn Fn(HeapType, HeapType) -> bool) -> bool {
+    let val = |a: &ValType, b: &ValType| match (a, b) {
+        (ValType::Ref(a), ValType::Ref(b)) => a.nullable == b.nullable && same(a.heap, b.heap),
+        _ => a == b,
+    };
+    let vals = |a: &[ValType], b: &[ValType]| {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| val(a, b))
+    };
+    let field = |a: &FieldType, b: &FieldType| {
+        a.mutable == b.mutable && match (&a.storage, &b.storage) {
+            (StorageType::Val(a), StorageType::Val(b)) => val(a, b),
+            (a, b) => a == b,
+        }
+    };
+    let supertypes = match (a.supertype, b.supertype) {
+        (Some(a), Some(b)) => same(HeapType::Concrete(a), HeapType::Concrete(b)),
+        (a, b) => a == b,
+    };
+    a.is_final == b.is_final && supertypes && match (&a.composite, &b.composite) {
+        (CompositeType::Func(a), CompositeType::Func(b)) => {
+            vals(&a.params, &b.params) && vals(&a.results, &b.results)
+        }
+        (CompositeType::Struct(a), CompositeType::Struct(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| field(a, b))
+        }
+        (CompositeType::Array(a), CompositeType::Array(b)) => field(a, b),
+        _ => false,
+    }
+}
+
+/// Decodes the section starting at `start` in the module's bytes, for a module whose
+/// bytes arrive a section at a time.
+pub fn load_section(module: &mut WasmModule, start: usize) -> Result<(), WasmLoadError> {
+    let bytes = std::mem::take(&mut module.bytes);
+    let mut loader = WasmModuleLoader::new(&bytes, start, std::mem::take(module));
+    let result = loader.reader.u8().and_then(|id| loader.section(id));
+    let result = result.map_err(|msg| loader.error(&msg));
+    *module = loader.module;
+    module.bytes = bytes;
+    result
+}
+
+/// Decodes the size and locals of function `idx`, whose body starts at `start` in the
+/// module's bytes, within its code section.
+pub fn load_function_body(
+    module: &mut WasmModule,
+    idx: usize,
+    start: usize,
+) -> Result<(), WasmLoadError> {
+    let bytes = std::mem::take(&mut module.bytes);
+    let mut loader = WasmModuleLoader::new(&bytes, start, std::mem::take(module));
+    let code_start = loader.module.code_range.start;
+    let result = loader.function_body(idx, code_start).map_err(|msg| loader.error(&msg));
+    *module = loader.module;
+    module.bytes = bytes;
+    result
+}
+
+/// Locates a validation failure, which is relative to the code section.
+pub fn validation_error(err: validate::ValidationError, code_offset: usize) -> WasmLoadError {
+    WasmLoadError { byte: code_offset + err.offset, msg: err.msg }
 }
 
 pub struct WasmLoadError {
@@ -22,222 +427,637 @@ pub struct WasmLoadError {
 }
 
 impl WasmLoadError {
+    /// An error in the byte at `offset`.
+    pub fn at(offset: usize, msg: &str) -> Self {
+        Self { byte: offset + 1, msg: msg.to_string() }
+    }
+
+    /// The error moved `start` bytes later, for a binary nested in another.
+    pub fn offset_by(self, start: usize) -> Self {
+        Self { byte: self.byte + start, ..self }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
     pub fn formatted(&self) -> String {
         format!("Error at byte {:#04x}: {}", self.byte-1, self.msg)
     }
 }
 
-const WASM_BINARY_MAGIC: u32 = 0x0061_736d;
+pub const WASM_BINARY_MAGIC: u32 = 0x0061_736d;
+/// The layer field of the preamble, which tells core modules from components.
+pub const MODULE_LAYER: u16 = 0x00;
+pub const COMPONENT_LAYER: u16 = 0x01;
+const MAX_LOCALS: usize = 50_000;
 
-struct WasmModuleLoader<'a> {
-    bytecode: &'a [u8],
-    byte: usize,
-    module: WasmModule,
-    error: Option<WasmLoadError>,
-}
-
-impl<'a> WasmModuleLoader<'a> {
-    fn new(bytecode: &'a [u8]) -> Self {
-        Self {
-            bytecode,
-            byte: 0,
-            module: WasmModule::default(),
-            error: None,
-        }
-    }
-
-    fn load(mut self) -> Result<WasmModule, WasmLoadError> {
-        match self.preliminary() {
-            Ok(version) => self.module.version = version,
-            Err(msg) => self.error(&msg),
-        }
-
-        while self.error.is_none() && self.byte < self.bytecode.len() {
-            match self.read_byte() {
-                0x00 => self.custom(),
-                0x01 => self.types(),
-                0x02 => self.imports(),
-                0x03 => self.functions(),
-                0x04 => self.tables(),
-                0x05 => self.memory(),
-                0x06 => self.globals(),
-                0x07 => self.exports(),
-                0x08 => self.start(),
-                0x09 => self.element(),
-                0x0a => self.code(),
-                0x0b => self.data(),
-                b => self.error(&format!("Invalid section code {b:#04x}.")),
+/// Decodes the sections of a module from `reader` into `module`, which gets the bytes
+/// once they have all been decoded.
+struct WasmModuleLoader<'a, 'm> {
+    reader: Reader<'a>,
+    module: WasmModule<'m>,
+}
+
+impl<'a, 'm> WasmModuleLoader<'a, 'm> {
+    fn new(bytes: &'a [u8], pos: usize, module: WasmModule<'m>) -> Self {
+        Self { reader: Reader::new(bytes, pos), module }
+    }
+
+    fn load(mut self) -> Result<WasmModule<'m>, WasmLoadError> {
+        self.reader.pos = 8;
+        match preamble(self.reader.bytes) {
+            Ok((version, MODULE_LAYER)) => self.module.version = version as u32,
+            Ok((_, COMPONENT_LAYER)) => {
+                return Err(self.error("This is a component, which must be loaded with component::load"));
             }
+            Ok((_, layer)) => return Err(self.error(&format!("Unknown layer {layer:#06x}"))),
+            Err(msg) => return Err(self.error(&msg)),
         }
 
-        match self.error {
-            None => Ok(self.module),
-            Some(err) => Err(err),
+        while !self.reader.at_end() {
+            let result = self.reader.u8().and_then(|id| self.section(id));
+            if let Err(msg) = result {
+                return Err(self.error(&msg));
+            }
         }
+        Ok(self.module)
     }
 
-    fn preliminary(&mut self) -> Result<u32, String> {
-        let magic = self.read_32();
-        if magic != WASM_BINARY_MAGIC {
-            return Err(String::from("Magic Number not found"));
+    /// Reads the section with this id, which must end where its size says.
+    fn section(&mut self, id: u8) -> Result<(), String> {
+        if id > 0x0d {
+            return Err(format!("Invalid section code {id:#04x}."));
         }
-        Ok(self.read_32())
+        let start = self.reader.pos - 1;
+        let size = self.reader.u32()? as usize;
+        let end = self.reader.pos.checked_add(size)
+            .filter(|end| *end <= self.reader.bytes.len())
+            .ok_or("Section extends past the end of the module")?;
+        match id {
+            0x00 => self.custom(start, end)?,
+            0x01 => self.types()?,
+            0x02 => self.imports()?,
+            0x03 => self.functions()?,
+            0x04 => self.tables()?,
+            0x05 => self.memory()?,
+            0x06 => self.globals()?,
+            0x07 => self.exports()?,
+            0x08 => self.start()?,
+            0x09 => self.element()?,
+            0x0a => self.code(end)?,
+            0x0b => self.data()?,
+            0x0c => self.data_count()?,
+            _ => self.tags()?,
+        }
+        if self.reader.pos != end {
+            return Err(format!("Section {id:#04x} does not end where its size says"));
+        }
+        Ok(())
     }
 
-    fn custom(&mut self) {
-        self.skip_section();
+    /// Records where a custom section's contents are, leaving them to be decoded when
+    /// asked for.
+    fn custom(&mut self, offset: usize, end: usize) -> Result<(), String> {
+        let name = self.reader.name()?;
+        if self.reader.pos > end {
+            return Err(String::from("Custom section name extends past the section"));
+        }
+        let contents = self.reader.pos..end;
+        self.module.custom_sections.push(CustomSection { name, offset, contents });
+        self.reader.pos = end;
+        Ok(())
     }
 
-    fn types(&mut self) {
-        self.read_size();       // section size
-
-        let num_types = self.read_size();
-        for _ in 0..num_types {
-            if self.read_byte() != 0x60 {
-                return self.error("Expected the function type 0x60");
+    fn types(&mut self) -> Result<(), String> {
+        let num_groups = self.read_size()?;
+        for _ in 0..num_groups {
+            // A type outside a `rec` is a group of its own
+            let num_types = if self.reader.peek() == Some(0x4e) {
+                self.reader.pos += 1;
+                self.read_size()?
+            } else {
+                1
+            };
+            let start = self.module.types.len() as u32;
+            for _ in 0..num_types {
+                let ty = self.sub_type()?;
+                self.module.types.push(ty);
             }
+            self.module.rec_groups.push(start..self.module.types.len() as u32);
+        }
+        Ok(())
+    }
 
-            let mut func_type = FuncType::default();
+    /// Reads a type definition, which `0x50` (or `0x4f` if it is final) prefixes when
+    /// it declares a supertype.
+    fn sub_type(&mut self) -> Result<SubType, String> {
+        let is_final = match self.reader.peek() {
+            Some(0x50) => false,
+            Some(0x4f) => true,
+            _ => return Ok(SubType::plain(self.composite_type()?)),
+        };
+        self.reader.pos += 1;
+        let supertype = match self.read_size()? {
+            0 => None,
+            1 => Some(self.reader.u32()?),
+            _ => return Err(String::from("Types may have at most one supertype")),
+        };
+        Ok(SubType { is_final, supertype, composite: self.composite_type()? })
+    }
 
-            let num_params = self.read_size();
-            for _ in 0..num_params {
-                match self.value_type() {
-                    Ok(t) => func_type.params.push(t),
-                    Err(msg) => return self.error(&msg),
+    fn composite_type(&mut self) -> Result<CompositeType, String> {
+        match self.reader.u8()? {
+            0x60 => {
+                let mut func_type = FuncType::default();
+                for _ in 0..self.read_size()? {
+                    func_type.params.push(self.value_type()?);
                 }
-            }
-
-            let num_results = self.read_size();
-            for _ in 0..num_results {
-                match self.value_type() {
-                    Ok(t) => func_type.results.push(t),
-                    Err(msg) => return self.error(&msg),
+                for _ in 0..self.read_size()? {
+                    func_type.results.push(self.value_type()?);
                 }
+                Ok(CompositeType::Func(func_type))
             }
-
-            self.module.types.push(func_type);
+            0x5f => {
+                let num_fields = self.read_size()?;
+                let fields = (0..num_fields).map(|_| self.field_type()).collect::<Result<_, _>>()?;
+                Ok(CompositeType::Struct(fields))
+            }
+            0x5e => Ok(CompositeType::Array(self.field_type()?)),
+            other => Err(format!("Invalid composite type {other:#04x}")),
         }
     }
 
-    fn imports(&mut self) {
-        self.unimplemented_section();
+    fn field_type(&mut self) -> Result<FieldType, String> {
+        let storage = match self.reader.peek() {
+            Some(0x78) => StorageType::I8,
+            Some(0x77) => StorageType::I16,
+            _ => StorageType::Val(self.value_type()?),
+        };
+        if storage.is_packed() {
+            self.reader.pos += 1;
+        }
+        let mutable = match self.reader.u8()? {
+            0x00 => false,
+            0x01 => true,
+            other => return Err(format!("Invalid mutability {other:#04x}")),
+        };
+        Ok(FieldType { storage, mutable })
     }
 
-    fn functions(&mut self) {
-        self.read_size();       // section size
+    /// Reads the imports. Functions, memories and immutable globals can be imported;
+    /// the host provides them when instantiating.
+    fn imports(&mut self) -> Result<(), String> {
+        let num_imports = self.read_size()?;
+        for _ in 0..num_imports {
+            let module = self.reader.name()?;
+            let name = self.reader.name()?;
+            let byte = self.reader.u8()?;
+            let Some(kind) = ExternalKind::from_byte(byte) else {
+                return Err(format!("Invalid import kind {byte:#04x}"));
+            };
+            let unsupported = |what: &str| {
+                format!("Importing {what} {module}.{name} is unsupported")
+            };
+            let index = match kind {
+                ExternalKind::Func => {
+                    let type_idx = self.read_size()?;
+                    self.module.functions.push(Function::imported(type_idx));
+                    self.module.functions.len() - 1
+                }
+                ExternalKind::Memory => {
+                    let memory = self.memory_type()?;
+                    self.module.memories.push(memory);
+                    self.module.memories.len() - 1
+                }
+                ExternalKind::Global => match self.global_type()? {
+                    ty if ty.mutable => return Err(unsupported("mutable global")),
+                    ty => {
+                        self.module.globals.push(Global { ty, init: None });
+                        self.module.globals.len() - 1
+                    }
+                },
+                _ => return Err(unsupported(&format!("{kind:?}"))),
+            };
+            self.module.imports.push(Import { module, name, kind, index });
+        }
+        Ok(())
+    }
 
-        let num_funcs = self.read_size();
+    fn functions(&mut self) -> Result<(), String> {
+        let num_funcs = self.read_size()?;
         for _ in 0..num_funcs {
-            let type_idx = self.read_byte() as usize;
+            let type_idx = self.read_size()?;
             self.module.functions.push(Function::new(type_idx));
         }
+        Ok(())
+    }
+
+    fn tables(&mut self) -> Result<(), String> {
+        let num_tables = self.read_size()?;
+        for _ in 0..num_tables {
+            let table = self.table()?;
+            self.module.tables.push(table);
+        }
+        Ok(())
+    }
+
+    /// Reads a table type, which `0x40 0x00` prefixes when an initializer for its
+    /// elements follows it.
+    fn table(&mut self) -> Result<Table, String> {
+        let has_init = self.reader.peek() == Some(0x40);
+        if has_init {
+            self.reader.pos += 1;
+            if self.reader.u8()? != 0x00 {
+                return Err(String::from("Expected 0x00 after 0x40 in a table type"));
+            }
+        }
+        let elem = self.ref_type()?;
+        let limits = self.limits()?;
+        let init = if has_init { Some(self.const_expr()?) } else { None };
+        Ok(Table { ty: TableType { elem, limits }, init })
     }
 
-    fn tables(&mut self) {
-        self.unimplemented_section();
+    fn memory(&mut self) -> Result<(), String> {
+        let num_memories = self.read_size()?;
+        for _ in 0..num_memories {
+            let memory = self.memory_type()?;
+            self.module.memories.push(memory);
+        }
+        Ok(())
     }
 
-    fn memory(&mut self) {
-        self.unimplemented_section();
+    fn tags(&mut self) -> Result<(), String> {
+        let num_tags = self.read_size()?;
+        for _ in 0..num_tags {
+            if self.reader.u8()? != 0x00 {
+                return Err(String::from("Invalid tag attribute"));
+            }
+            let type_idx = self.read_size()?;
+            self.module.tags.push(type_idx);
+        }
+        Ok(())
     }
 
-    fn globals(&mut self) {
-        self.unimplemented_section();
+    fn globals(&mut self) -> Result<(), String> {
+        let num_globals = self.read_size()?;
+        for _ in 0..num_globals {
+            let ty = self.global_type()?;
+            let init = self.const_expr()?;
+            self.module.globals.push(Global { ty, init: Some(init) });
+        }
+        Ok(())
     }
 
-    fn exports(&mut self) {
-        self.unimplemented_section();
+    fn exports(&mut self) -> Result<(), String> {
+        let num_exports = self.read_size()?;
+        for _ in 0..num_exports {
+            let name = self.reader.name()?;
+            let byte = self.reader.u8()?;
+            let Some(kind) = ExternalKind::from_byte(byte) else {
+                return Err(format!("Invalid export kind {byte:#04x}"));
+            };
+            let index = self.read_size()?;
+            self.module.exports.push(Export { name, kind, index });
+        }
+        Ok(())
     }
 
-    fn start(&mut self) {
-        self.read_size();       // section size
-        let function_idx = self.read_byte() as usize;
-        self.module.start_function = Some(function_idx);
+    fn start(&mut self) -> Result<(), String> {
+        self.module.start_function = Some(self.read_size()?);
+        Ok(())
     }
 
-    fn element(&mut self) {
-        self.unimplemented_section();
+    fn element(&mut self) -> Result<(), String> {
+        let num_segments = self.read_size()?;
+        for _ in 0..num_segments {
+            let segment = self.element_segment()?;
+            self.module.elements.push(segment);
+        }
+        Ok(())
     }
 
-    fn code(&mut self) {
-        let code_section_size = self.read_size();
-        let code_start = self.byte;
-        let code_end = code_start + code_section_size;
+    /// Reads an element segment. The low three bits of its flags select passive or
+    /// declarative rather than active, an explicit table index or declarative, and
+    /// constant expressions rather than function indices.
+    fn element_segment(&mut self) -> Result<ElementSegment, String> {
+        let flags = self.reader.u32()?;
+        if flags > 7 {
+            return Err(format!("Invalid element segment flags {flags}"));
+        }
+        let (uses_exprs, has_index) = (flags & 0b100 != 0, flags & 0b010 != 0);
+        let mode = if flags & 0b001 == 0 {
+            let index = if has_index { self.reader.u32()? } else { 0 };
+            SegmentMode::Active { index, offset: self.const_expr()? }
+        } else if has_index {
+            SegmentMode::Declarative
+        } else {
+            SegmentMode::Passive
+        };
 
-        // Create a copy of all the code into the module itself
-        self.module.code_section.extend_from_slice(&self.bytecode[code_start..code_end]);
+        let ty = match (flags & 0b011 == 0, uses_exprs) {
+            (true, _) => RefType::FUNCREF,
+            (false, false) => match self.reader.u8()? {
+                0x00 => RefType::FUNCREF,
+                other => return Err(format!("Invalid element kind {other:#04x}")),
+            },
+            (false, true) => self.ref_type()?,
+        };
 
-        let num_funcs = self.read_size();
-        for i in 0..num_funcs {
-            let body_size = self.read_size();
+        let num_elements = self.read_size()?;
+        let mut init = Vec::new();
+        for _ in 0..num_elements {
+            init.push(if uses_exprs {
+                self.const_expr()?
+            } else {
+                ConstExpr::value(Value::FuncRef(self.reader.u32()?))
+            });
+        }
+        Ok(ElementSegment { ty, mode, init })
+    }
+
+    fn code(&mut self, end: usize) -> Result<(), String> {
+        let code_start = self.reader.pos;
+
+        // Function bodies refer to the code where it is in the module's bytes
+        self.module.code_range = code_start..end;
 
-            // The Function refers to the code within the module, not the original bytecode
-            let function = &mut self.module.functions[i];
-            function.code_start = self.byte - code_start;
-            function.code_len = body_size;
-            self.byte += body_size;
+        // Imported functions come first and have no body
+        let num_imported = self.module.functions.iter().filter(|f| f.imported).count();
+        let num_funcs = self.read_size()?;
+        if num_funcs != self.module.functions.len() - num_imported {
+            return Err(String::from("Function and code section have inconsistent lengths"));
         }
+        for i in num_imported..num_imported + num_funcs {
+            self.function_body(i, code_start)?;
+        }
+        Ok(())
     }
 
-    fn data(&mut self) {
-        self.unimplemented_section();
+    /// Reads the size and locals of function `idx`, whose code starts after them. The
+    /// code section starts at `code_start`.
+    fn function_body(&mut self, idx: usize, code_start: usize) -> Result<(), String> {
+        let body_size = self.read_size()?;
+        let body_end = self.reader.pos.checked_add(body_size)
+            .filter(|end| *end <= self.reader.bytes.len())
+            .ok_or("Function body extends past the end of the module")?;
+
+        let mut locals = Vec::new();
+        let num_groups = self.read_size()?;
+        for _ in 0..num_groups {
+            let count = self.read_size()?;
+            let value_type = self.value_type()?;
+            if locals.len() + count > MAX_LOCALS {
+                return Err(String::from("Too many locals"));
+            }
+            locals.extend(std::iter::repeat_n(value_type, count));
+        }
+        if self.reader.pos > body_end {
+            return Err(String::from("Function locals extend past the end of its body"));
+        }
+
+        // The Function refers to the code within the code section
+        let function = &mut self.module.functions[idx];
+        function.locals = locals;
+        function.code_start = self.reader.pos - code_start;
+        function.code_len = body_end - self.reader.pos;
+        self.reader.pos = body_end;
+        Ok(())
     }
 
-    fn skip_section(&mut self) {
-        println!("Skipping section {:#04x}", self.bytecode[self.byte-1]);
-        let size = self.read_size();
-        self.byte += size;
+    fn data(&mut self) -> Result<(), String> {
+        let num_segments = self.read_size()?;
+        for _ in 0..num_segments {
+            let mode = match self.reader.u32()? {
+                0x00 => SegmentMode::Active { index: 0, offset: self.const_expr()? },
+                0x01 => SegmentMode::Passive,
+                0x02 => {
+                    let index = self.reader.u32()?;
+                    SegmentMode::Active { index, offset: self.const_expr()? }
+                }
+                other => return Err(format!("Invalid data segment flags {other}")),
+            };
+            let len = self.read_size()?;
+            let init = self.reader.take(len)
+                .map_err(|_| "Data segment extends past the end of the module")?
+                .to_vec();
+            self.module.data.push(DataSegment { mode, init });
+        }
+        Ok(())
     }
 
-    fn unimplemented_section(&mut self) {
-        let section_code = self.bytecode[self.byte-1];
-        self.error(&format!("Section {section_code:#04x} is currently unimplemented"));
+    fn data_count(&mut self) -> Result<(), String> {
+        self.module.data_count = Some(self.reader.u32()?);
+        Ok(())
     }
 
     fn value_type(&mut self) -> Result<ValType, String> {
-        match self.read_byte() {
-            0x7f => Ok(ValType::I32),
-            0x7e => Ok(ValType::I64),
-            0x7d => Ok(ValType::F32),
-            0x7c => Ok(ValType::F64),
-            other => Err(format!("Invalid value type {other:#04x}")),
+        let byte = self.reader.peek().unwrap_or_default();
+        let (ty, size) = bytecode::read::read_val_type(self.reader.bytes, self.reader.pos)
+            .ok_or_else(|| format!("Invalid value type {byte:#04x}"))?;
+        self.reader.pos += size;
+        Ok(ty)
+    }
+
+    fn ref_type(&mut self) -> Result<RefType, String> {
+        match self.value_type()? {
+            ValType::Ref(ty) => Ok(ty),
+            _ => Err(String::from("Expected a reference type")),
         }
     }
 
-    fn name(&mut self) -> Result<String, String> {
-        let len = self.read_size();
-        let end = self.byte + (len as usize);
-        match std::str::from_utf8(&self.bytecode[self.byte..end]) {
-            Ok(s) => Ok(s.to_string()),
-            Err(e) => Err(format!("{e}")),
+    /// Reads a constant expression up to its `end`. Its types are checked during
+    /// validation.
+    fn const_expr(&mut self) -> Result<ConstExpr, String> {
+        let mut ops = Vec::new();
+        loop {
+            if self.reader.at_end() {
+                return Err(String::from("Expected the end of the constant expression"));
+            }
+            let op = match self.reader.u8()? {
+                END => return Ok(ConstExpr(ops)),
+                I32_CONST => ConstOp::Value(Value::I32(self.reader.s32()?)),
+                I64_CONST => ConstOp::Value(Value::I64(self.reader.s64()?)),
+                F32_CONST => ConstOp::Value(Value::F32(self.reader.f32()?)),
+                F64_CONST => ConstOp::Value(Value::F64(self.reader.f64()?)),
+                REF_NULL => match bytecode::read::read_heap_type(self.reader.bytes, self.reader.pos) {
+                    Some((heap, size)) => {
+                        self.reader.pos += size;
+                        ConstOp::Value(Value::RefNull(heap))
+                    }
+                    None => return Err(String::from("Invalid heap type")),
+                },
+                REF_FUNC => ConstOp::Value(Value::FuncRef(self.reader.u32()?)),
+                GLOBAL_GET => ConstOp::GlobalGet(self.reader.u32()?),
+                I32_ADD => ConstOp::I32Add,
+                I32_SUB => ConstOp::I32Sub,
+                I32_MUL => ConstOp::I32Mul,
+                I64_ADD => ConstOp::I64Add,
+                I64_SUB => ConstOp::I64Sub,
+                I64_MUL => ConstOp::I64Mul,
+                GC_PREFIX if self.reader.u32()? == gc::REF_I31 => ConstOp::RefI31,
+                SIMD_PREFIX if self.reader.u32()? == simd::V128_CONST => {
+                    let bytes = self.reader.take(16)?;
+                    ConstOp::Value(Value::V128(i128::from_le_bytes(bytes.try_into().unwrap())))
+                }
+                op => return Err(format!("Unsupported constant expression instruction {op:#04x}")),
+            };
+            ops.push(op);
         }
     }
 
-    fn read_byte(&mut self) -> u8 {
-        self.byte += 1;
-        self.bytecode[self.byte - 1]
+    fn global_type(&mut self) -> Result<GlobalType, String> {
+        let ty = self.value_type()?;
+        let mutable = match self.reader.u8()? {
+            0x00 => false,
+            0x01 => true,
+            other => return Err(format!("Invalid mutability {other:#04x}")),
+        };
+        Ok(GlobalType { ty, mutable })
     }
 
-    fn read_16(&mut self) -> u16 {
-        self.byte += 2;
-        bytecode::read::read_16(self.bytecode, self.byte - 2)
+    /// Memory limits, whose flags may also mark the memory as shared (bit 1) or as
+    /// having 64-bit addresses (bit 2), which makes the limits u64s rather than u32s.
+    fn memory_type(&mut self) -> Result<MemoryType, String> {
+        let flags = self.reader.u8()?;
+        if flags > 0x07 {
+            return Err(format!("Invalid limits flag {flags:#04x}"));
+        }
+        let shared = flags & 0x02 != 0;
+        if shared && flags & 0x01 == 0 {
+            return Err(String::from("Shared memory must have a maximum"));
+        }
+        let memory64 = flags & 0x04 != 0;
+        let mut bound = || if memory64 { self.reader.u64() } else { self.reader.u32().map(u64::from) };
+        let min = bound()?;
+        let max = if flags & 0x01 != 0 { Some(bound()?) } else { None };
+        Ok(MemoryType { limits: Limits { min, max }, shared, memory64 })
     }
 
-    fn read_32(&mut self) -> u32 {
-        self.byte += 4;
-        bytecode::read::read_32(self.bytecode, self.byte - 4)
+    fn limits(&mut self) -> Result<Limits, String> {
+        match self.reader.u8()? {
+            0x00 => {
+                let min = self.reader.u32()? as u64;
+                Ok(Limits { min, max: None })
+            }
+            0x01 => {
+                let min = self.reader.u32()? as u64;
+                let max = self.reader.u32()? as u64;
+                Ok(Limits { min, max: Some(max) })
+            }
+            other => Err(format!("Invalid limits flag {other:#04x}")),
+        }
+    }
+
+    /// A count or index, which are u32s.
+    fn read_size(&mut self) -> Result<usize, String> {
+        Ok(self.reader.u32()? as usize)
+    }
+
+    /// An error at the byte before the reader's position.
+    fn error(&self, msg: &str) -> WasmLoadError {
+        WasmLoadError { byte: self.reader.pos.saturating_sub(1), msg: msg.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{name, ModuleBuilder};
+    use crate::value::ValType::*;
+
+    fn within(code: &[u8], bytes: &[u8]) -> bool {
+        bytes.as_ptr_range().contains(&code.as_ptr())
+    }
+
+    #[test]
+    fn zero_copy() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[I32], &[], &[I32_CONST, 7, END]);
+        let names = [&[0x01, 0x07, 0x01, 0x00][..], &name("main")].concat();
+        builder.custom("name", &names);
+        builder.custom(".debug_str", b"abc");
+        let bytecode = builder.build();
+
+        let module = load(&bytecode).ok().unwrap();
+        assert!(within(module.function_code(0), &bytecode));
+        assert!(within(module.custom_section(".debug_str").unwrap(), &bytecode));
+        assert_eq!(Some(&b"abc"[..]), module.custom_section(".debug_str"));
+
+        // Names are only decoded when first asked for
+        assert!(module.function_names.get().is_none());
+        assert_eq!(Some("main"), module.function_name(0));
+        assert_eq!(Some(0), module.function_by_name("main"));
+
+        let shared: Arc<[u8]> = bytecode.clone().into();
+        let module = load_bytes(shared.clone().into()).ok().unwrap();
+        assert!(within(module.function_code(0), &shared));
+        assert_eq!(&[I32_CONST, 7, END], module.function_code(0));
+
+        let path = std::env::temp_dir().join(format!("wavm-zero-copy-{}.wasm", std::process::id()));
+        std::fs::write(&path, &bytecode).unwrap();
+        let mapped = Bytes::map(&File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let module = load_bytes(mapped).ok().unwrap();
+        assert!(matches!(module.bytes, Bytes::Mapped(_)));
+        assert!(within(module.function_code(0), &module.bytes));
+        assert_eq!(&[I32_CONST, 7, END], module.function_code(0));
+        assert_eq!(Some("main"), module.function_name(0));
     }
 
-    fn read_size(&mut self) -> usize {
-        let (val, offset) = bytecode::read::read_size(self.bytecode, self.byte);
-        self.byte += offset;
-        val as usize
+    #[test]
+    fn malformed() {
+        let error = |bytecode: &[u8]| load(bytecode).err().unwrap().formatted();
+
+        // A start index of 200 takes two LEB128 bytes
+        let mut builder = ModuleBuilder::new();
+        for _ in 0..=200 {
+            builder.func(&[], &[], &[], &[END]);
+        }
+        builder.start(200);
+        assert_eq!(Some(200), load(&builder.build()).ok().unwrap().start_function);
+
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[END]);
+        builder.start(0);
+        builder.custom("c", b"");
+        let bytecode = builder.build();
+        let start = bytecode.windows(3).position(|w| w == [0x08, 0x01, 0x00]).unwrap();
+        let mut padded = bytecode.clone();
+        padded[start + 1] = 0x02;
+        padded.insert(start + 3, 0x00);
+        assert!(error(&padded).ends_with("Section 0x08 does not end where its size says"));
+
+        let end = bytecode.len();
+        let mut long = bytecode.clone();
+        long[end - 3] += 1;
+        assert!(error(&long).ends_with("Section extends past the end of the module"));
+
+        let mut overlong = bytecode[..end - 3].to_vec();
+        overlong.extend([0xff, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert!(error(&overlong).ends_with("Integer representation too long"));
     }
 
-    fn error(&mut self, msg: &str) {
-        self.error = Some(WasmLoadError {
-            byte: self.byte - 1,
-            msg: msg.to_string(),
-        });
+    #[test]
+    fn custom_sections() {
+        let mut builder = ModuleBuilder::new();
+        builder.func(&[], &[], &[], &[END]);
+        builder.custom("a", b"one");
+        builder.custom("b", b"");
+        builder.custom("a", b"two");
+        let bytecode = builder.build();
+        let module = load(&bytecode).ok().unwrap();
+
+        let names: Vec<_> = module.custom_sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(vec!["a", "b", "a"], names);
+        let found: Vec<_> = module.custom_sections_named("a").collect();
+        assert_eq!(vec![&b"one"[..], &b"two"[..]], found);
+        assert_eq!(Some(&b""[..]), module.custom_section("b"));
+        assert_eq!(None, module.custom_section("c"));
+
+        let last = module.custom_sections.last().unwrap();
+        assert_eq!(bytecode.len() - 7, last.offset);
+        assert_eq!(&[0x00, 0x05, 0x01, b'a'], &bytecode[last.offset..last.contents.start]);
+        assert_eq!(b"two", module.custom_contents(last));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,675 @@
+#![allow(dead_code)]
+
+//! WIT, the text format describing the interfaces and worlds components import and
+//! export.
+//!
+//! A file is parsed as one package. Only the interfaces it defines can be referred to;
+//! those of other packages would need their files too.
+
+/// Parses a package from WIT source.
+pub fn parse(source: &str) -> Result<Package, String> {
+    let tokens = lex(source)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let package = parser.package();
+    package.map_err(|msg| format!("Line {}: {msg}", parser.line()))
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Package {
+    /// The `namespace:name` the package declares, without its version.
+    pub name: Option<String>,
+    pub interfaces: Vec<Interface>,
+    pub worlds: Vec<World>,
+}
+
+impl Package {
+    pub fn interface(&self, name: &str) -> Option<&Interface> {
+        self.interfaces.iter().find(|interface| interface.name == name)
+    }
+
+    pub fn world(&self, name: &str) -> Option<&World> {
+        self.worlds.iter().find(|world| world.name == name)
+    }
+
+    /// The name a component imports or exports the interface `name` of this package by.
+    pub fn qualified(&self, name: &str) -> String {
+        match &self.name {
+            Some(package) => format!("{package}/{name}"),
+            None => name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub uses: Vec<Use>,
+    pub types: Vec<TypeDef>,
+    pub funcs: Vec<Function>,
+}
+
+/// Types brought in from another interface, each as its name there and here.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Use {
+    pub interface: String,
+    pub names: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDef {
+    pub name: String,
+    pub kind: TypeDefKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeDefKind {
+    Alias(Type),
+    Record(Vec<(String, Type)>),
+    Variant(Vec<(String, Option<Type>)>),
+    Enum(Vec<String>),
+    Flags(Vec<String>),
+    /// A resource and its constructor, methods and static functions.
+    Resource(Vec<Function>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Bool,
+    S8,
+    U8,
+    S16,
+    U16,
+    S32,
+    U32,
+    S64,
+    U64,
+    F32,
+    F64,
+    Char,
+    String,
+    List(Box<Type>),
+    Option(Box<Type>),
+    Result(Option<Box<Type>>, Option<Box<Type>>),
+    Tuple(Vec<Type>),
+    Borrow(String),
+    /// A defined type, or an owned handle to a resource.
+    Named(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub kind: FuncKind,
+    pub params: Vec<(String, Type)>,
+    /// The results, which are named unless there is a single one.
+    pub results: Vec<(String, Type)>,
+}
+
+impl Function {
+    /// The name a component gives the function: for resources, the kind of function
+    /// in brackets then the resource.
+    pub fn component_name(&self) -> String {
+        match &self.kind {
+            FuncKind::Freestanding => self.name.clone(),
+            FuncKind::Constructor(resource) => format!("[constructor]{resource}"),
+            FuncKind::Method(resource) => format!("[method]{resource}.{}", self.name),
+            FuncKind::Static(resource) => format!("[static]{resource}.{}", self.name),
+        }
+    }
+}
+
+/// Whether a function belongs to a resource, and how.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FuncKind {
+    Freestanding,
+    Constructor(String),
+    /// A method, whose first parameter is `self`, a borrowed handle.
+    Method(String),
+    Static(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct World {
+    pub name: String,
+    pub uses: Vec<Use>,
+    pub types: Vec<TypeDef>,
+    pub imports: Vec<WorldItem>,
+    pub exports: Vec<WorldItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorldItem {
+    Func(Function),
+    /// An interface defined in the world under `name`.
+    Inline { name: String, interface: Interface },
+    /// An interface of the package, referred to by name.
+    Interface(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    /// A version after `@`.
+    Version(String),
+    Symbol(char),
+    Arrow,
+}
+
+/// The tokens of `source`, each with its line. Comments, including doc comments, are
+/// dropped.
+fn lex(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            last = c;
+                        }
+                        None => return Err(format!("Line {line}: Unterminated comment")),
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push((Token::Arrow, line));
+            }
+            '@' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let mut version = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || "-+.".contains(*c)) {
+                    version.push(c);
+                }
+                // A path may go on with `.{`, which isn't part of the version
+                if version.ends_with('.') && chars.peek() == Some(&'{') {
+                    version.pop();
+                    tokens.push((Token::Version(version), line));
+                    tokens.push((Token::Symbol('.'), line));
+                } else {
+                    tokens.push((Token::Version(version), line));
+                }
+            }
+            '%' | 'a'..='z' | 'A'..='Z' | '0'..='9' => {
+                let mut ident = String::new();
+                if c != '%' {
+                    ident.push(c);
+                }
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '-') {
+                    ident.push(c);
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            '{' | '}' | '(' | ')' | '<' | '>' | ':' | ';' | ',' | '.' | '=' | '/' | '@' | '_'
+            | '*' => tokens.push((Token::Symbol(c), line)),
+            c => return Err(format!("Line {line}: Unexpected character {c:?}")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn package(&mut self) -> Result<Package, String> {
+        let mut package = Package::default();
+        while self.pos < self.tokens.len() {
+            self.gates()?;
+            match self.ident()?.as_str() {
+                "package" if package.name.is_none() => {
+                    let name = self.package_name()?;
+                    self.symbol(';')?;
+                    package.name = Some(name);
+                }
+                "interface" => {
+                    let name = self.ident()?;
+                    package.interfaces.push(self.interface(name)?);
+                }
+                "world" => package.worlds.push(self.world()?),
+                "use" => {
+                    // Top-level uses only name interfaces for the package's other files
+                    while !self.eat(';') {
+                        self.next()?;
+                    }
+                }
+                other => return Err(format!("Expected an interface or world, found {other}")),
+            }
+        }
+        Ok(package)
+    }
+
+    /// `namespace:name`, dropping any version.
+    fn package_name(&mut self) -> Result<String, String> {
+        let namespace = self.ident()?;
+        self.symbol(':')?;
+        let name = self.ident()?;
+        self.version();
+        Ok(format!("{namespace}:{name}"))
+    }
+
+    fn version(&mut self) {
+        if let Some((Token::Version(_), _)) = self.tokens.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn interface(&mut self, name: String) -> Result<Interface, String> {
+        let mut interface = Interface { name, ..Interface::default() };
+        self.symbol('{')?;
+        while !self.eat('}') {
+            self.gates()?;
+            let ident = self.ident()?;
+            if ident == "use" {
+                interface.uses.push(self.use_item()?);
+            } else if let Some(ty) = self.type_def(&ident)? {
+                interface.types.push(ty);
+            } else {
+                self.symbol(':')?;
+                interface.funcs.push(self.func(ident, FuncKind::Freestanding)?);
+            }
+        }
+        Ok(interface)
+    }
+
+    fn world(&mut self) -> Result<World, String> {
+        let mut world = World { name: self.ident()?, ..World::default() };
+        self.symbol('{')?;
+        while !self.eat('}') {
+            self.gates()?;
+            let ident = self.ident()?;
+            match ident.as_str() {
+                "import" => world.imports.push(self.world_item()?),
+                "export" => world.exports.push(self.world_item()?),
+                "use" => world.uses.push(self.use_item()?),
+                "include" => return Err(String::from("include is unsupported")),
+                _ => match self.type_def(&ident)? {
+                    Some(ty) => world.types.push(ty),
+                    None => return Err(format!("Unexpected {ident} in a world")),
+                },
+            }
+        }
+        Ok(world)
+    }
+
+    fn world_item(&mut self) -> Result<WorldItem, String> {
+        let name = self.ident()?;
+        if self.eat(':') {
+            if self.peek_ident("interface") {
+                self.pos += 1;
+                let interface = self.interface(name.clone())?;
+                return Ok(WorldItem::Inline { name, interface });
+            }
+            if !self.peek_ident("func") {
+                // Another package's interface, as `namespace:package/interface`
+                let package = self.ident()?;
+                return Err(format!("Interface {name}:{package} is not defined in this file"));
+            }
+            let func = self.func(name, FuncKind::Freestanding)?;
+            return Ok(WorldItem::Func(func));
+        }
+        self.symbol(';')?;
+        Ok(WorldItem::Interface(name))
+    }
+
+    fn use_item(&mut self) -> Result<Use, String> {
+        let interface = self.ident()?;
+        if self.eat(':') {
+            return Err(format!("Interfaces of package {interface} can't be used"));
+        }
+        self.symbol('.')?;
+        self.symbol('{')?;
+        let mut names = Vec::new();
+        while !self.eat('}') {
+            let name = self.ident()?;
+            let alias = match self.peek_ident("as") {
+                true => {
+                    self.pos += 1;
+                    self.ident()?
+                }
+                false => name.clone(),
+            };
+            names.push((name, alias));
+            if !self.eat(',') {
+                self.symbol('}')?;
+                break;
+            }
+        }
+        self.symbol(';')?;
+        Ok(Use { interface, names })
+    }
+
+    /// Reads a type definition if `keyword` starts one.
+    fn type_def(&mut self, keyword: &str) -> Result<Option<TypeDef>, String> {
+        let kind = match keyword {
+            "type" => {
+                let name = self.ident()?;
+                self.symbol('=')?;
+                let ty = self.ty()?;
+                self.symbol(';')?;
+                return Ok(Some(TypeDef { name, kind: TypeDefKind::Alias(ty) }));
+            }
+            "record" | "variant" | "enum" | "flags" | "resource" => keyword,
+            _ => return Ok(None),
+        };
+        let name = self.ident()?;
+        let kind = match kind {
+            "record" => TypeDefKind::Record(self.list(|p| {
+                let field = p.ident()?;
+                p.symbol(':')?;
+                Ok((field, p.ty()?))
+            })?),
+            "variant" => TypeDefKind::Variant(self.list(|p| {
+                let case = p.ident()?;
+                let payload = match p.eat('(') {
+                    true => {
+                        let ty = p.ty()?;
+                        p.symbol(')')?;
+                        Some(ty)
+                    }
+                    false => None,
+                };
+                Ok((case, payload))
+            })?),
+            "enum" => TypeDefKind::Enum(self.list(Self::ident)?),
+            "flags" => TypeDefKind::Flags(self.list(Self::ident)?),
+            _ => TypeDefKind::Resource(self.resource(&name)?),
+        };
+        Ok(Some(TypeDef { name, kind }))
+    }
+
+    /// Reads a resource's functions, if it has a body.
+    fn resource(&mut self, resource: &str) -> Result<Vec<Function>, String> {
+        let mut funcs = Vec::new();
+        if self.eat(';') {
+            return Ok(funcs);
+        }
+        self.symbol('{')?;
+        while !self.eat('}') {
+            self.gates()?;
+            let name = self.ident()?;
+            if name == "constructor" {
+                let params = self.params()?;
+                self.symbol(';')?;
+                let results = vec![(String::new(), Type::Named(resource.to_string()))];
+                let kind = FuncKind::Constructor(resource.to_string());
+                funcs.push(Function { name, kind, params, results });
+                continue;
+            }
+            self.symbol(':')?;
+            let kind = match self.peek_ident("static") {
+                true => {
+                    self.pos += 1;
+                    FuncKind::Static(resource.to_string())
+                }
+                false => FuncKind::Method(resource.to_string()),
+            };
+            let mut func = self.func(name, kind)?;
+            if let FuncKind::Method(resource) = &func.kind {
+                func.params.insert(0, (String::from("self"), Type::Borrow(resource.clone())));
+            }
+            funcs.push(func);
+        }
+        Ok(funcs)
+    }
+
+    /// Reads a function type and the `;` ending it.
+    fn func(&mut self, name: String, kind: FuncKind) -> Result<Function, String> {
+        if self.ident()? != "func" {
+            return Err(format!("Expected func after {name}"));
+        }
+        let params = self.params()?;
+        let mut results = Vec::new();
+        if self.eat_arrow() {
+            match self.tokens.get(self.pos) {
+                Some((Token::Symbol('('), _)) => results = self.params()?,
+                _ => results.push((String::new(), self.ty()?)),
+            }
+        }
+        self.symbol(';')?;
+        Ok(Function { name, kind, params, results })
+    }
+
+    fn params(&mut self) -> Result<Vec<(String, Type)>, String> {
+        self.symbol('(')?;
+        let mut params = Vec::new();
+        while !self.eat(')') {
+            let name = self.ident()?;
+            self.symbol(':')?;
+            params.push((name, self.ty()?));
+            if !self.eat(',') {
+                self.symbol(')')?;
+                break;
+            }
+        }
+        Ok(params)
+    }
+
+    fn ty(&mut self) -> Result<Type, String> {
+        let name = self.ident()?;
+        Ok(match name.as_str() {
+            "bool" => Type::Bool,
+            "s8" => Type::S8,
+            "u8" => Type::U8,
+            "s16" => Type::S16,
+            "u16" => Type::U16,
+            "s32" => Type::S32,
+            "u32" => Type::U32,
+            "s64" => Type::S64,
+            "u64" => Type::U64,
+            "f32" | "float32" => Type::F32,
+            "f64" | "float64" => Type::F64,
+            "char" => Type::Char,
+            "string" => Type::String,
+            "list" => Type::List(Box::new(self.type_arg()?)),
+            "option" => Type::Option(Box::new(self.type_arg()?)),
+            "own" => {
+                let Type::Named(resource) = self.type_arg()? else {
+                    return Err(String::from("own takes a resource"));
+                };
+                Type::Named(resource)
+            }
+            "borrow" => {
+                let Type::Named(resource) = self.type_arg()? else {
+                    return Err(String::from("borrow takes a resource"));
+                };
+                Type::Borrow(resource)
+            }
+            "result" => {
+                if !self.eat('<') {
+                    return Ok(Type::Result(None, None));
+                }
+                let ok = match self.eat('_') {
+                    true => None,
+                    false => Some(Box::new(self.ty()?)),
+                };
+                let err = match self.eat(',') {
+                    true => Some(Box::new(self.ty()?)),
+                    false => None,
+                };
+                self.symbol('>')?;
+                Type::Result(ok, err)
+            }
+            "tuple" => {
+                self.symbol('<')?;
+                let mut types = Vec::new();
+                while !self.eat('>') {
+                    types.push(self.ty()?);
+                    if !self.eat(',') {
+                        self.symbol('>')?;
+                        break;
+                    }
+                }
+                Type::Tuple(types)
+            }
+            _ => Type::Named(name),
+        })
+    }
+
+    fn type_arg(&mut self) -> Result<Type, String> {
+        self.symbol('<')?;
+        let ty = self.ty()?;
+        self.symbol('>')?;
+        Ok(ty)
+    }
+
+    /// Reads a braced, comma-separated list.
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, String>)
+        -> Result<Vec<T>, String>
+    {
+        self.symbol('{')?;
+        let mut items = Vec::new();
+        while !self.eat('}') {
+            items.push(item(self)?);
+            if !self.eat(',') {
+                self.symbol('}')?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Skips feature gates such as `@since(version = 0.2.0)`, which don't change types.
+    fn gates(&mut self) -> Result<(), String> {
+        while self.eat('@') {
+            self.ident()?;
+            if self.eat('(') {
+                while !self.eat(')') {
+                    self.next()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let (token, _) = self.tokens.get(self.pos).cloned().ok_or("Unexpected end of file")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            other => Err(format!("Expected a name, found {other:?}")),
+        }
+    }
+
+    fn peek_ident(&self, ident: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some((Token::Ident(found), _)) if found == ident)
+    }
+
+    fn symbol(&mut self, symbol: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Symbol(found) if found == symbol => Ok(()),
+            other => Err(format!("Expected {symbol:?}, found {other:?}")),
+        }
+    }
+
+    /// Skips `symbol` if it is next, returning whether it was.
+    fn eat(&mut self, symbol: char) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some((Token::Symbol(s), _)) if *s == symbol);
+        self.pos += found as usize;
+        found
+    }
+
+    fn eat_arrow(&mut self) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some((Token::Arrow, _)));
+        self.pos += found as usize;
+        found
+    }
+
+    /// The line of the token last read.
+    fn line(&self) -> usize {
+        let pos = self.pos.saturating_sub(1).min(self.tokens.len().saturating_sub(1));
+        self.tokens.get(pos).map_or(1, |(_, line)| *line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "
+        package example:shapes@0.1.0;
+
+        /// Shapes and what to do with them.
+        interface shapes {
+            record point { x: f64, y: f64 }
+            variant shape { circle(f64), polygon(list<point>), empty }
+            enum color { red, green, blue, }
+            flags edges { top, bottom }
+            resource canvas {
+                constructor(width: u32, height: u32);
+                draw: func(s: shape, c: option<color>) -> result<_, string>;
+                open: static func(name: string) -> canvas;
+            }
+            area: func(s: shape) -> f64;
+        }
+
+        world app {
+            use shapes.{point as pt};
+            import shapes;
+            import log: func(msg: string);
+            export run: func(args: list<string>) -> (code: s32, out: tuple<pt, bool>);
+            export extra: interface {
+                /* not much */
+                version: func() -> string;
+            }
+        }
+    ";
+
+    #[test]
+    fn parses() {
+        let package = parse(SOURCE).unwrap();
+        assert_eq!(Some("example:shapes"), package.name.as_deref());
+        assert_eq!("example:shapes/shapes", package.qualified("shapes"));
+
+        let shapes = package.interface("shapes").unwrap();
+        assert_eq!(5, shapes.types.len());
+        assert_eq!(
+            TypeDefKind::Variant(vec![
+                (String::from("circle"), Some(Type::F64)),
+                (String::from("polygon"), Some(Type::List(Box::new(Type::Named(String::from("point")))))),
+                (String::from("empty"), None),
+            ]),
+            shapes.types[1].kind,
+        );
+        let TypeDefKind::Resource(funcs) = &shapes.types[4].kind else { panic!() };
+        let names: Vec<_> = funcs.iter().map(Function::component_name).collect();
+        assert_eq!(vec!["[constructor]canvas", "[method]canvas.draw", "[static]canvas.open"], names);
+        assert_eq!((String::from("self"), Type::Borrow(String::from("canvas"))), funcs[1].params[0]);
+        assert_eq!(
+            vec![(String::new(), Type::Result(None, Some(Box::new(Type::String))))],
+            funcs[1].results,
+        );
+
+        let app = package.world("app").unwrap();
+        assert_eq!(vec![(String::from("point"), String::from("pt"))], app.uses[0].names);
+        assert_eq!(WorldItem::Interface(String::from("shapes")), app.imports[0]);
+        let WorldItem::Func(run) = &app.exports[0] else { panic!() };
+        assert_eq!("code", run.results[0].0);
+        assert!(matches!(&app.exports[1], WorldItem::Inline { name, .. } if name == "extra"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(String::from("Line 3: Expected ':', found Symbol('(')")),
+            parse("interface i {\n  type t = u32;\n  f(x: u32);\n}"));
+        assert_eq!(Err(String::from("Line 1: Interface wasi:cli is not defined in this file")),
+            parse("world w { import wasi:cli/stdout; }"));
+        assert!(parse("world w { export f: func() -> ; }").is_err());
+    }
+}